@@ -0,0 +1,24 @@
+/*
+ * 反复创建一个channel、收发一个msg、然后销毁，测量每个channel的平均耗时
+ * 开启recycle时Counter的内存放回当前线程的缓存，下一次创建直接复用，对比默认的每次都分配、释放
+ */
+mod common;
+
+use channel::mpmc::Builder;
+
+const CHANNELS: u64 = 100_000;
+
+fn churn(builder: fn() -> Builder) -> impl FnMut() {
+    move || {
+        for i in 0..CHANNELS {
+            let (s, r) = builder().channel::<u64>();
+            s.send(i).unwrap();
+            std::hint::black_box(r.recv().unwrap());
+        }
+    }
+}
+
+fn main() {
+    common::bench("list create/destroy", CHANNELS, churn(Builder::new));
+    common::bench("list create/destroy, recycle", CHANNELS, churn(|| Builder::new().recycle()));
+}
@@ -0,0 +1,17 @@
+// benches共用的计时工具，只依赖std::time，不需要criterion之类的外部crate
+use std::time::Instant;
+
+// 每轮调用一次f，f内部执行ops次操作；预热一轮之后取ROUNDS轮中最快的一轮，输出每次操作的平均耗时
+pub fn bench(name: &str, ops: u64, mut f: impl FnMut()) {
+    const ROUNDS: usize = 5;
+    f();
+    let best = (0..ROUNDS)
+        .map(|_| {
+            let start = Instant::now();
+            f();
+            start.elapsed()
+        })
+        .min()
+        .unwrap();
+    println!("{name:<48} {:>10.1} ns/op", best.as_nanos() as f64 / ops as f64);
+}
@@ -0,0 +1,41 @@
+/*
+ * 多个producer向同一个channel发送，唯一的consumer接收，测量每个msg的平均耗时
+ * recv总是用CAS移动head，recv_exclusive在唯一的receiver上直接store，对比两者可以看出MPSC快速路径的收益
+ */
+mod common;
+
+use channel::mpmc::{channel, sync_channel, Receiver, Sender};
+use std::thread;
+
+const PRODUCERS: u64 = 4;
+const PER_PRODUCER: u64 = 50_000;
+const TOTAL: u64 = PRODUCERS * PER_PRODUCER;
+
+fn mpsc(make: impl Fn() -> (Sender<u64>, Receiver<u64>), exclusive: bool) -> impl FnMut() {
+    move || {
+        let (s, mut r) = make();
+        let producers: Vec<_> = (0..PRODUCERS)
+            .map(|_| {
+                let s = s.clone();
+                thread::spawn(move || {
+                    for i in 0..PER_PRODUCER {
+                        s.send(i).unwrap();
+                    }
+                })
+            })
+            .collect();
+        drop(s);
+        for _ in 0..TOTAL {
+            let msg = if exclusive { r.recv_exclusive() } else { r.recv() };
+            std::hint::black_box(msg.unwrap());
+        }
+        producers.into_iter().for_each(|t| t.join().unwrap());
+    }
+}
+
+fn main() {
+    common::bench("list mpsc, recv", TOTAL, mpsc(channel, false));
+    common::bench("list mpsc, recv_exclusive", TOTAL, mpsc(channel, true));
+    common::bench("array(1024) mpsc, recv", TOTAL, mpsc(|| sync_channel(1024), false));
+    common::bench("array(1024) mpsc, recv_exclusive", TOTAL, mpsc(|| sync_channel(1024), true));
+}
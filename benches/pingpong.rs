@@ -0,0 +1,39 @@
+/*
+ * 两个线程通过一对无界channel来回传递一个msg，测量一次往返的延迟
+ * spin_before_park(0)相当于list的recv在start_recv失败后立即注册、park，
+ * 对比默认的自旋上限，可以看出自旋吸收了多少短暂的空channel
+ * 自旋需要对端在另一个CPU上同时运行，只有一个CPU时自旋只会推迟park，默认配置反而更慢
+ */
+mod common;
+
+use channel::mpmc::{Builder, Receiver, Sender};
+use std::thread;
+
+const ROUNDTRIPS: u64 = 20_000;
+
+fn pingpong(make: impl Fn() -> (Sender<u64>, Receiver<u64>)) -> impl FnMut() {
+    move || {
+        let (ping_s, ping_r) = make();
+        let (pong_s, pong_r) = make();
+        let echo = thread::spawn(move || {
+            for msg in ping_r.iter() {
+                pong_s.send(msg).unwrap();
+            }
+        });
+        for i in 0..ROUNDTRIPS {
+            ping_s.send(i).unwrap();
+            assert_eq!(pong_r.recv(), Ok(i));
+        }
+        drop(ping_s);
+        echo.join().unwrap();
+    }
+}
+
+fn main() {
+    common::bench(
+        "list ping-pong, park immediately",
+        ROUNDTRIPS,
+        pingpong(|| Builder::new().spin_before_park(0).channel()),
+    );
+    common::bench("list ping-pong, default spin", ROUNDTRIPS, pingpong(|| Builder::new().channel()));
+}
@@ -0,0 +1,45 @@
+/*
+ * 没有receiver等待时，多个线程反复在同一个zero channel上try_send
+ * try_send先检查原子标志，失败时不加锁；has_waiting_receiver每次都要获取channel的Mutex，
+ * 与加入标志之前try_send的加锁检查相同，作为对照
+ */
+mod common;
+
+use channel::mpmc::{sync_channel, Sender, TrySendError};
+use std::thread;
+
+const POLLS: u64 = 200_000;
+
+fn poll(threads: u64, f: fn(&Sender<u64>)) -> impl FnMut() {
+    let (s, r) = sync_channel::<u64>(0);
+    move || {
+        // receiver存活但不接收，try_send总是返回Full
+        let _r = &r;
+        thread::scope(|scope| {
+            for _ in 0..threads {
+                let s = &s;
+                scope.spawn(move || {
+                    for _ in 0..POLLS {
+                        f(s);
+                    }
+                });
+            }
+        });
+    }
+}
+
+fn try_send(s: &Sender<u64>) {
+    assert!(matches!(s.try_send(1), Err(TrySendError::Full(1))));
+}
+
+fn locked_check(s: &Sender<u64>) {
+    assert!(!std::hint::black_box(s.has_waiting_receiver()));
+}
+
+fn main() {
+    for threads in [1, 4] {
+        let ops = threads * POLLS;
+        common::bench(&format!("zero try_send Full, {threads} threads"), ops, poll(threads, try_send));
+        common::bench(&format!("zero locked check, {threads} threads"), ops, poll(threads, locked_check));
+    }
+}
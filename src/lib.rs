@@ -1,7 +1,3268 @@
+// 测试需要std的test harness，no_std的代码路径在测试中仍然按没有std feature编译
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+extern crate alloc;
+
+// 没有std时不能阻塞线程，mpmc只包含array/list channel的非阻塞部分
 pub mod mpmc;
 
 
 #[test]
 fn test(){
     println!("{}",1<<1);
-}
\ No newline at end of file
+}
+#[test]
+#[cfg(feature = "std")]
+fn weak_sender_upgrade_fails_after_last_sender_dropped() {
+    let (s, r) = crate::mpmc::channel::<i32>();
+    let weak = s.downgrade();
+    weak.upgrade().unwrap().send(1).unwrap();
+    drop(s);
+    assert!(weak.upgrade().is_none());
+    assert_eq!(r.recv(), Ok(1));
+    assert!(r.recv().is_err());
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn weak_receiver_upgrade_fails_after_last_receiver_dropped() {
+    let (s, r) = crate::mpmc::sync_channel::<i32>(1);
+    let weak = r.downgrade();
+    s.send(1).unwrap();
+    assert_eq!(weak.upgrade().unwrap().recv(), Ok(1));
+    drop(r);
+    assert!(weak.upgrade().is_none());
+    assert!(s.send(2).is_err());
+    drop(s);
+    drop(weak);
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn handle_counts_track_clones_and_drops() {
+    let (s, r) = crate::mpmc::sync_channel::<i32>(0);
+    let s2 = s.clone();
+    assert_eq!(s.sender_count(), 2);
+    assert_eq!(r.sender_count(), 2);
+    assert_eq!(s.receiver_count(), 1);
+    drop(s2);
+    drop(r);
+    assert_eq!(s.sender_count(), 1);
+    assert_eq!(s.receiver_count(), 0);
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn explicit_disconnect_is_idempotent() {
+    let (s, r) = crate::mpmc::channel::<i32>();
+    s.send(1).unwrap();
+    assert!(s.disconnect());
+    assert!(!r.disconnect());
+    assert!(s.send(2).is_err());
+    assert_eq!(r.recv(), Ok(1));
+    assert!(r.recv().is_err());
+
+    let (s, r) = crate::mpmc::sync_channel::<i32>(0);
+    let t = std::thread::spawn(move || r.recv());
+    std::thread::sleep(std::time::Duration::from_millis(50));
+    assert!(s.disconnect());
+    assert!(t.join().unwrap().is_err());
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn drain_discards_buffered_messages() {
+    let (s, r) = crate::mpmc::channel::<String>();
+    for i in 0..100 {
+        s.send(i.to_string()).unwrap();
+    }
+    assert_eq!(r.drain(), 100);
+    assert!(r.is_empty());
+    s.send("next".to_string()).unwrap();
+    assert_eq!(r.recv().unwrap(), "next");
+
+    let (s, r) = crate::mpmc::sync_channel::<i32>(4);
+    s.send(1).unwrap();
+    s.send(2).unwrap();
+    assert_eq!(r.drain(), 2);
+    assert_eq!(crate::mpmc::sync_channel::<i32>(0).1.drain(), 0);
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn collect_drained_returns_buffered_messages() {
+    let (s, r) = crate::mpmc::sync_channel::<i32>(8);
+    assert!(r.collect_drained().is_empty());
+
+    for i in 0..5 {
+        s.send(i).unwrap();
+    }
+    let t = std::thread::spawn(move || {
+        for i in 5..1000 {
+            s.send(i).unwrap();
+        }
+    });
+    let first = r.collect_drained();
+    assert!(first.len() >= 5);
+    assert_eq!(first[..5], [0, 1, 2, 3, 4]);
+    let mut all = first;
+    while let Ok(msg) = r.recv() {
+        all.push(msg);
+    }
+    t.join().unwrap();
+    assert_eq!(all, (0..1000).collect::<Vec<_>>());
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn remaining_reports_free_slots() {
+    let (s, r) = crate::mpmc::sync_channel::<i32>(3);
+    assert_eq!(s.remaining(), Some(3));
+    s.send(1).unwrap();
+    s.send(2).unwrap();
+    assert_eq!(s.remaining(), Some(1));
+    r.recv().unwrap();
+    assert_eq!(r.remaining(), Some(2));
+    assert_eq!(crate::mpmc::channel::<i32>().0.remaining(), None);
+    assert_eq!(crate::mpmc::sync_channel::<i32>(0).0.remaining(), Some(0));
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn send_errors_give_back_the_message() {
+    use crate::mpmc::*;
+    let (s, r) = sync_channel::<String>(1);
+    s.send("a".to_string()).unwrap();
+    let err = s.try_send("b".to_string()).unwrap_err();
+    assert!(err.is_full() && !err.is_disconnected());
+    assert_eq!(err.into_inner(), "b");
+    let err = s.send_timeout("c".to_string(), std::time::Duration::from_millis(1)).unwrap_err();
+    assert!(err.is_timeout());
+    assert_eq!(err.into_inner(), "c");
+    drop(r);
+    let err = s.send_timeout("d".to_string(), std::time::Duration::from_millis(1)).unwrap_err();
+    assert!(err.is_disconnected());
+    assert_eq!(s.send("e".to_string()).unwrap_err().into_inner(), "e");
+}
+
+#[test]
+fn error_conversions_compose() {
+    use crate::mpmc::*;
+    fn forward(s: &Sender<i32>, msg: i32) -> Result<(), SendTimeoutError<i32>> {
+        s.try_send(msg)?;
+        Ok(())
+    }
+    let (s, r) = sync_channel::<i32>(1);
+    forward(&s, 1).unwrap();
+    assert_eq!(forward(&s, 2), Err(SendTimeoutError::Timeout(2)));
+    assert_eq!(SendError::try_from(SendTimeoutError::Timeout(3)), Err(SendTimeoutError::Timeout(3)));
+    assert_eq!(SendError::try_from(TrySendError::Disconnected(4)), Ok(SendError(4)));
+    assert_eq!(RecvTimeoutError::from(TryRecvError::Empty), RecvTimeoutError::Timeout);
+    assert_eq!(RecvError::try_from(RecvTimeoutError::Disconnected), Ok(RecvError));
+    drop(r);
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn error_kind_compares_without_payload_eq() {
+    use crate::mpmc::*;
+    struct NoEq;
+    let (s, r) = sync_channel::<NoEq>(1);
+    s.send(NoEq).unwrap();
+    assert_eq!(s.try_send(NoEq).unwrap_err().kind(), TrySendErrorKind::Full);
+    let err = s.send_timeout(NoEq, std::time::Duration::from_millis(1)).unwrap_err();
+    assert_eq!(err.kind(), SendTimeoutErrorKind::Timeout);
+    drop(r);
+    assert_eq!(s.try_send(NoEq).unwrap_err().kind(), TrySendErrorKind::Disconnected);
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn debug_shows_channel_state() {
+    let (s, r) = crate::mpmc::sync_channel::<i32>(16);
+    s.send(1).unwrap();
+    assert_eq!(format!("{:?}", s), "Sender { len: 1, capacity: Some(16), disconnected: false }");
+    drop(s);
+    assert_eq!(format!("{:?}", r), "Receiver { len: 1, capacity: Some(16), disconnected: true }");
+    let (s, _r) = crate::mpmc::sync_channel::<i32>(0);
+    assert_eq!(format!("{:?}", s), "Sender { len: 0, capacity: Some(0), disconnected: false }");
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn builder_name_is_shared_by_clones() {
+    let (s, r) = crate::mpmc::Builder::new().name("events").sync_channel::<i32>(2);
+    assert_eq!(s.clone().name(), Some("events"));
+    assert_eq!(r.name(), Some("events"));
+    assert_eq!(
+        format!("{:?}", r),
+        "Receiver { name: \"events\", len: 0, capacity: Some(2), disconnected: false }"
+    );
+    let (s, _r) = crate::mpmc::Builder::new().name(String::from("jobs")).channel::<i32>();
+    assert_eq!(s.name(), Some("jobs"));
+    assert_eq!(crate::mpmc::channel::<i32>().0.name(), None);
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn same_channel_across_halves() {
+    let (s1, r1) = crate::mpmc::channel::<i32>();
+    let (s2, r2) = crate::mpmc::channel::<i32>();
+    let (s3, _r3) = crate::mpmc::sync_channel::<i32>(1);
+    assert!(s1.same_channel_as_receiver(&r1.clone()));
+    assert!(!s1.same_channel_as_receiver(&r2));
+    assert!(!s2.same_channel_as_receiver(&r1));
+    assert!(!s3.same_channel_as_receiver(&r1));
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn recv_deadline_into_reports_status() {
+    use crate::mpmc::*;
+    use std::time::{Duration, Instant};
+    let (s, r) = sync_channel::<i32>(1);
+    let mut out = None;
+    let soon = || Instant::now() + Duration::from_millis(10);
+    assert_eq!(r.recv_deadline_into(soon(), &mut out), RecvStatus::TimedOut);
+    assert_eq!(out, None);
+    s.send(7).unwrap();
+    assert_eq!(r.recv_deadline_into(soon(), &mut out), RecvStatus::Received);
+    assert_eq!(out, Some(7));
+    drop(s);
+    assert_eq!(r.recv_deadline_into(soon(), &mut out), RecvStatus::Disconnected);
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn zero_channel_single_thread_never_pairs_with_itself() {
+    use crate::mpmc::*;
+    use std::time::Duration;
+    let (s, r) = sync_channel::<i32>(0);
+    assert_eq!(s.try_send(1), Err(TrySendError::Full(1)));
+    assert_eq!(s.send_timeout(2, Duration::from_millis(10)), Err(SendTimeoutError::Timeout(2)));
+    assert_eq!(r.try_recv(), Err(TryRecvError::Empty));
+    assert_eq!(r.recv_timeout(Duration::from_millis(10)), Err(RecvTimeoutError::Timeout));
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn readiness_probes() {
+    let (s, r) = crate::mpmc::sync_channel::<i32>(1);
+    assert!(s.is_ready() && !r.is_ready());
+    s.send(1).unwrap();
+    assert!(!s.is_ready() && r.is_ready());
+    r.recv().unwrap();
+    drop(s);
+    assert!(r.is_empty() && r.is_ready());
+
+    let (s, r) = crate::mpmc::sync_channel::<i32>(0);
+    assert!(!r.is_ready());
+    let t = std::thread::spawn(move || s.send(1));
+    while !r.is_ready() {
+        std::thread::yield_now();
+    }
+    assert_eq!(r.try_recv(), Ok(1));
+    t.join().unwrap().unwrap();
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn cloned_receivers_share_the_stream() {
+    const RECEIVERS: usize = 4;
+    const MESSAGES: usize = 20_000;
+    let (s, r) = crate::mpmc::sync_channel::<usize>(1);
+    let handles: Vec<_> = (0..RECEIVERS)
+        .map(|_| {
+            let r = r.clone();
+            std::thread::spawn(move || {
+                let mut n = 0;
+                while r.recv().is_ok() {
+                    n += 1;
+                }
+                n
+            })
+        })
+        .collect();
+    drop(r);
+    for i in 0..MESSAGES {
+        s.send(i).unwrap();
+    }
+    drop(s);
+    let counts: Vec<usize> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+    // 分配是否均匀取决于线程调度，轮转本身由waker的单元测试覆盖，这里只检查每条msg恰好被接收一次
+    assert_eq!(counts.iter().sum::<usize>(), MESSAGES);
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn disconnected_and_empty_waits_for_buffered_messages() {
+    for (s, r) in [crate::mpmc::channel::<i32>(), crate::mpmc::sync_channel::<i32>(4)] {
+        s.send(1).unwrap();
+        drop(s);
+        assert!(r.is_disconnected());
+        assert!(!r.disconnected_and_empty());
+        assert_eq!(r.recv(), Ok(1));
+        assert!(r.disconnected_and_empty());
+    }
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn local_channel_carries_rc() {
+    use crate::mpmc::{local, TryRecvError};
+    use std::rc::Rc;
+    let (s, r) = local::channel::<Rc<i32>>();
+    let value = Rc::new(5);
+    s.clone().send(value.clone()).unwrap();
+    assert_eq!(Rc::strong_count(&value), 2);
+    assert_eq!(*r.try_recv().unwrap(), 5);
+    assert_eq!(r.try_recv(), Err(TryRecvError::Empty));
+    s.send(value.clone()).unwrap();
+    drop(r);
+    assert_eq!(Rc::strong_count(&value), 1);
+    assert!(s.send(value).is_err());
+
+    let (s, r) = local::channel::<i32>();
+    s.send(1).unwrap();
+    drop(s);
+    assert_eq!(r.try_recv(), Ok(1));
+    assert_eq!(r.try_recv(), Err(TryRecvError::Disconnected));
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn try_send_for_spins_then_gives_up() {
+    use crate::mpmc::*;
+    use std::time::Duration;
+    let (s, r) = sync_channel::<i32>(1);
+    s.send(1).unwrap();
+    assert_eq!(s.try_send_for(2, Duration::from_millis(5)), Err(TrySendError::Full(2)));
+    let t = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(10));
+        r.recv().unwrap();
+        r
+    });
+    assert_eq!(s.try_send_for(3, Duration::from_secs(10)), Ok(()));
+    drop(t.join().unwrap());
+    assert_eq!(s.try_send_for(4, Duration::from_secs(10)), Err(TrySendError::Disconnected(4)));
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn recv_metered_measures_handshake() {
+    use crate::mpmc::{Rendezvous, Side};
+    use std::time::Duration;
+    let (s, r) = crate::mpmc::sync_channel::<i32>(0);
+    let t = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(50));
+        s.send(1).unwrap();
+    });
+    let (msg, meta) = r.recv_metered().unwrap();
+    assert_eq!(msg, 1);
+    assert_eq!(meta.first, Side::Receiver);
+    assert!(meta.waited >= Duration::from_millis(40), "{:?}", meta);
+    t.join().unwrap();
+    assert!(r.recv_metered().is_err());
+
+    // sender先阻塞在send中，receiver到达时直接配对，不需要等待
+    let (s, r) = crate::mpmc::sync_channel::<i32>(0);
+    let t = std::thread::spawn(move || s.send(2).unwrap());
+    while !r.has_waiting_sender() {
+        std::thread::yield_now();
+    }
+    let (msg, meta) = r.recv_metered().unwrap();
+    assert_eq!(msg, 2);
+    assert_eq!(meta, Rendezvous { waited: Duration::ZERO, first: Side::Sender });
+    t.join().unwrap();
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn broadcast_every_receiver_sees_every_message() {
+    use crate::mpmc::{broadcast, Overflow};
+    let (s, r) = broadcast::<usize>(4, Overflow::Block);
+    let handles: Vec<_> = (0..3)
+        .map(|_| {
+            let r = r.clone();
+            std::thread::spawn(move || {
+                let mut seen = Vec::new();
+                while let Ok(msg) = r.recv() {
+                    seen.push(msg);
+                }
+                seen
+            })
+        })
+        .collect();
+    drop(r);
+    for i in 0..1000 {
+        s.send(i).unwrap();
+    }
+    drop(s);
+    for h in handles {
+        assert_eq!(h.join().unwrap(), (0..1000).collect::<Vec<_>>());
+    }
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn broadcast_drop_oldest_skips_for_slow_receivers() {
+    use crate::mpmc::{broadcast, Overflow, TrySendError};
+    let (s, r) = broadcast::<i32>(2, Overflow::DropOldest);
+    for i in 0..5 {
+        s.try_send(i).unwrap();
+    }
+    assert_eq!(r.len(), 2);
+    assert_eq!(r.try_recv(), Ok(3));
+    assert_eq!(r.try_recv(), Ok(4));
+
+    let (s, r) = broadcast::<i32>(1, Overflow::Block);
+    s.try_send(1).unwrap();
+    assert_eq!(s.try_send(2), Err(TrySendError::Full(2)));
+    drop(r);
+    assert_eq!(s.try_send(3), Err(TrySendError::Disconnected(3)));
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn priority_channel_prefers_higher_levels() {
+    use crate::mpmc::priority_channel;
+    let (s, r) = priority_channel::<i32>(3);
+    for i in 0..100 {
+        s.send(i, 0).unwrap();
+    }
+    s.send(1000, 2).unwrap();
+    s.send(500, 1).unwrap();
+    assert_eq!(r.recv(), Ok(1000));
+    assert_eq!(r.recv(), Ok(500));
+    assert_eq!(r.recv(), Ok(0));
+    assert_eq!(r.len(), 99);
+
+    let t = std::thread::spawn(move || r.recv());
+    std::thread::sleep(std::time::Duration::from_millis(20));
+    drop(s);
+    assert_eq!(t.join().unwrap(), Ok(1));
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn priority_recv_wakes_on_any_level() {
+    use crate::mpmc::priority_channel;
+    let (s, r) = priority_channel::<i32>(4);
+    let t = std::thread::spawn(move || (r.recv(), r.recv()));
+    std::thread::sleep(std::time::Duration::from_millis(20));
+    s.send(1, 1).unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(20));
+    s.send(3, 3).unwrap();
+    assert_eq!(t.join().unwrap(), (Ok(1), Ok(3)));
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn send_with_skips_slot_when_producer_panics() {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    let (s, r) = crate::mpmc::sync_channel::<String>(4);
+    s.send_with(|| "a".repeat(3)).unwrap();
+    let res = catch_unwind(AssertUnwindSafe(|| s.send_with(|| panic!("producer"))));
+    assert!(res.is_err());
+    s.send_with(|| "b".to_string()).unwrap();
+    assert_eq!(r.recv().unwrap(), "aaa");
+    assert_eq!(r.recv().unwrap(), "b");
+    assert!(r.is_empty());
+
+    // 还没被跳过的空slot在channel销毁时不能被drop
+    let _ = catch_unwind(AssertUnwindSafe(|| s.send_with(|| panic!("producer"))));
+    s.send_with(|| "c".to_string()).unwrap();
+    drop(r);
+    assert_eq!(s.send_with(|| unreachable!()), Err(crate::mpmc::SendError(())));
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn broadcast_subscribe_mid_stream() {
+    use crate::mpmc::{broadcast, Overflow};
+    let (s, r1) = broadcast::<i32>(8, Overflow::Block);
+    s.send(1).unwrap();
+    s.send(2).unwrap();
+    let r2 = r1.subscribe();
+    assert!(r2.is_empty());
+    s.send(3).unwrap();
+    s.send(4).unwrap();
+    drop(s);
+    let all: Vec<_> = std::iter::from_fn(|| r1.recv().ok()).collect();
+    let tail: Vec<_> = std::iter::from_fn(|| r2.recv().ok()).collect();
+    assert_eq!(all, [1, 2, 3, 4]);
+    assert_eq!(tail, [3, 4]);
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn broadcast_survives_panicking_clone() {
+    use crate::mpmc::{broadcast, Overflow};
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    #[derive(Debug, PartialEq)]
+    struct Fragile(i32);
+    impl Clone for Fragile {
+        fn clone(&self) -> Self {
+            assert!(self.0 >= 0, "clone failed");
+            Fragile(self.0)
+        }
+    }
+
+    let (s, r1) = broadcast(4, Overflow::Block);
+    let r2 = r1.clone();
+    s.send(Fragile(-1)).unwrap();
+    s.send(Fragile(1)).unwrap();
+    // clone panic时锁被poison，但cursor没有移动，channel仍然可用
+    assert!(catch_unwind(AssertUnwindSafe(|| r1.try_recv())).is_err());
+    assert!(catch_unwind(AssertUnwindSafe(|| r2.try_recv())).is_err());
+    assert_eq!(r1.len(), 2);
+    s.send(Fragile(2)).unwrap();
+    assert_eq!(r2.len(), 3);
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn try_recv_array_stops_at_empty() {
+    let (s, r) = crate::mpmc::channel::<String>();
+    for i in 0..5 {
+        s.send(i.to_string()).unwrap();
+    }
+    let mut first = r.try_recv_array::<3>();
+    assert_eq!(first.len(), 3);
+    assert_eq!(first.next().as_deref(), Some("0"));
+    // 没有取走的msg随迭代器一起drop
+    drop(first);
+    let rest: Vec<_> = r.try_recv_array::<8>().collect();
+    assert_eq!(rest, ["3", "4"]);
+    assert_eq!(r.try_recv_array::<4>().len(), 0);
+}
+
+#[cfg(all(feature = "metrics", feature = "std"))]
+#[test]
+fn metrics_count_a_known_workload() {
+    use crate::mpmc::{channel, sync_channel, ChannelMetrics};
+    let (s, r) = sync_channel::<i32>(8);
+    for i in 0..5 {
+        s.send(i).unwrap();
+    }
+    for _ in 0..3 {
+        r.recv().unwrap();
+    }
+    s.send(5).unwrap();
+    assert_eq!(r.metrics(), ChannelMetrics { sent: 6, received: 3, peak_len: 5, spurious_wakeups: 0 });
+
+    let (s, r) = channel::<i32>();
+    for i in 0..40 {
+        s.send(i).unwrap();
+    }
+    r.collect_drained();
+    assert_eq!(s.metrics(), ChannelMetrics { sent: 40, received: 40, peak_len: 40, spurious_wakeups: 0 });
+
+    let (s, r) = sync_channel::<i32>(0);
+    let t = std::thread::spawn(move || (0..3).for_each(|i| s.send(i).unwrap()));
+    for _ in 0..3 {
+        r.recv().unwrap();
+    }
+    t.join().unwrap();
+    assert_eq!(r.metrics(), ChannelMetrics { sent: 3, received: 3, peak_len: 0, spurious_wakeups: 0 });
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn offer_returns_unsent_message() {
+    let (s, r) = crate::mpmc::sync_channel::<i32>(1);
+    assert_eq!(s.offer(1), None);
+    assert_eq!(s.offer(2), Some(2));
+    drop(r);
+    assert_eq!(s.offer(3), Some(3));
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn next_blocking_ends_on_disconnect() {
+    let (s, r) = crate::mpmc::sync_channel::<i32>(0);
+    let t = std::thread::spawn(move || (0..4).for_each(|i| s.send(i).unwrap()));
+    let mut got = Vec::new();
+    while let Some(m) = r.next_blocking() {
+        got.push(m);
+    }
+    t.join().unwrap();
+    assert_eq!(got, [0, 1, 2, 3]);
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn select_wakes_on_send_slot_freed_by_recv() {
+    use crate::mpmc::{sync_channel, Select};
+    use std::time::Duration;
+    // s1满了，r2是空的，两个操作都没有ready，select只能在r1读取之后通过senders.notify被唤醒
+    let (s1, r1) = sync_channel::<i32>(1);
+    let (_s2, r2) = sync_channel::<i32>(1);
+    s1.send(1).unwrap();
+    let t = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(r1.recv(), Ok(1));
+        r1.recv()
+    });
+    let mut sel = Select::new();
+    let send = sel.send(&s1);
+    let recv = sel.recv(&r2);
+    let oper = sel.select_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(oper.index(), send);
+    assert_ne!(oper.index(), recv);
+    oper.send(&s1, 2).unwrap();
+    assert_eq!(t.join().unwrap(), Ok(2));
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn select_picks_both_ready_branches() {
+    use crate::mpmc::{channel, sync_channel, Select};
+    let (s1, r1) = sync_channel::<i32>(1);
+    let (s2, r2) = channel::<i32>();
+    let mut counts = [0; 2];
+    for i in 0..1000 {
+        s2.send(i).unwrap();
+        let mut sel = Select::new();
+        let send = sel.send(&s1);
+        let recv = sel.recv(&r2);
+        let oper = sel.select();
+        if oper.index() == send {
+            oper.send(&s1, i).unwrap();
+            r1.recv().unwrap();
+            r2.recv().unwrap();
+            counts[0] += 1;
+        } else {
+            assert_eq!(oper.index(), recv);
+            assert_eq!(oper.recv(&r2), Ok(i));
+            counts[1] += 1;
+        }
+    }
+    assert!(counts[0] > 0 && counts[1] > 0, "{:?}", counts);
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn select_on_zero_channels_and_timeout() {
+    use crate::mpmc::{sync_channel, Select, SelectTimeoutError, TrySelectError};
+    use std::time::Duration;
+    let (s, r) = sync_channel::<i32>(0);
+    let (s2, r2) = sync_channel::<i32>(0);
+    let t = std::thread::spawn(move || {
+        s.send(7).unwrap();
+        r2.recv().unwrap()
+    });
+    let mut sel = Select::new();
+    sel.recv(&r);
+    assert_eq!(sel.select().recv(&r), Ok(7));
+
+    let mut sel = Select::new();
+    sel.send(&s2);
+    sel.select().send(&s2, 8).unwrap();
+    assert_eq!(t.join().unwrap(), 8);
+
+    let mut sel = Select::new();
+    sel.recv(&r);
+    // sender已经drop，接收操作因为断开而ready
+    assert!(sel.select().recv(&r).is_err());
+
+    let (_s3, r3) = sync_channel::<i32>(1);
+    let mut sel = Select::new();
+    sel.recv(&r3);
+    assert_eq!(sel.try_select().err(), Some(TrySelectError));
+    assert_eq!(sel.select_timeout(Duration::from_millis(20)).err(), Some(SelectTimeoutError));
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn unbounded_send_never_times_out() {
+    use crate::mpmc::{channel, SendTimeoutError};
+    use std::time::{Duration, Instant};
+    let (s, r) = channel::<i32>();
+    let past = Instant::now() - Duration::from_millis(10);
+    assert_eq!(s.send_deadline(1, past), Ok(()));
+    assert_eq!(s.send_timeout(2, Duration::ZERO), Ok(()));
+    drop(r);
+    assert_eq!(s.send_deadline(3, past), Err(SendTimeoutError::Disconnected(3)));
+    assert_eq!(s.send_timeout(4, Duration::ZERO), Err(SendTimeoutError::Disconnected(4)));
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn far_future_deadline_parks_until_send() {
+    use std::time::{Duration, Instant};
+    // 找到一个尽量接近Instant上限的deadline
+    let now = Instant::now();
+    let mut step = Duration::from_secs(u64::MAX);
+    let mut far = now;
+    while step > Duration::from_secs(1) {
+        if let Some(d) = far.checked_add(step) {
+            far = d;
+        }
+        step /= 2;
+    }
+    assert!(far > now + Duration::from_secs(1 << 40));
+
+    let (s, r) = crate::mpmc::sync_channel::<i32>(1);
+    let t = std::thread::spawn(move || r.recv_deadline(far));
+    std::thread::sleep(Duration::from_millis(50));
+    s.send(1).unwrap();
+    assert_eq!(t.join().unwrap(), Ok(1));
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn register_notify_stress_has_no_lost_wakeups() {
+    use crate::mpmc::sync_channel;
+    // 容量为1和0的channel上大部分send/recv都要经过register/park/notify，丢失唤醒会让测试卡住
+    for cap in [0, 1] {
+        let (s, r) = sync_channel::<usize>(cap);
+        let producers: Vec<_> = (0..4)
+            .map(|_| {
+                let s = s.clone();
+                std::thread::spawn(move || (0..5000).for_each(|i| s.send(i).unwrap()))
+            })
+            .collect();
+        drop(s);
+        let consumers: Vec<_> = (0..4)
+            .map(|_| {
+                let r = r.clone();
+                std::thread::spawn(move || std::iter::from_fn(|| r.recv().ok()).sum::<usize>())
+            })
+            .collect();
+        producers.into_iter().for_each(|t| t.join().unwrap());
+        let total: usize = consumers.into_iter().map(|t| t.join().unwrap()).sum();
+        assert_eq!(total, 4 * (0..5000).sum::<usize>());
+    }
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn custom_parker_drives_blocking_recv() {
+    use crate::mpmc::{with_parker, Parker};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread::Thread;
+    use std::time::Duration;
+
+    struct Counting {
+        thread: Thread,
+        parks: Arc<AtomicUsize>,
+        unparks: Arc<AtomicUsize>,
+    }
+    impl Parker for Counting {
+        fn park(&self) {
+            self.parks.fetch_add(1, Ordering::SeqCst);
+            std::thread::park();
+        }
+        fn park_timeout(&self, timeout: Duration) {
+            self.parks.fetch_add(1, Ordering::SeqCst);
+            std::thread::park_timeout(timeout);
+        }
+        fn unpark(&self) {
+            self.unparks.fetch_add(1, Ordering::SeqCst);
+            self.thread.unpark();
+        }
+    }
+
+    let parks = Arc::new(AtomicUsize::new(0));
+    let unparks = Arc::new(AtomicUsize::new(0));
+    let (s, r) = crate::mpmc::channel::<i32>();
+    let (p, u) = (parks.clone(), unparks.clone());
+    let t = std::thread::spawn(move || {
+        let parker = Counting { thread: std::thread::current(), parks: p, unparks: u };
+        with_parker(parker, || r.recv())
+    });
+    std::thread::sleep(Duration::from_millis(50));
+    s.send(1).unwrap();
+    assert_eq!(t.join().unwrap(), Ok(1));
+    assert!(parks.load(Ordering::SeqCst) >= 1);
+    assert!(unparks.load(Ordering::SeqCst) >= 1);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn snapshot_reads_without_consuming() {
+    use crate::mpmc::{channel, sync_channel};
+    let (s, mut r) = sync_channel::<String>(4);
+    for i in 0..6 {
+        if i >= 4 {
+            r.recv().unwrap();
+        }
+        s.send(i.to_string()).unwrap();
+    }
+    let snap = r.snapshot().unwrap();
+    assert_eq!(snap.capacity, Some(4));
+    assert_eq!(snap.messages, ["2", "3", "4", "5"]);
+    assert_eq!(r.len(), 4);
+
+    // 跨越多个block的list channel
+    let (s, mut r) = channel::<usize>();
+    (0..100).for_each(|i| s.send(i).unwrap());
+    (0..40).for_each(|i| assert_eq!(r.recv(), Ok(i)));
+    let snap = r.snapshot().unwrap();
+    assert_eq!(snap.capacity, None);
+    assert_eq!(snap.messages, (40..100).collect::<Vec<_>>());
+
+    // 还有其他receiver或者WeakReceiver时不能快照
+    let r2 = r.clone();
+    assert!(r.snapshot().is_none());
+    drop(r2);
+    let weak = r.downgrade();
+    assert!(r.snapshot().is_none());
+    drop(weak);
+    assert_eq!(r.snapshot().unwrap().messages.len(), 60);
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn bounded_from_iter_reads_input_in_order() {
+    use crate::mpmc::bounded_from_iter;
+    let (s, r) = bounded_from_iter(0..10);
+    assert_eq!(s.capacity(), Some(10));
+    assert!(s.is_full());
+    drop(s);
+    let mut got = Vec::new();
+    while let Ok(i) = r.recv() {
+        got.push(i);
+    }
+    assert_eq!(got, (0..10).collect::<Vec<_>>());
+
+    let (s, r) = bounded_from_iter(Vec::<u8>::new());
+    assert_eq!(s.capacity(), Some(1));
+    assert!(r.is_empty());
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn on_disconnect_runs_once() {
+    use crate::mpmc::Builder;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+    for cap in [None, Some(0), Some(2)] {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let c = calls.clone();
+        let builder = Builder::new().on_disconnect(Box::new(move || {
+            c.fetch_add(1, Ordering::SeqCst);
+        }));
+        let (s, r) = match cap {
+            None => builder.channel::<i32>(),
+            Some(cap) => builder.sync_channel::<i32>(cap),
+        };
+        let s2 = s.clone();
+        drop(r);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert!(s.send(1).is_err());
+        assert!(!s.disconnect());
+        drop(s);
+        drop(s2);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    // 显式disconnect也会触发
+    let calls = Arc::new(AtomicUsize::new(0));
+    let c = calls.clone();
+    let (s, r) = Builder::new()
+        .on_disconnect(Box::new(move || {
+            c.fetch_add(1, Ordering::SeqCst);
+        }))
+        .sync_channel::<i32>(0);
+    assert!(s.disconnect());
+    assert!(r.is_disconnected());
+    drop((s, r));
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn wait_empty_with_slow_consumer() {
+    use crate::mpmc::{channel, sync_channel};
+    use std::{thread, time::Duration};
+    for (s, r) in [sync_channel::<i32>(8), channel::<i32>()] {
+        (0..5).for_each(|i| s.send(i).unwrap());
+        assert!(!s.wait_empty(Some(Duration::from_millis(20))));
+
+        let h = thread::spawn(move || {
+            for i in 0..5 {
+                thread::sleep(Duration::from_millis(10));
+                assert_eq!(r.recv(), Ok(i));
+            }
+            r
+        });
+        assert!(s.wait_empty(None));
+        assert!(s.is_empty());
+        let r = h.join().unwrap();
+
+        // 等待期间所有receiver被drop，array channel不可能再变空
+        s.send(5).unwrap();
+        let h = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            drop(r);
+        });
+        let drained = s.wait_empty(Some(Duration::from_secs(10)));
+        assert_eq!(drained, s.capacity().is_none());
+        h.join().unwrap();
+    }
+    let (s, _r) = sync_channel::<i32>(0);
+    assert!(s.wait_empty(Some(Duration::ZERO)));
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn select_ready_reports_without_completing() {
+    use crate::mpmc::{channel, sync_channel, Select};
+    use std::{thread, time::Duration};
+    let (s1, r1) = sync_channel::<i32>(1);
+    let (s2, r2) = channel::<i32>();
+    let mut sel = Select::new();
+    let i1 = sel.recv(&r1);
+    let i2 = sel.recv(&r2);
+    assert!(sel.try_ready().is_err());
+    assert!(sel.ready_timeout(Duration::from_millis(20)).is_err());
+
+    let h = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(20));
+        s2.send(7).unwrap();
+        s2
+    });
+    assert_eq!(sel.ready(), i2);
+    // ready不会消耗msg
+    assert_eq!(r2.try_recv(), Ok(7));
+    let _s2 = h.join().unwrap();
+
+    s1.send(1).unwrap();
+    assert_eq!(sel.ready(), i1);
+    assert_eq!(r1.len(), 1);
+
+    // 发送端在有空位时ready
+    let mut sel = Select::new();
+    let i = sel.send(&s1);
+    assert!(sel.try_ready().is_err());
+    let h = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(20));
+        r1.recv().unwrap()
+    });
+    assert_eq!(sel.ready(), i);
+    assert_eq!(h.join().unwrap(), 1);
+
+    // zero channel在另一端阻塞时ready
+    let (s, r) = sync_channel::<i32>(0);
+    let mut sel = Select::new();
+    let i = sel.recv(&r);
+    let h = thread::spawn(move || s.send(3).unwrap());
+    assert_eq!(sel.ready(), i);
+    assert_eq!(r.recv(), Ok(3));
+    h.join().unwrap();
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn many_producers_consumers_leave_no_message_stuck() {
+    use crate::mpmc::{channel, sync_channel, RecvTimeoutError};
+    use std::time::Duration;
+    const PRODUCERS: usize = 8;
+    const CONSUMERS: usize = 8;
+    const PER_PRODUCER: usize = 2000;
+    // 丢失唤醒时receiver会一直park，而msg留在channel中，recv_timeout让测试失败而不是卡住
+    for cap in [None, Some(0), Some(1), Some(3)] {
+        let (s, r) = match cap {
+            None => channel::<usize>(),
+            Some(cap) => sync_channel::<usize>(cap),
+        };
+        let consumers: Vec<_> = (0..CONSUMERS)
+            .map(|_| {
+                let r = r.clone();
+                std::thread::spawn(move || {
+                    let mut count = 0;
+                    loop {
+                        match r.recv_timeout(Duration::from_secs(10)) {
+                            Ok(_) => count += 1,
+                            Err(RecvTimeoutError::Disconnected) => return count,
+                            Err(RecvTimeoutError::Timeout) => {
+                                panic!("receiver stuck with {} messages queued", r.len())
+                            }
+                        }
+                    }
+                })
+            })
+            .collect();
+        drop(r);
+        let producers: Vec<_> = (0..PRODUCERS)
+            .map(|_| {
+                let s = s.clone();
+                std::thread::spawn(move || (0..PER_PRODUCER).for_each(|i| s.send(i).unwrap()))
+            })
+            .collect();
+        drop(s);
+        producers.into_iter().for_each(|t| t.join().unwrap());
+        let total: usize = consumers.into_iter().map(|t| t.join().unwrap()).sum();
+        assert_eq!(total, PRODUCERS * PER_PRODUCER);
+    }
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn recv_into_spans_buffer_boundaries() {
+    use crate::mpmc::{channel, sync_channel};
+    let (s, r) = sync_channel::<i32>(16);
+    (0..10).for_each(|i| s.send(i).unwrap());
+    let mut a = Vec::with_capacity(3);
+    let mut b = Vec::with_capacity(4);
+    let mut c = Vec::with_capacity(8);
+    // max_total在第二个buf中间结束
+    assert_eq!(r.recv_into(&mut [&mut a, &mut b, &mut c], 5), 5);
+    assert_eq!((a.as_slice(), b.as_slice(), c.len()), (&[0, 1, 2][..], &[3, 4][..], 0));
+
+    // 已经有内容的buf只使用剩余容量，channel变空时停止
+    assert_eq!(r.recv_into(&mut [&mut a, &mut b, &mut c], 100), 5);
+    assert_eq!(a.len(), 3);
+    assert_eq!(b, [3, 4, 5, 6]);
+    assert_eq!(c, [7, 8, 9]);
+    assert_eq!(c.capacity(), 8);
+
+    let (s, r) = channel::<i32>();
+    (0..4).for_each(|i| s.send(i).unwrap());
+    drop(s);
+    let mut a = Vec::with_capacity(2);
+    let mut b = Vec::with_capacity(8);
+    assert_eq!(r.recv_into(&mut [&mut a, &mut b], usize::MAX), 4);
+    assert_eq!((a, b), (vec![0, 1], vec![2, 3]));
+    assert_eq!(r.recv_into(&mut [], 1), 0);
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn mapped_receiver_transforms_on_recv() {
+    use crate::mpmc::{sync_channel, RecvError, TryRecvError};
+    let (s, r) = sync_channel::<i32>(4);
+    let mut calls = 0;
+    let mut r = r.map(|i| {
+        calls += 1;
+        format!("#{}", i)
+    });
+    assert_eq!(r.try_recv(), Err(TryRecvError::Empty));
+    (1..=3).for_each(|i| s.send(i).unwrap());
+    assert_eq!(r.try_recv(), Ok(String::from("#1")));
+    assert_eq!(r.recv(), Ok(String::from("#2")));
+    s.send(4).unwrap();
+    drop(s);
+    assert_eq!(r.iter().collect::<Vec<_>>(), ["#3", "#4"]);
+    assert_eq!(r.recv(), Err(RecvError));
+    assert_eq!(r.try_recv(), Err(TryRecvError::Disconnected));
+    assert!(r.into_inner().is_disconnected());
+    assert_eq!(calls, 4);
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn mapped_sender_transforms_before_send() {
+    use crate::mpmc::{sync_channel, SendError, TrySendError};
+    let (s, r) = sync_channel::<String>(1);
+    let mut s = s.comap(|i: i32| i.to_string());
+    assert_eq!(s.try_send(1), Ok(()));
+    // 错误中是已经转换过的msg
+    assert_eq!(s.try_send(2), Err(TrySendError::Full(String::from("2"))));
+    assert_eq!(r.recv().unwrap(), "1");
+    let mut s2 = s.clone();
+    assert_eq!(s2.send(3), Ok(()));
+    assert_eq!(r.recv().unwrap(), "3");
+    drop(r);
+    assert_eq!(s.try_send(4), Err(TrySendError::Disconnected(String::from("4"))));
+    assert_eq!(s2.send(5), Err(SendError(String::from("5"))));
+    assert!(s.into_inner().is_disconnected());
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn channel_bundle_splits_and_delegates() {
+    use crate::mpmc::Channel;
+    let chan = Channel::unbounded();
+    chan.send(1).unwrap();
+    assert_eq!(chan.recv(), Ok(1));
+
+    let chan = Channel::bounded(2);
+    chan.send("a").unwrap();
+    assert_eq!(chan.sender().len(), 1);
+    let (s, r) = chan.split();
+    s.send("b").unwrap();
+    assert!(s.is_full());
+    assert_eq!(r.recv(), Ok("a"));
+
+    let chan = Channel::from((s, r));
+    assert!(chan.sender().same_channel_as_receiver(chan.receiver()));
+    assert_eq!(chan.recv(), Ok("b"));
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn blocked_array_senders_are_not_starved() {
+    use crate::mpmc::{sync_channel, with_parker, Parker};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread::Thread;
+    use std::time::Duration;
+
+    // 第一次park时计数，用来确认sender已经注册在senders中
+    struct FirstPark {
+        thread: Thread,
+        parked: AtomicBool,
+        count: Arc<AtomicUsize>,
+    }
+    impl FirstPark {
+        fn mark(&self) {
+            if !self.parked.swap(true, Ordering::SeqCst) {
+                self.count.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+    }
+    impl Parker for FirstPark {
+        fn park(&self) {
+            self.mark();
+            std::thread::park();
+        }
+        fn park_timeout(&self, timeout: Duration) {
+            self.mark();
+            std::thread::park_timeout(timeout);
+        }
+        fn unpark(&self) {
+            self.thread.unpark();
+        }
+    }
+
+    const SENDERS: usize = 4;
+    let (s, r) = sync_channel::<usize>(1);
+    s.send(usize::MAX).unwrap();
+    let count = Arc::new(AtomicUsize::new(0));
+    let senders: Vec<_> = (0..SENDERS)
+        .map(|id| {
+            let (s, c) = (s.clone(), count.clone());
+            let t = std::thread::spawn(move || {
+                let parker = FirstPark { thread: std::thread::current(), parked: AtomicBool::new(false), count: c };
+                with_parker(parker, || s.send(id)).unwrap();
+            });
+            // 一个一个地阻塞，注册的顺序就是id的顺序
+            while count.load(Ordering::SeqCst) <= id {
+                std::thread::yield_now();
+            }
+            t
+        })
+        .collect();
+
+    // 每次释放slot都交给等待最久的sender
+    assert_eq!(r.recv(), Ok(usize::MAX));
+    for id in 0..SENDERS {
+        assert_eq!(r.recv(), Ok(id));
+    }
+    senders.into_iter().for_each(|t| t.join().unwrap());
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn poll_reports_three_states() {
+    use crate::mpmc::{channel, sync_channel, Poll3};
+    let (s, r) = sync_channel::<i32>(1);
+    assert_eq!(r.poll(), Poll3::Empty);
+    s.send(2).unwrap();
+    assert_eq!(r.poll().map(|i| i * 10), Poll3::Ready(20));
+    s.send(3).unwrap();
+    drop(s);
+    assert_eq!(r.poll(), Poll3::Ready(3));
+    assert_eq!(r.poll(), Poll3::Closed);
+
+    let (s, r) = channel::<&str>();
+    s.send("a").unwrap();
+    assert_eq!(r.poll().map(str::len), Poll3::Ready(1));
+    drop(s);
+    assert_eq!(r.poll(), Poll3::Closed);
+
+    let (_s, r) = sync_channel::<i32>(0);
+    assert_eq!(r.poll(), Poll3::Empty);
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn single_sender_single_receiver_preserves_order() {
+    use crate::mpmc::{channel, sync_channel};
+    const N: usize = 100_000;
+    for cap in [None, Some(0), Some(1), Some(7), Some(64)] {
+        let (s, r) = match cap {
+            None => channel::<usize>(),
+            Some(cap) => sync_channel::<usize>(cap),
+        };
+        let h = std::thread::spawn(move || (0..N).for_each(|i| s.send(i).unwrap()));
+        for i in 0..N {
+            assert_eq!(r.recv(), Ok(i), "cap {:?}", cap);
+        }
+        assert!(r.recv().is_err());
+        h.join().unwrap();
+    }
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn single_sender_many_receivers_keep_per_receiver_order() {
+    use crate::mpmc::{channel, sync_channel};
+    const N: usize = 50_000;
+    const RECEIVERS: usize = 4;
+    for cap in [None, Some(1), Some(16)] {
+        let (s, r) = match cap {
+            None => channel::<usize>(),
+            Some(cap) => sync_channel::<usize>(cap),
+        };
+        let receivers: Vec<_> = (0..RECEIVERS)
+            .map(|_| {
+                let r = r.clone();
+                std::thread::spawn(move || std::iter::from_fn(|| r.recv().ok()).collect::<Vec<_>>())
+            })
+            .collect();
+        drop(r);
+        (0..N).for_each(|i| s.send(i).unwrap());
+        drop(s);
+
+        let mut all = Vec::with_capacity(N);
+        for got in receivers.into_iter().map(|t| t.join().unwrap()) {
+            // 每个receiver收到的msg保持发送顺序
+            assert!(got.windows(2).all(|w| w[0] < w[1]), "cap {:?}", cap);
+            all.extend(got);
+        }
+        // 所有receiver收到的msg合起来恰好是发送的msg
+        all.sort_unstable();
+        assert!(all.iter().copied().eq(0..N), "cap {:?}", cap);
+    }
+}
+
+#[cfg(feature = "profiling")]
+#[test]
+fn profiled_retries_grow_under_contention() {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+    let (s, r) = crate::mpmc::channel::<usize>();
+    // 没有竞争时CAS不会失败
+    for i in 0..1000 {
+        assert_eq!(s.try_send_profiled(i), (Ok(()), 0));
+        assert_eq!(r.try_recv_profiled(), (Ok(i), 0));
+    }
+
+    // 多个sender同时发送，直到观察到CAS重试
+    let retries = Arc::new(AtomicU32::new(0));
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let senders: Vec<_> = (0..4)
+        .map(|_| {
+            let (s, retries) = (s.clone(), retries.clone());
+            std::thread::spawn(move || {
+                while retries.load(Ordering::Relaxed) == 0 && Instant::now() < deadline {
+                    for i in 0..1000 {
+                        let (res, n) = s.try_send_profiled(i);
+                        res.unwrap();
+                        retries.fetch_add(n, Ordering::Relaxed);
+                    }
+                }
+            })
+        })
+        .collect();
+    senders.into_iter().for_each(|t| t.join().unwrap());
+    assert!(retries.load(Ordering::Relaxed) > 0);
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn wait_sendable_until_receiver_frees_slot() {
+    use crate::mpmc::{channel, sync_channel};
+    use std::time::Duration;
+    let (s, r) = sync_channel(1);
+    s.send(1).unwrap();
+    assert!(!s.wait_sendable(Some(Duration::from_millis(10))));
+    let t = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(r.recv(), Ok(1));
+        r
+    });
+    assert!(s.wait_sendable(None));
+    assert_eq!(s.try_send(2), Ok(()));
+    drop(t.join().unwrap());
+    // 断开之后try_send会立即返回，也算可以发送
+    assert!(s.wait_sendable(None));
+
+    let (s, _r) = channel();
+    assert!(s.wait_sendable(Some(Duration::ZERO)));
+    s.send(1).unwrap();
+
+    // zero channel要等到有receiver在等待
+    let (s, r) = sync_channel(0);
+    assert!(!s.wait_sendable(Some(Duration::from_millis(10))));
+    let t = std::thread::spawn(move || r.recv());
+    assert!(s.wait_sendable(None));
+    s.send(3).unwrap();
+    assert_eq!(t.join().unwrap(), Ok(3));
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn close_when_empty_lets_receivers_drain() {
+    use crate::mpmc::{channel, sync_channel, RecvError, SendError, TrySendError};
+    use std::time::Duration;
+    for cap in [None, Some(4)] {
+        let (s, r) = match cap {
+            None => channel(),
+            Some(cap) => sync_channel(cap),
+        };
+        s.send(1).unwrap();
+        s.send(2).unwrap();
+        assert!(s.close_when_empty());
+        assert!(!s.close_when_empty());
+        // 关闭之后不再接受msg，但是还没有断开
+        assert_eq!(s.try_send(3), Err(TrySendError::Disconnected(3)));
+        assert_eq!(s.send(4), Err(SendError(4)));
+        assert!(!r.is_disconnected());
+        assert_eq!(r.recv(), Ok(1));
+        assert_eq!(r.recv(), Ok(2));
+        assert!(r.is_disconnected());
+        assert_eq!(r.recv(), Err(RecvError));
+    }
+
+    // 取完msg之后阻塞的receiver在关闭时被唤醒并看到断开
+    let (s, r) = sync_channel(1);
+    s.send(1).unwrap();
+    let r2 = r.clone();
+    let blocked = std::thread::spawn(move || {
+        let got = r2.recv();
+        (got, r2.recv())
+    });
+    std::thread::sleep(Duration::from_millis(20));
+    s.close_when_empty();
+    let (a, b) = blocked.join().unwrap();
+    assert_eq!((a, b), (Ok(1), Err(RecvError)));
+    assert_eq!(r.recv(), Err(RecvError));
+
+    // 阻塞在满channel上的sender被唤醒并返回Disconnected
+    let (s, r) = sync_channel(1);
+    s.send(1).unwrap();
+    let s2 = s.clone();
+    let blocked = std::thread::spawn(move || s2.send(2));
+    std::thread::sleep(Duration::from_millis(20));
+    s.close_when_empty();
+    assert_eq!(blocked.join().unwrap(), Err(SendError(2)));
+    assert_eq!(r.recv(), Ok(1));
+    assert_eq!(r.recv(), Err(RecvError));
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn close_when_empty_runs_on_disconnect_once() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    let calls = Arc::new(AtomicUsize::new(0));
+    let c = calls.clone();
+    let (s, r) = crate::mpmc::Builder::new()
+        .on_disconnect(Box::new(move || {
+            c.fetch_add(1, Ordering::SeqCst);
+        }))
+        .sync_channel::<i32>(2);
+    s.send(1).unwrap();
+    s.close_when_empty();
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+    assert_eq!(r.recv(), Ok(1));
+    // 取完之后channel已经断开
+    assert!(!s.disconnect());
+    drop((s, r));
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn timeouts_fire_at_virtual_instants() {
+    use crate::mpmc::{sync_channel, with_clock, with_parker, Clock, Parker, RecvTimeoutError, Select, SendTimeoutError};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    // 只在park_timeout中前进的虚拟时间
+    struct ManualClock(Mutex<Instant>);
+    impl Clock for ManualClock {
+        fn now(&self) -> Instant {
+            *self.0.lock().unwrap()
+        }
+    }
+    struct Advance(Arc<ManualClock>);
+    impl Parker for Advance {
+        fn park(&self) {
+            std::thread::park();
+        }
+        fn park_timeout(&self, timeout: Duration) {
+            *self.0 .0.lock().unwrap() += timeout;
+        }
+        fn unpark(&self) {}
+    }
+
+    let start = Instant::now();
+    let clock = Arc::new(ManualClock(Mutex::new(start)));
+    let real = Instant::now();
+    with_clock(clock.clone(), || {
+        with_parker(Advance(clock.clone()), || {
+            let (s, r) = sync_channel::<i32>(1);
+            assert_eq!(r.recv_timeout(Duration::from_secs(5)), Err(RecvTimeoutError::Timeout));
+            assert_eq!(clock.now(), start + Duration::from_secs(5));
+
+            s.send(1).unwrap();
+            assert_eq!(s.send_timeout(2, Duration::from_secs(60)), Err(SendTimeoutError::Timeout(2)));
+            assert_eq!(clock.now(), start + Duration::from_secs(65));
+
+            let (_s2, r2) = sync_channel::<i32>(0);
+            let mut sel = Select::new();
+            sel.recv(&r2);
+            assert!(sel.select_timeout(Duration::from_millis(1500)).is_err());
+            assert_eq!(clock.now(), start + Duration::from_millis(66_500));
+        })
+    });
+    // 没有真的等待
+    assert!(real.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn recv_timeout_detailed_reports_waited_time() {
+    use crate::mpmc::{channel, RecvTimeoutError};
+    use std::time::Duration;
+    let (s, r) = channel();
+    let timeout = Duration::from_millis(20);
+    match r.recv_timeout_detailed(timeout) {
+        Err((RecvTimeoutError::Timeout, waited)) => assert!(waited >= timeout),
+        other => panic!("{:?}", other),
+    }
+    s.send(1).unwrap();
+    assert_eq!(r.recv_timeout_detailed(timeout), Ok(1));
+    drop(s);
+    match r.recv_timeout_detailed(Duration::from_secs(10)) {
+        Err((RecvTimeoutError::Disconnected, waited)) => assert!(waited < Duration::from_secs(10)),
+        other => panic!("{:?}", other),
+    }
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn dropping_channel_skips_forgotten_reserved_slots() {
+    use crate::mpmc::sync_channel;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[derive(Debug)]
+    struct Counted(Arc<AtomicUsize>);
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    let drops = Arc::new(AtomicUsize::new(0));
+    let (s, r) = sync_channel(4);
+    s.send(Counted(drops.clone())).unwrap();
+    let mut res = s.reserve(2).unwrap();
+    res.push(Counted(drops.clone())).unwrap();
+    // 第二个预留的slot既没有写入也没有被标记为跳过
+    std::mem::forget(res);
+    s.send(Counted(drops.clone())).unwrap();
+    drop((s, r));
+    // 只drop真正写入的3个msg
+    assert_eq!(drops.load(Ordering::SeqCst), 3);
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn reserve_publishes_slots_in_order() {
+    use crate::mpmc::{channel, sync_channel, TryRecvError, TrySendError};
+    use std::time::Duration;
+    let (s, r) = sync_channel(4);
+    s.send(0).unwrap();
+    let mut res = s.reserve(3).unwrap();
+    assert_eq!(s.reserve(1).unwrap_err(), TrySendError::Full(()));
+    assert_eq!(r.recv(), Ok(0));
+    // 预留但还没有写入的slot不会被读到，try_recv不会等待它
+    assert_eq!(r.try_recv(), Err(TryRecvError::Empty));
+    res.push(1).unwrap();
+    assert_eq!(r.try_recv(), Ok(1));
+    // 预留之后发送的msg排在预留的slot之后
+    s.send(10).unwrap();
+    res.push(2).unwrap();
+    assert_eq!(res.remaining(), 1);
+    assert_eq!(r.recv(), Ok(2));
+    assert_eq!(r.try_recv(), Err(TryRecvError::Empty));
+    // drop时没有写入的slot被跳过
+    drop(res);
+    assert_eq!(r.recv(), Ok(10));
+    assert!(r.is_empty());
+
+    // 阻塞的receiver在预留的slot被写入时醒来
+    let mut res = s.reserve(2).unwrap();
+    let t = std::thread::spawn(move || (r.recv(), r.recv(), r));
+    std::thread::sleep(Duration::from_millis(20));
+    res.push(20).unwrap();
+    res.push(21).unwrap();
+    assert_eq!(res.push(22), Err(22));
+    let (a, b, r) = t.join().unwrap();
+    assert_eq!((a, b), (Ok(20), Ok(21)));
+
+    // 多次绕过buffer的末尾
+    for i in 0..100 {
+        let mut res = s.reserve(3).unwrap();
+        for j in 0..3 {
+            res.push(i * 3 + j).unwrap();
+        }
+        drop(res);
+        for j in 0..3 {
+            assert_eq!(r.try_recv(), Ok(i * 3 + j));
+        }
+    }
+    assert_eq!(s.reserve(5).unwrap_err(), TrySendError::Full(()));
+    drop(r);
+    assert_eq!(s.reserve(1).unwrap_err(), TrySendError::Disconnected(()));
+
+    let (s, r) = channel();
+    let mut res = s.reserve(2).unwrap();
+    res.push(1).unwrap();
+    res.push(2).unwrap();
+    assert_eq!(res.push(3), Err(3));
+    assert_eq!((r.try_recv(), r.try_recv()), (Ok(1), Ok(2)));
+
+    let (s, _r) = sync_channel::<i32>(0);
+    assert_eq!(s.reserve(1).unwrap_err(), TrySendError::Full(()));
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn exclusive_recv_with_many_senders() {
+    use crate::mpmc::{channel, sync_channel, TryRecvError};
+    for (s, mut r) in [channel(), sync_channel(3)] {
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                let s = s.clone();
+                std::thread::spawn(move || {
+                    for j in 0..200 {
+                        s.send(i * 1000 + j).unwrap();
+                    }
+                })
+            })
+            .collect();
+        drop(s);
+        // 每个sender发送的msg仍然是有序的，跨过list的block和array的一圈也不会丢失
+        let mut last = [None; 4];
+        let mut count = 0;
+        while let Ok(v) = r.recv_exclusive() {
+            let i = v / 1000;
+            assert!(last[i].is_none_or(|prev| prev < v));
+            last[i] = Some(v);
+            count += 1;
+        }
+        assert_eq!(count, 800);
+        assert_eq!(r.try_recv_exclusive(), Err(TryRecvError::Disconnected));
+        for h in handles {
+            h.join().unwrap();
+        }
+    }
+
+    // 存在其他receiver时退回CAS路径
+    let (s, mut r) = channel();
+    let r2 = r.clone();
+    s.send(1).unwrap();
+    s.send(2).unwrap();
+    assert_eq!(r.try_recv_exclusive(), Ok(1));
+    assert_eq!(r2.try_recv(), Ok(2));
+    assert_eq!(r.try_recv_exclusive(), Err(TryRecvError::Empty));
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn send_cancellable_aborts_when_cancelled() {
+    use crate::mpmc::{channel, sync_channel, SendError};
+    use std::time::Duration;
+    let (s, r) = sync_channel(1);
+    let (cancel_s, cancel_r) = channel::<()>();
+    s.send_cancellable(1, &cancel_r).unwrap();
+
+    // channel已满，cancel触发之后msg被退回
+    let t = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(20));
+        cancel_s.send(()).unwrap();
+        cancel_s
+    });
+    assert_eq!(s.send_cancellable(2, &cancel_r), Err(SendError(2)));
+    let _cancel_s = t.join().unwrap();
+    // cancel中的msg没有被取走
+    assert!(cancel_r.is_ready());
+
+    // 两者都ready时优先发送
+    assert_eq!(r.recv(), Ok(1));
+    s.send_cancellable(3, &cancel_r).unwrap();
+    assert_eq!(r.recv(), Ok(3));
+
+    // 没有取消时等待receiver腾出slot
+    let (_c, cancel_r) = channel::<()>();
+    s.send(4).unwrap();
+    let t = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(20));
+        (r.recv(), r.recv())
+    });
+    s.send_cancellable(5, &cancel_r).unwrap();
+    assert_eq!(t.join().unwrap(), (Ok(4), Ok(5)));
+    assert_eq!(s.send_cancellable(6, &cancel_r), Err(SendError(6)));
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn try_iter_size_hint_preallocates() {
+    use crate::mpmc::{channel, sync_channel};
+    let (s, r) = channel();
+    for i in 0..100 {
+        s.send(i).unwrap();
+    }
+    assert_eq!(r.try_iter().size_hint(), (100, None));
+    // 下界足够时collect只分配一次
+    let v: Vec<i32> = r.try_iter().collect();
+    assert_eq!(v, (0..100).collect::<Vec<_>>());
+    assert_eq!(v.capacity(), 100);
+    assert_eq!(r.try_iter().size_hint(), (0, None));
+
+    let (s, r) = sync_channel(4);
+    s.send(1).unwrap();
+    s.send(2).unwrap();
+    drop(s);
+    assert_eq!(r.iter().size_hint(), (2, None));
+    // 另一个receiver可能取走这些msg，下界不再成立
+    let r2 = r.clone();
+    assert_eq!(r.iter().size_hint(), (0, None));
+    assert_eq!(r.try_iter().size_hint(), (0, None));
+    drop(r2);
+    assert_eq!((&r).into_iter().collect::<Vec<_>>(), [1, 2]);
+    assert_eq!(r.into_iter().size_hint(), (0, None));
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn handles_hash_by_channel() {
+    use crate::mpmc::{channel, sync_channel};
+    use std::collections::HashSet;
+    let (s1, r1) = channel::<i32>();
+    let (s2, r2) = sync_channel::<i32>(1);
+    let (s3, _r3) = sync_channel::<i32>(0);
+    let mut senders = HashSet::new();
+    assert!(senders.insert(s1.clone()));
+    assert!(!senders.insert(s1.clone()));
+    assert!(senders.insert(s2.clone()));
+    assert!(senders.insert(s3));
+    assert!(!senders.insert(s2));
+    assert_eq!(senders.len(), 3);
+    assert!(senders.contains(&s1));
+
+    let mut receivers = HashSet::new();
+    assert!(receivers.insert(r1.clone()));
+    assert!(!receivers.insert(r1));
+    assert!(receivers.insert(r2));
+    assert_eq!(receivers.len(), 2);
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn panic_while_blocked_unregisters_operation() {
+    use crate::mpmc::{channel, sync_channel, with_parker, Parker, Select};
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use std::time::Duration;
+
+    // park时panic，模拟阻塞期间被unwind的线程
+    struct Poisoned;
+    impl Parker for Poisoned {
+        fn park(&self) {
+            panic!("poisoned parker");
+        }
+        fn park_timeout(&self, _timeout: Duration) {
+            panic!("poisoned parker");
+        }
+        fn unpark(&self) {}
+    }
+    fn poisoned<R>(f: impl FnOnce() -> R) {
+        assert!(catch_unwind(AssertUnwindSafe(|| with_parker(Poisoned, f))).is_err());
+    }
+
+    for cap in [None, Some(0), Some(1)] {
+        let (s, r) = match cap {
+            Some(cap) => sync_channel(cap),
+            None => channel(),
+        };
+        poisoned(|| r.recv());
+        poisoned(|| r.recv_timeout(Duration::from_secs(10)));
+        poisoned(|| {
+            let mut sel = Select::new();
+            sel.recv(&r);
+            sel.ready()
+        });
+        poisoned(|| {
+            let mut sel = Select::new();
+            sel.recv(&r);
+            let _ = sel.select();
+        });
+        // 留在waker中的Entry会抢走这次唤醒，另一个receiver就收不到msg
+        let t = std::thread::spawn({
+            let r = r.clone();
+            move || r.recv()
+        });
+        std::thread::sleep(Duration::from_millis(20));
+        s.send(1).unwrap();
+        assert_eq!(t.join().unwrap(), Ok(1));
+
+        if cap.is_some() {
+            if cap == Some(1) {
+                s.send(2).unwrap();
+            }
+            poisoned(|| s.send(3));
+            poisoned(|| s.wait_sendable(None));
+            if cap == Some(1) {
+                assert_eq!(r.recv(), Ok(2));
+            }
+            assert!(r.try_recv().is_err());
+        }
+        // Waker的drop在debug构建中会检查没有残留的Entry
+        drop((s, r));
+    }
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn zero_channel_survives_poisoned_lock() {
+    use crate::mpmc::{sync_channel, with_parker, Parker, Select, SendTimeoutError};
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    // 第一次unpark时panic，panic发生在唤醒它的线程上，那时它持有zero channel的锁
+    struct PanicOnUnpark(Arc<AtomicBool>);
+    impl Parker for PanicOnUnpark {
+        fn park(&self) {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        fn park_timeout(&self, timeout: Duration) {
+            std::thread::sleep(timeout.min(Duration::from_millis(1)));
+        }
+        fn unpark(&self) {
+            if !self.0.swap(true, Ordering::SeqCst) {
+                panic!("unpark panicked");
+            }
+        }
+    }
+
+    let (s, r) = sync_channel(0);
+    let panicked = Arc::new(AtomicBool::new(false));
+    let observer = std::thread::spawn({
+        let (r, panicked) = (r.clone(), panicked.clone());
+        move || {
+            // sender已经在等待时ready会直接返回，所以一直观察到unpark被调用为止
+            with_parker(PanicOnUnpark(panicked.clone()), || {
+                while !panicked.load(Ordering::SeqCst) {
+                    let mut sel = Select::new();
+                    sel.recv(&r);
+                    let _ = sel.ready_timeout(Duration::from_millis(10));
+                }
+            })
+        }
+    });
+    // 发送时唤醒observer，在持有锁时panic
+    while !panicked.load(Ordering::SeqCst) {
+        let res = catch_unwind(AssertUnwindSafe(|| s.send_timeout(1, Duration::from_millis(1))));
+        assert!(matches!(res, Err(_) | Ok(Err(SendTimeoutError::Timeout(1)))));
+    }
+    observer.join().unwrap();
+
+    // 锁已经被poison，其他线程仍然可以正常使用channel
+    let t = std::thread::spawn(move || s.send(2));
+    assert_eq!(r.recv(), Ok(2));
+    assert_eq!(t.join().unwrap(), Ok(()));
+    assert!(r.recv().is_err());
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn zero_try_send_pairs_only_with_waiting_receiver() {
+    use crate::mpmc::{sync_channel, RecvTimeoutError, TrySendError};
+    use std::time::Duration;
+    let (s, r) = sync_channel(0);
+    for _ in 0..1000 {
+        assert_eq!(s.try_send(1), Err(TrySendError::Full(1)));
+    }
+    // 超时的receiver取消注册之后，try_send又回到不加锁的Full
+    assert_eq!(r.recv_timeout(Duration::from_millis(1)), Err(RecvTimeoutError::Timeout));
+    assert_eq!(s.try_send(2), Err(TrySendError::Full(2)));
+
+    let t = std::thread::spawn(move || (r.recv(), r));
+    let mut msg = 3;
+    loop {
+        match s.try_send(msg) {
+            Ok(()) => break,
+            Err(TrySendError::Full(m)) => msg = m,
+            Err(e) => panic!("{e:?}"),
+        }
+        std::thread::yield_now();
+    }
+    let (res, r) = t.join().unwrap();
+    assert_eq!(res, Ok(3));
+    drop(r);
+    assert_eq!(s.try_send(4), Err(TrySendError::Disconnected(4)));
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn with_next_consumes_each_message_once() {
+    use crate::mpmc::{channel, sync_channel, RecvError};
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    struct Counted(u32, Arc<AtomicUsize>);
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            self.1.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    for (s, r) in [channel(), sync_channel(2), sync_channel(0)] {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let t = std::thread::spawn({
+            let drops = drops.clone();
+            move || {
+                for i in 0..40 {
+                    s.send(Counted(i, drops.clone())).unwrap();
+                }
+            }
+        });
+        for i in 0..20 {
+            assert_eq!(r.with_next(|msg| msg.0), Ok(2 * i));
+            // f返回之后msg才被drop
+            assert_eq!(r.with_next(|msg| (msg.0, drops.load(Ordering::SeqCst))), Ok((2 * i + 1, 2 * i as usize + 1)));
+            if i == 10 {
+                // f panic时msg仍然被取走，不会卡住head
+                let res = catch_unwind(AssertUnwindSafe(|| r.with_next(|_| panic!("f panicked"))));
+                assert!(res.is_err());
+                assert_eq!(r.with_next(|msg| msg.0), Ok(23));
+                break;
+            }
+        }
+        let rest: Vec<u32> = r.iter().map(|msg| msg.0).collect();
+        assert_eq!(rest, (24..40).collect::<Vec<_>>());
+        t.join().unwrap();
+        assert_eq!(drops.load(Ordering::SeqCst), 40);
+        assert_eq!(r.with_next(|msg| msg.0), Err(RecvError));
+    }
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn raw_handles_round_trip() {
+    use crate::mpmc::{channel, sync_channel, Receiver, Sender};
+    for (s, r) in [channel(), sync_channel(1), sync_channel(0)] {
+        let s2 = s.clone();
+        let raw = s.into_raw();
+        // into_raw不改变计数，channel不会断开
+        assert_eq!(s2.sender_count(), 2);
+        let raw_r = r.into_raw();
+        assert!(!s2.is_disconnected());
+        let s = unsafe { Sender::<i32>::from_raw(raw) };
+        let r = unsafe { Receiver::<i32>::from_raw(raw_r) };
+        assert!(s.same_channel(&s2));
+        let t = std::thread::spawn(move || s.send(1));
+        assert_eq!(r.recv(), Ok(1));
+        assert_eq!(t.join().unwrap(), Ok(()));
+        assert_eq!(s2.sender_count(), 1);
+        drop(s2);
+        assert!(r.recv().is_err());
+    }
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn on_recv_reports_each_receive() {
+    use crate::mpmc::{Builder, RecvEvent, RecvOutcome};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    let events = Arc::new(Mutex::new(Vec::<RecvEvent>::new()));
+    for cap in [None, Some(0), Some(2)] {
+        events.lock().unwrap().clear();
+        let builder = Builder::new().on_recv(Box::new({
+            let events = events.clone();
+            move |e| events.lock().unwrap().push(e)
+        }));
+        let (s, r) = match cap {
+            Some(cap) => builder.sync_channel(cap),
+            None => builder.channel(),
+        };
+        assert!(r.try_recv().is_err());
+        assert!(r.recv_timeout(Duration::from_millis(5)).is_err());
+        let t = std::thread::spawn({
+            let s = s.clone();
+            move || {
+                std::thread::sleep(Duration::from_millis(10));
+                s.send(1).unwrap();
+            }
+        });
+        assert_eq!(r.recv(), Ok(1));
+        t.join().unwrap();
+        if cap != Some(0) {
+            s.send(2).unwrap();
+            assert_eq!(r.recv(), Ok(2));
+        }
+        drop(s);
+        assert!(r.recv().is_err());
+
+        let events = events.lock().unwrap();
+        let outcomes: Vec<_> = events.iter().map(|e| (e.outcome, e.blocked)).collect();
+        let mut expected = vec![
+            (RecvOutcome::Empty, false),
+            (RecvOutcome::Timeout, true),
+            (RecvOutcome::Received, true),
+        ];
+        if cap != Some(0) {
+            expected.push((RecvOutcome::Received, false));
+        }
+        expected.push((RecvOutcome::Disconnected, false));
+        assert_eq!(outcomes, expected);
+        assert!(events[1].waited >= Duration::from_millis(5));
+        assert!(events[2].waited > Duration::ZERO);
+    }
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn on_recv_covers_every_receive_path() {
+    use crate::mpmc::{Builder, Poll3, RecvEvent, RecvOutcome};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    let events = Arc::new(Mutex::new(Vec::<RecvEvent>::new()));
+    let builder = || {
+        let events = events.clone();
+        Builder::new().on_recv(Box::new(move |e| events.lock().unwrap().push(e)))
+    };
+    // 执行f，返回期间报告的(outcome, blocked)
+    let observe = |f: &mut dyn FnMut()| {
+        events.lock().unwrap().clear();
+        f();
+        events.lock().unwrap().iter().map(|e| (e.outcome, e.blocked)).collect::<Vec<_>>()
+    };
+    let received = (RecvOutcome::Received, false);
+
+    let (s, mut r) = builder().sync_channel::<i32>(8);
+    s.send(1).unwrap();
+    assert_eq!(observe(&mut || assert_eq!(r.next_blocking(), Some(1))), [received]);
+    assert_eq!(observe(&mut || assert_eq!(r.poll(), Poll3::Empty)), [(RecvOutcome::Empty, false)]);
+    s.send(2).unwrap();
+    assert_eq!(observe(&mut || assert_eq!(r.with_next(|m| *m), Ok(2))), [received]);
+    s.send(3).unwrap();
+    assert_eq!(observe(&mut || assert_eq!(r.recv_exclusive(), Ok(3))), [received]);
+    s.send(4).unwrap();
+    assert_eq!(observe(&mut || assert_eq!(r.try_recv_exclusive(), Ok(4))), [received]);
+    s.send(5).unwrap();
+    assert_eq!(observe(&mut || assert_eq!(r.recv_metered().unwrap().0, 5)), [received]);
+    for i in 0..3 {
+        s.send(i).unwrap();
+    }
+    let mut buf = Vec::with_capacity(8);
+    assert_eq!(observe(&mut || assert_eq!(r.recv_into(&mut [&mut buf], 8), 3)), [received; 3]);
+    for i in 0..3 {
+        s.send(i).unwrap();
+    }
+    let timeout = Duration::from_secs(1);
+    assert_eq!(observe(&mut || assert_eq!(r.recv_many_timeout(&mut buf, 8, timeout), 3)), [received; 3]);
+
+    // zero channel上的recv_metered需要等待sender
+    let (s, r) = builder().rendezvous::<i32>();
+    let events_zero = observe(&mut || {
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                std::thread::sleep(Duration::from_millis(10));
+                s.send(6).unwrap();
+            });
+            assert_eq!(r.recv_metered().unwrap().0, 6);
+        })
+    });
+    assert_eq!(events_zero, [(RecvOutcome::Received, true)]);
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn reservation_push_with_panic_mid_batch() {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    for (s, r) in [crate::mpmc::sync_channel::<String>(4), crate::mpmc::channel::<String>()] {
+        let res = catch_unwind(AssertUnwindSafe(|| {
+            let mut batch = s.reserve(3).unwrap();
+            assert!(batch.push_with(|| "a".to_string()).is_ok());
+            assert!(batch.push_with(|| panic!("producer")).is_ok());
+            assert!(batch.push_with(|| "never".to_string()).is_ok());
+        }));
+        assert!(res.is_err());
+        s.send("b".to_string()).unwrap();
+        assert_eq!(r.recv().unwrap(), "a");
+        assert_eq!(r.recv().unwrap(), "b");
+        assert!(r.try_recv().is_err());
+
+        // 跳过的slot被回收，channel可以继续装满
+        for i in 0..4 {
+            s.send(i.to_string()).unwrap();
+        }
+        let got: Vec<_> = r.try_iter().collect();
+        assert_eq!(got, ["0", "1", "2", "3"]);
+    }
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn reservation_push_with_returns_msg_after_disconnect() {
+    use crate::mpmc::PushWithError;
+    let (s, r) = crate::mpmc::channel::<i32>();
+    let mut batch = s.reserve(2).unwrap();
+    // f执行期间最后一个receiver被drop，生成的msg没有被发送，而是返回给调用者
+    let res = batch.push_with(move || {
+        drop(r);
+        1
+    });
+    assert!(matches!(res, Err(PushWithError::Disconnected(1))));
+    assert!(matches!(batch.push_with(|| 2), Err(PushWithError::Unused(_))));
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn len_relaxed_matches_len_when_quiescent() {
+    for (s, r) in [crate::mpmc::sync_channel::<usize>(5), crate::mpmc::channel::<usize>()] {
+        // 多转几圈，覆盖array的lap以及list跨block的情况
+        for round in 0..40 {
+            for i in 0..round % 6 {
+                s.try_send(i).unwrap_or_else(|_| unreachable!());
+            }
+            assert_eq!(r.len_relaxed(), r.len());
+            assert_eq!(s.len_relaxed(), s.len());
+            while r.try_recv().is_ok() {}
+            assert_eq!(r.len_relaxed(), 0);
+        }
+    }
+    let (s, r) = crate::mpmc::sync_channel::<usize>(4);
+    std::thread::scope(|scope| {
+        scope.spawn(|| {
+            for i in 0..10_000 {
+                s.send(i).unwrap();
+            }
+        });
+        for _ in 0..10_000 {
+            assert!(r.len_relaxed() <= 4);
+            r.recv().unwrap();
+        }
+    });
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn sync_channel_rejects_oversized_capacity() {
+    use crate::mpmc::MAX_CAPACITY;
+    for cap in [MAX_CAPACITY + 1, usize::MAX] {
+        let err = std::panic::catch_unwind(|| crate::mpmc::sync_channel::<u8>(cap)).unwrap_err();
+        let msg = err.downcast_ref::<String>().unwrap();
+        assert!(msg.contains("exceeds the maximum array channel capacity"), "{}", msg);
+    }
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn for_each_available_drains_bursts_with_few_parks() {
+    use crate::mpmc::{with_parker, Parker};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread::Thread;
+    use std::time::Duration;
+
+    struct Counting(Thread, Arc<AtomicUsize>);
+    impl Parker for Counting {
+        fn park(&self) {
+            self.1.fetch_add(1, Ordering::SeqCst);
+            std::thread::park();
+        }
+        fn park_timeout(&self, timeout: Duration) {
+            self.1.fetch_add(1, Ordering::SeqCst);
+            std::thread::park_timeout(timeout);
+        }
+        fn unpark(&self) {
+            self.0.unpark();
+        }
+    }
+
+    const BURSTS: usize = 10;
+    const BURST: usize = 200;
+    let parks = Arc::new(AtomicUsize::new(0));
+    let (s, r) = crate::mpmc::channel::<usize>();
+    let p = parks.clone();
+    let t = std::thread::spawn(move || {
+        let parker = Counting(std::thread::current(), p);
+        with_parker(parker, || {
+            let mut got = Vec::new();
+            while r.for_each_available(|msg| got.push(msg)).is_ok() {}
+            got
+        })
+    });
+    for b in 0..BURSTS {
+        std::thread::sleep(Duration::from_millis(10));
+        for i in 0..BURST {
+            s.send(b * BURST + i).unwrap();
+        }
+    }
+    drop(s);
+    let got = t.join().unwrap();
+    assert_eq!(got, (0..BURSTS * BURST).collect::<Vec<_>>());
+    assert!(parks.load(Ordering::SeqCst) < BURSTS * BURST / 10);
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn rendezvous_is_zero_capacity() {
+    let (s, r) = crate::mpmc::rendezvous::<i32>();
+    assert_eq!(s.capacity(), Some(0));
+    assert!(s.try_send(1).is_err());
+    std::thread::scope(|scope| {
+        scope.spawn(|| s.send(2).unwrap());
+        assert_eq!(r.recv(), Ok(2));
+    });
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn merge_drains_sources_finishing_at_different_times() {
+    use crate::mpmc::{merge, TryRecvError};
+    use std::time::Duration;
+
+    let mut senders = Vec::new();
+    let mut receivers = Vec::new();
+    for _ in 0..3 {
+        let (s, r) = crate::mpmc::channel::<(usize, usize)>();
+        senders.push(s);
+        receivers.push(r);
+    }
+    let mut merged = merge(receivers);
+    assert_eq!(merged.try_recv(), Err(TryRecvError::Empty));
+
+    let handles: Vec<_> = senders
+        .into_iter()
+        .enumerate()
+        .map(|(src, s)| {
+            std::thread::spawn(move || {
+                for i in 0..(src + 1) * 10 {
+                    s.send((src, i)).unwrap();
+                }
+                std::thread::sleep(Duration::from_millis(20 * src as u64));
+            })
+        })
+        .collect();
+
+    let mut got = vec![Vec::new(); 3];
+    for (src, i) in merged.iter() {
+        got[src].push(i);
+    }
+    for h in handles {
+        h.join().unwrap();
+    }
+    for (src, msgs) in got.iter().enumerate() {
+        assert_eq!(*msgs, (0..(src + 1) * 10).collect::<Vec<_>>());
+    }
+    assert_eq!(merged.sources(), 0);
+    assert_eq!(merged.try_recv(), Err(TryRecvError::Disconnected));
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn channel_id_survives_type_erasure() {
+    use crate::mpmc::{ChannelId, Sender};
+    use std::any::Any;
+    use std::collections::HashMap;
+
+    let (s1, r1) = crate::mpmc::channel::<i32>();
+    let (s2, _r2) = crate::mpmc::sync_channel::<String>(1);
+    let (s3, _r3) = crate::mpmc::sync_channel::<i32>(0);
+    assert_eq!(s1.id(), r1.id());
+    assert_eq!(s1.id(), s1.clone().id());
+
+    let mut registry: HashMap<ChannelId, Box<dyn Any>> = HashMap::new();
+    registry.insert(s1.id(), Box::new(s1.clone()));
+    registry.insert(s2.id(), Box::new(s2.clone()));
+    registry.insert(s3.id(), Box::new(s3.clone()));
+    assert_eq!(registry.len(), 3);
+
+    let erased = &registry[&r1.id()];
+    let s = erased.downcast_ref::<Sender<i32>>().unwrap();
+    assert_eq!(s.id(), s1.id());
+    assert_ne!(s.id(), s3.id());
+    s.send(7).unwrap();
+    assert_eq!(r1.recv(), Ok(7));
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn soft_limit_wakes_one_sender_per_freed_slot() {
+    use crate::mpmc::Builder;
+    use std::time::Duration;
+
+    const SENDERS: usize = 4;
+    let (s, r) = Builder::new().soft_limit(2).channel::<usize>();
+    s.send(0).unwrap();
+    s.send(0).unwrap();
+    let mut got = Vec::new();
+    std::thread::scope(|scope| {
+        for id in 1..=SENDERS {
+            let s = s.clone();
+            scope.spawn(move || s.send(id).unwrap());
+        }
+        std::thread::sleep(Duration::from_millis(50));
+        // 每接收一个msg只放行一个阻塞的sender，len回到上限而不会超过它
+        for _ in 0..SENDERS {
+            got.push(r.recv().unwrap());
+            std::thread::sleep(Duration::from_millis(20));
+            assert_eq!(r.len(), 2);
+        }
+    });
+    got.extend(r.try_iter());
+    got.sort_unstable();
+    assert_eq!(got, [0, 0, 1, 2, 3, 4]);
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn soft_limit_blocks_list_sender_until_consumed() {
+    use crate::mpmc::{Builder, SendTimeoutError, TrySendError};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    let (s, r) = Builder::new().soft_limit(3).channel::<usize>();
+    assert_eq!(s.capacity(), None);
+    for i in 0..3 {
+        s.send(i).unwrap();
+    }
+    assert!(s.is_full());
+    assert_eq!(s.try_send(3), Err(TrySendError::Full(3)));
+    assert_eq!(s.send_timeout(3, Duration::from_millis(20)), Err(SendTimeoutError::Timeout(3)));
+
+    let sent = AtomicUsize::new(3);
+    std::thread::scope(|scope| {
+        scope.spawn(|| {
+            for i in 3..10 {
+                s.send(i).unwrap();
+                sent.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+        // 生产者停在上限处，直到有msg被接收
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(sent.load(Ordering::SeqCst), 3);
+        assert_eq!(r.len(), 3);
+        for i in 0..10 {
+            assert_eq!(r.recv(), Ok(i));
+            assert!(r.len() <= 3);
+        }
+    });
+    assert_eq!(sent.load(Ordering::SeqCst), 10);
+
+    // receiver断开时阻塞的sender返回错误
+    for _ in 0..3 {
+        s.send(0).unwrap();
+    }
+    std::thread::scope(|scope| {
+        let t = scope.spawn(|| s.send(1));
+        std::thread::sleep(Duration::from_millis(20));
+        drop(r);
+        assert!(t.join().unwrap().is_err());
+    });
+}
+
+#[cfg(all(feature = "metrics", feature = "std"))]
+#[test]
+fn single_notify_wakes_single_receiver() {
+    use std::time::Duration;
+    let (s, r) = crate::mpmc::sync_channel::<i32>(1);
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..8).map(|_| scope.spawn(|| r.recv())).collect();
+        std::thread::sleep(Duration::from_millis(50));
+        s.send(1).unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(r.metrics().received, 1);
+        assert!(r.metrics().spurious_wakeups <= 1);
+        drop(s);
+        let got: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        assert_eq!(got.iter().filter(|res| res.is_ok()).count(), 1);
+    });
+    assert!(r.metrics().spurious_wakeups <= 1);
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn sender_disconnect_wakes_every_blocked_receiver() {
+    use crate::mpmc::RecvError;
+    use std::time::Duration;
+    for (s, r) in [crate::mpmc::channel::<i32>(), crate::mpmc::sync_channel(2), crate::mpmc::sync_channel(0)] {
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..6).map(|_| scope.spawn(|| r.recv())).collect();
+            std::thread::sleep(Duration::from_millis(50));
+            assert!(s.disconnect());
+            for h in handles {
+                assert_eq!(h.join().unwrap(), Err(RecvError));
+            }
+        });
+        // 重复调用没有效果，Sender仍然可以用来查询状态
+        assert!(!s.disconnect());
+        assert!(s.is_disconnected());
+        assert_eq!(s.sender_count(), 1);
+        assert!(s.send(1).is_err());
+    }
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn recycled_channel_reuses_allocation() {
+    use crate::mpmc::Builder;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    struct Tracked(Arc<AtomicUsize>);
+    impl Drop for Tracked {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    let drops = Arc::new(AtomicUsize::new(0));
+    let (s, r) = Builder::new().recycle().channel::<Tracked>();
+    let id = s.id();
+    s.send(Tracked(drops.clone())).unwrap();
+    s.send(Tracked(drops.clone())).unwrap();
+    drop((s, r));
+    // 剩余的msg在放回缓存之前已经被drop
+    assert_eq!(drops.load(Ordering::SeqCst), 2);
+
+    // 同一线程上大小相同的channel复用这块内存，而且是一个全新的空channel
+    let (s, r) = Builder::new().recycle().channel::<Tracked>();
+    assert_eq!(s.id(), id);
+    assert!(r.is_empty());
+    assert!(!r.is_disconnected());
+    s.send(Tracked(drops.clone())).unwrap();
+    assert!(r.recv().is_ok());
+    assert_eq!(drops.load(Ordering::SeqCst), 3);
+
+    // 弱引用还活着时不会回收
+    let weak = s.downgrade();
+    drop((s, r));
+    let (s2, _r2) = Builder::new().recycle().channel::<Tracked>();
+    assert_ne!(s2.id(), id);
+    drop(weak);
+
+    // 高频创建、销毁
+    for i in 0..1000 {
+        let (s, r) = Builder::new().recycle().sync_channel::<usize>(4);
+        s.send(i).unwrap();
+        assert_eq!(r.recv(), Ok(i));
+    }
+}
+
+#[cfg(debug_assertions)]
+#[test]
+#[cfg(feature = "std")]
+fn zero_self_rendezvous_is_reported_in_debug() {
+    use crate::mpmc::{self_rendezvous, Select};
+    use std::time::Duration;
+
+    // 只有当前线程的操作可以配对：提示之后照常等待，到了超时返回
+    let (s, r) = crate::mpmc::rendezvous::<i32>();
+    let before = self_rendezvous::count();
+    let mut sel = Select::new();
+    sel.recv(&r);
+    sel.send(&s);
+    assert!(sel.select_timeout(Duration::from_millis(20)).is_err());
+    assert!(self_rendezvous::count() > before);
+
+    // 注册已经被取消，channel仍然可以正常配对
+    std::thread::scope(|scope| {
+        scope.spawn(|| s.send(1).unwrap());
+        assert_eq!(r.recv(), Ok(1));
+    });
+
+    // 只有一对handle，但是其他线程通过引用共享了Sender，可以完成这次select，不能panic
+    std::thread::scope(|scope| {
+        let s = &s;
+        scope.spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            s.send(2).unwrap();
+        });
+        let mut sel = Select::new();
+        sel.recv(&r);
+        sel.send(s);
+        let oper = sel.select();
+        assert_eq!(oper.index(), 0);
+        assert_eq!(oper.recv(&r), Ok(2));
+    });
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn spin_before_park_zero_parks_blocked_operations() {
+    use crate::mpmc::{spin_count, with_parker, Builder, Parker};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread::Thread;
+    use std::time::Duration;
+
+    struct Counting(Thread, Arc<AtomicUsize>);
+    impl Parker for Counting {
+        fn park(&self) {
+            self.1.fetch_add(1, Ordering::SeqCst);
+            std::thread::park();
+        }
+        fn park_timeout(&self, timeout: Duration) {
+            self.1.fetch_add(1, Ordering::SeqCst);
+            std::thread::park_timeout(timeout);
+        }
+        fn unpark(&self) {
+            self.0.unpark();
+        }
+    }
+
+    // array channel满时的send
+    let (s, r) = Builder::new().spin_before_park(0).sync_channel::<i32>(1);
+    s.send(1).unwrap();
+    let parks = Arc::new(AtomicUsize::new(0));
+    std::thread::scope(|scope| {
+        let p = parks.clone();
+        let t = scope.spawn(|| with_parker(Counting(std::thread::current(), p), || s.send(2)));
+        while parks.load(Ordering::SeqCst) == 0 {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(r.recv(), Ok(1));
+        t.join().unwrap().unwrap();
+    });
+    assert_eq!(r.recv(), Ok(2));
+
+    // list channel为空时的recv：两种配置都会park，但spin_before_park(0)在park之前几乎不自旋
+    let blocked_recv = |builder: Builder| {
+        let (s, r) = builder.channel::<i32>();
+        let parks = Arc::new(AtomicUsize::new(0));
+        let spins = std::thread::scope(|scope| {
+            let p = parks.clone();
+            let t = scope.spawn(|| {
+                let before = spin_count();
+                assert_eq!(with_parker(Counting(std::thread::current(), p), || r.recv()), Ok(3));
+                spin_count() - before
+            });
+            while parks.load(Ordering::SeqCst) == 0 {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            s.send(3).unwrap();
+            t.join().unwrap()
+        });
+        (spins, parks.load(Ordering::SeqCst))
+    };
+    let (spins_zero, parks_zero) = blocked_recv(Builder::new().spin_before_park(0));
+    let (spins_default, parks_default) = blocked_recv(Builder::new());
+    assert!(parks_zero >= 1 && parks_default >= 1);
+    assert!(
+        spins_zero < spins_default,
+        "spin_before_park(0) spun {spins_zero} times, the default spun {spins_default} times"
+    );
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn recv_many_timeout_returns_partial_batches() {
+    use std::time::{Duration, Instant};
+    let (s, r) = crate::mpmc::sync_channel::<i32>(16);
+    let mut buf = Vec::new();
+
+    // 超时之前凑不满，返回已有的部分
+    s.send(1).unwrap();
+    s.send(2).unwrap();
+    assert_eq!(r.recv_many_timeout(&mut buf, 5, Duration::from_millis(50)), 2);
+    assert_eq!(buf, [1, 2]);
+
+    // 一条都没有时等到超时，返回0
+    let start = Instant::now();
+    assert_eq!(r.recv_many_timeout(&mut buf, 5, Duration::from_millis(30)), 0);
+    assert!(start.elapsed() >= Duration::from_millis(30));
+
+    // 已经有足够的msg时不等待，最多取max条
+    for i in 3..10 {
+        s.send(i).unwrap();
+    }
+    let start = Instant::now();
+    assert_eq!(r.recv_many_timeout(&mut buf, 4, Duration::from_secs(10)), 4);
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert_eq!(buf, [1, 2, 3, 4, 5, 6]);
+
+    buf.clear();
+    assert_eq!(r.recv_many_timeout(&mut buf, 8, Duration::from_secs(10)), 3);
+    assert_eq!(buf, [7, 8, 9]);
+
+    // 等到第一条msg之后立即返回
+    std::thread::scope(|scope| {
+        scope.spawn(|| {
+            std::thread::sleep(Duration::from_millis(20));
+            s.send(100).unwrap();
+        });
+        assert_eq!(r.recv_many_timeout(&mut buf, 8, Duration::from_secs(10)), 1);
+        assert_eq!(buf, [7, 8, 9, 100]);
+    });
+
+    // 断开时返回剩下的msg
+    s.send(11).unwrap();
+    drop(s);
+    assert_eq!(r.recv_many_timeout(&mut buf, 8, Duration::from_secs(10)), 1);
+    assert_eq!(r.recv_many_timeout(&mut buf, 8, Duration::from_secs(10)), 0);
+}
+
+#[cfg(not(loom))]
+#[test]
+#[cfg(feature = "std")]
+fn global_channel_via_once_lock() {
+    use crate::mpmc::{Builder, Receiver, Sender};
+    use std::sync::OnceLock;
+
+    static BUILDER: Builder = Builder::new().soft_limit(64);
+    static BUS: OnceLock<(Sender<u32>, Receiver<u32>)> = OnceLock::new();
+    fn bus() -> &'static (Sender<u32>, Receiver<u32>) {
+        BUS.get_or_init(|| BUILDER.clone().channel())
+    }
+
+    std::thread::scope(|scope| {
+        for i in 0..4 {
+            scope.spawn(move || bus().0.send(i).unwrap());
+        }
+    });
+    let mut got: Vec<_> = bus().1.try_iter().collect();
+    got.sort();
+    assert_eq!(got, [0, 1, 2, 3]);
+    assert!(bus().0.same_channel(&BUS.get().unwrap().0));
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn select_drains_disconnected_channel_before_reporting_disconnect() {
+    use crate::mpmc::{RecvError, Select};
+    for (s, r) in [crate::mpmc::sync_channel::<i32>(4), crate::mpmc::channel::<i32>()] {
+        let (_idle_s, idle) = crate::mpmc::channel::<i32>();
+        for i in 0..3 {
+            s.send(i).unwrap();
+        }
+        drop(s);
+        assert!(r.is_disconnected());
+
+        let mut got = Vec::new();
+        loop {
+            let mut sel = Select::new();
+            sel.recv(&idle);
+            let i = sel.recv(&r);
+            assert_eq!(sel.ready(), i);
+            let oper = sel.select();
+            assert_eq!(oper.index(), i);
+            match oper.recv(&r) {
+                Ok(msg) => got.push(msg),
+                Err(RecvError) => break,
+            }
+        }
+        assert_eq!(got, [0, 1, 2]);
+    }
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn zero_send_timeout_races_with_receiver_without_loss_or_duplication() {
+    use crate::mpmc::{RecvTimeoutError, SendTimeoutError};
+    const N: usize = 2000;
+    let (s, r) = crate::mpmc::sync_channel::<usize>(0);
+    let (sent, timed_out) = std::thread::scope(|scope| {
+        let sender = scope.spawn(move || {
+            let (mut sent, mut timed_out) = (Vec::new(), Vec::new());
+            for i in 0..N {
+                match s.send_timeout(i, std::time::Duration::from_micros(20 + (i % 7) as u64 * 10)) {
+                    Ok(()) => sent.push(i),
+                    // 超时返回的必须是这次发送的msg
+                    Err(SendTimeoutError::Timeout(msg)) => {
+                        assert_eq!(msg, i);
+                        timed_out.push(i);
+                    }
+                    Err(SendTimeoutError::Disconnected(_)) => unreachable!(),
+                }
+            }
+            (sent, timed_out)
+        });
+        let mut received = Vec::new();
+        loop {
+            match r.recv_timeout(std::time::Duration::from_micros(30)) {
+                Ok(msg) => received.push(msg),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+        let (sent, timed_out) = sender.join().unwrap();
+        // send_timeout返回Ok的msg恰好都被收到一次，返回Timeout的msg一个都没有被收到
+        assert_eq!(received, sent);
+        (sent, timed_out)
+    });
+    assert_eq!(sent.len() + timed_out.len(), N);
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn byte_channels_round_trip_through_io() {
+    use crate::mpmc::{ByteReceiver, ByteSender};
+    use std::io::{Read, Write};
+    let data: Vec<u8> = (0..10_000u32).map(|i| (i * 7) as u8).collect();
+
+    // Vec<u8>：msg比读缓冲区大时剩余的字节留到下一次read
+    let (s, r) = crate::mpmc::sync_channel::<Vec<u8>>(2);
+    let (mut w, mut rd) = (ByteSender::new(s), ByteReceiver::new(r));
+    let out = std::thread::scope(|scope| {
+        scope.spawn(|| {
+            for chunk in data.chunks(1000) {
+                w.write_all(chunk).unwrap();
+            }
+            w.write_all(&[]).unwrap();
+            w.flush().unwrap();
+            drop(w);
+        });
+        let mut out = Vec::new();
+        let mut buf = [0u8; 333];
+        loop {
+            let n = rd.read(&mut buf).unwrap();
+            if n == 0 {
+                break;
+            }
+            assert!(n <= 333);
+            out.extend_from_slice(&buf[..n]);
+        }
+        out
+    });
+    assert_eq!(out, data);
+
+    // u8：每个字节一个msg，read_to_end读到断开为止
+    let (s, r) = crate::mpmc::channel::<u8>();
+    let (mut w, mut rd) = (ByteSender::new(s), ByteReceiver::new(r));
+    w.write_all(&data).unwrap();
+    drop(w);
+    let mut out = Vec::new();
+    rd.read_to_end(&mut out).unwrap();
+    assert_eq!(out, data);
+
+    // receiver断开之后write返回BrokenPipe
+    let (s, r) = crate::mpmc::channel::<Vec<u8>>();
+    let mut w = ByteSender::new(s);
+    drop(r);
+    assert_eq!(w.write(b"x").unwrap_err().kind(), std::io::ErrorKind::BrokenPipe);
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn select_over_heterogeneous_message_types() {
+    use crate::mpmc::Select;
+    let (s1, r1) = crate::mpmc::channel::<i32>();
+    let (s2, r2) = crate::mpmc::sync_channel::<String>(1);
+    s2.send("hello".to_string()).unwrap();
+    s1.send(7).unwrap();
+
+    let (mut ints, mut strings) = (Vec::new(), Vec::new());
+    for _ in 0..2 {
+        let mut sel = Select::new();
+        let i1 = sel.recv(&r1);
+        let i2 = sel.recv(&r2);
+        let index = sel.ready();
+        if index == i1 {
+            ints.push(r1.try_recv().unwrap());
+        } else {
+            assert_eq!(index, i2);
+            strings.push(r2.try_recv().unwrap());
+        }
+    }
+    assert_eq!(ints, [7]);
+    assert_eq!(strings, ["hello"]);
+
+    // select返回的SelectedOperation同样按序号用对应类型的Receiver完成
+    s2.send("world".to_string()).unwrap();
+    let mut sel = Select::new();
+    sel.recv(&r1);
+    let i2 = sel.recv(&r2);
+    let oper = sel.select();
+    assert_eq!(oper.index(), i2);
+    assert_eq!(oper.recv(&r2).unwrap(), "world");
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn select_prefers_higher_priority_when_both_ready() {
+    use crate::mpmc::Select;
+    // 没有内置的timer channel，用一个已经有msg的channel代替已经触发的timer
+    let (data_s, data_r) = crate::mpmc::channel::<i32>();
+    let (timer_s, timer_r) = crate::mpmc::sync_channel::<()>(1);
+    timer_s.send(()).unwrap();
+    for i in 0..100 {
+        data_s.send(i).unwrap();
+    }
+
+    for i in 0..100 {
+        let mut sel = Select::new();
+        sel.recv(&timer_r);
+        let data = sel.recv_with_priority(&data_r, 1);
+        match i % 3 {
+            0 => {
+                let oper = sel.select();
+                assert_eq!(oper.index(), data);
+                assert_eq!(oper.recv(&data_r), Ok(i));
+            }
+            1 => {
+                let oper = sel.try_select().unwrap();
+                assert_eq!(oper.index(), data);
+                assert_eq!(oper.recv(&data_r), Ok(i));
+            }
+            _ => {
+                assert_eq!(sel.ready(), data);
+                assert_eq!(data_r.try_recv(), Ok(i));
+            }
+        }
+    }
+
+    // data取空之后选中timer
+    let mut sel = Select::new();
+    let timer = sel.recv(&timer_r);
+    sel.recv_with_priority(&data_r, 1);
+    let oper = sel.select();
+    assert_eq!(oper.index(), timer);
+    assert_eq!(oper.recv(&timer_r), Ok(()));
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn dropping_all_handles_frees_counter() {
+    use crate::mpmc::{live_counters, Builder};
+    let base = live_counters();
+    {
+        let (s, r) = crate::mpmc::channel::<String>();
+        s.send("leftover".to_string()).unwrap();
+        let _s2 = s.clone();
+        let _r2 = r.clone();
+        assert_eq!(live_counters(), base + 1);
+    }
+    assert_eq!(live_counters(), base);
+
+    // 弱引用比所有强引用活得更久时，Counter要等到最后一个弱引用释放
+    let (s, r) = crate::mpmc::sync_channel::<i32>(1);
+    let weak = r.downgrade();
+    drop((s, r));
+    assert_eq!(live_counters(), base + 1);
+    assert!(weak.upgrade().is_none());
+    drop(weak);
+    assert_eq!(live_counters(), base);
+
+    // 在其他线程释放最后一个handle，以及recycle放回pool时同样计数
+    let (s, r) = crate::mpmc::sync_channel::<i32>(0);
+    let t = std::thread::spawn(move || r.recv());
+    s.send(1).unwrap();
+    drop(s);
+    assert_eq!(t.join().unwrap(), Ok(1));
+    let (s, r) = Builder::new().recycle().channel::<i32>();
+    drop((s, r));
+    assert_eq!(live_counters(), base);
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn zero_send_confirmed_reports_handoff_path() {
+    use crate::mpmc::Handoff;
+    let (s, r) = crate::mpmc::rendezvous::<i32>();
+
+    // receiver先阻塞，sender直接把msg交给它
+    std::thread::scope(|scope| {
+        let t = scope.spawn(|| r.recv());
+        while !s.is_ready() {
+            std::thread::yield_now();
+        }
+        assert_eq!(s.send_confirmed(1), Ok(Handoff::Immediate));
+        assert_eq!(t.join().unwrap(), Ok(1));
+    });
+
+    // sender先park，之后被receiver配对
+    std::thread::scope(|scope| {
+        let t = scope.spawn(|| s.send_confirmed(2));
+        while !r.is_ready() {
+            std::thread::yield_now();
+        }
+        assert_eq!(r.recv(), Ok(2));
+        assert_eq!(t.join().unwrap(), Ok(Handoff::Parked));
+    });
+
+    let (bs, _br) = crate::mpmc::sync_channel::<i32>(1);
+    assert_eq!(bs.send_confirmed(3), Ok(Handoff::Buffered));
+    drop(r);
+    assert_eq!(s.send_confirmed(4), Err(crate::mpmc::SendError(4)));
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn is_closing_distinguishes_live_from_draining() {
+    use crate::mpmc::TryRecvError;
+    for (s, r) in [crate::mpmc::sync_channel::<i32>(4), crate::mpmc::channel::<i32>()] {
+        // 有msg
+        s.send(1).unwrap();
+        assert!(!r.is_closing());
+        assert_eq!(r.try_recv(), Ok(1));
+        // 空并且仍然连接
+        assert_eq!(r.try_recv(), Err(TryRecvError::Empty));
+        assert!(!r.is_closing());
+        // 正在关闭：剩余的msg仍然可以取出，取空之后断开
+        s.send(2).unwrap();
+        assert!(s.close_when_empty());
+        assert!(r.is_closing());
+        assert!(!r.is_disconnected());
+        assert_eq!(r.try_recv(), Ok(2));
+        assert_eq!(r.try_recv(), Err(TryRecvError::Disconnected));
+        assert!(r.is_closing());
+    }
+
+    let (s, r) = crate::mpmc::rendezvous::<i32>();
+    assert!(!r.is_closing());
+    s.close_when_empty();
+    assert!(r.is_closing());
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn replay_receiver_replays_sequence_identically() {
+    use crate::mpmc::live_counters;
+    let base = live_counters();
+    {
+        let (s, r) = crate::mpmc::channel::<String>();
+        let mut r = r.replay().unwrap();
+        s.send("before".to_string()).unwrap();
+        assert_eq!(r.recv().unwrap(), "before");
+
+        // 跨过多个block，rewind之后两次读到完全相同的序列
+        let cp = r.checkpoint();
+        for i in 0..100 {
+            s.send(i.to_string()).unwrap();
+        }
+        let first: Vec<String> = (0..100).map(|_| r.recv().unwrap()).collect();
+        assert!(r.is_empty());
+        r.rewind(&cp);
+        assert_eq!(r.len(), 100);
+        let second: Vec<String> = (0..100).map(|_| r.try_recv().unwrap()).collect();
+        assert_eq!(first, second);
+        assert_eq!(first[99], "99");
+
+        // 中途的checkpoint，drop所有checkpoint之后恢复正常接收
+        r.rewind(&cp);
+        for _ in 0..40 {
+            r.recv().unwrap();
+        }
+        let mid = r.checkpoint();
+        assert_eq!(r.recv().unwrap(), "40");
+        r.rewind(&mid);
+        assert_eq!(r.recv().unwrap(), "40");
+        drop((cp, mid));
+        assert_eq!(r.recv().unwrap(), "41");
+        s.send("after".to_string()).unwrap();
+
+        // 在发送任何msg之前创建的checkpoint
+        let (s2, r2) = crate::mpmc::channel::<i32>();
+        let mut r2 = r2.replay().unwrap();
+        let cp = r2.checkpoint();
+        r2.rewind(&cp);
+        s2.send(1).unwrap();
+        s2.send(2).unwrap();
+        assert_eq!(r2.recv(), Ok(1));
+        r2.rewind(&cp);
+        assert_eq!(r2.recv(), Ok(1));
+        assert_eq!(r2.recv(), Ok(2));
+        drop(s2);
+        assert!(r2.recv().is_err());
+        r2.rewind(&cp);
+        assert_eq!(r2.recv(), Ok(1));
+        let r2 = r2.into_inner();
+        assert_eq!(r2.recv(), Ok(2));
+
+        // 不是唯一的receiver，或者不是list channel时原样返回
+        let r = r.into_inner();
+        let r_clone = r.clone();
+        assert!(r.replay().is_err());
+        drop(r_clone);
+        let (_s3, r3) = crate::mpmc::sync_channel::<i32>(1);
+        assert!(r3.replay().is_err());
+    }
+    // 保留的msg和block都被释放
+    assert_eq!(live_counters(), base);
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn latest_channel_coalesces_pending_updates_per_key() {
+    use crate::mpmc::{latest_channel, RecvTimeoutError, TryRecvError};
+    let (s, r) = latest_channel::<&str, u32>();
+    assert_eq!(s.send("a", 1), Ok(None));
+    assert_eq!(s.send("b", 1), Ok(None));
+    assert_eq!(s.send("a", 2), Ok(Some(1)));
+    assert_eq!(s.len(), 2);
+    // 被替换的key保持原来的位置
+    assert_eq!(r.try_recv(), Ok(("a", 2)));
+    assert_eq!(r.try_recv(), Ok(("b", 1)));
+    assert_eq!(r.try_recv(), Err(TryRecvError::Empty));
+    assert_eq!(r.recv_timeout(std::time::Duration::from_millis(1)), Err(RecvTimeoutError::Timeout));
+
+    // 慢的consumer只看到每个key接收时最新的值，并且每个key的值不会倒退
+    let (s, r) = latest_channel::<u32, u32>();
+    let received = std::thread::scope(|scope| {
+        scope.spawn(move || {
+            for v in 0..10_000 {
+                s.send(v % 4, v).unwrap();
+            }
+        });
+        let mut received = Vec::new();
+        while let Ok(msg) = r.recv() {
+            received.push(msg);
+            std::thread::sleep(std::time::Duration::from_micros(50));
+        }
+        received
+    });
+    assert!(received.len() < 10_000);
+    let mut last = [None; 4];
+    for &(k, v) in &received {
+        assert_eq!(v % 4, k);
+        assert!(last[k as usize] < Some(v));
+        last[k as usize] = Some(v);
+    }
+    // 所有sender断开之前发送的最后的值一定会被收到
+    assert_eq!(last, [Some(9996), Some(9997), Some(9998), Some(9999)]);
+
+    let (s, r) = latest_channel::<u8, String>();
+    drop(r);
+    assert!(s.send(1, "x".to_string()).is_err());
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn ack_channel_redelivers_unacked_messages() {
+    use crate::mpmc::{ack_channel, TryRecvError};
+    let (s, r) = ack_channel::<String>();
+    s.send("a".to_string()).unwrap();
+    s.send("b".to_string()).unwrap();
+
+    // ack之后不会再投递
+    let d = r.recv().unwrap();
+    assert_eq!(*d, "a");
+    assert!(!d.is_redelivered());
+    assert_eq!(r.in_flight(), 1);
+    assert_eq!(d.ack(), "a");
+    assert_eq!(r.in_flight(), 0);
+
+    // nack之后重新投递，优先于新的msg
+    s.send("c".to_string()).unwrap();
+    r.recv().unwrap().nack();
+    let d = r.recv().unwrap();
+    assert_eq!(*d, "b");
+    assert!(d.is_redelivered());
+    d.ack();
+
+    // 处理时panic，drop的Delivery把msg放回，另一个receiver可以接收
+    let r2 = r.clone();
+    let worker = std::thread::spawn(move || {
+        let _d = r.recv().unwrap();
+        panic!("worker crashed");
+    });
+    assert!(worker.join().is_err());
+    let d = r2.recv().unwrap();
+    assert_eq!(*d, "c");
+    assert!(d.is_redelivered());
+
+    // sender断开之后，还有in_flight的msg时不会返回Disconnected
+    drop(s);
+    assert_eq!(r2.try_recv().unwrap_err(), TryRecvError::Empty);
+    let t = {
+        let r2 = r2.clone();
+        std::thread::spawn(move || r2.recv().map(|d| d.ack()))
+    };
+    std::thread::sleep(std::time::Duration::from_millis(20));
+    drop(d);
+    assert_eq!(t.join().unwrap(), Ok("c".to_string()));
+    assert!(r2.recv().is_err());
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn with_spin_overrides_spin_per_sender_handle() {
+    use crate::mpmc::{with_parker, Parker};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread::Thread;
+    use std::time::Duration;
+
+    struct Counting(Thread, Arc<AtomicUsize>);
+    impl Parker for Counting {
+        fn park(&self) {
+            self.1.fetch_add(1, Ordering::SeqCst);
+            std::thread::park();
+        }
+        fn park_timeout(&self, timeout: Duration) {
+            self.1.fetch_add(1, Ordering::SeqCst);
+            std::thread::park_timeout(timeout);
+        }
+        fn unpark(&self) {
+            self.0.unpark();
+        }
+    }
+
+    let (s, r) = crate::mpmc::sync_channel::<i32>(1);
+    let eager = s.clone().with_spin(0);
+    // 自旋的上限足够大，slot在它用完之前就会被释放，所以不会park
+    let patient = s.with_spin(2000);
+    let patient_clone = patient.clone();
+    drop(patient);
+    eager.send(0).unwrap();
+
+    let parks = Arc::new(AtomicUsize::new(0));
+    std::thread::scope(|scope| {
+        let p = parks.clone();
+        let t = scope.spawn(|| with_parker(Counting(std::thread::current(), p), || eager.send(1)));
+        while parks.load(Ordering::SeqCst) == 0 {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(r.recv(), Ok(0));
+        t.join().unwrap().unwrap();
+    });
+    assert_eq!(r.recv(), Ok(1));
+
+    eager.send(2).unwrap();
+    let parks = Arc::new(AtomicUsize::new(0));
+    std::thread::scope(|scope| {
+        let p = parks.clone();
+        let t = scope.spawn(|| with_parker(Counting(std::thread::current(), p), || patient_clone.send(3)));
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(r.recv(), Ok(2));
+        t.join().unwrap().unwrap();
+    });
+    assert_eq!(parks.load(Ordering::SeqCst), 0);
+    assert_eq!(r.recv(), Ok(3));
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn recv_with_parker_yields_to_scheduler_until_unpark() {
+    use crate::mpmc::{with_parker, Parker};
+    use std::cell::{Cell, RefCell};
+    use std::collections::VecDeque;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Condvar, Mutex};
+    use std::time::Duration;
+
+    // 单线程的协作式调度：每次让出时运行一个排队的任务，其中一个任务发送msg
+    let (s, r) = crate::mpmc::channel::<i32>();
+    let ran = Cell::new(0);
+    let tasks: RefCell<VecDeque<Box<dyn FnOnce() + '_>>> = RefCell::new(VecDeque::new());
+    for i in 0..3 {
+        let s = s.clone();
+        let ran = &ran;
+        tasks.borrow_mut().push_back(Box::new(move || {
+            ran.set(ran.get() + 1);
+            if i == 2 {
+                s.send(42).unwrap();
+            }
+        }));
+    }
+    let yields = Cell::new(0);
+    let scheduler = || {
+        yields.set(yields.get() + 1);
+        if let Some(task) = tasks.borrow_mut().pop_front() {
+            task();
+        }
+    };
+    assert_eq!(r.recv_with_parker(&scheduler), Ok(42));
+    assert_eq!(ran.get(), 3);
+    assert!(yields.get() >= 3);
+
+    // 调度器在yield_now中阻塞到unpark到来，其他线程发送msg时通过Parker的unpark唤醒它，不会调用park
+    struct Wakeup {
+        woken: Mutex<bool>,
+        cond: Condvar,
+        parks: AtomicUsize,
+        unparks: AtomicUsize,
+    }
+    impl Wakeup {
+        fn wait(&self) {
+            let mut woken = self.woken.lock().unwrap();
+            while !*woken {
+                woken = self.cond.wait(woken).unwrap();
+            }
+            *woken = false;
+        }
+    }
+    impl Parker for Arc<Wakeup> {
+        fn park(&self) {
+            self.parks.fetch_add(1, Ordering::SeqCst);
+            self.wait();
+        }
+        fn park_timeout(&self, _timeout: Duration) {
+            self.park();
+        }
+        fn unpark(&self) {
+            self.unparks.fetch_add(1, Ordering::SeqCst);
+            *self.woken.lock().unwrap() = true;
+            self.cond.notify_one();
+        }
+    }
+
+    let wakeup = Arc::new(Wakeup {
+        woken: Mutex::new(false),
+        cond: Condvar::new(),
+        parks: AtomicUsize::new(0),
+        unparks: AtomicUsize::new(0),
+    });
+    let blocked = Cell::new(0);
+    let res = std::thread::scope(|scope| {
+        scope.spawn(|| {
+            std::thread::sleep(Duration::from_millis(20));
+            s.send(7).unwrap();
+        });
+        with_parker(wakeup.clone(), || {
+            r.recv_with_parker(&|| {
+                blocked.set(blocked.get() + 1);
+                wakeup.wait();
+            })
+        })
+    });
+    assert_eq!(res, Ok(7));
+    assert_eq!(blocked.get(), 1);
+    assert_eq!(wakeup.unparks.load(Ordering::SeqCst), 1);
+    assert_eq!(wakeup.parks.load(Ordering::SeqCst), 0);
+
+    drop(s);
+    assert!(r.recv_with_parker(&|| {}).is_err());
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn send_all_wakes_as_many_receivers_as_messages() {
+    const N: usize = 4;
+    let (s, r) = mpmc::channel();
+    let mut got = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..N)
+            .map(|_| {
+                let r = r.clone();
+                scope.spawn(move || r.recv().unwrap())
+            })
+            .collect();
+        // 让所有receiver都阻塞在空的channel上
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        assert_eq!(s.send_all(0..N), Ok(N));
+        handles.into_iter().map(|h| h.join().unwrap()).collect::<Vec<_>>()
+    });
+    got.sort();
+    assert_eq!(got, (0..N).collect::<Vec<_>>());
+
+    // 中途断开时返回没能发送的msg
+    drop(r);
+    assert_eq!(s.send_all(vec![1, 2]), Err(mpmc::SendError(1)));
+
+    // 其他flavor逐个发送
+    let (s, r) = mpmc::sync_channel(4);
+    assert_eq!(s.send_all([1, 2, 3]), Ok(3));
+    assert_eq!(r.try_iter().collect::<Vec<_>>(), [1, 2, 3]);
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn zero_channel_reports_waiting_peers() {
+    let (s, r) = mpmc::sync_channel::<i32>(0);
+    assert!(s.is_empty() && s.is_full());
+    assert!(!s.has_waiting_receiver() && !r.has_waiting_sender());
+
+    std::thread::scope(|scope| {
+        let h = scope.spawn(|| r.recv());
+        while !s.has_waiting_receiver() {
+            std::thread::yield_now();
+        }
+        // 有receiver在等待时不是满的，但仍然是空的
+        assert!(!s.is_full() && s.is_empty());
+        assert_eq!(s.try_send(1), Ok(()));
+        assert_eq!(h.join().unwrap(), Ok(1));
+    });
+    assert!(s.is_full());
+
+    std::thread::scope(|scope| {
+        let h = scope.spawn(|| s.send(2));
+        while !r.has_waiting_sender() {
+            std::thread::yield_now();
+        }
+        assert_eq!(r.try_recv(), Ok(2));
+        h.join().unwrap().unwrap();
+    });
+    assert!(!r.has_waiting_sender());
+
+    let (s, r) = mpmc::channel::<i32>();
+    assert!(!s.has_waiting_receiver() && !r.has_waiting_sender());
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn recv_until_returns_none_when_flag_is_set() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    let (s, r) = mpmc::channel::<i32>();
+    let stop = AtomicBool::new(false);
+
+    s.send(1).unwrap();
+    assert_eq!(r.recv_until(&stop), Ok(Some(1)));
+
+    // 另一个线程设置flag，阻塞的recv_until返回None
+    let res = std::thread::scope(|scope| {
+        scope.spawn(|| {
+            std::thread::sleep(std::time::Duration::from_millis(30));
+            stop.store(true, Ordering::Release);
+        });
+        r.recv_until(&stop)
+    });
+    assert_eq!(res, Ok(None));
+
+    // flag已经设置时即使有msg也不接收
+    s.send(2).unwrap();
+    assert_eq!(r.recv_until(&stop), Ok(None));
+    assert_eq!(r.len(), 1);
+
+    stop.store(false, Ordering::Release);
+    assert_eq!(r.recv_until(&stop), Ok(Some(2)));
+    drop(s);
+    assert_eq!(r.recv_until(&stop), Err(mpmc::RecvError));
+}
+
+// 只使用no_std下也有的非阻塞API，`cargo test --no-default-features`时覆盖no_std的代码路径
+#[test]
+fn try_send_try_recv_without_blocking_api() {
+    use crate::mpmc::{channel, sync_channel, TryRecvError, TrySendError};
+
+    let (s, r) = sync_channel(2);
+    assert_eq!(s.capacity(), Some(2));
+    s.try_send(1).unwrap();
+    s.try_send(2).unwrap();
+    assert!(s.is_full());
+    assert_eq!(s.try_send(3), Err(TrySendError::Full(3)));
+    assert_eq!(r.len(), 2);
+    assert_eq!(r.try_recv(), Ok(1));
+    assert_eq!(r.try_recv(), Ok(2));
+    assert_eq!(r.try_recv(), Err(TryRecvError::Empty));
+    drop(s);
+    assert!(r.is_disconnected());
+    assert_eq!(r.try_recv(), Err(TryRecvError::Disconnected));
+
+    let (s, mut r) = channel();
+    let s2 = s.clone();
+    for i in 0..100 {
+        s.try_send(i).unwrap();
+    }
+    s2.try_send(100).unwrap();
+    assert_eq!(s.capacity(), None);
+    assert_eq!(r.len(), 101);
+    for i in 0..=100 {
+        assert_eq!(r.try_recv_exclusive(), Ok(i));
+    }
+    assert!(r.is_empty());
+    drop(r);
+    assert!(s.is_disconnected());
+    assert_eq!(s2.try_send(0), Err(TrySendError::Disconnected(0)));
+}
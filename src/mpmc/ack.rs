@@ -0,0 +1,288 @@
+use std::{
+    fmt,
+    ops::Deref,
+    time::{Duration, Instant},
+};
+
+use super::{
+    context::now,
+    counter,
+    errors::*,
+    list,
+    sync::atomic::{AtomicUsize, Ordering},
+    waker::SyncWaker,
+};
+
+/*
+ * 需要确认的channel(at-least-once)：接收得到的是Delivery，处理完之后调用ack确认
+ * 调用nack或者没有ack就drop(比如处理时panic)的Delivery会把msg放回channel，之后由任意一个receiver重新接收
+ * 内部是两个list::Channel：queue保存新发送的msg，redelivery保存被放回的msg，接收时先取redelivery
+ * in_flight是已经被接收但还没有ack/nack的msg数量，它们还可能被放回，所以所有sender断开、
+ * 两个队列都为空并且in_flight为0时，recv才返回Disconnected
+ */
+pub(crate) struct Channel<T> {
+    queue: list::Channel<T>,
+    redelivery: list::Channel<T>,
+    in_flight: AtomicUsize,
+    // 有新的msg、msg被放回、in_flight变为0或者sender断开时通知阻塞的receivers
+    changed: SyncWaker,
+}
+
+impl<T> Channel<T> {
+    fn send(&self, msg: T) -> Result<(), SendError<T>> {
+        self.queue.send(msg)?;
+        self.changed.notify();
+        Ok(())
+    }
+
+    // 先增加in_flight再取msg，其他receiver不会在msg离开队列、还没有计入in_flight时误判为断开
+    fn try_recv(&self) -> Result<(T, bool), TryRecvError> {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        let res = match self.redelivery.try_recv() {
+            Ok(msg) => Ok((msg, true)),
+            Err(_) => self.queue.try_recv().map(|msg| (msg, false)),
+        };
+        match res {
+            Ok(msg) => Ok(msg),
+            Err(_) => {
+                self.finish();
+                if self.is_drained() {
+                    Err(TryRecvError::Disconnected)
+                } else {
+                    Err(TryRecvError::Empty)
+                }
+            }
+        }
+    }
+
+    fn recv(&self, deadline: Option<Instant>) -> Result<(T, bool), RecvTimeoutError> {
+        loop {
+            match self.try_recv() {
+                Ok(msg) => return Ok(msg),
+                Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+                Err(TryRecvError::Empty) => {}
+            }
+            if !self.changed.wait_observed(deadline, || {
+                !self.queue.is_empty() || !self.redelivery.is_empty() || self.is_drained()
+            }) {
+                return Err(RecvTimeoutError::Timeout);
+            }
+        }
+    }
+
+    // 所有sender都已经断开，没有排队的msg，也没有可能被放回的msg
+    fn is_drained(&self) -> bool {
+        self.queue.is_disconnected_and_empty()
+            && self.redelivery.is_empty()
+            && self.in_flight.load(Ordering::SeqCst) == 0
+    }
+
+    // 一个in_flight的msg结束(ack、放回或者没有取到msg)
+    fn finish(&self) {
+        if self.in_flight.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.changed.notify();
+        }
+    }
+
+    // 先放回再减少in_flight，看到in_flight为0的receiver一定也能看到放回的msg
+    fn requeue(&self, msg: T) {
+        // redelivery只在最后一个handle释放时断开，Delivery持有handle，所以这里不会失败
+        let _ = self.redelivery.send(msg);
+        self.changed.notify();
+        self.finish();
+    }
+
+    fn len(&self) -> usize {
+        self.queue.len() + self.redelivery.len()
+    }
+
+    fn disconnect_senders(&self) -> bool {
+        let first = self.queue.disconnect_senders();
+        self.changed.disconnect();
+        first
+    }
+
+    fn disconnect_receivers(&self) -> bool {
+        self.redelivery.disconnect_receivers();
+        self.queue.disconnect_receivers()
+    }
+}
+
+// 创建一个需要确认的无界channel，见Channel
+pub fn ack_channel<T>() -> (AckSender<T>, AckReceiver<T>) {
+    let chan = Channel {
+        queue: list::Channel::new(),
+        redelivery: list::Channel::new(),
+        in_flight: AtomicUsize::new(0),
+        changed: SyncWaker::new(),
+    };
+    let (s, r) = counter::new(chan, None, None, None);
+    (AckSender { chan: s }, AckReceiver { chan: r })
+}
+
+pub struct AckSender<T> {
+    chan: counter::Sender<Channel<T>>,
+}
+
+unsafe impl<T: Send> Send for AckSender<T> {}
+unsafe impl<T: Send> Sync for AckSender<T> {}
+
+impl<T> AckSender<T> {
+    pub fn send(&self, msg: T) -> Result<(), SendError<T>> {
+        self.chan.send(msg)
+    }
+    // 等待接收的msg数量，包括被放回的，不包括in_flight的
+    pub fn len(&self) -> usize {
+        self.chan.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T> Drop for AckSender<T> {
+    fn drop(&mut self) {
+        unsafe {
+            self.chan.release(|c| c.disconnect_senders());
+        }
+    }
+}
+
+impl<T> Clone for AckSender<T> {
+    fn clone(&self) -> Self {
+        AckSender {
+            chan: self.chan.acquire(),
+        }
+    }
+}
+
+impl<T> fmt::Debug for AckSender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("AckSender { .. }")
+    }
+}
+
+pub struct AckReceiver<T> {
+    chan: counter::Receiver<Channel<T>>,
+}
+
+unsafe impl<T: Send> Send for AckReceiver<T> {}
+unsafe impl<T: Send> Sync for AckReceiver<T> {}
+
+impl<T> AckReceiver<T> {
+    fn delivery(&self, (msg, redelivered): (T, bool)) -> Delivery<T> {
+        Delivery {
+            msg: Some(msg),
+            redelivered,
+            chan: self.chan.acquire(),
+        }
+    }
+    pub fn try_recv(&self) -> Result<Delivery<T>, TryRecvError> {
+        self.chan.try_recv().map(|msg| self.delivery(msg))
+    }
+    pub fn recv(&self) -> Result<Delivery<T>, RecvError> {
+        self.chan
+            .recv(None)
+            .map(|msg| self.delivery(msg))
+            .map_err(|_| RecvError)
+    }
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Delivery<T>, RecvTimeoutError> {
+        match now().checked_add(timeout) {
+            Some(deadline) => self.chan.recv(Some(deadline)).map(|msg| self.delivery(msg)),
+            None => self.recv().map_err(RecvTimeoutError::from),
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.chan.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    // 已经被接收但还没有ack/nack的msg数量
+    pub fn in_flight(&self) -> usize {
+        self.chan.in_flight.load(Ordering::SeqCst)
+    }
+}
+
+impl<T> Drop for AckReceiver<T> {
+    fn drop(&mut self) {
+        unsafe {
+            self.chan.release(|c| c.disconnect_receivers());
+        }
+    }
+}
+
+impl<T> Clone for AckReceiver<T> {
+    fn clone(&self) -> Self {
+        AckReceiver {
+            chan: self.chan.acquire(),
+        }
+    }
+}
+
+impl<T> fmt::Debug for AckReceiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("AckReceiver { .. }")
+    }
+}
+
+/*
+ * AckReceiver接收得到的msg，必须调用ack确认，否则drop时msg会被放回channel
+ * Delivery持有一个receiver计数，即使所有AckReceiver都被drop了，channel也要等它结束之后才断开
+ */
+pub struct Delivery<T> {
+    // ack或nack之后为None
+    msg: Option<T>,
+    redelivered: bool,
+    chan: counter::Receiver<Channel<T>>,
+}
+
+unsafe impl<T: Send> Send for Delivery<T> {}
+unsafe impl<T: Sync> Sync for Delivery<T> {}
+
+impl<T> Delivery<T> {
+    // 确认msg已经处理完，取回msg，它不会再被投递
+    pub fn ack(mut self) -> T {
+        let msg = self.msg.take().unwrap();
+        self.chan.finish();
+        msg
+    }
+
+    // 放回channel，之后由任意一个receiver重新接收
+    pub fn nack(self) {
+        drop(self);
+    }
+
+    // 这个msg之前被nack或者没有ack就drop过
+    pub fn is_redelivered(&self) -> bool {
+        self.redelivered
+    }
+}
+
+impl<T> Deref for Delivery<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.msg.as_ref().unwrap()
+    }
+}
+
+impl<T> Drop for Delivery<T> {
+    fn drop(&mut self) {
+        if let Some(msg) = self.msg.take() {
+            self.chan.requeue(msg);
+        }
+        unsafe {
+            self.chan.release(|c| c.disconnect_receivers());
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Delivery<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Delivery")
+            .field("msg", &self.msg)
+            .field("redelivered", &self.redelivered)
+            .finish()
+    }
+}
@@ -1,18 +1,57 @@
-use std::{ptr,cell::UnsafeCell, mem::MaybeUninit, sync::atomic::{self,AtomicUsize,Ordering}, time::Instant};
-use super::context::Context;
+use alloc::boxed::Box;
+use core::{ptr,cell::UnsafeCell, mem::{self, MaybeUninit}};
+use super::sync::atomic::{self,AtomicBool,AtomicUsize,Ordering};
+#[cfg(feature = "std")]
+use std::time::Instant;
+#[cfg(feature = "std")]
+use super::context::{now, Context};
 use super::errors::*;
-use super::select::{Operation, Selected, Token};
-use super::utils::{Backoff, CachePadded};
+use super::select::Token;
+#[cfg(feature = "std")]
+use super::select::{Operation, SelectHandle, Selected};
+#[cfg(feature = "std")]
+use super::waker::Entry;
+#[cfg(feature = "std")]
+use super::utils::UnwindGuard;
+use super::utils::{Backoff, CachePadded, SPIN_LIMIT};
 use super::waker::SyncWaker;
+#[cfg(feature = "metrics")]
+use super::metrics::Metrics;
 
 //有界的channel以预分配内存的array为基础
 
+// notify_senders没能替阻塞的sender写入msg时交给它的packet，不会与任何msg的地址相同
+#[cfg(feature = "std")]
+const NOT_SENT: *mut () = ptr::dangling_mut();
+
+// 最大容量，mark_bit和one_lap都必须放得下，并且head/tail的高位至少还留一位给lap计数
+pub(crate) const MAX_CAPACITY: usize = usize::MAX >> 2;
+
+// 根据容量计算(mark_bit, one_lap)，容量超过MAX_CAPACITY时panic，而不是在计算中溢出
+fn lap_layout(cap: usize) -> (usize, usize) {
+    assert!(
+        cap <= MAX_CAPACITY,
+        "capacity {} exceeds the maximum array channel capacity {}",
+        cap,
+        MAX_CAPACITY
+    );
+    let mark_bit = (cap + 1).next_power_of_two();
+    (mark_bit, mark_bit * 2)
+}
+
 //Channel内部的一个信息的封装
 struct Slot<T> {
-    // 当前的stamp 戳记(TODO:可能为了防止ABA问题)
+    // 当前的stamp 戳记，与head/tail同样是{ lap, mark, index }，lap区分不同圈中的同一个slot，避免ABA
+    // lap用wrapping_add递增，越过usize::MAX之后从0开始，比较只用相等判断，所以回绕不影响正确性
     stamp: AtomicUsize,
     // 代表具体的一个信息
     msg: UnsafeCell<MaybeUninit<T>>,
+    // send_with的f在预留slot之后panic时为true，这个slot没有msg，receiver会直接跳过它
+    // 读写都被stamp的Release/Acquire保护，所以用Relaxed就够了
+    skip: AtomicBool,
+    // 被Sender::reserve预留、还没有写入msg时为true
+    // receiver到达这样的slot时不会自旋等待写入，而是当作channel暂时没有msg
+    reserved: AtomicBool,
 }
 
 // 一些列信息的封装 (channel本身)
@@ -25,7 +64,7 @@ pub(crate) struct ArrayToken {
 impl Default for ArrayToken {
     fn default() -> Self {
         ArrayToken {
-            slot: std::ptr::null(),
+            slot: ptr::null(),
             stamp: 0,
         }
     }
@@ -48,6 +87,15 @@ pub(crate) struct Channel<T> {
     senders: SyncWaker,
     //
     receivers: SyncWaker,
+    // close_when_empty之后为true：不再接受新的msg，取完剩余的msg之后断开
+    closing: AtomicBool,
+    // 带着msg注册在senders中、等待hand_off的sender数量，不为0时新来的sender不自旋抢slot，直接排队
+    #[cfg(feature = "std")]
+    parked: AtomicUsize,
+    // 阻塞的send在注册、park之前用Backoff自旋的上限，见Builder::spin_before_park
+    spin_limit: u32,
+    #[cfg(feature = "metrics")]
+    pub(crate) metrics: Metrics,
 }
 impl<T> Channel<T> {
     /// Creates a bounded channel of capacity `cap`.
@@ -55,8 +103,7 @@ impl<T> Channel<T> {
         assert!(cap > 0, "capacity must be positive");
 
         // Compute constants `mark_bit` and `one_lap`.
-        let mark_bit = (cap + 1).next_power_of_two();
-        let one_lap = mark_bit * 2;
+        let (mark_bit, one_lap) = lap_layout(cap);
 
         // Head is initialized to `{ lap: 0, mark: 0, index: 0 }`.
         let head = 0;
@@ -68,7 +115,12 @@ impl<T> Channel<T> {
         let buffer: Box<[Slot<T>]> = (0..cap)
             .map(|i| {
                 // Set the stamp to `{ lap: 0, mark: 0, index: i }`.
-                Slot { stamp: AtomicUsize::new(i), msg: UnsafeCell::new(MaybeUninit::uninit()) }
+                Slot {
+                    stamp: AtomicUsize::new(i),
+                    msg: UnsafeCell::new(MaybeUninit::uninit()),
+                    skip: AtomicBool::new(false),
+                    reserved: AtomicBool::new(false),
+                }
             })
             .collect();
 
@@ -81,17 +133,30 @@ impl<T> Channel<T> {
             tail: CachePadded::new(AtomicUsize::new(tail)),
             senders: SyncWaker::new(),
             receivers: SyncWaker::new(),
+            closing: AtomicBool::new(false),
+            #[cfg(feature = "std")]
+            parked: AtomicUsize::new(0),
+            spin_limit: SPIN_LIMIT,
+            #[cfg(feature = "metrics")]
+            metrics: Metrics::new(),
         }
     }
 
+    pub(crate) fn spin_before_park(mut self, limit: u32) -> Self {
+        self.spin_limit = limit;
+        self
+    }
+
     /// Attempts to reserve a slot for sending a message.
     fn start_send(&self, token: &mut Token) -> bool {
         let backoff = Backoff::new();
         let mut tail = self.tail.load(Ordering::Relaxed);
+        // 正在关闭的channel对sender来说已经断开
+        let closing = self.closing.load(Ordering::SeqCst);
 
         loop {
             // Check if the channel is disconnected.
-            if tail & self.mark_bit != 0 {
+            if tail & self.mark_bit != 0 || closing {
                 token.array.slot = ptr::null();
                 token.array.stamp = 0;
                 return true;
@@ -132,6 +197,7 @@ impl<T> Channel<T> {
                         return true;
                     }
                     Err(_) => {
+                        token.retry();
                         backoff.spin_light();
                         tail = self.tail.load(Ordering::Relaxed);
                     }
@@ -168,12 +234,47 @@ impl<T> Channel<T> {
         // Write the message into the slot and update the stamp.
         slot.msg.get().write(MaybeUninit::new(msg));
         slot.stamp.store(token.array.stamp, Ordering::Release);
+        #[cfg(feature = "metrics")]
+        self.metrics.on_send(self.len());
 
         // Wake a sleeping receiver.
         self.receivers.notify();
         Ok(())
     }
 
+    // 在已经预留的slot中调用f()写入msg，避免先在栈上构造一个很大的T再移动进来
+    // 如果f panic，guard把slot标记为skip并照常更新stamp，receiver会把它当作已经被读过的slot跳过
+    pub(crate) unsafe fn write_with<F: FnOnce() -> T>(&self, token: &mut Token, f: F) -> Result<(), F> {
+        if token.array.slot.is_null() {
+            return Err(f);
+        }
+
+        let slot: &Slot<T> = &*(token.array.slot as *const Slot<T>);
+
+        struct Guard<'a, T> {
+            chan: &'a Channel<T>,
+            slot: &'a Slot<T>,
+            stamp: usize,
+        }
+        impl<T> Drop for Guard<'_, T> {
+            fn drop(&mut self) {
+                self.slot.skip.store(true, Ordering::Relaxed);
+                self.slot.stamp.store(self.stamp, Ordering::Release);
+                self.chan.receivers.notify();
+            }
+        }
+
+        let guard = Guard { chan: self, slot, stamp: token.array.stamp };
+        slot.msg.get().write(MaybeUninit::new(f()));
+        mem::forget(guard);
+
+        slot.stamp.store(token.array.stamp, Ordering::Release);
+        #[cfg(feature = "metrics")]
+        self.metrics.on_send(self.len());
+        self.receivers.notify();
+        Ok(())
+    }
+
     /// Attempts to reserve a slot for receiving a message.
     fn start_recv(&self, token: &mut Token) -> bool {
         let backoff = Backoff::new();
@@ -202,19 +303,32 @@ impl<T> Channel<T> {
                 };
 
                 // Try moving the head.
-                match self.head.compare_exchange_weak(
-                    head,
-                    new,
-                    Ordering::SeqCst,
-                    Ordering::Relaxed,
-                ) {
+                // 唯一的receiver不会与其他线程竞争head，直接store即可，sender只会读取head
+                let moved = if token.exclusive {
+                    self.head.store(new, Ordering::Release);
+                    Ok(head)
+                } else {
+                    self.head
+                        .compare_exchange_weak(head, new, Ordering::SeqCst, Ordering::Relaxed)
+                };
+                match moved {
                     Ok(_) => {
+                        // send_with的f panic留下的空slot，直接释放给sender然后继续找下一个
+                        if slot.skip.load(Ordering::Relaxed) {
+                            slot.skip.store(false, Ordering::Relaxed);
+                            slot.stamp.store(head.wrapping_add(self.one_lap), Ordering::Release);
+                            self.notify_senders();
+                            head = new;
+                            continue;
+                        }
+
                         // Prepare the token for the follow-up call to `read`.
                         token.array.slot = slot as *const Slot<T> as *const u8;
                         token.array.stamp = head.wrapping_add(self.one_lap);
                         return true;
                     }
                     Err(_) => {
+                        token.retry();
                         backoff.spin_light();
                         head = self.head.load(Ordering::Relaxed);
                     }
@@ -231,12 +345,23 @@ impl<T> Channel<T> {
                         token.array.slot = ptr::null();
                         token.array.stamp = 0;
                         return true;
+                    } else if self.closing.load(Ordering::SeqCst) {
+                        // 正在关闭的channel已经被取空，现在真正断开
+                        self.disconnect();
+                        token.array.slot = ptr::null();
+                        token.array.stamp = 0;
+                        return true;
                     } else {
                         // Otherwise, the receive operation is not ready.
                         return false;
                     }
                 }
 
+                // 被预留的slot可能很久之后才会写入，不在这里自旋
+                if slot.reserved.load(Ordering::SeqCst) {
+                    return false;
+                }
+
                 backoff.spin_light();
                 head = self.head.load(Ordering::Relaxed);
             } else {
@@ -258,16 +383,141 @@ impl<T> Channel<T> {
 
         // Read the message from the slot and update the stamp.
         let msg = slot.msg.get().read().assume_init();
-        slot.stamp.store(token.array.stamp, Ordering::Release);
+        self.release_slot(slot, token.array.stamp);
+        Ok(msg)
+    }
+
+    // 与read相同，但是先把slot中msg的引用交给f，f返回或者panic之后再drop msg并释放slot
+    #[cfg(feature = "std")]
+    pub(crate) unsafe fn read_with<R>(&self, token: &mut Token, f: impl FnOnce(&T) -> R) -> Result<R, ()> {
+        if token.array.slot.is_null() {
+            return Err(());
+        }
+
+        struct Consume<'a, T> {
+            chan: &'a Channel<T>,
+            slot: &'a Slot<T>,
+            stamp: usize,
+        }
+        impl<T> Drop for Consume<'_, T> {
+            fn drop(&mut self) {
+                unsafe {
+                    drop(self.slot.msg.get().read().assume_init());
+                }
+                self.chan.release_slot(self.slot, self.stamp);
+            }
+        }
+
+        let slot: &Slot<T> = &*(token.array.slot as *const Slot<T>);
+        let consume = Consume {
+            chan: self,
+            slot,
+            stamp: token.array.stamp,
+        };
+        let res = f((*slot.msg.get()).assume_init_ref());
+        drop(consume);
+        Ok(res)
+    }
+
+    // msg已经被取走，把slot交还给sender
+    fn release_slot(&self, slot: &Slot<T>, stamp: usize) {
+        slot.stamp.store(stamp, Ordering::Release);
+        #[cfg(feature = "metrics")]
+        self.metrics.on_recv();
 
         // Wake a sleeping sender.
+        self.notify_senders();
+        self.finish_closing();
+    }
+
+    // 正在关闭的channel中最后一个msg被取走时真正断开，唤醒其他阻塞的receiver
+    fn finish_closing(&self) {
+        if self.closing.load(Ordering::SeqCst) && self.is_empty() {
+            self.disconnect();
+        }
+    }
+
+    /*
+     * 进入关闭状态：之后的send立即返回Disconnected，receiver可以继续取出剩余的msg，channel变空时才断开
+     * 阻塞的sender被唤醒后会看到关闭状态并返回Disconnected
+     * 只有把channel从连接状态变为关闭状态的那一次调用返回true
+     */
+    pub(crate) fn close_when_empty(&self) -> bool {
+        if self.closing.swap(true, Ordering::SeqCst) || self.is_disconnected() {
+            return false;
+        }
+        self.senders.disconnect();
+        if self.is_empty() {
+            self.disconnect();
+        }
+        true
+    }
+
+    /*
+     * slot被释放之后唤醒一个阻塞的sender
+     * 如果只是唤醒，sender从park中醒来需要时间，这期间正在自旋的新sender几乎总能抢先拿到slot，
+     * 阻塞的sender可能一直被饿死。所以阻塞在send中的sender注册时把自己的msg作为packet，
+     * 这里按注册的顺序(ticket)选中等待最久的sender，在唤醒它之前直接替它预留slot，
+     * slot不会在等待它醒来的期间被抢走
+     * write会唤醒receiver(可能调用用户的Parker)，所以msg在释放senders的锁之后才写入
+     */
+    fn notify_senders(&self) {
+        #[cfg(feature = "std")]
+        {
+            let mut handed = None;
+            self.senders
+                .notify_oldest_with(|entry| self.hand_off(entry, &mut handed));
+            if let Some((mut token, msg)) = handed {
+                let _ = unsafe { self.write(&mut token, msg) };
+            }
+        }
+        #[cfg(not(feature = "std"))]
         self.senders.notify();
-        Ok(msg)
+    }
+
+    // entry已经被选中，在收到packet之前它不会访问自己的msg
+    // 在senders的锁中只预留slot、取出msg，写入由notify_senders在解锁之后完成
+    // select和send_with注册的操作没有packet，它们醒来后自己完成预留
+    #[cfg(feature = "std")]
+    fn hand_off(&self, entry: &Entry, handed: &mut Option<(Token, T)>) -> *mut () {
+        if entry.packet.is_null() {
+            return ptr::null_mut();
+        }
+        // entry已经从senders中移除，由这里而不是sender自己减少parked
+        self.parked.fetch_sub(1, Ordering::SeqCst);
+        let mut token = Token::default();
+        // slot在释放之后立即被别的sender抢走，或者channel已经断开时，只唤醒它，让它自己重新尝试
+        if !self.start_send(&mut token) || token.array.slot.is_null() {
+            return NOT_SENT;
+        }
+        let msg = unsafe { (*(entry.packet as *mut Option<T>)).take().unwrap() };
+        *handed = Some((token, msg));
+        entry.packet
+    }
+
+    // 阻塞直到channel为空、abandoned返回true或者到了deadline，返回channel是否已经为空
+    // 每次read之后都会notify senders，等待的线程作为senders中的observer被唤醒
+    #[cfg(feature = "std")]
+    pub(crate) fn wait_empty(&self, deadline: Option<Instant>, abandoned: impl Fn() -> bool) -> bool {
+        self.senders
+            .wait_observed(deadline, || self.is_empty() || abandoned());
+        self.is_empty()
     }
 
     /// Attempts to send a message into the channel.
     pub(crate) fn try_send(&self, msg: T) -> Result<(), TrySendError<T>> {
+        self.try_send_token(&mut Token::default(), msg)
+    }
+
+    // 与try_send相同，同时返回start_send中CAS重试的次数
+    #[cfg(feature = "profiling")]
+    pub(crate) fn try_send_profiled(&self, msg: T) -> (Result<(), TrySendError<T>>, u32) {
         let token = &mut Token::default();
+        let res = self.try_send_token(token, msg);
+        (res, token.retries)
+    }
+
+    fn try_send_token(&self, token: &mut Token, msg: T) -> Result<(), TrySendError<T>> {
         if self.start_send(token) {
             unsafe { self.write(token, msg).map_err(TrySendError::Disconnected) }
         } else {
@@ -275,20 +525,102 @@ impl<T> Channel<T> {
         }
     }
 
-    /// Sends a message into the channel.
+    /*
+     * 阻塞发送msg
+     * 与reserve的区别是阻塞时把msg作为packet注册到senders中，notify_senders可以直接把它写入释放出来的slot，
+     * 醒来时msg已经发送完成
+     * spin是发送这个msg的Sender设置的自旋上限(Sender::with_spin)，None时使用channel的spin_limit
+     */
+    #[cfg(feature = "std")]
     pub(crate) fn send(
         &self,
         msg: T,
         deadline: Option<Instant>,
+        spin: Option<u32>,
     ) -> Result<(), SendTimeoutError<T>> {
         let token = &mut Token::default();
+        // 被notify_senders写入channel之后变为None
+        let mut pending = Some(msg);
+        let backoff = Backoff::with_limit(spin.unwrap_or(self.spin_limit));
+        // 第一次注册时领到的ticket，被唤醒但没有拿到slot时带着它重新注册，不会排到后来者后面
+        let mut ticket = None;
+        loop {
+            backoff.reset();
+            // 已经有sender在排队时不抢slot，直接排到它们后面
+            while ticket.is_some() || self.parked.load(Ordering::SeqCst) == 0 {
+                if self.start_send(token) {
+                    let msg = pending.take().unwrap();
+                    return unsafe { self.write(token, msg) }.map_err(SendTimeoutError::Disconnected);
+                }
+
+                if backoff.is_completed() {
+                    break;
+                } else {
+                    backoff.spin_light();
+                }
+            }
+
+            if let Some(d) = deadline {
+                if now() >= d {
+                    return Err(SendTimeoutError::Timeout(pending.take().unwrap()));
+                }
+            }
+
+            let sent = Context::with(|cx| {
+                let packet = &mut pending as *mut Option<T> as *mut ();
+                let oper = Operation::hook(token);
+                self.parked.fetch_add(1, Ordering::SeqCst);
+                ticket = Some(self.senders.register_ticketed(oper, packet, ticket, cx));
+
+                // 被选中之后hand_off在持有waker的锁时就已经处理了这个entry，unregister失败时不需要等待
+                let guard = UnwindGuard::new(|| {
+                    if self.senders.unregister(oper).is_some() {
+                        self.parked.fetch_sub(1, Ordering::SeqCst);
+                    }
+                });
+
+                if !self.is_full() || self.is_disconnected() {
+                    let _ = cx.try_select(Selected::Aborted);
+                }
+
+                let sel = cx.wait_until(deadline);
+                guard.disarm();
+                match sel {
+                    Selected::Waiting => unreachable!(),
+                    Selected::Aborted | Selected::Disconnected => {
+                        self.senders.unregister(oper).unwrap();
+                        self.parked.fetch_sub(1, Ordering::SeqCst);
+                        false
+                    }
+                    // 被notify_senders选中，收到的packet就是自己的msg时说明它已经被写入channel
+                    Selected::Operation(_) => cx.wait_packet() == packet,
+                }
+            });
+            if sent {
+                return Ok(());
+            }
+        }
+    }
+
+    // 阻塞直到预留到一个slot后才调用f生成msg，channel断开时把f原样返回
+    #[cfg(feature = "std")]
+    pub(crate) fn send_with<F: FnOnce() -> T>(&self, f: F, spin: Option<u32>) -> Result<(), F> {
+        let token = &mut Token::default();
+        let reserved = self.reserve(token, None, spin);
+        debug_assert!(reserved);
+        unsafe { self.write_with(token, f) }
+    }
+
+    // 阻塞直到start_send成功(预留到slot或者channel断开)，到达deadline时返回false
+    #[cfg(feature = "std")]
+    fn reserve(&self, token: &mut Token, deadline: Option<Instant>, spin: Option<u32>) -> bool {
+        let backoff = Backoff::with_limit(spin.unwrap_or(self.spin_limit));
         loop {
-            // Try sending a message several times.
-            let backoff = Backoff::new();
+            // Try reserving a slot several times.
+            backoff.reset();
             loop {
                 if self.start_send(token) {
-                    let res = unsafe { self.write(token, msg) };
-                    return res.map_err(SendTimeoutError::Disconnected);
+                    return true;
                 }
 
                 if backoff.is_completed() {
@@ -299,8 +631,8 @@ impl<T> Channel<T> {
             }
 
             if let Some(d) = deadline {
-                if Instant::now() >= d {
-                    return Err(SendTimeoutError::Timeout(msg));
+                if now() >= d {
+                    return false;
                 }
             }
 
@@ -308,6 +640,9 @@ impl<T> Channel<T> {
                 // Prepare for blocking until a receiver wakes us up.
                 let oper = Operation::hook(token);
                 self.senders.register(oper, cx);
+                let guard = UnwindGuard::new(|| {
+                    self.senders.unregister(oper);
+                });
 
                 // Has the channel become ready just now?
                 if !self.is_full() || self.is_disconnected() {
@@ -316,6 +651,7 @@ impl<T> Channel<T> {
 
                 // Block the current thread.
                 let sel = cx.wait_until(deadline);
+                guard.disarm();
 
                 match sel {
                     Selected::Waiting => unreachable!(),
@@ -330,8 +666,26 @@ impl<T> Channel<T> {
 
     /// Attempts to receive a message without blocking.
     pub(crate) fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.try_recv_token(&mut Token::default())
+    }
+
+    // 与try_recv相同，同时返回start_recv中CAS重试的次数
+    #[cfg(feature = "profiling")]
+    pub(crate) fn try_recv_profiled(&self) -> (Result<T, TryRecvError>, u32) {
         let token = &mut Token::default();
+        let res = self.try_recv_token(token);
+        (res, token.retries)
+    }
+
+    /*
+     * 与try_recv相同，但是start_recv不用CAS移动head
+     * Safety: 调用期间不能有其他线程在这个channel上接收
+     */
+    pub(crate) unsafe fn try_recv_exclusive(&self) -> Result<T, TryRecvError> {
+        self.try_recv_token(&mut Token::exclusive())
+    }
 
+    fn try_recv_token(&self, token: &mut Token) -> Result<T, TryRecvError> {
         if self.start_recv(token) {
             unsafe { self.read(token).map_err(|_| TryRecvError::Disconnected) }
         } else {
@@ -340,32 +694,74 @@ impl<T> Channel<T> {
     }
 
     /// Receives a message from the channel.
+    #[cfg(feature = "std")]
     pub(crate) fn recv(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        self.recv_token(&mut Token::default(), deadline)
+    }
+
+    // 与recv相同，Safety同try_recv_exclusive
+    #[cfg(feature = "std")]
+    pub(crate) unsafe fn recv_exclusive(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        self.recv_token(&mut Token::exclusive(), deadline)
+    }
+
+    #[cfg(feature = "std")]
+    fn recv_token(&self, token: &mut Token, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        if !self.start_recv_blocking(token, deadline) {
+            return Err(RecvTimeoutError::Timeout);
+        }
+        unsafe { self.read(token) }.map_err(|_| RecvTimeoutError::Disconnected)
+    }
+
+    /*
+     * 与recv相同，但是msg留在slot中，把它的引用交给f，f返回之后才取走msg并释放slot
+     * f panic时msg仍然会被取走(drop)，slot照常释放
+     */
+    #[cfg(feature = "std")]
+    pub(crate) fn recv_with<R>(&self, deadline: Option<Instant>, f: impl FnOnce(&T) -> R) -> Result<R, RecvTimeoutError> {
         let token = &mut Token::default();
+        if !self.start_recv_blocking(token, deadline) {
+            return Err(RecvTimeoutError::Timeout);
+        }
+        unsafe { self.read_with(token, f) }.map_err(|_| RecvTimeoutError::Disconnected)
+    }
+
+    // 阻塞直到start_recv成功(token中是可以读取的slot或者断开)，到了deadline返回false
+    #[cfg(feature = "std")]
+    fn start_recv_blocking(&self, token: &mut Token, deadline: Option<Instant>) -> bool {
+        // 上一次park是否被sender的notify唤醒
+        let mut woken = false;
         loop {
             if self.start_recv(token) {
-                let res = unsafe { self.read(token) };
-                return res.map_err(|_| RecvTimeoutError::Disconnected);
+                return true;
+            }
+            if woken {
+                #[cfg(feature = "metrics")]
+                self.metrics.on_spurious_wakeup();
             }
 
             if let Some(d) = deadline {
-                if Instant::now() >= d {
-                    return Err(RecvTimeoutError::Timeout);
+                if now() >= d {
+                    return false;
                 }
             }
 
-            Context::with(|cx| {
+            woken = Context::with(|cx| {
                 // Prepare for blocking until a sender wakes us up.
                 let oper = Operation::hook(token);
                 self.receivers.register(oper, cx);
+                let guard = UnwindGuard::new(|| {
+                    self.receivers.unregister(oper);
+                });
 
                 // Has the channel become ready just now?
-                if !self.is_empty() || self.is_disconnected() {
+                if self.is_recv_ready() {
                     let _ = cx.try_select(Selected::Aborted);
                 }
 
                 // Block the current thread.
                 let sel = cx.wait_until(deadline);
+                guard.disarm();
 
                 match sel {
                     Selected::Waiting => unreachable!(),
@@ -373,13 +769,110 @@ impl<T> Channel<T> {
                         self.receivers.unregister(oper).unwrap();
                         // If the channel was disconnected, we still have to check for remaining
                         // messages.
+                        false
                     }
-                    Selected::Operation(_) => {}
+                    Selected::Operation(_) => true,
                 }
             });
         }
     }
 
+    // try_send是否会立即返回(成功或Disconnected)
+    pub(crate) fn is_send_ready(&self) -> bool {
+        !self.is_full() || self.is_disconnected() || self.closing.load(Ordering::SeqCst)
+    }
+
+    // try_recv是否会立即返回(收到msg或Disconnected)
+    // head处是被预留而还没有写入的slot时，即使channel已经断开也要等待它被写入或者放弃
+    pub(crate) fn is_recv_ready(&self) -> bool {
+        (!self.is_empty() || self.is_disconnected()) && !self.head_reserved()
+    }
+
+    fn head_reserved(&self) -> bool {
+        let head = self.head.load(Ordering::SeqCst);
+        let slot = &self.buffer[head & (self.mark_bit - 1)];
+        slot.reserved.load(Ordering::SeqCst) && slot.stamp.load(Ordering::Acquire) != head + 1
+    }
+
+    // pos之后的下一个位置
+    fn next_pos(&self, pos: usize) -> usize {
+        if (pos & (self.mark_bit - 1)) + 1 < self.cap {
+            pos + 1
+        } else {
+            (pos & !(self.one_lap - 1)).wrapping_add(self.one_lap)
+        }
+    }
+
+    /*
+     * 一次预留从tail开始的n个连续的slot，空间不够时返回Full，不会阻塞
+     * 预留的slot按顺序写入，每写入一个就对receiver可见。receiver到达还没有写入的slot时停下来，
+     * 就像channel暂时为空，之后的msg也不能越过它，直到这个slot被写入或者在drop Reservation时被放弃
+     */
+    pub(crate) fn reserve_slots(&self, n: usize) -> Result<Reservation<'_, T>, TrySendError<()>> {
+        if n > self.cap {
+            return Err(TrySendError::Full(()));
+        }
+        let backoff = Backoff::new();
+        let mut tail = self.tail.load(Ordering::SeqCst);
+        'retry: loop {
+            if tail & self.mark_bit != 0 || self.closing.load(Ordering::SeqCst) {
+                return Err(TrySendError::Disconnected(()));
+            }
+
+            // 只有先移动tail才能写入tail之后的slot，所以在CAS成功之前这些slot不会被其他sender占用
+            let mut end = tail;
+            for _ in 0..n {
+                let slot = &self.buffer[end & (self.mark_bit - 1)];
+                if slot.stamp.load(Ordering::Acquire) != end {
+                    let current = self.tail.load(Ordering::SeqCst);
+                    if current == tail {
+                        return Err(TrySendError::Full(()));
+                    }
+                    tail = current;
+                    continue 'retry;
+                }
+                end = self.next_pos(end);
+            }
+
+            match self.tail.compare_exchange_weak(tail, end, Ordering::SeqCst, Ordering::Relaxed) {
+                Ok(_) => {
+                    let mut pos = tail;
+                    for _ in 0..n {
+                        self.buffer[pos & (self.mark_bit - 1)].reserved.store(true, Ordering::SeqCst);
+                        pos = self.next_pos(pos);
+                    }
+                    return Ok(Reservation { chan: self, next: tail, remaining: n });
+                }
+                Err(current) => {
+                    backoff.spin_light();
+                    tail = current;
+                }
+            }
+        }
+    }
+
+    // 丢弃channel中的msg并返回丢弃的数量
+    // 只处理调用时已经在channel中的msg(以len为上限)，并发的sender之后写入的msg不受影响
+    pub(crate) fn drain(&self) -> usize {
+        self.try_recv_batch(self.len(), drop)
+    }
+
+    // 非阻塞地取出最多max条msg依次交给f，channel变空或者断开时提前停止，返回取出的数量
+    pub(crate) fn try_recv_batch(&self, max: usize, mut f: impl FnMut(T)) -> usize {
+        let token = &mut Token::default();
+        let mut count = 0;
+        while count < max && self.start_recv(token) {
+            match unsafe { self.read(token) } {
+                Ok(msg) => {
+                    f(msg);
+                    count += 1;
+                }
+                Err(()) => break,
+            }
+        }
+        count
+    }
+
     /// Returns the current number of messages inside the channel.
     pub(crate) fn len(&self) -> usize {
         loop {
@@ -405,12 +898,38 @@ impl<T> Channel<T> {
         }
     }
 
+    /*
+     * len的wait-free版本，只做一次relaxed的tail/head读取，不重试
+     * 结果可能是过时的，也可能与任何时刻的真实长度都不一致(读取之间head/tail都可能移动)，
+     * 但总在[0, cap]之内，只适合用于监控、日志
+     */
+    pub(crate) fn len_relaxed(&self) -> usize {
+        let tail = self.tail.load(Ordering::Relaxed) & !self.mark_bit;
+        let head = self.head.load(Ordering::Relaxed);
+        let lap_mask = !(self.one_lap - 1);
+        let hix = head & (self.mark_bit - 1);
+        let tix = tail & (self.mark_bit - 1);
+        if tail & lap_mask == head & lap_mask {
+            // head在读取tail之后越过了它
+            tix.saturating_sub(hix)
+        } else if tail & lap_mask == (head & lap_mask).wrapping_add(self.one_lap) {
+            (self.cap - hix + tix).min(self.cap)
+        } else {
+            0
+        }
+    }
+
     /// Returns the capacity of the channel.
     #[allow(clippy::unnecessary_wraps)] // This is intentional.
     pub(crate) fn capacity(&self) -> Option<usize> {
         Some(self.cap)
     }
 
+    // 剩余可写入的slot数量，len本身就是基于同一对head/tail计算的一致快照
+    pub(crate) fn remaining(&self) -> Option<usize> {
+        Some(self.cap - self.len())
+    }
+
     /// Disconnects the channel and wakes up all blocked senders and receivers.
     ///
     /// Returns `true` if this call disconnected the channel.
@@ -426,11 +945,67 @@ impl<T> Channel<T> {
         }
     }
 
+    /*
+     * 最后一个Receiver被drop时调用
+     * channel可能已经被显式断开，这时disconnect不会再唤醒任何线程，
+     * 但是此后channel不可能再变空，wait_empty中的observer也需要被唤醒
+     */
+    pub(crate) fn disconnect_receivers(&self) -> bool {
+        let first = self.disconnect();
+        if !first {
+            // 阻塞在send中的sender也可能被选中，经过hand_off才会把它当作没有发送
+            self.notify_senders();
+        }
+        first
+    }
+
     /// Returns `true` if the channel is disconnected.
     pub(crate) fn is_disconnected(&self) -> bool {
         self.tail.load(Ordering::SeqCst) & self.mark_bit != 0
     }
 
+    // close_when_empty之后或者已经断开时为true，之后不会再有新的msg写入
+    pub(crate) fn is_closing(&self) -> bool {
+        self.closing.load(Ordering::SeqCst) || self.is_disconnected()
+    }
+
+    /*
+     * 不消耗msg，按顺序clone从head到tail之间已经写入的msg
+     * 调用者必须保证没有其他线程在接收，否则正在clone的msg可能被读走并释放
+     * 并发的sender可能已经预留了slot但还没有写入，stamp不等于pos+1的slot会被跳过
+     */
+    #[cfg(feature = "serde")]
+    pub(crate) unsafe fn snapshot(&self) -> Vec<T>
+    where
+        T: Clone,
+    {
+        let mut msgs = Vec::new();
+        let mut pos = self.head.load(Ordering::Acquire);
+        let tail = self.tail.load(Ordering::Acquire) & !self.mark_bit;
+        while pos != tail {
+            let index = pos & (self.mark_bit - 1);
+            let lap = pos & !(self.one_lap - 1);
+            let slot = self.buffer.get_unchecked(index);
+            if slot.stamp.load(Ordering::Acquire) == pos + 1 && !slot.skip.load(Ordering::Relaxed) {
+                msgs.push((*slot.msg.get()).assume_init_ref().clone());
+            }
+            pos = if index + 1 < self.cap {
+                pos + 1
+            } else {
+                lap.wrapping_add(self.one_lap)
+            };
+        }
+        msgs
+    }
+
+    // channel已经断开并且没有剩余的msg，之后再也不会有msg可读
+    // 断开后tail不会再前进，所以只需要一次读取tail和head
+    pub(crate) fn is_disconnected_and_empty(&self) -> bool {
+        let tail = self.tail.load(Ordering::SeqCst);
+        let head = self.head.load(Ordering::SeqCst);
+        tail & self.mark_bit != 0 && (tail & !self.mark_bit) == head
+    }
+
     /// Returns `true` if the channel is empty.
     pub(crate) fn is_empty(&self) -> bool {
         let head = self.head.load(Ordering::SeqCst);
@@ -458,20 +1033,252 @@ impl<T> Channel<T> {
 
 impl<T> Drop for Channel<T> {
     fn drop(&mut self) {
-        // Get the index of the head.
-        let hix = self.head.load(Ordering::Relaxed) & (self.mark_bit - 1);
-
-        // Loop over all slots that hold a message and drop them.
-        for i in 0..self.len() {
-            // Compute the index of the next slot holding a message.
-            let index = if hix + i < self.cap { hix + i } else { hix + i - self.cap };
-
-            unsafe {
-                debug_assert!(index < self.buffer.len());
-                let slot = self.buffer.get_unchecked_mut(index);
-                let msg = &mut *slot.msg.get();
-                msg.as_mut_ptr().drop_in_place();
+        let mut pos = self.head.load(Ordering::Relaxed);
+        let tail = self.tail.load(Ordering::Relaxed) & !self.mark_bit;
+
+        // 从head到tail之间的slot不一定都有msg：被mem::forget的Reservation留下的slot仍然是预留状态，
+        // 既没有写入也没有被标记为skip，所以只drop确实写入了msg(stamp为pos+1)、并且不是skip的slot
+        while pos != tail {
+            let slot = &mut self.buffer[pos & (self.mark_bit - 1)];
+            if slot.stamp.load(Ordering::Relaxed) == pos + 1
+                && !slot.skip.load(Ordering::Relaxed)
+                && !slot.reserved.load(Ordering::Relaxed)
+            {
+                unsafe { slot.msg.get_mut().as_mut_ptr().drop_in_place() };
             }
+            pos = self.next_pos(pos);
         }
     }
 }
+
+// Channel::reserve_slots预留的slot，next是下一个要写入的位置
+pub(crate) struct Reservation<'a, T> {
+    chan: &'a Channel<T>,
+    next: usize,
+    remaining: usize,
+}
+
+impl<T> Reservation<'_, T> {
+    pub(crate) fn remaining(&self) -> usize {
+        self.remaining
+    }
+
+    // 写入下一个预留的slot并让receiver可见，预留的slot已经用完时把msg返回
+    pub(crate) fn push(&mut self, msg: T) -> Result<(), T> {
+        if self.remaining == 0 {
+            return Err(msg);
+        }
+        let slot = self.unreserve();
+        let token = &mut Token::default();
+        token.array.slot = slot as *const Slot<T> as *const u8;
+        token.array.stamp = self.next_stamp();
+        self.advance();
+        unsafe { self.chan.write(token, msg) }
+    }
+
+    // 由f生成msg直接写入下一个预留的slot，f panic时这个slot和后面还没写入的slot都被标记为skip
+    pub(crate) fn push_with<F: FnOnce() -> T>(&mut self, f: F) -> Result<(), F> {
+        if self.remaining == 0 {
+            return Err(f);
+        }
+        let slot = self.unreserve();
+        let token = &mut Token::default();
+        token.array.slot = slot as *const Slot<T> as *const u8;
+        token.array.stamp = self.next_stamp();
+        self.advance();
+        unsafe { self.chan.write_with(token, f) }
+    }
+
+    fn unreserve(&self) -> &Slot<T> {
+        let slot = &self.chan.buffer[self.next & (self.chan.mark_bit - 1)];
+        // 先清除标记再更新stamp，这样receiver看到reserved为false时这个slot最多只是正在写入
+        slot.reserved.store(false, Ordering::SeqCst);
+        slot
+    }
+
+    fn next_stamp(&self) -> usize {
+        self.next + 1
+    }
+
+    fn advance(&mut self) {
+        self.next = self.chan.next_pos(self.next);
+        self.remaining -= 1;
+    }
+}
+
+// 没有写入的slot像send_with中f panic时一样标记为skip，receiver会直接跳过它们
+impl<T> Drop for Reservation<'_, T> {
+    fn drop(&mut self) {
+        if self.remaining == 0 {
+            return;
+        }
+        while self.remaining > 0 {
+            let slot = self.unreserve();
+            slot.skip.store(true, Ordering::Relaxed);
+            slot.stamp.store(self.next_stamp(), Ordering::Release);
+            self.advance();
+        }
+        self.chan.receivers.notify();
+    }
+}
+
+// select使用的句柄，分别代表在这个channel上的接收操作和发送操作
+#[cfg(feature = "std")]
+pub(crate) struct Receiver<'a, T>(&'a Channel<T>);
+#[cfg(feature = "std")]
+pub(crate) struct Sender<'a, T>(&'a Channel<T>);
+
+#[cfg(feature = "std")]
+impl<T> Channel<T> {
+    pub(crate) fn receiver(&self) -> Receiver<'_, T> {
+        Receiver(self)
+    }
+    pub(crate) fn sender(&self) -> Sender<'_, T> {
+        Sender(self)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T> SelectHandle for Receiver<'_, T> {
+    fn try_select(&self, token: &mut Token) -> bool {
+        self.0.start_recv(token)
+    }
+    fn register(&self, oper: Operation, cx: &Context) -> bool {
+        self.0.receivers.register(oper, cx);
+        self.is_ready()
+    }
+    fn unregister(&self, oper: Operation) {
+        self.0.receivers.unregister(oper);
+    }
+    fn accept(&self, token: &mut Token, _cx: &Context) -> bool {
+        self.try_select(token)
+    }
+    fn is_ready(&self) -> bool {
+        self.0.is_recv_ready()
+    }
+    fn watch(&self, oper: Operation, cx: &Context) -> bool {
+        self.0.receivers.watch(oper, cx);
+        self.is_ready()
+    }
+    fn unwatch(&self, oper: Operation) {
+        self.0.receivers.unwatch(oper);
+    }
+}
+
+// read之后会调用senders.notify，所以注册在senders中的select会在有slot空出来时被唤醒
+#[cfg(feature = "std")]
+impl<T> SelectHandle for Sender<'_, T> {
+    fn try_select(&self, token: &mut Token) -> bool {
+        self.0.start_send(token)
+    }
+    fn register(&self, oper: Operation, cx: &Context) -> bool {
+        self.0.senders.register(oper, cx);
+        self.is_ready()
+    }
+    fn unregister(&self, oper: Operation) {
+        self.0.senders.unregister(oper);
+    }
+    fn accept(&self, token: &mut Token, _cx: &Context) -> bool {
+        self.try_select(token)
+    }
+    fn is_ready(&self) -> bool {
+        self.0.is_send_ready()
+    }
+    fn watch(&self, oper: Operation, cx: &Context) -> bool {
+        self.0.senders.watch(oper, cx);
+        self.is_ready()
+    }
+    fn unwatch(&self, oper: Operation) {
+        self.0.senders.unwatch(oper);
+    }
+}
+
+#[test]
+fn lap_layout_accepts_max_capacity() {
+    let (mark_bit, one_lap) = lap_layout(MAX_CAPACITY);
+    assert!(mark_bit > MAX_CAPACITY);
+    assert_eq!(one_lap, mark_bit * 2);
+    // 至少留有一位lap
+    assert!(one_lap.checked_mul(2).is_none());
+    assert_eq!(one_lap.wrapping_add(one_lap), 0);
+}
+
+#[test]
+#[should_panic(expected = "exceeds the maximum array channel capacity")]
+fn lap_layout_rejects_past_max_capacity() {
+    lap_layout(MAX_CAPACITY + 1);
+}
+
+// 把一个空channel的head/tail以及所有slot的stamp移动到第lap圈，用来测试lap计数回绕
+#[cfg(test)]
+fn rewind_to_lap<T>(chan: &Channel<T>, lap: usize) {
+    let start = lap.wrapping_mul(chan.one_lap);
+    chan.head.store(start, Ordering::SeqCst);
+    chan.tail.store(start, Ordering::SeqCst);
+    for (i, slot) in chan.buffer.iter().enumerate() {
+        slot.stamp.store(start + i, Ordering::SeqCst);
+    }
+}
+
+#[test]
+fn lap_counter_wraps_without_loss_or_duplication() {
+    for cap in [1, 3, 4] {
+        let chan = Channel::with_capacity(cap);
+        let laps = usize::MAX / chan.one_lap;
+        rewind_to_lap(&chan, laps - 2);
+        let start = chan.tail.load(Ordering::SeqCst);
+        let (mut sent, mut recvd) = (0usize, 0usize);
+        // 每轮写入的数量不同，让head/tail在回绕前后处于各种相对位置
+        for round in 0..200 {
+            for _ in 0..round % (cap + 1) {
+                chan.try_send(sent).unwrap();
+                sent += 1;
+            }
+            assert_eq!(chan.len(), sent - recvd);
+            assert_eq!(chan.len_relaxed(), sent - recvd);
+            if sent - recvd == cap {
+                assert!(chan.is_full());
+                assert!(matches!(chan.try_send(usize::MAX), Err(TrySendError::Full(_))));
+            }
+            while let Ok(msg) = chan.try_recv() {
+                assert_eq!(msg, recvd);
+                recvd += 1;
+            }
+            assert!(chan.is_empty());
+        }
+        // 确实越过了usize::MAX
+        assert!(chan.tail.load(Ordering::SeqCst) < start);
+    }
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn lap_counter_wraps_under_contention() {
+    struct Shared<'a>(&'a Channel<usize>);
+    unsafe impl Sync for Shared<'_> {}
+
+    const THREADS: usize = 4;
+    const PER_THREAD: usize = 2_000;
+    let chan = Channel::with_capacity(2);
+    let laps = usize::MAX / chan.one_lap;
+    rewind_to_lap(&chan, laps - 8);
+    let shared = Shared(&chan);
+    let mut seen = vec![false; THREADS * PER_THREAD];
+    std::thread::scope(|scope| {
+        for t in 0..THREADS {
+            let shared = &shared;
+            scope.spawn(move || {
+                for i in 0..PER_THREAD {
+                    shared.0.send(t * PER_THREAD + i, None, None).unwrap();
+                }
+            });
+        }
+        for _ in 0..THREADS * PER_THREAD {
+            let msg = shared.0.recv(None).unwrap();
+            assert!(!seen[msg], "duplicated {}", msg);
+            seen[msg] = true;
+        }
+    });
+    assert!(seen.iter().all(|&s| s));
+    assert!(chan.try_recv().is_err());
+}
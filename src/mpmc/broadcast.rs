@@ -0,0 +1,394 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    sync::{Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+
+use super::{
+    context::{now, Context},
+    counter,
+    errors::*,
+    select::{Operation, Selected, Token},
+    waker::Waker,
+};
+
+/*
+ * 广播channel：每个receiver都能收到每一条msg，而不是多个receiver竞争同一条msg
+ * 每个receiver有自己的读取位置(cursor)，一条msg只有在所有receiver都读过之后才会被释放，
+ * 需要在所有cursor中找最小值，所以和zero channel一样用Mutex保护内部状态，阻塞和唤醒仍然使用Waker
+ * 没有复用list channel的block：block的释放需要在所有receiver的head都越过它之后进行，
+ * 每次读取都要比较全部cursor，无锁的实现仍然绕不开这一步，而DropOldest还需要同时移动所有落后的cursor
+ * msg的clone在持有锁时进行，clone panic不会修改任何状态，所以锁被poison之后直接取回guard继续使用
+ * 缓存满时的行为由Overflow决定：阻塞sender，或者丢弃最旧的msg(慢的receiver会跳过这些msg)
+ */
+
+// 缓存满时sender的行为
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    // 阻塞sender，直到最慢的receiver读取了最旧的msg
+    Block,
+    // 丢弃最旧的msg，还没有读取它的receiver会直接跳过
+    DropOldest,
+}
+
+struct Inner<T> {
+    // 缓存的msg，queue[0]的序号是head
+    queue: VecDeque<T>,
+    head: usize,
+    // 每个receiver下一条要读取的msg的序号，None表示这个位置空闲
+    cursors: Vec<Option<usize>>,
+    // 等待缓存空出位置的sender
+    senders: Waker,
+    // 等待新msg的receiver
+    receivers: Waker,
+    is_disconnected: bool,
+}
+
+impl<T> Inner<T> {
+    // 序号在所有cursor之前的msg都已经被读过，可以释放
+    fn reclaim(&mut self) {
+        let min = self.cursors.iter().flatten().min().copied();
+        let end = min.unwrap_or(self.head + self.queue.len());
+        while self.head < end {
+            let Some(msg) = self.queue.pop_front() else {
+                break;
+            };
+            // 先更新head再drop，drop panic时状态仍然是一致的
+            self.head += 1;
+            drop(msg);
+        }
+    }
+
+    // 唤醒所有等待的线程，它们醒来后自己重新检查状态
+    fn wake_all(waker: &mut Waker) {
+        while waker.try_select().is_some() {}
+    }
+}
+
+pub(crate) struct Channel<T> {
+    inner: Mutex<Inner<T>>,
+    cap: usize,
+    overflow: Overflow,
+}
+
+impl<T> Channel<T> {
+    // 与zero channel的lock相同，T::clone或者T::drop在持有锁时panic不会让channel不可用
+    fn lock(&self) -> MutexGuard<'_, Inner<T>> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn new(cap: usize, overflow: Overflow) -> Self {
+        assert!(cap > 0, "capacity must be positive");
+        Channel {
+            inner: Mutex::new(Inner {
+                queue: VecDeque::with_capacity(cap),
+                head: 0,
+                cursors: vec![Some(0)],
+                senders: Waker::new(),
+                receivers: Waker::new(),
+                is_disconnected: false,
+            }),
+            cap,
+            overflow,
+        }
+    }
+
+    // 在持有锁的情况下写入msg，缓存满并且是Overflow::Block时原样返回msg
+    fn push(&self, inner: &mut Inner<T>, msg: T) -> Result<(), T> {
+        let mut oldest = None;
+        if inner.queue.len() == self.cap {
+            if self.overflow == Overflow::Block {
+                return Err(msg);
+            }
+            oldest = inner.queue.pop_front();
+            inner.head += 1;
+            let head = inner.head;
+            for cursor in inner.cursors.iter_mut().flatten() {
+                *cursor = (*cursor).max(head);
+            }
+        }
+        inner.queue.push_back(msg);
+        Inner::<T>::wake_all(&mut inner.receivers);
+        // 状态更新完之后才drop被丢弃的msg，drop panic时状态仍然是一致的
+        drop(oldest);
+        Ok(())
+    }
+
+    fn send(&self, mut msg: T, deadline: Option<Instant>) -> Result<(), SendTimeoutError<T>> {
+        let token = &mut Token::default();
+        loop {
+            let mut inner = self.lock();
+            if inner.is_disconnected {
+                return Err(SendTimeoutError::Disconnected(msg));
+            }
+            match self.push(&mut inner, msg) {
+                Ok(()) => return Ok(()),
+                Err(m) => msg = m,
+            }
+
+            if let Some(d) = deadline {
+                if now() >= d {
+                    return Err(SendTimeoutError::Timeout(msg));
+                }
+            }
+
+            Context::with(|cx| {
+                // 持有锁时注册，receiver只有拿到锁才能释放位置，所以不会丢失唤醒
+                let oper = Operation::hook(token);
+                inner.senders.register(oper, cx);
+                drop(inner);
+
+                let sel = cx.wait_until(deadline);
+
+                match sel {
+                    Selected::Waiting => unreachable!(),
+                    Selected::Aborted | Selected::Disconnected => {
+                        self.lock().senders.unregister(oper).unwrap();
+                    }
+                    Selected::Operation(_) => {}
+                }
+            });
+        }
+    }
+
+    fn try_send(&self, msg: T) -> Result<(), TrySendError<T>> {
+        let mut inner = self.lock();
+        if inner.is_disconnected {
+            return Err(TrySendError::Disconnected(msg));
+        }
+        self.push(&mut inner, msg).map_err(TrySendError::Full)
+    }
+
+    fn disconnect(&self) -> bool {
+        let mut inner = self.lock();
+        if !inner.is_disconnected {
+            inner.is_disconnected = true;
+            inner.senders.disconnect();
+            inner.receivers.disconnect();
+            true
+        } else {
+            false
+        }
+    }
+
+    // 为新的receiver分配一个cursor，位置由pos根据当前状态决定，返回它的id
+    // 读取位置和插入cursor在同一次加锁中完成，避免中间有msg被DropOldest丢弃导致cursor落在head之前
+    fn add_cursor(&self, pos: impl FnOnce(&Inner<T>) -> usize) -> usize {
+        let mut inner = self.lock();
+        let pos = Some(pos(&inner));
+        match inner.cursors.iter().position(Option::is_none) {
+            Some(id) => {
+                inner.cursors[id] = pos;
+                id
+            }
+            None => {
+                inner.cursors.push(pos);
+                inner.cursors.len() - 1
+            }
+        }
+    }
+
+    fn remove_cursor(&self, id: usize) {
+        let mut inner = self.lock();
+        inner.cursors[id] = None;
+        let len = inner.queue.len();
+        inner.reclaim();
+        if inner.queue.len() < len {
+            Inner::<T>::wake_all(&mut inner.senders);
+        }
+    }
+
+    fn pending(&self, id: usize) -> usize {
+        let inner = self.lock();
+        inner.head + inner.queue.len() - inner.cursors[id].unwrap()
+    }
+}
+
+impl<T: Clone> Channel<T> {
+    fn try_recv(&self, id: usize) -> Result<T, TryRecvError> {
+        let mut inner = self.lock();
+        let pos = inner.cursors[id].unwrap();
+        match inner.queue.get(pos - inner.head) {
+            Some(msg) => {
+                let msg = msg.clone();
+                inner.cursors[id] = Some(pos + 1);
+                // 如果这是最慢的receiver，最旧的msg可以释放了
+                if pos == inner.head {
+                    inner.reclaim();
+                    Inner::<T>::wake_all(&mut inner.senders);
+                }
+                Ok(msg)
+            }
+            None if inner.is_disconnected => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    fn recv(&self, id: usize, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        let token = &mut Token::default();
+        loop {
+            match self.try_recv(id) {
+                Ok(msg) => return Ok(msg),
+                Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+                Err(TryRecvError::Empty) => {}
+            }
+
+            if let Some(d) = deadline {
+                if now() >= d {
+                    return Err(RecvTimeoutError::Timeout);
+                }
+            }
+
+            Context::with(|cx| {
+                let oper = Operation::hook(token);
+                let mut inner = self.lock();
+                // 加锁之后重新检查，避免在try_recv和register之间错过新的msg
+                let pos = inner.cursors[id].unwrap();
+                if pos < inner.head + inner.queue.len() || inner.is_disconnected {
+                    return;
+                }
+                inner.receivers.register(oper, cx);
+                drop(inner);
+
+                let sel = cx.wait_until(deadline);
+
+                match sel {
+                    Selected::Waiting => unreachable!(),
+                    Selected::Aborted | Selected::Disconnected => {
+                        self.lock().receivers.unregister(oper).unwrap();
+                    }
+                    Selected::Operation(_) => {}
+                }
+            });
+        }
+    }
+}
+
+// 创建一个容量为cap的广播channel，clone出来的每个BroadcastReceiver都会收到之后的每一条msg
+pub fn broadcast<T: Clone>(cap: usize, overflow: Overflow) -> (BroadcastSender<T>, BroadcastReceiver<T>) {
+    let (s, r) = counter::new(Channel::new(cap, overflow), None, None, None);
+    (BroadcastSender { chan: s }, BroadcastReceiver { chan: r, id: 0 })
+}
+
+pub struct BroadcastSender<T> {
+    chan: counter::Sender<Channel<T>>,
+}
+
+unsafe impl<T: Send> Send for BroadcastSender<T> {}
+unsafe impl<T: Send> Sync for BroadcastSender<T> {}
+
+impl<T> BroadcastSender<T> {
+    // 发送msg，Overflow::Block时缓存满了会阻塞，直到最慢的receiver跟上
+    pub fn send(&self, msg: T) -> Result<(), SendError<T>> {
+        self.chan.send(msg, None).map_err(|err| match err {
+            SendTimeoutError::Disconnected(msg) => SendError(msg),
+            SendTimeoutError::Timeout(_) => unreachable!(),
+        })
+    }
+    pub fn send_timeout(&self, msg: T, timeout: Duration) -> Result<(), SendTimeoutError<T>> {
+        match now().checked_add(timeout) {
+            Some(deadline) => self.chan.send(msg, Some(deadline)),
+            None => self.send(msg).map_err(SendTimeoutError::from),
+        }
+    }
+    pub fn try_send(&self, msg: T) -> Result<(), TrySendError<T>> {
+        self.chan.try_send(msg)
+    }
+    pub fn receiver_count(&self) -> usize {
+        self.chan.receiver_count()
+    }
+}
+
+impl<T> Drop for BroadcastSender<T> {
+    fn drop(&mut self) {
+        unsafe {
+            self.chan.release(|c| c.disconnect());
+        }
+    }
+}
+
+impl<T> Clone for BroadcastSender<T> {
+    fn clone(&self) -> Self {
+        BroadcastSender {
+            chan: self.chan.acquire(),
+        }
+    }
+}
+
+impl<T> fmt::Debug for BroadcastSender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("BroadcastSender { .. }")
+    }
+}
+
+pub struct BroadcastReceiver<T> {
+    chan: counter::Receiver<Channel<T>>,
+    // 在Channel::cursors中的位置
+    id: usize,
+}
+
+unsafe impl<T: Send> Send for BroadcastReceiver<T> {}
+unsafe impl<T: Send> Sync for BroadcastReceiver<T> {}
+
+impl<T: Clone> BroadcastReceiver<T> {
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.chan.try_recv(self.id)
+    }
+    pub fn recv(&self) -> Result<T, RecvError> {
+        self.chan.recv(self.id, None).map_err(|_| RecvError)
+    }
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        match now().checked_add(timeout) {
+            Some(deadline) => self.chan.recv(self.id, Some(deadline)),
+            None => self.recv().map_err(RecvTimeoutError::from),
+        }
+    }
+}
+
+impl<T> BroadcastReceiver<T> {
+    /*
+     * 创建一个从当前最新位置开始的receiver，它只会收到之后发送的msg，
+     * 而clone出来的receiver会从当前receiver的位置开始，包括这个receiver还没有读取的msg
+     */
+    pub fn subscribe(&self) -> BroadcastReceiver<T> {
+        let id = self.chan.add_cursor(|inner| inner.head + inner.queue.len());
+        BroadcastReceiver {
+            chan: self.chan.acquire(),
+            id,
+        }
+    }
+    // 这个receiver还没有读取的msg数量
+    pub fn len(&self) -> usize {
+        self.chan.pending(self.id)
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T> Drop for BroadcastReceiver<T> {
+    fn drop(&mut self) {
+        self.chan.remove_cursor(self.id);
+        unsafe {
+            self.chan.release(|c| c.disconnect());
+        }
+    }
+}
+
+// clone出来的receiver从当前receiver的位置开始，之后两者各自独立地收到每一条msg
+impl<T> Clone for BroadcastReceiver<T> {
+    fn clone(&self) -> Self {
+        let id = self.chan.add_cursor(|inner| inner.cursors[self.id].unwrap());
+        BroadcastReceiver {
+            chan: self.chan.acquire(),
+            id,
+        }
+    }
+}
+
+impl<T> fmt::Debug for BroadcastReceiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("BroadcastReceiver { .. }")
+    }
+}
@@ -0,0 +1,149 @@
+use std::{fmt, io};
+
+use super::{errors::*, Receiver, Sender};
+
+/*
+ * 把传输字节的channel适配成std::io::Read/Write，msg类型是u8或者Vec<u8>
+ * ByteReceiver在channel为空时阻塞，断开并且取空之后read返回Ok(0)，也就是EOF；
+ * Vec<u8>的msg比读缓冲区大时，没读完的部分留到下一次read。空的Vec<u8>不会被当成EOF，直接跳过
+ * ByteSender每次write发送一个msg(u8时每个字节一个msg)，receiver已经断开时返回BrokenPipe
+ */
+pub struct ByteReceiver<T> {
+    receiver: Receiver<T>,
+    // 上一个msg中还没有被读取的字节，只有Vec<u8>会用到
+    pending: Vec<u8>,
+    pos: usize,
+}
+
+impl<T> ByteReceiver<T> {
+    pub fn new(receiver: Receiver<T>) -> Self {
+        ByteReceiver {
+            receiver,
+            pending: Vec::new(),
+            pos: 0,
+        }
+    }
+
+    pub fn get_ref(&self) -> &Receiver<T> {
+        &self.receiver
+    }
+
+    // 还没有被读取的字节会被丢弃
+    pub fn into_inner(self) -> Receiver<T> {
+        self.receiver
+    }
+}
+
+impl io::Read for ByteReceiver<Vec<u8>> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        while self.pos == self.pending.len() {
+            match self.receiver.recv() {
+                Ok(msg) => {
+                    self.pending = msg;
+                    self.pos = 0;
+                }
+                Err(RecvError) => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.pending.len() - self.pos);
+        buf[..n].copy_from_slice(&self.pending[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+impl io::Read for ByteReceiver<u8> {
+    // 阻塞等待第一个字节，之后只取已经在channel中的字节，不会为了填满buf而继续阻塞
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let Some((first, rest)) = buf.split_first_mut() else {
+            return Ok(0);
+        };
+        match self.receiver.recv() {
+            Ok(byte) => *first = byte,
+            Err(RecvError) => return Ok(0),
+        }
+        let mut n = 1;
+        for slot in rest {
+            match self.receiver.try_recv() {
+                Ok(byte) => *slot = byte,
+                Err(_) => break,
+            }
+            n += 1;
+        }
+        Ok(n)
+    }
+}
+
+impl<T> fmt::Debug for ByteReceiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ByteReceiver")
+            .field("receiver", &self.receiver)
+            .field("pending", &(self.pending.len() - self.pos))
+            .finish()
+    }
+}
+
+pub struct ByteSender<T> {
+    sender: Sender<T>,
+}
+
+impl<T> ByteSender<T> {
+    pub fn new(sender: Sender<T>) -> Self {
+        ByteSender { sender }
+    }
+
+    pub fn get_ref(&self) -> &Sender<T> {
+        &self.sender
+    }
+
+    pub fn into_inner(self) -> Sender<T> {
+        self.sender
+    }
+}
+
+fn broken_pipe() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "channel receivers disconnected")
+}
+
+impl io::Write for ByteSender<Vec<u8>> {
+    // 空的buf不发送msg，receiver那边不会收到空的Vec<u8>
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.sender.send(buf.to_vec()).map_err(|_| broken_pipe())?;
+        Ok(buf.len())
+    }
+
+    // 已经write的字节都已经在channel中，没有需要flush的缓冲
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl io::Write for ByteSender<u8> {
+    // 中途断开时返回已经发送的字节数，一个字节都没发出去时才返回BrokenPipe
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for (i, &byte) in buf.iter().enumerate() {
+            if self.sender.send(byte).is_err() {
+                return if i == 0 { Err(broken_pipe()) } else { Ok(i) };
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<T> fmt::Debug for ByteSender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ByteSender")
+            .field("sender", &self.sender)
+            .finish()
+    }
+}
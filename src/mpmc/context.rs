@@ -1,44 +1,206 @@
 // 线程本地的channel上下文
 use std::{
-    cell::Cell,
+    cell::{Cell, RefCell},
+    fmt,
     ptr,
-    sync::{
-        atomic::{AtomicPtr, AtomicUsize, Ordering},
-        Arc,
-    },
-    thread::{self, Thread},
-    time::Instant,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+#[cfg(not(loom))]
+use super::sync::thread::Thread;
+use super::sync::{
+    atomic::{AtomicPtr, AtomicUsize, Ordering},
+    thread,
+    thread_local,
 };
 
 use super::select::Selected;
+use super::utils::Backoff;
 use super::waker::current_thread_id;
+/*
+ * 阻塞线程的方式，默认使用thread::park/unpark
+ * 在自定义的调度器(例如fiber或者runtime)中，可以通过with_parker替换当前线程阻塞和唤醒的方式
+ * park和park_timeout只会被拥有这个Parker的线程调用，unpark可能被任意线程调用，
+ * 与thread::park一样允许虚假返回，但是不能丢失在park之前调用的unpark
+ */
+pub trait Parker: Send + Sync {
+    fn park(&self);
+    fn park_timeout(&self, timeout: Duration);
+    fn unpark(&self);
+}
+
+// 默认的Parker
+#[cfg(not(loom))]
+struct ThreadParker(Thread);
+
+#[cfg(not(loom))]
+impl ThreadParker {
+    fn current() -> Self {
+        ThreadParker(thread::current())
+    }
+}
+
+#[cfg(not(loom))]
+impl Parker for ThreadParker {
+    fn park(&self) {
+        thread::park();
+    }
+    fn park_timeout(&self, timeout: Duration) {
+        thread::park_timeout(timeout);
+    }
+    fn unpark(&self) {
+        self.0.unpark();
+    }
+}
+
+// loom的unpark会把阻塞在Mutex上的线程也变成可运行的，所以loom下用Notify模拟park/unpark
+#[cfg(loom)]
+struct ThreadParker(loom::sync::Notify);
+
+#[cfg(loom)]
+impl ThreadParker {
+    fn current() -> Self {
+        ThreadParker(loom::sync::Notify::new())
+    }
+}
+
+#[cfg(loom)]
+impl Parker for ThreadParker {
+    fn park(&self) {
+        self.0.wait();
+    }
+    // loom不模拟时间，带超时的park当作一次虚假返回
+    fn park_timeout(&self, _timeout: Duration) {
+        thread::yield_now();
+    }
+    fn unpark(&self) {
+        self.0.notify();
+    }
+}
+
+/*
+ * 计算超时使用的时钟，默认是Instant::now
+ * 通过with_clock可以换成虚拟的时间，再配合一个在park_timeout中推进虚拟时间的Parker，
+ * 阻塞操作的超时就会在确定的时刻触发，测试不需要真的等待
+ * 只有deadline的计算和检查使用这个时钟，try_send_for的自旋时长和recv_metered的计时仍然是真实时间
+ */
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+}
+
+impl<C: Clock + ?Sized> Clock for Arc<C> {
+    fn now(&self) -> Instant {
+        (**self).now()
+    }
+}
+
+thread_local! {
+    static CONTEXT: Cell<Option<Context>> = Cell::new(Some(Context::new()));
+    static CLOCK: RefCell<Option<Box<dyn Clock>>> = RefCell::new(None);
+    // with_yield设置的yield_now，存在时无限等待的wait_until调用它代替park
+    static YIELD_NOW: Cell<Option<*const (dyn Fn() + 'static)>> = Cell::new(None);
+}
+
+// 在f执行期间，当前线程上计算超时使用clock，f返回(或者panic)之后恢复原来的时钟
+pub fn with_clock<C, R>(clock: C, f: impl FnOnce() -> R) -> R
+where
+    C: Clock + 'static,
+{
+    struct Restore(Option<Box<dyn Clock>>);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            let prev = self.0.take();
+            let _ = CLOCK.try_with(|cell| *cell.borrow_mut() = prev);
+        }
+    }
+
+    let _restore = Restore(CLOCK.with(|cell| cell.borrow_mut().replace(Box::new(clock))));
+    f()
+}
+
+// 当前线程的时钟给出的当前时间
+pub(crate) fn now() -> Instant {
+    CLOCK
+        .try_with(|cell| cell.borrow().as_ref().map(|clock| clock.now()))
+        .ok()
+        .flatten()
+        .unwrap_or_else(Instant::now)
+}
+
+// 在f执行期间，当前线程上的channel操作使用parker阻塞和唤醒，f返回(或者panic)之后恢复原来的方式
+pub fn with_parker<P, R>(parker: P, f: impl FnOnce() -> R) -> R
+where
+    P: Parker + 'static,
+{
+    struct Restore(Option<Context>);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            let prev = self.0.take();
+            let _ = CONTEXT.try_with(|cell| cell.set(prev));
+        }
+    }
+
+    let cx = Context::with_parker_box(Box::new(parker));
+    let _restore = Restore(CONTEXT.with(|cell| cell.replace(Some(cx))));
+    f()
+}
+
+/*
+ * 在f执行期间，当前线程上无限等待的channel操作调用yield_now代替Parker::park，
+ * 唤醒仍然是Waker选中Entry之后调用cx.unpark()，经过当前的Parker(默认是Thread::unpark，
+ * 或者with_parker设置的Parker)到达调用者的调度器，yield_now可以阻塞到unpark到来，也可以直接返回
+ * yield_now返回时还没有被选中就放弃这次等待(Selected::Aborted)，由调用者重新尝试：
+ * Waker不会把同一个线程上的sender和receiver配对，同一个线程上的其他任务发送msg时不会选中这里
+ */
+pub(crate) fn with_yield<R>(yield_now: &dyn Fn(), f: impl FnOnce() -> R) -> R {
+    struct Restore(Option<*const (dyn Fn() + 'static)>);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            let prev = self.0;
+            let _ = YIELD_NOW.try_with(|cell| cell.set(prev));
+        }
+    }
+
+    let yield_now: *const (dyn Fn() + '_) = yield_now;
+    // Safety: 指针只在f执行期间可见，f返回(或者panic)之后Restore恢复原来的值，不会悬垂
+    let yield_now: *const (dyn Fn() + 'static) = unsafe { std::mem::transmute(yield_now) };
+    let _restore = Restore(YIELD_NOW.with(|cell| cell.replace(Some(yield_now))));
+    f()
+}
+
 //线程本地上下文
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct Context {
     inner: Arc<Inner>,
 }
 
 //代表Context
-#[derive(Debug)]
 struct Inner {
     // Selected操作
     select: AtomicUsize,
     // 这是一个Slot，其他线程可能将packet指针存入其中
     packet: AtomicPtr<()>,
-    thread: Thread,
+    // 阻塞和唤醒这个线程的方式
+    parker: Box<dyn Parker>,
     thread_id: usize,
 }
 
+impl fmt::Debug for Context {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Context")
+            .field("select", &self.selected())
+            .field("thread_id", &self.inner.thread_id)
+            .finish()
+    }
+}
+
 impl Context {
     // 在闭包中创建一个新的上下文
     pub fn with<F, R>(f: F) -> R
     where
         F: FnOnce(&Context) -> R,
     {
-        thread_local! {
-            static CONTEXT:Cell<Option<Context>>= Cell::new(Some(Context::new()));
-        }
-
         let mut f = Some(f);
         let mut f = |cx: &Context| -> R {
             let f = f.take().unwrap();
@@ -60,11 +222,15 @@ impl Context {
 
     #[cold]
     fn new() -> Context {
+        Context::with_parker_box(Box::new(ThreadParker::current()))
+    }
+
+    fn with_parker_box(parker: Box<dyn Parker>) -> Context {
         Context {
             inner: Arc::new(Inner {
                 select: AtomicUsize::new(Selected::Waiting.into()),
                 packet: AtomicPtr::new(ptr::null_mut()),
-                thread: thread::current(),
+                parker,
                 thread_id: current_thread_id(),
             }),
         }
@@ -94,6 +260,12 @@ impl Context {
             .map_err(|e| e.into())
     }
 
+    // 当前的Selected状态
+    #[inline]
+    pub fn selected(&self) -> Selected {
+        Selected::from(self.inner.select.load(Ordering::Acquire))
+    }
+
     // 存入一个packet
     // 这个方法必须被try_select方法成功并且提供一个packet后被调用
     #[inline]
@@ -101,8 +273,28 @@ impl Context {
         self.inner.packet.store(packet, Ordering::Release);
     }
 
-    // 等待直到一个操作被select并返回它
-    // 如果到了deadline，Selected::Aborted会被select
+    // 等待直到packet被存入并返回它
+    // select被唤醒之后，唤醒它的线程可能还没来得及调用store_packet
+    #[inline]
+    pub fn wait_packet(&self) -> *mut () {
+        let backoff = Backoff::new();
+        loop {
+            let packet = self.inner.packet.load(Ordering::Acquire);
+            if !packet.is_null() {
+                return packet;
+            }
+            backoff.spin_heavy();
+        }
+    }
+
+    /*
+     * 等待直到一个操作被select并返回它
+     * 如果到了deadline，Selected::Aborted会被select
+     * 不会丢失唤醒：唤醒方总是先用try_select(AcqRel的CAS)写入select，再调用unpark，
+     * 这里先用Acquire读取select，读到Waiting之后才park。如果unpark发生在读取和park之间，
+     * unpark留下的token会让随后的park立即返回，下一轮循环就能读到新的select；
+     * park/park_timeout的虚假返回也只会让循环重新检查一次select
+     */
     #[inline]
     pub fn wait_until(&self, deadline: Option<Instant>) -> Selected {
         loop{
@@ -112,25 +304,35 @@ impl Context {
                 return sel;
             }
             // deadline和无限park
+            // 剩余时间用checked_duration_since计算，不直接做Instant的减法，
+            // 即使deadline非常接近Instant能表示的上限也不会panic，park_timeout本身会处理过大的时长
             if let Some(end)=deadline{
-                let now=Instant::now();
-                if now<end{
-                    thread::park_timeout(end-now);
-                }else{
-                    return match self.try_select(Selected::Aborted) {
-                        Ok(()) => Selected::Aborted,
-                        Err(s) => s,
-                    };
+                match end.checked_duration_since(now()) {
+                    Some(timeout) if !timeout.is_zero() => self.inner.parker.park_timeout(timeout),
+                    _ => {
+                        return match self.try_select(Selected::Aborted) {
+                            Ok(()) => Selected::Aborted,
+                            Err(s) => s,
+                        };
+                    }
                 }
+            }else if let Some(yield_now)=YIELD_NOW.try_with(|cell| cell.take()).ok().flatten(){
+                // 调用期间取消设置，yield_now中同一个线程上其他任务的阻塞操作照常park
+                unsafe { (*yield_now)() };
+                let _ = YIELD_NOW.try_with(|cell| cell.set(Some(yield_now)));
+                return match self.try_select(Selected::Aborted) {
+                    Ok(()) => Selected::Aborted,
+                    Err(s) => s,
+                };
             }else{
-                thread::park();
+                self.inner.parker.park();
             }
         }
     }
 
     #[inline]
     pub fn unpark(&self) {
-        self.inner.thread.unpark();
+        self.inner.parker.unpark();
     }
 
     #[inline]
@@ -1,7 +1,9 @@
-use std::{
-    ops,
-    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
-};
+use alloc::{alloc::{dealloc, Layout}, boxed::Box, sync::Arc};
+use core::{ops, ptr};
+use super::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use super::event::RecvEvent;
+use super::utils::abort;
 
 /*
  * Receiver和Sender非常像Arc的实现，其内部的Counter中的chan才是真正的数据存在的地方
@@ -16,25 +18,216 @@ struct Counter<C> {
     // 与channel相关联的senders和receivers的数量
     senders: AtomicUsize,
     receivers: AtomicUsize,
+    // 弱引用的数量，所有的强引用(Sender/Receiver)共同持有其中的1
+    weak: AtomicUsize,
+    // 其中WeakReceiver的数量，它们可以升级出新的receiver
+    weak_receivers: AtomicUsize,
     // 如果最后一个sender或receiver取消了channel的分配，这个值就为true
     destroy: AtomicBool,
+    // channel的名字，用于诊断，所有的Sender/Receiver共享
+    name: Option<Arc<str>>,
+    // channel断开时调用一次的回调
+    on_disconnect: Option<OnDisconnect>,
+    // 每次接收之后调用的回调
+    on_recv: Option<OnRecv>,
+    // on_disconnect是否已经被调用
+    notified: AtomicBool,
+    // 释放时把内存交给当前线程的pool，而不是还给分配器
+    recycle: bool,
+    // 测试时记录创建这个Counter的线程上还活着的Counter数量
+    #[cfg(all(test, feature = "std"))]
+    live: live::Live,
     // 内部的Channel
     chan: C,
 }
 
+// Counter的地址至少按4字节对齐，低2位可以给Sender::into_raw用来记录channel的类型
+pub(crate) const ADDR_TAG_MASK: usize = 0b11;
+const _: () = assert!(core::mem::align_of::<Counter<()>>() > ADDR_TAG_MASK);
+
+// 通过Builder::on_disconnect注册的回调
+pub(crate) type OnDisconnect = Arc<dyn Fn() + Send + Sync>;
+// 通过Builder::on_recv注册的回调
+pub(crate) type OnRecv = Arc<dyn Fn(RecvEvent) + Send + Sync>;
+
 #[allow(dead_code)]
-pub(crate) fn new<C>(chan: C) -> (Sender<C>, Receiver<C>) {
-    let counter = Box::into_raw(Box::new(Counter {
-        senders: AtomicUsize::new(1),
-        receivers: AtomicUsize::new(1),
-        destroy: AtomicBool::new(false),
-        chan,
-    }));
+pub(crate) fn new<C>(
+    chan: C,
+    name: Option<Arc<str>>,
+    on_disconnect: Option<OnDisconnect>,
+    on_recv: Option<OnRecv>,
+) -> (Sender<C>, Receiver<C>) {
+    let counter = Box::into_raw(Box::new(Counter::new(chan, name, on_disconnect, on_recv, false)));
     let sender = Sender { counter };
     let recv = Receiver { counter };
     (sender, recv)
 }
 
+// 与new相同，但优先使用当前线程pool中大小相同的Counter内存，释放时也放回pool
+#[cfg(feature = "std")]
+pub(crate) fn new_recycled<C>(
+    chan: C,
+    name: Option<Arc<str>>,
+    on_disconnect: Option<OnDisconnect>,
+    on_recv: Option<OnRecv>,
+) -> (Sender<C>, Receiver<C>) {
+    let value = Counter::new(chan, name, on_disconnect, on_recv, true);
+    let counter = match pool::take(Layout::new::<Counter<C>>()) {
+        Some(mem) => {
+            let counter = mem as *mut Counter<C>;
+            unsafe { counter.write(value) };
+            counter
+        }
+        None => Box::into_raw(Box::new(value)),
+    };
+    let sender = Sender { counter };
+    let recv = Receiver { counter };
+    (sender, recv)
+}
+
+/*
+ * 每个线程缓存的Counter内存，按Layout区分，不同T的channel只要Counter大小和对齐相同就可以复用
+ * 放回pool之前Counter已经被drop(channel中剩余的msg和block都已经释放)，pool中只是未初始化的内存
+ * 内存与Box::new分配的完全相同，所以从pool中取出的Counter最终可以用Box::from_raw释放，反之亦然
+ */
+#[cfg(all(feature = "std", not(loom)))]
+mod pool {
+    use std::alloc::{dealloc, Layout};
+    use std::cell::RefCell;
+
+    // 每个线程最多缓存的内存块数量，超过之后直接还给分配器
+    const CAPACITY: usize = 16;
+
+    struct Pool(Vec<(Layout, *mut u8)>);
+
+    impl Drop for Pool {
+        fn drop(&mut self) {
+            for (layout, mem) in self.0.drain(..) {
+                unsafe { dealloc(mem, layout) };
+            }
+        }
+    }
+
+    thread_local! {
+        static POOL: RefCell<Pool> = const { RefCell::new(Pool(Vec::new())) };
+    }
+
+    pub(super) fn take(layout: Layout) -> Option<*mut u8> {
+        POOL.try_with(|pool| {
+            let mut pool = pool.borrow_mut();
+            let i = pool.0.iter().position(|&(l, _)| l == layout)?;
+            Some(pool.0.swap_remove(i).1)
+        })
+        .ok()
+        .flatten()
+    }
+
+    // 放不进pool时(已满或者线程正在退出)把内存交还给调用者
+    pub(super) fn put(layout: Layout, mem: *mut u8) -> Result<(), *mut u8> {
+        POOL.try_with(|pool| {
+            let mut pool = pool.borrow_mut();
+            if pool.0.len() < CAPACITY {
+                pool.0.push((layout, mem));
+                Ok(())
+            } else {
+                Err(mem)
+            }
+        })
+        .unwrap_or(Err(mem))
+    }
+}
+
+// 没有thread_local时不缓存
+#[cfg(not(all(feature = "std", not(loom))))]
+mod pool {
+    use core::alloc::Layout;
+
+    pub(super) fn take(_layout: Layout) -> Option<*mut u8> {
+        None
+    }
+    pub(super) fn put(_layout: Layout, mem: *mut u8) -> Result<(), *mut u8> {
+        Err(mem)
+    }
+}
+
+/*
+ * 只在测试中使用：统计每个线程创建的、还没有被释放的Counter
+ * 计数属于创建Counter的线程，即使Counter在其他线程被释放也会减在这个线程的计数上，
+ * 并行运行的其他测试不会影响当前测试读到的值。Counter被drop时(包括放回pool)计数-1
+ */
+#[cfg(all(test, feature = "std"))]
+pub(crate) mod live {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    thread_local! {
+        static LIVE: Arc<AtomicUsize> = Arc::new(AtomicUsize::new(0));
+    }
+
+    pub(super) struct Live(Arc<AtomicUsize>);
+
+    impl Live {
+        pub(super) fn new() -> Live {
+            let count = LIVE.with(Arc::clone);
+            count.fetch_add(1, Ordering::SeqCst);
+            Live(count)
+        }
+    }
+
+    impl Drop for Live {
+        fn drop(&mut self) {
+            self.0.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    // 当前线程创建的Counter中还没有被释放的数量
+    pub(crate) fn count() -> usize {
+        LIVE.with(|count| count.load(Ordering::SeqCst))
+    }
+}
+
+impl<C> Counter<C> {
+    fn new(
+        chan: C,
+        name: Option<Arc<str>>,
+        on_disconnect: Option<OnDisconnect>,
+        on_recv: Option<OnRecv>,
+        recycle: bool,
+    ) -> Self {
+        Counter {
+            senders: AtomicUsize::new(1),
+            receivers: AtomicUsize::new(1),
+            weak: AtomicUsize::new(1),
+            weak_receivers: AtomicUsize::new(0),
+            destroy: AtomicBool::new(false),
+            name,
+            on_disconnect,
+            on_recv,
+            notified: AtomicBool::new(false),
+            recycle,
+            #[cfg(all(test, feature = "std"))]
+            live: live::Live::new(),
+            chan,
+        }
+    }
+
+    /*
+     * channel的disconnect方法只有真正把channel从连接状态变为断开的那一次调用返回true，
+     * 但是close_when_empty进入关闭状态时也返回true，之后断开channel的调用会再返回一次true，
+     * 所以用notified保证回调只被调用一次。回调在disconnect返回之后调用，此时不持有channel内部的锁，
+     * 回调中可以再操作这个channel
+     */
+    fn disconnect_with<F: FnOnce(&C) -> bool>(&self, disconnect: F) -> bool {
+        let first = disconnect(&self.chan);
+        if first && !self.notified.swap(true, Ordering::AcqRel) {
+            if let Some(f) = &self.on_disconnect {
+                f();
+            }
+        }
+        first
+    }
+}
+
 pub(crate) struct Sender<C> {
     counter: *mut Counter<C>,
 }
@@ -47,7 +240,7 @@ impl<C> Sender<C> {
     pub(crate) fn acquire(&self) -> Sender<C> {
         let count = self.counter().senders.fetch_add(1, Ordering::Relaxed);
         if count > isize::MAX as usize {
-            std::process::abort();
+            abort();
         }
         Sender {
             counter: self.counter,
@@ -55,12 +248,43 @@ impl<C> Sender<C> {
     }
     pub(crate) unsafe fn release<F: FnOnce(&C) -> bool>(&self, disconnect: F) {
         if self.counter().senders.fetch_sub(1, Ordering::AcqRel) == 1 {
-            disconnect(&self.counter().chan);
+            self.disconnect_with(disconnect);
             if self.counter().destroy.swap(true, Ordering::AcqRel) {
-                drop(Box::from_raw(self.counter));
+                release_weak(self.counter);
             }
         }
     }
+    // 断开channel，返回true表示是这次调用断开了channel
+    pub(crate) fn disconnect_with<F: FnOnce(&C) -> bool>(&self, disconnect: F) -> bool {
+        self.counter().disconnect_with(disconnect)
+    }
+    pub(crate) fn name(&self) -> Option<&str> {
+        self.counter().name.as_deref()
+    }
+    // Counter的地址，同一个channel的所有Sender/Receiver都相同
+    pub(crate) fn addr(&self) -> usize {
+        self.counter as usize
+    }
+    // 由addr重新得到这个handle，不改变计数，调用者保证addr来自一个被mem::forget的同类型handle
+    pub(crate) unsafe fn from_addr(addr: usize) -> Self {
+        Sender {
+            counter: addr as *mut Counter<C>,
+        }
+    }
+    // senders/receivers计数的快照，读取之后计数可能已经被其他线程改变
+    pub(crate) fn sender_count(&self) -> usize {
+        self.counter().senders.load(Ordering::SeqCst)
+    }
+    pub(crate) fn receiver_count(&self) -> usize {
+        self.counter().receivers.load(Ordering::SeqCst)
+    }
+    // 创建一个不增加senders计数的弱引用
+    pub(crate) fn downgrade(&self) -> WeakSender<C> {
+        acquire_weak(self.counter);
+        WeakSender {
+            counter: self.counter,
+        }
+    }
 }
 
 impl<C> ops::Deref for Sender<C> {
@@ -76,6 +300,13 @@ impl<C> PartialEq for Sender<C> {
     }
 }
 
+// Sender和Receiver共享同一个Counter指针，指针相同就说明属于同一个channel
+impl<C> PartialEq<Receiver<C>> for Sender<C> {
+    fn eq(&self, other: &Receiver<C>) -> bool {
+        self.counter == other.counter
+    }
+}
+
 pub(crate) struct Receiver<C> {
     counter: *mut Counter<C>,
 }
@@ -88,7 +319,7 @@ impl<C> Receiver<C> {
     pub(crate) fn acquire(&self) -> Receiver<C> {
         let count = self.counter().receivers.fetch_add(1, Ordering::Relaxed);
         if count > isize::MAX as usize {
-            std::process::abort();
+            abort();
         }
 
         Receiver {
@@ -97,12 +328,56 @@ impl<C> Receiver<C> {
     }
     pub(crate) unsafe fn release<F: FnOnce(&C) -> bool>(&self, disconnect: F) {
         if self.counter().receivers.fetch_sub(1, Ordering::AcqRel) == 1 {
-            disconnect(&self.counter().chan);
+            self.disconnect_with(disconnect);
             if self.counter().destroy.swap(true, Ordering::AcqRel) {
-                drop(Box::from_raw(self.counter));
+                release_weak(self.counter);
             }
         }
     }
+    // 断开channel，返回true表示是这次调用断开了channel
+    pub(crate) fn disconnect_with<F: FnOnce(&C) -> bool>(&self, disconnect: F) -> bool {
+        self.counter().disconnect_with(disconnect)
+    }
+    pub(crate) fn name(&self) -> Option<&str> {
+        self.counter().name.as_deref()
+    }
+    // Counter的地址，同一个channel的所有Sender/Receiver都相同
+    pub(crate) fn addr(&self) -> usize {
+        self.counter as usize
+    }
+    pub(crate) fn on_recv(&self) -> Option<&OnRecv> {
+        self.counter().on_recv.as_ref()
+    }
+    // 与Sender::from_addr相同
+    pub(crate) unsafe fn from_addr(addr: usize) -> Self {
+        Receiver {
+            counter: addr as *mut Counter<C>,
+        }
+    }
+    // senders/receivers计数的快照，读取之后计数可能已经被其他线程改变
+    pub(crate) fn sender_count(&self) -> usize {
+        self.counter().senders.load(Ordering::SeqCst)
+    }
+    pub(crate) fn receiver_count(&self) -> usize {
+        self.counter().receivers.load(Ordering::SeqCst)
+    }
+    /*
+     * 是否只有这一个receiver，并且不存在可以升级出新receiver的WeakReceiver
+     * 新的receiver只能通过clone已有的receiver或者升级WeakReceiver得到，
+     * 所以调用者持有&mut的Receiver并且这里返回true时，之后也不会有其他线程在接收
+     */
+    pub(crate) fn is_sole_receiver(&self) -> bool {
+        self.counter().receivers.load(Ordering::SeqCst) == 1
+            && self.counter().weak_receivers.load(Ordering::SeqCst) == 0
+    }
+    // 创建一个不增加receivers计数的弱引用
+    pub(crate) fn downgrade(&self) -> WeakReceiver<C> {
+        acquire_weak(self.counter);
+        self.counter().weak_receivers.fetch_add(1, Ordering::SeqCst);
+        WeakReceiver {
+            counter: self.counter,
+        }
+    }
 }
 
 impl<C> ops::Deref for Receiver<C> {
@@ -118,3 +393,106 @@ impl<C> PartialEq for Receiver<C> {
         self.counter == other.counter
     }
 }
+
+/*
+ * 弱引用只增加Counter中的weak计数，不会阻止channel断开
+ * 当senders/receivers都减到0时，强引用共同持有的那1个weak计数被释放
+ * 只有weak计数减到0时才会真正的释放Counter
+ */
+
+fn acquire_weak<C>(counter: *mut Counter<C>) {
+    let count = unsafe { &*counter }.weak.fetch_add(1, Ordering::Relaxed);
+    if count > isize::MAX as usize {
+        abort();
+    }
+}
+
+unsafe fn release_weak<C>(counter: *mut Counter<C>) {
+    if (*counter).weak.fetch_sub(1, Ordering::AcqRel) != 1 {
+        return;
+    }
+    if !(*counter).recycle {
+        drop(Box::from_raw(counter));
+        return;
+    }
+    let layout = Layout::new::<Counter<C>>();
+    ptr::drop_in_place(counter);
+    if let Err(mem) = pool::put(layout, counter as *mut u8) {
+        dealloc(mem, layout);
+    }
+}
+
+// 只有在计数不为0时才能+1，计数一旦归0，channel已经断开，不能再恢复
+fn upgrade(count: &AtomicUsize) -> bool {
+    let mut n = count.load(Ordering::Relaxed);
+    loop {
+        if n == 0 {
+            return false;
+        }
+        if n > isize::MAX as usize {
+            abort();
+        }
+        match count.compare_exchange_weak(n, n + 1, Ordering::Acquire, Ordering::Relaxed) {
+            Ok(_) => return true,
+            Err(cur) => n = cur,
+        }
+    }
+}
+
+pub(crate) struct WeakSender<C> {
+    counter: *mut Counter<C>,
+}
+
+impl<C> WeakSender<C> {
+    fn counter(&self) -> &Counter<C> {
+        unsafe { &*self.counter }
+    }
+    pub(crate) fn upgrade(&self) -> Option<Sender<C>> {
+        if upgrade(&self.counter().senders) {
+            Some(Sender {
+                counter: self.counter,
+            })
+        } else {
+            None
+        }
+    }
+    pub(crate) fn acquire(&self) -> WeakSender<C> {
+        acquire_weak(self.counter);
+        WeakSender {
+            counter: self.counter,
+        }
+    }
+    pub(crate) unsafe fn release(&self) {
+        release_weak(self.counter);
+    }
+}
+
+pub(crate) struct WeakReceiver<C> {
+    counter: *mut Counter<C>,
+}
+
+impl<C> WeakReceiver<C> {
+    fn counter(&self) -> &Counter<C> {
+        unsafe { &*self.counter }
+    }
+    pub(crate) fn upgrade(&self) -> Option<Receiver<C>> {
+        if upgrade(&self.counter().receivers) {
+            Some(Receiver {
+                counter: self.counter,
+            })
+        } else {
+            None
+        }
+    }
+    pub(crate) fn acquire(&self) -> WeakReceiver<C> {
+        acquire_weak(self.counter);
+        self.counter().weak_receivers.fetch_add(1, Ordering::SeqCst);
+        WeakReceiver {
+            counter: self.counter,
+        }
+    }
+    pub(crate) unsafe fn release(&self) {
+        self.counter().weak_receivers.fetch_sub(1, Ordering::SeqCst);
+        release_weak(self.counter);
+    }
+}
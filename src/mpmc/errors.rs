@@ -1,4 +1,4 @@
-use std::{error, fmt};
+use core::{error, fmt};
 
 // send_timeout方法可能会返回一个Error，就是这个Error
 // 错误会被包含在信息中，因此它能够被恢复
@@ -11,6 +11,36 @@ pub enum SendTimeoutError<T> {
     // channel已经关闭因此信息不能被发送
     Disconnected(T)
 }
+impl<T> SendTimeoutError<T> {
+    // 不论是哪种错误，都取回没有发送出去的msg
+    pub fn into_inner(self) -> T {
+        match self {
+            SendTimeoutError::Timeout(msg) => msg,
+            SendTimeoutError::Disconnected(msg) => msg,
+        }
+    }
+    pub fn is_timeout(&self) -> bool {
+        matches!(self, SendTimeoutError::Timeout(_))
+    }
+    pub fn is_disconnected(&self) -> bool {
+        matches!(self, SendTimeoutError::Disconnected(_))
+    }
+    // 返回不包含msg的错误类型，便于在不要求T: PartialEq的情况下比较
+    pub fn kind(&self) -> SendTimeoutErrorKind {
+        match self {
+            SendTimeoutError::Timeout(_) => SendTimeoutErrorKind::Timeout,
+            SendTimeoutError::Disconnected(_) => SendTimeoutErrorKind::Disconnected,
+        }
+    }
+}
+
+// SendTimeoutError去掉msg后的变体
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Hash)]
+pub enum SendTimeoutErrorKind {
+    Timeout,
+    Disconnected,
+}
+
 impl<T: Send> error::Error for SendTimeoutError<T> {}
 impl<T> fmt::Debug for SendTimeoutError<T>{
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -39,6 +69,13 @@ impl<T> From<SendError<T>> for SendTimeoutError<T> {
 #[derive(PartialEq, Eq, Clone, Copy)]
 pub struct SendError<T>(pub T);
 
+impl<T> SendError<T> {
+    // 取回没有发送出去的msg
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
 impl<T> fmt::Debug for SendError<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("SendError").finish_non_exhaustive()
@@ -66,6 +103,36 @@ pub enum TrySendError<T> {
     Disconnected(T),
 }
 
+impl<T> TrySendError<T> {
+    // 不论是哪种错误，都取回没有发送出去的msg
+    pub fn into_inner(self) -> T {
+        match self {
+            TrySendError::Full(msg) => msg,
+            TrySendError::Disconnected(msg) => msg,
+        }
+    }
+    pub fn is_full(&self) -> bool {
+        matches!(self, TrySendError::Full(_))
+    }
+    pub fn is_disconnected(&self) -> bool {
+        matches!(self, TrySendError::Disconnected(_))
+    }
+    // 返回不包含msg的错误类型，便于在不要求T: PartialEq的情况下比较
+    pub fn kind(&self) -> TrySendErrorKind {
+        match self {
+            TrySendError::Full(_) => TrySendErrorKind::Full,
+            TrySendError::Disconnected(_) => TrySendErrorKind::Disconnected,
+        }
+    }
+}
+
+// TrySendError去掉msg后的变体
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Hash)]
+pub enum TrySendErrorKind {
+    Full,
+    Disconnected,
+}
+
 impl<T> fmt::Debug for TrySendError<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match *self {
@@ -103,6 +170,35 @@ impl<T> From<SendError<T>> for TrySendError<T> {
     }
 }
 
+// SendReservation::push_with的错误
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum PushWithError<F, T> {
+    // 预留的slot已经用完或者channel已经断开，f没有被调用，原样返回
+    Unused(F),
+    // f已经生成了msg，但是在发送之前channel断开了，返回这个msg
+    Disconnected(T),
+}
+
+impl<F, T> fmt::Debug for PushWithError<F, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            PushWithError::Unused(..) => "Unused(..)".fmt(f),
+            PushWithError::Disconnected(..) => "Disconnected(..)".fmt(f),
+        }
+    }
+}
+
+impl<F, T> fmt::Display for PushWithError<F, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            PushWithError::Unused(..) => "no reserved slot left".fmt(f),
+            PushWithError::Disconnected(..) => "sending on a closed channel".fmt(f),
+        }
+    }
+}
+
+impl<F: Send, T: Send> error::Error for PushWithError<F, T> {}
+
 // 在Receiver的recv方法中可能会产生这个错误
 // RecvError，当recv接受msg时，sender传送msg到一半而channel(include sync_channel)关闭了就会产生这个错误
 #[derive(PartialEq, Eq, Clone, Copy, Debug)]
@@ -186,3 +282,136 @@ impl From<RecvError> for RecvTimeoutError {
         }
     }
 }
+
+/*
+ * try_*和*_timeout两类错误之间的转换，便于用?把一种错误传递到另一种返回类型的函数中
+ * Full/Empty对应Timeout：都表示操作在等待结束时还没有就绪
+ */
+impl<T> From<TrySendError<T>> for SendTimeoutError<T> {
+    fn from(err: TrySendError<T>) -> SendTimeoutError<T> {
+        match err {
+            TrySendError::Full(t) => SendTimeoutError::Timeout(t),
+            TrySendError::Disconnected(t) => SendTimeoutError::Disconnected(t),
+        }
+    }
+}
+
+impl<T> From<SendTimeoutError<T>> for TrySendError<T> {
+    fn from(err: SendTimeoutError<T>) -> TrySendError<T> {
+        match err {
+            SendTimeoutError::Timeout(t) => TrySendError::Full(t),
+            SendTimeoutError::Disconnected(t) => TrySendError::Disconnected(t),
+        }
+    }
+}
+
+impl From<TryRecvError> for RecvTimeoutError {
+    fn from(err: TryRecvError) -> RecvTimeoutError {
+        match err {
+            TryRecvError::Empty => RecvTimeoutError::Timeout,
+            TryRecvError::Disconnected => RecvTimeoutError::Disconnected,
+        }
+    }
+}
+
+impl From<RecvTimeoutError> for TryRecvError {
+    fn from(err: RecvTimeoutError) -> TryRecvError {
+        match err {
+            RecvTimeoutError::Timeout => TryRecvError::Empty,
+            RecvTimeoutError::Disconnected => TryRecvError::Disconnected,
+        }
+    }
+}
+
+// SendError/RecvError只表示断开，只有Disconnected能转换过去，其他情况原样返回错误
+impl<T> TryFrom<SendTimeoutError<T>> for SendError<T> {
+    type Error = SendTimeoutError<T>;
+
+    fn try_from(err: SendTimeoutError<T>) -> Result<SendError<T>, SendTimeoutError<T>> {
+        match err {
+            SendTimeoutError::Disconnected(t) => Ok(SendError(t)),
+            err => Err(err),
+        }
+    }
+}
+
+impl<T> TryFrom<TrySendError<T>> for SendError<T> {
+    type Error = TrySendError<T>;
+
+    fn try_from(err: TrySendError<T>) -> Result<SendError<T>, TrySendError<T>> {
+        match err {
+            TrySendError::Disconnected(t) => Ok(SendError(t)),
+            err => Err(err),
+        }
+    }
+}
+
+impl TryFrom<RecvTimeoutError> for RecvError {
+    type Error = RecvTimeoutError;
+
+    fn try_from(err: RecvTimeoutError) -> Result<RecvError, RecvTimeoutError> {
+        match err {
+            RecvTimeoutError::Disconnected => Ok(RecvError),
+            err => Err(err),
+        }
+    }
+}
+
+impl TryFrom<TryRecvError> for RecvError {
+    type Error = TryRecvError;
+
+    fn try_from(err: TryRecvError) -> Result<RecvError, TryRecvError> {
+        match err {
+            TryRecvError::Disconnected => Ok(RecvError),
+            err => Err(err),
+        }
+    }
+}
+
+// Select::try_select在没有任何操作ready时返回这个错误
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct TrySelectError;
+
+impl fmt::Display for TrySelectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        "all operations in select would block".fmt(f)
+    }
+}
+
+impl error::Error for TrySelectError {}
+
+// Select::select_timeout/select_deadline在到达deadline时仍然没有操作ready时返回这个错误
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct SelectTimeoutError;
+
+impl fmt::Display for SelectTimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        "timed out waiting on select".fmt(f)
+    }
+}
+
+impl error::Error for SelectTimeoutError {}
+
+// Select::try_ready在没有操作ready时返回这个错误
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct TryReadyError;
+
+impl fmt::Display for TryReadyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        "all operations are currently blocked".fmt(f)
+    }
+}
+
+impl error::Error for TryReadyError {}
+
+// Select::ready_timeout/ready_deadline在到达deadline时仍然没有操作ready时返回这个错误
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct ReadyTimeoutError;
+
+impl fmt::Display for ReadyTimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        "timed out waiting on ready".fmt(f)
+    }
+}
+
+impl error::Error for ReadyTimeoutError {}
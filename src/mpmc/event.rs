@@ -0,0 +1,24 @@
+use core::time::Duration;
+
+// Builder::on_recv注册的回调收到的事件，描述一次接收操作
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecvEvent {
+    // 调用时channel中没有可以立即取走的msg，需要等待(try_recv总是false)
+    pub blocked: bool,
+    // 从调用开始到返回经过的真实时间，try_recv总是0
+    pub waited: Duration,
+    pub outcome: RecvOutcome,
+}
+
+// 一次接收操作的结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecvOutcome {
+    // 收到了msg
+    Received,
+    // try_recv时channel为空
+    Empty,
+    // 到达deadline时仍然没有msg
+    Timeout,
+    // channel为空并且已经断开
+    Disconnected,
+}
@@ -0,0 +1,224 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    hash::Hash,
+    sync::PoisonError,
+    time::{Duration, Instant},
+};
+
+use super::{
+    context::now,
+    counter,
+    errors::*,
+    sync::{Mutex, MutexGuard},
+    waker::SyncWaker,
+};
+
+/*
+ * 按key合并的channel：send(k, v)时如果队列中已经有这个key还没有被接收的msg，就用v替换它，而不是追加
+ * 被替换的msg保持原来在队列中的位置，所以各个key之间仍然按第一次进入队列的顺序接收
+ * 接收慢的receiver对每个key只会看到它接收时最新的值，队列长度不会超过不同key的数量
+ * key同时存在于map和顺序队列中，所以需要Clone
+ */
+pub(crate) struct Channel<K, V> {
+    inner: Mutex<Inner<K, V>>,
+    // 队列为空时阻塞的receivers
+    receivers: SyncWaker,
+}
+
+struct Inner<K, V> {
+    // 等待接收的key，按第一次进入队列的顺序
+    order: VecDeque<K>,
+    // 每个key最新的值
+    values: HashMap<K, V>,
+    is_disconnected: bool,
+}
+
+impl<K: Eq + Hash + Clone, V> Channel<K, V> {
+    fn lock(&self) -> MutexGuard<'_, Inner<K, V>> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // 返回被替换掉的旧值
+    fn send(&self, key: K, value: V) -> Result<Option<V>, SendError<(K, V)>> {
+        let mut inner = self.lock();
+        if inner.is_disconnected {
+            return Err(SendError((key, value)));
+        }
+        if let Some(old) = inner.values.get_mut(&key) {
+            return Ok(Some(std::mem::replace(old, value)));
+        }
+        inner.order.push_back(key.clone());
+        inner.values.insert(key, value);
+        drop(inner);
+        self.receivers.notify();
+        Ok(None)
+    }
+
+    fn try_recv(&self) -> Result<(K, V), TryRecvError> {
+        let mut inner = self.lock();
+        match inner.order.pop_front() {
+            Some(key) => {
+                let value = inner.values.remove(&key).unwrap();
+                Ok((key, value))
+            }
+            None if inner.is_disconnected => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    fn recv(&self, deadline: Option<Instant>) -> Result<(K, V), RecvTimeoutError> {
+        loop {
+            match self.try_recv() {
+                Ok(msg) => return Ok(msg),
+                Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+                Err(TryRecvError::Empty) => {}
+            }
+            // 另一个receiver可能抢先取走了唤醒我们的msg，重新检查
+            if !self.receivers.wait_observed(deadline, || {
+                let inner = self.lock();
+                !inner.order.is_empty() || inner.is_disconnected
+            }) {
+                return Err(RecvTimeoutError::Timeout);
+            }
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.lock().order.len()
+    }
+}
+
+impl<K, V> Channel<K, V> {
+    // 所有sender都被drop之后，receiver取完剩余的msg才返回Disconnected
+    fn disconnect_senders(&self) -> bool {
+        let mut inner = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        if inner.is_disconnected {
+            return false;
+        }
+        inner.is_disconnected = true;
+        drop(inner);
+        self.receivers.disconnect();
+        true
+    }
+
+    // 所有receiver都被drop之后，剩余的msg不会再被接收，直接丢弃
+    fn disconnect_receivers(&self) -> bool {
+        let mut inner = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        let first = !inner.is_disconnected;
+        inner.is_disconnected = true;
+        let order = std::mem::take(&mut inner.order);
+        let values = std::mem::take(&mut inner.values);
+        // msg的drop可能panic，不能在持有锁时进行
+        drop(inner);
+        drop((order, values));
+        first
+    }
+}
+
+// 创建一个按key合并msg的无界channel，见Channel
+pub fn latest_channel<K: Eq + Hash + Clone, V>() -> (LatestSender<K, V>, LatestReceiver<K, V>) {
+    let chan = Channel {
+        inner: Mutex::new(Inner {
+            order: VecDeque::new(),
+            values: HashMap::new(),
+            is_disconnected: false,
+        }),
+        receivers: SyncWaker::new(),
+    };
+    let (s, r) = counter::new(chan, None, None, None);
+    (LatestSender { chan: s }, LatestReceiver { chan: r })
+}
+
+pub struct LatestSender<K, V> {
+    chan: counter::Sender<Channel<K, V>>,
+}
+
+unsafe impl<K: Send, V: Send> Send for LatestSender<K, V> {}
+unsafe impl<K: Send, V: Send> Sync for LatestSender<K, V> {}
+
+impl<K: Eq + Hash + Clone, V> LatestSender<K, V> {
+    // 发送不会阻塞；key已经在队列中时替换它的值，返回被替换掉的旧值
+    pub fn send(&self, key: K, value: V) -> Result<Option<V>, SendError<(K, V)>> {
+        self.chan.send(key, value)
+    }
+    // 队列中等待接收的key的数量
+    pub fn len(&self) -> usize {
+        self.chan.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<K, V> Drop for LatestSender<K, V> {
+    fn drop(&mut self) {
+        unsafe {
+            self.chan.release(|c| c.disconnect_senders());
+        }
+    }
+}
+
+impl<K, V> Clone for LatestSender<K, V> {
+    fn clone(&self) -> Self {
+        LatestSender {
+            chan: self.chan.acquire(),
+        }
+    }
+}
+
+impl<K, V> fmt::Debug for LatestSender<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("LatestSender { .. }")
+    }
+}
+
+pub struct LatestReceiver<K, V> {
+    chan: counter::Receiver<Channel<K, V>>,
+}
+
+unsafe impl<K: Send, V: Send> Send for LatestReceiver<K, V> {}
+unsafe impl<K: Send, V: Send> Sync for LatestReceiver<K, V> {}
+
+impl<K: Eq + Hash + Clone, V> LatestReceiver<K, V> {
+    pub fn try_recv(&self) -> Result<(K, V), TryRecvError> {
+        self.chan.try_recv()
+    }
+    pub fn recv(&self) -> Result<(K, V), RecvError> {
+        self.chan.recv(None).map_err(|_| RecvError)
+    }
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<(K, V), RecvTimeoutError> {
+        match now().checked_add(timeout) {
+            Some(deadline) => self.chan.recv(Some(deadline)),
+            None => self.recv().map_err(RecvTimeoutError::from),
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.chan.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<K, V> Drop for LatestReceiver<K, V> {
+    fn drop(&mut self) {
+        unsafe {
+            self.chan.release(|c| c.disconnect_receivers());
+        }
+    }
+}
+
+impl<K, V> Clone for LatestReceiver<K, V> {
+    fn clone(&self) -> Self {
+        LatestReceiver {
+            chan: self.chan.acquire(),
+        }
+    }
+}
+
+impl<K, V> fmt::Debug for LatestReceiver<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("LatestReceiver { .. }")
+    }
+}
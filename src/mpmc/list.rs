@@ -1,6 +1,15 @@
-use std::{cell::UnsafeCell, mem::MaybeUninit, sync::atomic::{AtomicUsize, AtomicPtr, Ordering, self}, marker::PhantomData, time::Instant, ptr};
-
-use super::{utils::CachePadded, context::*, utils::*, waker::SyncWaker, select::*, errors::*};
+use alloc::boxed::Box;
+use core::{cell::UnsafeCell, mem::MaybeUninit, marker::PhantomData, ptr};
+use super::sync::atomic::{AtomicBool, AtomicUsize, AtomicPtr, Ordering, self};
+#[cfg(feature = "std")]
+use std::time::Instant;
+
+use super::{utils::CachePadded, utils::*, waker::SyncWaker, select::*, errors::*};
+use super::sync::const_fn;
+#[cfg(feature = "std")]
+use super::context::*;
+#[cfg(feature = "metrics")]
+use super::metrics::Metrics;
 
 /*
  * 这三个位表示slot的状态：
@@ -55,6 +64,7 @@ struct Block<T> {
 
 
 impl<T> Block<T> {
+    #[cfg(not(loom))]
     fn new() -> Block<T> {
         // 这里使用MaybeUninit是安全的
         // Block::next 可以零初始化
@@ -64,6 +74,18 @@ impl<T> Block<T> {
         unsafe { MaybeUninit::zeroed().assume_init() }
     }
 
+    // loom的原子类型不能零初始化
+    #[cfg(loom)]
+    fn new() -> Block<T> {
+        Block {
+            next: AtomicPtr::new(ptr::null_mut()),
+            slots: core::array::from_fn(|_| Slot {
+                msg: UnsafeCell::new(MaybeUninit::uninit()),
+                state: AtomicUsize::new(0),
+            }),
+        }
+    }
+
     /// 等待next指针域被设置
     fn wait_next(&self) -> *mut Block<T> {
         let backoff = Backoff::new();
@@ -105,6 +127,17 @@ struct Position<T> {
     block: AtomicPtr<Block<T>>,
 }
 
+impl<T> Position<T> {
+    const_fn! {
+        fn new() -> Self {
+            Position {
+                index: AtomicUsize::new(0),
+                block: AtomicPtr::new(ptr::null_mut()),
+            }
+        }
+    }
+}
+
 // list flavor channel，记录msg在哪一个Block中，Block中的slots数组中的偏移量是多少
 #[derive(Debug)]
 pub(crate) struct ListToken {
@@ -114,6 +147,13 @@ pub(crate) struct ListToken {
     offset: usize,
 }
 
+// head在channel中的位置，见Channel::cursor
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Cursor {
+    pub(crate) index: usize,
+    pub(crate) block: *const u8,
+}
+
 impl Default for ListToken {
     #[inline]
     fn default() -> Self {
@@ -129,22 +169,49 @@ pub(crate) struct Channel<T> {
     tail: CachePadded<Position<T>>,
     // 当channel为空或者没有被断开时，Receivers会阻塞，这个SyncWaker就记录阻塞
     receivers: SyncWaker,
+    // 设置了soft_limit时，len达到上限后阻塞的senders，每次read之后通知
+    senders: SyncWaker,
+    // Builder::soft_limit设置的上限，len达到它时send阻塞、try_send返回Full
+    soft_limit: Option<usize>,
+    // 阻塞的recv在注册、park之前用Backoff自旋的上限，见Builder::spin_before_park
+    spin_limit: u32,
+    // 等待channel变空的线程(wait_empty)，每次read之后通知
+    drained: SyncWaker,
+    // close_when_empty之后为true：不再接受新的msg，取完剩余的msg之后断开
+    closing: AtomicBool,
+    #[cfg(feature = "metrics")]
+    pub(crate) metrics: Metrics,
     _marker: PhantomData<T>,
 }
 
 impl<T> Channel<T> {
-    pub(crate) fn new() -> Self {
-        Channel {
-            head: CachePadded::new(Position {
-                block: AtomicPtr::new(ptr::null_mut()),
-                index: AtomicUsize::new(0),
-            }),
-            tail: CachePadded::new(Position {
-                block: AtomicPtr::new(ptr::null_mut()),
-                index: AtomicUsize::new(0),
-            }),
-            receivers: SyncWaker::new(),
-            _marker: PhantomData,
+    const_fn! {
+        // 可以在const上下文中调用，例如与OnceLock一起实现全局的channel，见Builder::new
+        pub(crate) fn new() -> Self {
+            Self::with_soft_limit(None)
+        }
+    }
+
+    pub(crate) fn spin_before_park(mut self, limit: u32) -> Self {
+        self.spin_limit = limit;
+        self
+    }
+
+    const_fn! {
+        pub(crate) fn with_soft_limit(soft_limit: Option<usize>) -> Self {
+            Channel {
+                head: CachePadded::new(Position::new()),
+                tail: CachePadded::new(Position::new()),
+                receivers: SyncWaker::new(),
+                senders: SyncWaker::new(),
+                soft_limit,
+                spin_limit: SPIN_LIMIT,
+                drained: SyncWaker::new(),
+                closing: AtomicBool::new(false),
+                #[cfg(feature = "metrics")]
+                metrics: Metrics::new(),
+                _marker: PhantomData,
+            }
         }
     }
 
@@ -157,10 +224,12 @@ impl<T> Channel<T> {
         let mut tail = self.tail.index.load(Ordering::Acquire);
         let mut block = self.tail.block.load(Ordering::Acquire);
         let mut next_block = None;
+        // 正在关闭的channel对sender来说已经断开
+        let closing = self.closing.load(Ordering::SeqCst);
 
         loop {
             // tail的index是奇数时这个条件为真
-            if tail & MARK_BIT != 0 {
+            if tail & MARK_BIT != 0 || closing {
                 token.list.block = ptr::null();
                 return true;
             }
@@ -222,6 +291,7 @@ impl<T> Channel<T> {
                     return true;
                 },
                 Err(_) => {
+                    token.retry();
                     // TODO(这里为什么要自旋)
                     backoff.spin_light();
                     tail = self.tail.index.load(Ordering::Acquire);
@@ -233,6 +303,14 @@ impl<T> Channel<T> {
 
     // 将msg写入channel
     pub(crate) unsafe fn write(&self, token: &mut Token, msg: T) -> Result<(), T> {
+        self.write_slot(token, msg)?;
+        // 唤醒一个等待的receiver
+        self.receivers.notify();
+        Ok(())
+    }
+
+    // 与write相同，但是不唤醒receiver，由调用者负责
+    unsafe fn write_slot(&self, token: &mut Token, msg: T) -> Result<(), T> {
         // 如果list中没有slot那么代表channel已经disconnected
         if token.list.block.is_null() {
             return Err(msg);
@@ -245,27 +323,113 @@ impl<T> Channel<T> {
         slot.msg.get().write(MaybeUninit::new(msg));
         // slot中的msg已经被写入
         slot.state.fetch_or(WRITE, Ordering::Release);
-        // 唤醒一个等待的receiver
-        self.receivers.notify();
+        #[cfg(feature = "metrics")]
+        self.metrics.on_send(self.len());
         Ok(())
     }
     // 尝试发送一个msg到channel
     pub(crate) fn try_send(&self, msg: T) -> Result<(), TrySendError<T>> {
-        self.send(msg, None).map_err(|err| match err {
-            SendTimeoutError::Disconnected(msg) => TrySendError::Disconnected(msg),
-            SendTimeoutError::Timeout(_) => unreachable!(),
-        })
-    }
-
-    // 发送一个msg到channel
-    pub(crate) fn send(
-        &self,
-        msg: T,
-        _deadline: Option<Instant>,
-    ) -> Result<(), SendTimeoutError<T>> {
+        if self.is_full() && !self.is_disconnected() {
+            return Err(TrySendError::Full(msg));
+        }
+        self.send(msg).map_err(|SendError(msg)| TrySendError::Disconnected(msg))
+    }
+
+    /*
+     * 发送一个msg到channel，不检查soft_limit
+     * list channel没有容量限制，发送永远不会阻塞，所以也没有deadline参数，不会返回Timeout，
+     * 唯一的失败是channel已经断开，即使调用者给出的deadline已经过去也会返回Disconnected
+     */
+    pub(crate) fn send(&self, msg: T) -> Result<(), SendError<T>> {
+        self.send_token(&mut Token::default(), msg)
+    }
+
+    /*
+     * 遵守soft_limit的send：len达到上限时阻塞，直到有msg被接收、channel断开或者到了deadline
+     * 检查和写入之间没有同步，多个sender同时通过检查时len可能短暂超过上限，所以只是"软"上限
+     * 没有设置soft_limit时与send相同，不会阻塞
+     */
+    #[cfg(feature = "std")]
+    pub(crate) fn send_deadline(&self, msg: T, deadline: Option<Instant>) -> Result<(), SendTimeoutError<T>> {
+        if self.soft_limit.is_some() && !self.wait_not_full(deadline) {
+            return Err(SendTimeoutError::Timeout(msg));
+        }
+        self.send(msg).map_err(SendTimeoutError::from)
+    }
+
+    /*
+     * 阻塞直到channel不满、已经断开或者到了deadline，返回最后一次检查的结果
+     * 作为selector注册在senders中，每次read只唤醒一个sender，释放出一个slot不会让所有阻塞的sender同时越过上限
+     * 被唤醒之后slot已经被别的sender抢走时重新注册
+     */
+    #[cfg(feature = "std")]
+    fn wait_not_full(&self, deadline: Option<Instant>) -> bool {
+        let ready = || !self.is_full() || self.is_disconnected();
+        loop {
+            if ready() {
+                return true;
+            }
+            if deadline.is_some_and(|d| now() >= d) {
+                return false;
+            }
+            Context::with(|cx| {
+                let mut hook = 0u8;
+                let oper = Operation::hook(&mut hook);
+                self.senders.register(oper, cx);
+                // 被notify选中时entry已经被移除，其他情况需要自己移除
+                let _unregister = UnwindGuard::new(|| {
+                    self.senders.unregister(oper);
+                });
+                if ready() {
+                    let _ = cx.try_select(Selected::Aborted);
+                }
+                cx.wait_until(deadline);
+            });
+        }
+    }
+
+    /*
+     * 依次发送msgs中的msg，不检查soft_limit，返回发送的数量
+     * 全部写入之后只调用一次notify_many，在一次加锁中唤醒最多同样数量的receivers
+     * 中途断开时返回没能发送的msg，之前的msg已经在channel中，msgs中剩余的msg被丢弃
+     */
+    #[cfg(feature = "std")]
+    pub(crate) fn send_all(&self, msgs: impl IntoIterator<Item = T>) -> Result<usize, SendError<T>> {
+        // 放在drop中唤醒，迭代器panic时已经写入的msg也不会没人接收
+        struct Notify<'a> {
+            receivers: &'a SyncWaker,
+            sent: usize,
+        }
+        impl Drop for Notify<'_> {
+            fn drop(&mut self) {
+                self.receivers.notify_many(self.sent);
+            }
+        }
+
+        let mut notify = Notify {
+            receivers: &self.receivers,
+            sent: 0,
+        };
+        for msg in msgs {
+            let token = &mut Token::default();
+            assert!(self.start_send(token));
+            unsafe { self.write_slot(token, msg).map_err(SendError)? };
+            notify.sent += 1;
+        }
+        Ok(notify.sent)
+    }
+
+    // 与try_send相同，同时返回start_send中CAS重试的次数
+    #[cfg(feature = "profiling")]
+    pub(crate) fn try_send_profiled(&self, msg: T) -> (Result<(), TrySendError<T>>, u32) {
         let token = &mut Token::default();
+        let res = self.send_token(token, msg).map_err(|SendError(msg)| TrySendError::Disconnected(msg));
+        (res, token.retries)
+    }
+
+    fn send_token(&self, token: &mut Token, msg: T) -> Result<(), SendError<T>> {
         assert!(self.start_send(token));
-        unsafe { self.write(token, msg).map_err(SendTimeoutError::Disconnected) }
+        unsafe { self.write(token, msg).map_err(SendError) }
     }
 
     // 尝试为接收信息保留一个slot. 这里会选择性的更新head block index，如果head和tail不在一个Block中，head会被设置为奇数
@@ -299,6 +463,11 @@ impl<T> Channel<T> {
                         // 然后将block置空，并返回一个错误
                         token.list.block = ptr::null();
                         return true;
+                    } else if self.closing.load(Ordering::SeqCst) {
+                        // 正在关闭的channel已经被取空，现在真正断开
+                        self.disconnect();
+                        token.list.block = ptr::null();
+                        return true;
                     } else {
                         // 否则就是接收操作未就绪
                         return false;
@@ -320,12 +489,19 @@ impl<T> Channel<T> {
             }
 
             // 更新head index
-            match self.head.index.compare_exchange_weak(
-                head,
-                new_head,
-                Ordering::SeqCst,
-                Ordering::Acquire,
-            ) {
+            // 唯一的receiver不会与其他线程竞争head，直接store即可，sender不读取head
+            let moved = if token.exclusive {
+                self.head.index.store(new_head, Ordering::Release);
+                Ok(head)
+            } else {
+                self.head.index.compare_exchange_weak(
+                    head,
+                    new_head,
+                    Ordering::SeqCst,
+                    Ordering::Acquire,
+                )
+            };
+            match moved {
                 Ok(_) => unsafe {
                     // If we've reached the end of the block, move to the next one.
                     if offset + 1 == BLOCK_CAP {
@@ -344,6 +520,7 @@ impl<T> Channel<T> {
                     return true;
                 },
                 Err(_) => {
+                    token.retry();
                     backoff.spin_light();
                     head = self.head.index.load(Ordering::Acquire);
                     block = self.head.block.load(Ordering::Acquire);
@@ -364,22 +541,208 @@ impl<T> Channel<T> {
         let slot = (*block).slots.get_unchecked(offset);
         slot.wait_write();
         let msg = slot.msg.get().read().assume_init();
+        self.release_slot(block, offset);
+
+        Ok(msg)
+    }
+
+    // 与read相同，但是先把slot中msg的引用交给f，f返回或者panic之后再drop msg并释放slot
+    #[cfg(feature = "std")]
+    pub(crate) unsafe fn read_with<R>(&self, token: &mut Token, f: impl FnOnce(&T) -> R) -> Result<R, ()> {
+        if token.list.block.is_null() {
+            return Err(());
+        }
+
+        struct Consume<'a, T> {
+            chan: &'a Channel<T>,
+            block: *mut Block<T>,
+            offset: usize,
+        }
+        impl<T> Drop for Consume<'_, T> {
+            fn drop(&mut self) {
+                unsafe {
+                    let slot = (*self.block).slots.get_unchecked(self.offset);
+                    drop(slot.msg.get().read().assume_init());
+                    self.chan.release_slot(self.block, self.offset);
+                }
+            }
+        }
+
+        let block = token.list.block as *mut Block<T>;
+        let offset = token.list.offset;
+        let slot = (*block).slots.get_unchecked(offset);
+        slot.wait_write();
+        let consume = Consume {
+            chan: self,
+            block,
+            offset,
+        };
+        let res = f((*slot.msg.get()).assume_init_ref());
+        drop(consume);
+        Ok(res)
+    }
+
+    // slot中的msg已经被取走，必要时销毁block
+    unsafe fn release_slot(&self, block: *mut Block<T>, offset: usize) {
+        self.free_slot(block, offset);
+        self.on_read();
+    }
 
+    unsafe fn free_slot(&self, block: *mut Block<T>, offset: usize) {
         // Destroy the block if we've reached the end, or if another thread wanted to destroy but
         // couldn't because we were busy reading from the slot.
         if offset + 1 == BLOCK_CAP {
             Block::destroy(block, 0);
-        } else if slot.state.fetch_or(READ, Ordering::AcqRel) & DESTROY != 0 {
+        } else if (*block).slots.get_unchecked(offset).state.fetch_or(READ, Ordering::AcqRel) & DESTROY != 0 {
             Block::destroy(block, offset + 1);
         }
+    }
+
+    // 一个msg被接收之后通知等待的sender和wait_empty
+    fn on_read(&self) {
+        #[cfg(feature = "metrics")]
+        self.metrics.on_recv();
+        self.senders.notify();
+        self.drained.notify();
+        self.finish_closing();
+    }
 
+    /*
+     * 保留读取(ReplayReceiver)：head照常移动，但是msg被clone出来，原来的msg留在slot中，slot和block都不释放，
+     * 之后可以用rewind把head移回保留范围内的位置重新读取，release_retained释放整个保留范围
+     * 保留期间head之前的slot没有READ标记，其他receiver的start_recv/read和discard_all_messages都假设
+     * head之前的slot已经释放，所以这些方法的调用者必须是唯一的receiver
+     */
+    unsafe fn read_retained(&self, token: &mut Token) -> Result<T, ()>
+    where
+        T: Clone,
+    {
+        if token.list.block.is_null() {
+            return Err(());
+        }
+        let block = token.list.block as *mut Block<T>;
+        let slot = (*block).slots.get_unchecked(token.list.offset);
+        slot.wait_write();
+        let msg = (*slot.msg.get()).assume_init_ref().clone();
+        self.on_read();
         Ok(msg)
     }
 
+    // Safety同read_retained
+    pub(crate) unsafe fn try_recv_retained(&self) -> Result<T, TryRecvError>
+    where
+        T: Clone,
+    {
+        let token = &mut Token::exclusive();
+        if self.start_recv(token) {
+            self.read_retained(token).map_err(|_| TryRecvError::Disconnected)
+        } else {
+            Err(TryRecvError::Empty)
+        }
+    }
+
+    // Safety同read_retained
+    #[cfg(feature = "std")]
+    pub(crate) unsafe fn recv_retained(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError>
+    where
+        T: Clone,
+    {
+        let token = &mut Token::exclusive();
+        if !self.start_recv_blocking(token, deadline) {
+            return Err(RecvTimeoutError::Timeout);
+        }
+        self.read_retained(token).map_err(|_| RecvTimeoutError::Disconnected)
+    }
+
+    // 当前head的位置，还没有发送过msg时block为null
+    pub(crate) fn cursor(&self) -> Cursor {
+        Cursor {
+            index: self.head.index.load(Ordering::Acquire),
+            block: self.head.block.load(Ordering::Acquire) as *const u8,
+        }
+    }
+
+    // 把head移回cursor，Safety：cursor在保留范围内并且block不为null，调用者是唯一的receiver
+    pub(crate) unsafe fn rewind(&self, cursor: Cursor) {
+        self.head.block.store(cursor.block as *mut Block<T>, Ordering::Release);
+        self.head.index.store(cursor.index, Ordering::Release);
+    }
+
+    /*
+     * 结束保留：把从from到head之间被保留读取的msg drop掉，按正常接收的方式释放slot和block
+     * Safety：from是保留开始时的cursor，block不为null，调用者是唯一的receiver
+     */
+    pub(crate) unsafe fn release_retained(&self, from: Cursor) {
+        let end = self.head.index.load(Ordering::Acquire) >> SHIFT;
+        let mut index = from.index >> SHIFT;
+        let mut block = from.block as *mut Block<T>;
+        while index < end {
+            let offset = index % LAP;
+            if offset != BLOCK_CAP {
+                // 最后一个slot释放之后block就被销毁了，先取出next
+                let next = (*block).next.load(Ordering::Acquire);
+                let slot = (*block).slots.get_unchecked(offset);
+                ptr::drop_in_place((*slot.msg.get()).as_mut_ptr());
+                self.free_slot(block, offset);
+                if offset + 1 == BLOCK_CAP {
+                    block = next;
+                }
+            }
+            index += 1;
+        }
+    }
+
+    // 正在关闭的channel中最后一个msg被取走时真正断开，唤醒其他阻塞的receiver
+    fn finish_closing(&self) {
+        if self.closing.load(Ordering::SeqCst) && self.is_empty() {
+            self.disconnect();
+        }
+    }
+
+    /*
+     * 进入关闭状态：之后的send立即返回Disconnected，receiver可以继续取出剩余的msg，channel变空时才断开
+     * 只有把channel从连接状态变为关闭状态的那一次调用返回true
+     */
+    pub(crate) fn close_when_empty(&self) -> bool {
+        if self.closing.swap(true, Ordering::SeqCst) || self.is_disconnected() {
+            return false;
+        }
+        if self.is_empty() {
+            self.disconnect();
+        }
+        true
+    }
+
+    // 阻塞直到channel为空、abandoned返回true或者到了deadline，返回channel是否已经为空
+    #[cfg(feature = "std")]
+    pub(crate) fn wait_empty(&self, deadline: Option<Instant>, abandoned: impl Fn() -> bool) -> bool {
+        self.drained
+            .wait_observed(deadline, || self.is_empty() || abandoned());
+        self.is_empty()
+    }
+
     // 尝试接收一个msg(non-blocking)
     pub(crate) fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.try_recv_token(&mut Token::default())
+    }
+
+    // 与try_recv相同，同时返回start_recv中CAS重试的次数
+    #[cfg(feature = "profiling")]
+    pub(crate) fn try_recv_profiled(&self) -> (Result<T, TryRecvError>, u32) {
         let token = &mut Token::default();
+        let res = self.try_recv_token(token);
+        (res, token.retries)
+    }
 
+    /*
+     * 与try_recv相同，但是start_recv不用CAS移动head
+     * Safety: 调用期间不能有其他线程在这个channel上接收
+     */
+    pub(crate) unsafe fn try_recv_exclusive(&self) -> Result<T, TryRecvError> {
+        self.try_recv_token(&mut Token::exclusive())
+    }
+
+    fn try_recv_token(&self, token: &mut Token) -> Result<T, TryRecvError> {
         if self.start_recv(token) {
             unsafe { self.read(token).map_err(|_| TryRecvError::Disconnected) }
         } else {
@@ -388,25 +751,77 @@ impl<T> Channel<T> {
     }
 
     // 接收一个msg
+    #[cfg(feature = "std")]
     pub(crate) fn recv(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        self.recv_token(&mut Token::default(), deadline)
+    }
+
+    // 与recv相同，Safety同try_recv_exclusive
+    #[cfg(feature = "std")]
+    pub(crate) unsafe fn recv_exclusive(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        self.recv_token(&mut Token::exclusive(), deadline)
+    }
+
+    #[cfg(feature = "std")]
+    fn recv_token(&self, token: &mut Token, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        if !self.start_recv_blocking(token, deadline) {
+            return Err(RecvTimeoutError::Timeout);
+        }
+        unsafe { self.read(token) }.map_err(|_| RecvTimeoutError::Disconnected)
+    }
+
+    /*
+     * 与recv相同，但是msg留在slot中，把它的引用交给f，f返回之后才取走msg并释放slot
+     * f panic时msg仍然会被取走(drop)，slot照常释放
+     */
+    #[cfg(feature = "std")]
+    pub(crate) fn recv_with<R>(&self, deadline: Option<Instant>, f: impl FnOnce(&T) -> R) -> Result<R, RecvTimeoutError> {
         let token = &mut Token::default();
+        if !self.start_recv_blocking(token, deadline) {
+            return Err(RecvTimeoutError::Timeout);
+        }
+        unsafe { self.read_with(token, f) }.map_err(|_| RecvTimeoutError::Disconnected)
+    }
+
+    // 阻塞直到start_recv成功(token中是可以读取的slot或者断开)，到了deadline返回false
+    #[cfg(feature = "std")]
+    fn start_recv_blocking(&self, token: &mut Token, deadline: Option<Instant>) -> bool {
+        let backoff = Backoff::with_limit(self.spin_limit);
+        // 上一次park是否被sender的notify唤醒
+        let mut woken = false;
         loop {
-            if self.start_recv(token) {
-                unsafe {
-                    return self.read(token).map_err(|_| RecvTimeoutError::Disconnected);
+            // 先用Backoff自旋尝试几次，短暂的空闲不需要park线程
+            backoff.reset();
+            loop {
+                if self.start_recv(token) {
+                    return true;
                 }
+
+                if backoff.is_completed() {
+                    break;
+                } else {
+                    backoff.spin_light();
+                }
+            }
+
+            if woken {
+                #[cfg(feature = "metrics")]
+                self.metrics.on_spurious_wakeup();
             }
 
             if let Some(d) = deadline {
-                if Instant::now() >= d {
-                    return Err(RecvTimeoutError::Timeout);
+                if now() >= d {
+                    return false;
                 }
             }
 
             // Prepare for blocking until a sender wakes us up.
-            Context::with(|cx| {
+            woken = Context::with(|cx| {
                 let oper = Operation::hook(token);
                 self.receivers.register(oper, cx);
+                let guard = UnwindGuard::new(|| {
+                    self.receivers.unregister(oper);
+                });
 
                 // Has the channel become ready just now?
                 if !self.is_empty() || self.is_disconnected() {
@@ -415,6 +830,7 @@ impl<T> Channel<T> {
 
                 // Block the current thread.
                 let sel = cx.wait_until(deadline);
+                guard.disarm();
 
                 match sel {
                     Selected::Waiting => unreachable!(),
@@ -422,13 +838,57 @@ impl<T> Channel<T> {
                         self.receivers.unregister(oper).unwrap();
                         // If the channel was disconnected, we still have to check for remaining
                         // messages.
+                        false
                     }
-                    Selected::Operation(_) => {}
+                    Selected::Operation(_) => true,
                 }
             });
         }
     }
 
+    // 在receivers中注册/取消一个阻塞的接收操作，供需要同时等待多个channel的接收方使用
+    #[cfg(feature = "std")]
+    pub(crate) fn register_receiver(&self, oper: Operation, cx: &Context) {
+        self.receivers.register(oper, cx);
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn unregister_receiver(&self, oper: Operation) {
+        self.receivers.unregister(oper);
+    }
+
+    // try_send是否会立即返回(成功或Disconnected)
+    pub(crate) fn is_send_ready(&self) -> bool {
+        !self.is_full() || self.is_disconnected()
+    }
+
+    // try_recv是否会立即返回(收到msg或Disconnected)
+    pub(crate) fn is_recv_ready(&self) -> bool {
+        !self.is_empty() || self.is_disconnected()
+    }
+
+    // 丢弃channel中的msg并返回丢弃的数量
+    // 只处理调用时已经在channel中的msg(以len为上限)，并发的sender之后写入的msg不受影响
+    pub(crate) fn drain(&self) -> usize {
+        self.try_recv_batch(self.len(), drop)
+    }
+
+    // 非阻塞地取出最多max条msg依次交给f，channel变空或者断开时提前停止，返回取出的数量
+    pub(crate) fn try_recv_batch(&self, max: usize, mut f: impl FnMut(T)) -> usize {
+        let token = &mut Token::default();
+        let mut count = 0;
+        while count < max && self.start_recv(token) {
+            match unsafe { self.read(token) } {
+                Ok(msg) => {
+                    f(msg);
+                    count += 1;
+                }
+                Err(()) => break,
+            }
+        }
+        count
+    }
+
     /// Returns the current number of messages inside the channel.
     pub(crate) fn len(&self) -> usize {
         loop {
@@ -465,10 +925,35 @@ impl<T> Channel<T> {
         }
     }
 
+    // len的wait-free版本，只做一次relaxed的tail/head读取，结果可能过时，只适合用于监控、日志
+    pub(crate) fn len_relaxed(&self) -> usize {
+        let mut tail = self.tail.index.load(Ordering::Relaxed) & !((1 << SHIFT) - 1);
+        let mut head = self.head.index.load(Ordering::Relaxed) & !((1 << SHIFT) - 1);
+        // head在读取tail之后越过了它
+        if (tail.wrapping_sub(head) as isize) <= 0 {
+            return 0;
+        }
+        if (tail >> SHIFT) & (LAP - 1) == LAP - 1 {
+            tail = tail.wrapping_add(1 << SHIFT);
+        }
+        if (head >> SHIFT) & (LAP - 1) == LAP - 1 {
+            head = head.wrapping_add(1 << SHIFT);
+        }
+        let lap = (head >> SHIFT) / LAP;
+        tail = tail.wrapping_sub((lap * LAP) << SHIFT) >> SHIFT;
+        head = head.wrapping_sub((lap * LAP) << SHIFT) >> SHIFT;
+        (tail - head).saturating_sub(tail / LAP)
+    }
+
     pub(crate) fn capacity(&self) -> Option<usize> {
         None
     }
 
+    // 无界channel没有剩余容量的概念
+    pub(crate) fn remaining(&self) -> Option<usize> {
+        None
+    }
+
     /*
      * 断开senders并唤醒所有的receivers
      * 如果操作成功就返回true
@@ -479,12 +964,22 @@ impl<T> Channel<T> {
         let tail = self.tail.index.fetch_or(MARK_BIT, Ordering::SeqCst);
         if tail & MARK_BIT == 0 {
             self.receivers.disconnect();
+            self.senders.disconnect();
             true
         } else {
             false
         }
     }
 
+    /*
+     * 由Sender/Receiver显式断开channel时调用
+     * 与disconnect_receivers不同，这里不会丢弃msg，因为此时可能还有其他receiver正在读取，
+     * 剩余的msg在Channel被drop时释放
+     */
+    pub(crate) fn disconnect(&self) -> bool {
+        self.disconnect_senders()
+    }
+
     /*
      * 断开receivers，如果原来tail的索引就是奇数，说明已经disconnected
      * 此时应该丢弃所有消息并释放内存
@@ -496,8 +991,12 @@ impl<T> Channel<T> {
             // If receivers are dropped first, discard all messages to free
             // memory eagerly.
             self.discard_all_messages();
+            self.senders.disconnect();
+            self.drained.notify();
             true
         } else {
+            // channel已经被sender断开，剩余的msg不会再被接收，唤醒wait_empty让它放弃等待
+            self.drained.notify();
             false
         }
     }
@@ -558,14 +1057,62 @@ impl<T> Channel<T> {
         self.tail.index.load(Ordering::SeqCst) & MARK_BIT != 0
     }
 
+    // close_when_empty之后或者已经断开时为true，之后不会再有新的msg写入
+    pub(crate) fn is_closing(&self) -> bool {
+        self.closing.load(Ordering::SeqCst) || self.is_disconnected()
+    }
+
+    /*
+     * 不消耗msg，按顺序clone从head到tail之间已经写入的msg
+     * 调用者必须保证没有其他线程在接收：head不会移动，block也不会被释放
+     * 还没有设置WRITE的slot(sender已经预留但没有写完)会被跳过，
+     * 遇到还没有链接上的下一个block时停止，之后的slot都还没有写入
+     */
+    #[cfg(feature = "serde")]
+    pub(crate) unsafe fn snapshot(&self) -> Vec<T>
+    where
+        T: Clone,
+    {
+        let mut msgs = Vec::new();
+        let mut head = self.head.index.load(Ordering::Acquire) & !MARK_BIT;
+        let mut block = self.head.block.load(Ordering::Acquire);
+        let tail = self.tail.index.load(Ordering::Acquire) & !MARK_BIT;
+        while head >> SHIFT != tail >> SHIFT && !block.is_null() {
+            let offset = (head >> SHIFT) % LAP;
+            if offset == BLOCK_CAP {
+                block = (*block).next.load(Ordering::Acquire);
+                head = head.wrapping_add(1 << SHIFT);
+                continue;
+            }
+            let slot = (*block).slots.get_unchecked(offset);
+            if slot.state.load(Ordering::Acquire) & WRITE != 0 {
+                msgs.push((*slot.msg.get()).assume_init_ref().clone());
+            }
+            head = head.wrapping_add(1 << SHIFT);
+        }
+        msgs
+    }
+
+    // channel已经断开并且没有剩余的msg，之后再也不会有msg可读
+    pub(crate) fn is_disconnected_and_empty(&self) -> bool {
+        let tail = self.tail.index.load(Ordering::SeqCst);
+        let head = self.head.index.load(Ordering::SeqCst);
+        tail & MARK_BIT != 0 && head >> SHIFT == tail >> SHIFT
+    }
+
     pub(crate) fn is_empty(&self) -> bool {
         let head = self.head.index.load(Ordering::SeqCst);
         let tail = self.tail.index.load(Ordering::SeqCst);
         head >> SHIFT == tail >> SHIFT
     }
 
+    pub(crate) fn has_soft_limit(&self) -> bool {
+        self.soft_limit.is_some()
+    }
+
+    // 只有设置了soft_limit并且len达到上限时才是满的
     pub(crate) fn is_full(&self) -> bool {
-        false
+        self.soft_limit.is_some_and(|limit| self.len() >= limit)
     }
 }
 
@@ -607,3 +1154,83 @@ impl<T> Drop for Channel<T> {
         }
     }
 }
+
+// select使用的句柄，分别代表在这个channel上的接收操作和发送操作
+#[cfg(feature = "std")]
+pub(crate) struct Receiver<'a, T>(&'a Channel<T>);
+#[cfg(feature = "std")]
+pub(crate) struct Sender<'a, T>(&'a Channel<T>);
+
+#[cfg(feature = "std")]
+impl<T> Channel<T> {
+    pub(crate) fn receiver(&self) -> Receiver<'_, T> {
+        Receiver(self)
+    }
+    pub(crate) fn sender(&self) -> Sender<'_, T> {
+        Sender(self)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T> SelectHandle for Receiver<'_, T> {
+    fn try_select(&self, token: &mut Token) -> bool {
+        self.0.start_recv(token)
+    }
+    fn register(&self, oper: Operation, cx: &Context) -> bool {
+        self.0.receivers.register(oper, cx);
+        self.is_ready()
+    }
+    fn unregister(&self, oper: Operation) {
+        self.0.receivers.unregister(oper);
+    }
+    fn accept(&self, token: &mut Token, _cx: &Context) -> bool {
+        self.try_select(token)
+    }
+    fn is_ready(&self) -> bool {
+        self.0.is_recv_ready()
+    }
+    fn watch(&self, oper: Operation, cx: &Context) -> bool {
+        self.0.receivers.watch(oper, cx);
+        self.is_ready()
+    }
+    fn unwatch(&self, oper: Operation) {
+        self.0.receivers.unwatch(oper);
+    }
+}
+
+// 没有soft_limit时发送总是ready的，有soft_limit时与array channel一样在senders中等待
+#[cfg(feature = "std")]
+impl<T> SelectHandle for Sender<'_, T> {
+    fn try_select(&self, token: &mut Token) -> bool {
+        self.is_ready() && self.0.start_send(token)
+    }
+    fn register(&self, oper: Operation, cx: &Context) -> bool {
+        self.0.senders.register(oper, cx);
+        self.is_ready()
+    }
+    fn unregister(&self, oper: Operation) {
+        self.0.senders.unregister(oper);
+    }
+    fn accept(&self, token: &mut Token, _cx: &Context) -> bool {
+        self.try_select(token)
+    }
+    fn is_ready(&self) -> bool {
+        self.0.is_send_ready()
+    }
+    fn watch(&self, oper: Operation, cx: &Context) -> bool {
+        self.0.senders.watch(oper, cx);
+        self.is_ready()
+    }
+    fn unwatch(&self, oper: Operation) {
+        self.0.senders.unwatch(oper);
+    }
+}
+
+#[cfg(not(loom))]
+#[test]
+fn channel_new_is_const() {
+    let chan = const { Channel::<String>::new() };
+    chan.send("a".to_string()).unwrap();
+    assert_eq!(chan.len(), 1);
+    assert_eq!(chan.try_recv().unwrap(), "a");
+}
@@ -0,0 +1,143 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::VecDeque,
+    fmt, mem,
+    rc::Rc,
+};
+
+use super::errors::*;
+
+/*
+ * 单线程使用的无界channel
+ * 所有的handle都在同一个线程中，因此不需要原子操作和Waker，也不要求T: Send，
+ * 可以直接传递Rc之类的值。LocalSender/LocalReceiver本身也不能被发送到其他线程
+ * 因为不存在另一个线程来发送msg，所以没有阻塞的recv，只提供try_recv
+ * 不复用list channel的block链表：那套算法的复杂度(按slot的WRITE/READ状态位、block的延迟安装和销毁)
+ * 全部是为了让多个线程无锁地同时发送和接收，单线程下只剩开销。RefCell<VecDeque>按需扩容、
+ * 连续存储，push/pop都是O(1)，内存会被复用而不是每31条msg分配、释放一个block
+ */
+
+// 所有handle共享的状态，相当于counter::Counter和list::Channel的合体
+struct Shared<T> {
+    queue: RefCell<VecDeque<T>>,
+    senders: Cell<usize>,
+    receivers: Cell<usize>,
+}
+
+// 创建一个单线程的无界channel
+pub fn channel<T>() -> (LocalSender<T>, LocalReceiver<T>) {
+    let shared = Rc::new(Shared {
+        queue: RefCell::new(VecDeque::new()),
+        senders: Cell::new(1),
+        receivers: Cell::new(1),
+    });
+    let s = LocalSender {
+        shared: shared.clone(),
+    };
+    let r = LocalReceiver { shared };
+    (s, r)
+}
+
+pub struct LocalSender<T> {
+    shared: Rc<Shared<T>>,
+}
+
+impl<T> LocalSender<T> {
+    // 发送msg，channel无界所以不会阻塞，只有所有LocalReceiver都被drop时才会失败
+    pub fn send(&self, msg: T) -> Result<(), SendError<T>> {
+        if self.shared.receivers.get() == 0 {
+            return Err(SendError(msg));
+        }
+        self.shared.queue.borrow_mut().push_back(msg);
+        Ok(())
+    }
+    pub fn len(&self) -> usize {
+        self.shared.queue.borrow().len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.shared.queue.borrow().is_empty()
+    }
+    pub fn is_disconnected(&self) -> bool {
+        self.shared.receivers.get() == 0
+    }
+}
+
+impl<T> Clone for LocalSender<T> {
+    fn clone(&self) -> Self {
+        self.shared.senders.set(self.shared.senders.get() + 1);
+        LocalSender {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for LocalSender<T> {
+    fn drop(&mut self) {
+        self.shared.senders.set(self.shared.senders.get() - 1);
+    }
+}
+
+impl<T> fmt::Debug for LocalSender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LocalSender")
+            .field("len", &self.len())
+            .field("disconnected", &self.is_disconnected())
+            .finish()
+    }
+}
+
+pub struct LocalReceiver<T> {
+    shared: Rc<Shared<T>>,
+}
+
+impl<T> LocalReceiver<T> {
+    // 与Receiver::try_recv相同，channel为空时根据是否还有LocalSender返回Empty或Disconnected
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        // 先释放borrow再返回，msg的析构可能会再次访问这个channel
+        let msg = self.shared.queue.borrow_mut().pop_front();
+        match msg {
+            Some(msg) => Ok(msg),
+            None if self.shared.senders.get() == 0 => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.shared.queue.borrow().len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.shared.queue.borrow().is_empty()
+    }
+    pub fn is_disconnected(&self) -> bool {
+        self.shared.senders.get() == 0
+    }
+}
+
+impl<T> Clone for LocalReceiver<T> {
+    fn clone(&self) -> Self {
+        self.shared.receivers.set(self.shared.receivers.get() + 1);
+        LocalReceiver {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for LocalReceiver<T> {
+    fn drop(&mut self) {
+        let receivers = self.shared.receivers.get() - 1;
+        self.shared.receivers.set(receivers);
+        // 和list::Channel::disconnect_receivers一样，最后一个receiver离开时尽早释放所有msg
+        if receivers == 0 {
+            let msgs = mem::take(&mut *self.shared.queue.borrow_mut());
+            drop(msgs);
+        }
+    }
+}
+
+impl<T> fmt::Debug for LocalReceiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LocalReceiver")
+            .field("len", &self.len())
+            .field("disconnected", &self.is_disconnected())
+            .finish()
+    }
+}
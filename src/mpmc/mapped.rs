@@ -0,0 +1,129 @@
+use std::{fmt, marker::PhantomData};
+
+use super::{errors::*, Receiver, Sender};
+
+/*
+ * Receiver::map返回的适配器，在接收时用f把T转换成U
+ * 转换发生在接收的线程上，不需要中间的channel；f是FnMut，所以接收方法都需要&mut self
+ * 错误不包含msg，原样返回
+ */
+pub struct MappedReceiver<T, U, F> {
+    receiver: Receiver<T>,
+    f: F,
+    _marker: PhantomData<fn() -> U>,
+}
+
+impl<T, U, F: FnMut(T) -> U> MappedReceiver<T, U, F> {
+    pub(crate) fn new(receiver: Receiver<T>, f: F) -> Self {
+        MappedReceiver {
+            receiver,
+            f,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn recv(&mut self) -> Result<U, RecvError> {
+        self.receiver.recv().map(&mut self.f)
+    }
+
+    pub fn try_recv(&mut self) -> Result<U, TryRecvError> {
+        self.receiver.try_recv().map(&mut self.f)
+    }
+
+    // 阻塞地迭代转换后的msg，channel断开并且没有剩余msg时结束
+    pub fn iter(&mut self) -> MappedIter<'_, T, U, F> {
+        MappedIter { rx: self }
+    }
+
+    pub fn get_ref(&self) -> &Receiver<T> {
+        &self.receiver
+    }
+
+    // 取回原来的Receiver，丢弃f
+    pub fn into_inner(self) -> Receiver<T> {
+        self.receiver
+    }
+}
+
+impl<T, U, F> fmt::Debug for MappedReceiver<T, U, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MappedReceiver")
+            .field("receiver", &self.receiver)
+            .finish_non_exhaustive()
+    }
+}
+
+// MappedReceiver::iter返回的迭代器
+pub struct MappedIter<'a, T, U, F> {
+    rx: &'a mut MappedReceiver<T, U, F>,
+}
+
+impl<T, U, F: FnMut(T) -> U> Iterator for MappedIter<'_, T, U, F> {
+    type Item = U;
+
+    fn next(&mut self) -> Option<U> {
+        self.rx.recv().ok()
+    }
+}
+
+impl<T, U, F> fmt::Debug for MappedIter<'_, T, U, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MappedIter").finish_non_exhaustive()
+    }
+}
+
+/*
+ * Sender::comap返回的适配器，发送前先用f把U转换成T
+ * 转换在检查channel状态之前完成，所以发送失败时错误中携带的是已经转换过的T，原来的U已经被f消耗了
+ */
+pub struct MappedSender<U, T, F> {
+    sender: Sender<T>,
+    f: F,
+    _marker: PhantomData<fn(U)>,
+}
+
+impl<U, T, F: FnMut(U) -> T> MappedSender<U, T, F> {
+    pub(crate) fn new(sender: Sender<T>, f: F) -> Self {
+        MappedSender {
+            sender,
+            f,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn send(&mut self, msg: U) -> Result<(), SendError<T>> {
+        self.sender.send((self.f)(msg))
+    }
+
+    pub fn try_send(&mut self, msg: U) -> Result<(), TrySendError<T>> {
+        self.sender.try_send((self.f)(msg))
+    }
+
+    pub fn get_ref(&self) -> &Sender<T> {
+        &self.sender
+    }
+
+    // 取回原来的Sender，丢弃f
+    pub fn into_inner(self) -> Sender<T> {
+        self.sender
+    }
+}
+
+// f可以clone时，可以像Sender一样分发给多个producer
+impl<U, T, F: Clone> Clone for MappedSender<U, T, F> {
+    fn clone(&self) -> Self {
+        MappedSender {
+            sender: self.sender.clone(),
+            f: self.f.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<U, T, F> fmt::Debug for MappedSender<U, T, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MappedSender")
+            .field("sender", &self.sender)
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,97 @@
+use std::fmt;
+
+use super::{errors::*, Receiver, Select};
+
+/*
+ * 把多个Receiver合并成一个，从任意一个有msg的source接收
+ * 阻塞时通过Select同时等待所有source；某个source断开并且取空之后就被移除，
+ * 所有source都移除之后recv返回RecvError。每个source内部保持FIFO，不同source之间没有顺序保证
+ * 移除source需要修改内部的列表，所以接收方法都需要&mut self
+ */
+pub fn merge<T>(receivers: Vec<Receiver<T>>) -> Merged<T> {
+    Merged { receivers }
+}
+
+pub struct Merged<T> {
+    receivers: Vec<Receiver<T>>,
+}
+
+impl<T> Merged<T> {
+    pub fn recv(&mut self) -> Result<T, RecvError> {
+        while !self.receivers.is_empty() {
+            let (index, res) = {
+                let mut sel = Select::new();
+                for r in &self.receivers {
+                    sel.recv(r);
+                }
+                let oper = sel.select();
+                let index = oper.index();
+                (index, oper.recv(&self.receivers[index]))
+            };
+            match res {
+                Ok(msg) => return Ok(msg),
+                Err(RecvError) => drop(self.receivers.remove(index)),
+            }
+        }
+        Err(RecvError)
+    }
+
+    // 还有没断开的source但都暂时没有msg时返回Empty，所有source都已经断开并且取空时返回Disconnected
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        while !self.receivers.is_empty() {
+            let (index, res) = {
+                let mut sel = Select::new();
+                for r in &self.receivers {
+                    sel.recv(r);
+                }
+                let Ok(oper) = sel.try_select() else {
+                    return Err(TryRecvError::Empty);
+                };
+                let index = oper.index();
+                (index, oper.recv(&self.receivers[index]))
+            };
+            match res {
+                Ok(msg) => return Ok(msg),
+                Err(RecvError) => drop(self.receivers.remove(index)),
+            }
+        }
+        Err(TryRecvError::Disconnected)
+    }
+
+    // 阻塞地迭代所有source的msg，全部断开并且取空时结束
+    pub fn iter(&mut self) -> MergedIter<'_, T> {
+        MergedIter { merged: self }
+    }
+
+    // 还没有被移除的source数量
+    pub fn sources(&self) -> usize {
+        self.receivers.len()
+    }
+}
+
+impl<T> fmt::Debug for Merged<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Merged")
+            .field("receivers", &self.receivers)
+            .finish()
+    }
+}
+
+// Merged::iter返回的迭代器
+pub struct MergedIter<'a, T> {
+    merged: &'a mut Merged<T>,
+}
+
+impl<T> Iterator for MergedIter<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.merged.recv().ok()
+    }
+}
+
+impl<T> fmt::Debug for MergedIter<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MergedIter").finish_non_exhaustive()
+    }
+}
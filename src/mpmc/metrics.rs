@@ -0,0 +1,79 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/*
+ * channel的负载统计，只在开启metrics feature时编译
+ * 每个flavor的Channel持有一个Metrics，在write/read中更新，
+ * 计数只用于观察，不参与同步，所以使用Relaxed
+ */
+
+// Sender::metrics/Receiver::metrics返回的快照
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChannelMetrics {
+    // 成功写入channel的msg总数
+    pub sent: usize,
+    // 从channel读出的msg总数
+    pub received: usize,
+    // 写入msg之后观察到的最大len，zero channel不缓存msg，总是0
+    pub peak_len: usize,
+    // 阻塞的receiver被sender唤醒之后却没有取到msg、只能重新park的次数
+    // 一次notify只应该唤醒一个receiver，这个值持续增长说明唤醒了多余的receiver(惊群)
+    pub spurious_wakeups: usize,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Metrics {
+    sent: AtomicUsize,
+    received: AtomicUsize,
+    peak_len: AtomicUsize,
+    spurious_wakeups: AtomicUsize,
+}
+
+impl Metrics {
+    pub(crate) const fn new() -> Self {
+        Metrics {
+            sent: AtomicUsize::new(0),
+            received: AtomicUsize::new(0),
+            peak_len: AtomicUsize::new(0),
+            spurious_wakeups: AtomicUsize::new(0),
+        }
+    }
+
+    // 写入一个msg，len是写入之后channel的长度
+    pub(crate) fn on_send(&self, len: usize) {
+        self.sent.fetch_add(1, Ordering::Relaxed);
+        let mut peak = self.peak_len.load(Ordering::Relaxed);
+        while len > peak {
+            match self.peak_len.compare_exchange_weak(peak, len, Ordering::Relaxed, Ordering::Relaxed) {
+                Ok(_) => break,
+                Err(p) => peak = p,
+            }
+        }
+    }
+
+    pub(crate) fn on_recv(&self) {
+        self.received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn on_spurious_wakeup(&self) {
+        self.spurious_wakeups.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> ChannelMetrics {
+        ChannelMetrics {
+            sent: self.sent.load(Ordering::Relaxed),
+            received: self.received.load(Ordering::Relaxed),
+            peak_len: self.peak_len.load(Ordering::Relaxed),
+            spurious_wakeups: self.spurious_wakeups.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[test]
+fn peak_len_only_grows() {
+    let m = Metrics::new();
+    m.on_send(3);
+    m.on_send(1);
+    m.on_recv();
+    m.on_spurious_wakeup();
+    assert_eq!(m.snapshot(), ChannelMetrics { sent: 2, received: 1, peak_len: 3, spurious_wakeups: 1 });
+}
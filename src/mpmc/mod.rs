@@ -1,62 +1,445 @@
+/*
+ * 关闭std feature时crate是no_std的(需要alloc)：没有std就不能park线程，也没有Instant，
+ * 所以只有array和list两种channel的非阻塞API：try_send/try_recv以及长度、容量、断开状态的查询。
+ * zero channel必须两个线程同时阻塞才能配对，在no_std下不可用。channel的实现是同一份代码，
+ * 阻塞相关的部分由std feature控制，select和waker由nostd.rs中的最小实现代替
+ */
+
 // zero array list 分别是三种类型的channel
+#[cfg(feature = "std")]
 mod zero;
+#[cfg_attr(not(feature = "std"), allow(dead_code))]
 mod array;
+#[cfg_attr(not(feature = "std"), allow(dead_code))]
 mod list;
 // context
+#[cfg(feature = "std")]
 mod context;
 // utils
+#[cfg_attr(not(feature = "std"), allow(dead_code))]
 mod utils;
+// sync 原子类型和Mutex的来源，loom构建时替换成loom的实现
+#[cfg_attr(not(feature = "std"), allow(dead_code))]
+mod sync;
 // waker 通道中被阻塞线程的唤醒机制
+#[cfg(feature = "std")]
 mod waker;
-// select
+// select 同时等待多个channel上的操作
+#[cfg(feature = "std")]
 mod select;
+// nostd 没有std时代替select和waker的最小实现
+#[cfg(not(feature = "std"))]
+mod nostd;
+#[cfg(not(feature = "std"))]
+use self::nostd::{select, waker};
 // counter
+#[cfg_attr(not(feature = "std"), allow(dead_code))]
 mod counter;
 // errors
 mod errors;
+// event Builder::on_recv回调收到的接收事件
+#[cfg_attr(not(feature = "std"), allow(dead_code))]
+mod event;
+// local 单线程使用的channel，不需要原子操作和Send约束
+#[cfg(feature = "std")]
+pub mod local;
+// broadcast 每个receiver都能收到每一条msg的广播channel
+#[cfg(feature = "std")]
+mod broadcast;
+// priority 按优先级接收msg的channel
+#[cfg(feature = "std")]
+mod priority;
+// latest 按key合并msg，只保留每个key最新的值的channel
+#[cfg(feature = "std")]
+mod latest;
+// ack 需要确认的channel，没有确认的msg会被重新投递
+#[cfg(feature = "std")]
+mod ack;
+// mapped 在发送端或接收端转换msg的适配器
+#[cfg(feature = "std")]
+mod mapped;
+// merged 把多个Receiver合并成一个
+#[cfg(feature = "std")]
+mod merged;
+// bytes 传输字节的channel的io::Read/Write适配器
+#[cfg(feature = "std")]
+mod bytes;
+// replay 可以回到记录的位置重新读取的list channel receiver
+#[cfg(feature = "std")]
+mod replay;
+// metrics 可选的负载统计
+#[cfg(feature = "metrics")]
+#[cfg_attr(not(feature = "std"), allow(dead_code))]
+mod metrics;
+// snapshot 不消耗msg的channel快照
+#[cfg(feature = "serde")]
+mod snapshot;
+// model loom模型检查
+#[cfg(all(test, loom))]
+mod model;
 
+use core::panic::{RefUnwindSafe, UnwindSafe};
+#[cfg(feature = "std")]
 use std::fmt;
-use std::panic::{RefUnwindSafe, UnwindSafe};
+#[cfg(feature = "std")]
+use std::hash::{Hash, Hasher};
+#[cfg(feature = "std")]
+use std::mem::{self, MaybeUninit};
+#[cfg(feature = "std")]
+use std::sync::Arc;
+#[cfg(feature = "std")]
 use std::time::{Duration, Instant};
-use crate::mpmc::errors::*;
+pub use crate::mpmc::errors::*;
+#[cfg(feature = "std")]
+pub use crate::mpmc::event::{RecvEvent, RecvOutcome};
+#[cfg(feature = "std")]
+pub use crate::mpmc::select::{Select, SelectedOperation};
+#[cfg(feature = "std")]
+use crate::mpmc::context::Context;
+#[cfg(feature = "std")]
+pub use crate::mpmc::context::{with_clock, with_parker, Clock, Parker};
+#[cfg(feature = "std")]
+use crate::mpmc::select::{Operation, SelectHandle, Token};
+#[cfg(feature = "std")]
+use crate::mpmc::utils::Backoff;
+#[cfg(feature = "std")]
+pub use crate::mpmc::broadcast::{broadcast, BroadcastReceiver, BroadcastSender, Overflow};
+#[cfg(feature = "std")]
+pub use crate::mpmc::priority::{priority_channel, PriorityReceiver, PrioritySender};
+#[cfg(feature = "std")]
+pub use crate::mpmc::latest::{latest_channel, LatestReceiver, LatestSender};
+#[cfg(feature = "std")]
+pub use crate::mpmc::ack::{ack_channel, AckReceiver, AckSender, Delivery};
+#[cfg(feature = "std")]
+pub use crate::mpmc::mapped::{MappedIter, MappedReceiver, MappedSender};
+#[cfg(feature = "std")]
+pub use crate::mpmc::merged::{merge, Merged, MergedIter};
+#[cfg(feature = "std")]
+pub use crate::mpmc::bytes::{ByteReceiver, ByteSender};
+#[cfg(feature = "std")]
+pub use crate::mpmc::replay::{Checkpoint, ReplayReceiver};
+#[cfg(all(test, feature = "std"))]
+pub(crate) use crate::mpmc::counter::live::count as live_counters;
+#[cfg(all(test, feature = "std"))]
+pub(crate) use crate::mpmc::utils::spins::count as spin_count;
+#[cfg(feature = "metrics")]
+pub use crate::mpmc::metrics::ChannelMetrics;
+#[cfg(feature = "serde")]
+pub use crate::mpmc::snapshot::Snapshot;
+
+/*
+ * msg的顺序
+ * array和list channel中每条msg在start_send中用tail的CAS得到一个位置，receiver在start_recv中用head的CAS
+ * 按位置依次取出，所以channel本身是严格FIFO的：
+ * - 同一个sender依次发送的msg，位置一定递增，只有一个receiver时按发送顺序收到
+ * - 同一个receiver依次收到的msg，位置也一定递增，所以它收到的来自同一个sender的msg保持发送顺序
+ * - 多个receiver之间没有顺序保证：先取得位置的receiver可能更晚从recv返回
+ * - 多个sender并发发送时，msg的顺序就是它们CAS成功的顺序，与send被调用的先后无关
+ * zero channel每条msg都是一对sender和receiver直接交接，上面对单个sender和单个receiver的保证同样成立
+ */
+
+// sync_channel允许的最大容量，stamp需要在head/tail中同时放下index、mark位和lap计数
+pub const MAX_CAPACITY: usize = array::MAX_CAPACITY;
 
 // 创建无限容量的channel，即list::Channel<T>
+#[cfg(feature = "std")]
 pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
-    let (s, r) = counter::new(list::Channel::new());
-    let s = Sender {
-        flavor: SenderFlavor::List(s),
-    };
-    let r = Receiver {
-        flavor: ReceiverFlavor::List(r),
-    };
-    (s, r)
+    Builder::new().channel()
 }
 
 /*
  * 创建有限容量并且是发送和接收都是阻塞操作的同步channel
  * 当cap=0时，创建的是zero::Channel<T>，cap为0意味channel不持有msg，需要有一对线程同时协作，一个发送信息，一个接收信息
  * 当cao>0时，创建的时array::Channel<T>
+ * 注意zero channel的send需要另一个线程的recv配对，只有一个线程时send会永远阻塞
+ * cap大于MAX_CAPACITY时panic
  */
+#[cfg(feature = "std")]
 pub fn sync_channel<T>(cap: usize) -> (Sender<T>, Receiver<T>) {
-    if cap == 0 {
-        let (s, r) = counter::new(zero::Channel::new());
+    Builder::new().sync_channel(cap)
+}
+
+// no_std下没有Builder，直接创建无限容量的list channel
+#[cfg(not(feature = "std"))]
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let (s, r) = counter::new(list::Channel::new(), None, None, None);
+    (Sender { flavor: SenderFlavor::List(s) }, Receiver { flavor: ReceiverFlavor::List(r) })
+}
+
+// no_std下没有zero channel，cap必须大于0
+#[cfg(not(feature = "std"))]
+pub fn sync_channel<T>(cap: usize) -> (Sender<T>, Receiver<T>) {
+    let (s, r) = counter::new(array::Channel::with_capacity(cap), None, None, None);
+    (Sender { flavor: SenderFlavor::Array(s) }, Receiver { flavor: ReceiverFlavor::Array(r) })
+}
+
+/*
+ * 创建zero channel，等价于sync_channel(0)
+ * 每次send都要等到另一个线程的recv与它配对才返回，明确表示需要同步交接，不会因为传错cap而得到有缓冲的channel
+ */
+#[cfg(feature = "std")]
+pub fn rendezvous<T>() -> (Sender<T>, Receiver<T>) {
+    Builder::new().rendezvous()
+}
+
+/*
+ * 创建一个已经装满iter中所有msg的有限容量channel，receiver按iter的顺序接收
+ * 容量等于iter的长度，所以msg总能全部放进去，不会退化成list channel；之后的send会阻塞到有msg被接收
+ * iter为空时容量为1，而不是创建zero channel
+ */
+#[cfg(feature = "std")]
+pub fn bounded_from_iter<T, I: IntoIterator<Item = T>>(iter: I) -> (Sender<T>, Receiver<T>) {
+    let msgs: Vec<T> = iter.into_iter().collect();
+    let (s, r) = sync_channel(msgs.len().max(1));
+    for msg in msgs {
+        if s.try_send(msg).is_err() {
+            unreachable!("channel sized to the iterator cannot be full");
+        }
+    }
+    (s, r)
+}
+
+// channel的构造器，用于在创建channel时附加额外的配置
+#[cfg(feature = "std")]
+#[derive(Default, Clone)]
+pub struct Builder {
+    name: Option<Arc<str>>,
+    on_disconnect: Option<counter::OnDisconnect>,
+    on_recv: Option<counter::OnRecv>,
+    soft_limit: Option<usize>,
+    recycle: bool,
+    spin_before_park: Option<u32>,
+}
+
+#[cfg(feature = "std")]
+impl fmt::Debug for Builder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Builder")
+            .field("name", &self.name)
+            .field("on_disconnect", &self.on_disconnect.is_some())
+            .field("on_recv", &self.on_recv.is_some())
+            .field("soft_limit", &self.soft_limit)
+            .field("recycle", &self.recycle)
+            .field("spin_before_park", &self.spin_before_park)
+            .finish()
+    }
+}
+
+#[cfg(feature = "std")]
+impl Builder {
+    /*
+     * const fn，可以放在static中作为全局channel的配置
+     * channel的共享状态必须在堆上分配，无法直接放进static，全局channel推荐用OnceLock延迟创建：
+     *     static BUS: OnceLock<(Sender<Event>, Receiver<Event>)> = OnceLock::new();
+     *     let (tx, rx) = BUS.get_or_init(channel);
+     * 需要配置时可以把Builder::new().soft_limit(..)这样的Builder放在static中，在get_or_init里clone一份再创建channel
+     */
+    pub const fn new() -> Builder {
+        Builder {
+            name: None,
+            on_disconnect: None,
+            on_recv: None,
+            soft_limit: None,
+            recycle: false,
+            spin_before_park: None,
+        }
+    }
+
+    // 为channel设置名字，所有clone出来的Sender/Receiver共享这个名字，Debug输出中会包含它
+    pub fn name(mut self, name: impl Into<Arc<str>>) -> Builder {
+        self.name = Some(name.into());
+        self
+    }
+
+    /*
+     * channel断开时调用一次f，无论是最后一个Sender/Receiver被drop，还是显式调用了disconnect或close_when_empty
+     * 可以用来发现还在发送但已经没有receiver的channel这类生命周期问题
+     * f在断开的线程上、不持有channel内部锁的情况下执行，所以在f中操作这个channel不会死锁
+     */
+    pub fn on_disconnect(mut self, f: Box<dyn Fn() + Send + Sync>) -> Builder {
+        self.on_disconnect = Some(Arc::from(f));
+        self
+    }
+
+    /*
+     * 每次recv/recv_timeout/recv_deadline/try_recv返回之前调用一次f，事件中包含是否等待过、等待的时长和结果，
+     * 可以用来接入tracing之类的观测工具。与metrics不同，它不做任何聚合
+     * Receiver上其他接收msg的方法(with_next、recv_exclusive、recv_metered、poll、迭代器等)同样每次调用报告一次；
+     * recv_into、recv_many_timeout这类批量接收中不需要等待就取出的msg每条报告一次Received，不报告取完时的Empty
+     * 例外：drain丢弃的msg没有被接收，不会报告
+     * f在接收的线程上、所有内部锁都已经释放之后执行，在f中操作这个channel不会死锁
+     */
+    pub fn on_recv(mut self, f: Box<dyn Fn(RecvEvent) + Send + Sync>) -> Builder {
+        self.on_recv = Some(Arc::from(f));
+        self
+    }
+
+    /*
+     * 为channel()创建的无界channel设置软上限：len达到limit时send/send_timeout阻塞、try_send返回Full，
+     * 每接收一个msg唤醒一个阻塞的sender，就像一个容量可以随时变化的有界channel，但仍然按需分配block，没有预分配的数组
+     * 并发的sender可能同时通过检查，所以len可能短暂超过limit；reserve不受这个上限限制
+     * 对sync_channel()没有作用，limit为0时panic
+     */
+    pub const fn soft_limit(mut self, limit: usize) -> Builder {
+        assert!(limit > 0, "soft limit must be positive");
+        self.soft_limit = Some(limit);
+        self
+    }
+
+    /*
+     * 所有Sender/Receiver(包括弱引用)都被释放之后，不把channel的共享内存还给分配器，
+     * 而是放进释放它的线程的缓存中，之后这个线程上同样开启了recycle的Builder创建channel时直接复用
+     * 用于频繁创建、很快销毁的短命channel，减少分配次数；每个线程只缓存有限的数量
+     * 复用只涉及内存：旧channel中剩余的msg在放进缓存之前就已经被drop，新channel总是空的
+     */
+    pub const fn recycle(mut self) -> Builder {
+        self.recycle = true;
+        self
+    }
+
+    /*
+     * 阻塞操作在注册到waker、park线程之前自旋重试的上限(Backoff的步数)，默认与之前的行为相同
+     * 作用于array channel的send以及list channel的recv，它们是会在park之前先自旋的路径；
+     * 数值越小越早park，占用的CPU越少但唤醒延迟更高，0表示只重试一次就park。对zero channel没有作用
+     * 这是channel的默认值，单个Sender可以用Sender::with_spin覆盖
+     */
+    pub const fn spin_before_park(mut self, limit: u32) -> Builder {
+        self.spin_before_park = Some(limit);
+        self
+    }
+
+    fn counter<C>(self, chan: C) -> (counter::Sender<C>, counter::Receiver<C>) {
+        if self.recycle {
+            counter::new_recycled(chan, self.name, self.on_disconnect, self.on_recv)
+        } else {
+            counter::new(chan, self.name, self.on_disconnect, self.on_recv)
+        }
+    }
+
+    // 与channel()相同，创建无限容量的channel
+    pub fn channel<T>(self) -> (Sender<T>, Receiver<T>) {
+        let mut chan = list::Channel::with_soft_limit(self.soft_limit);
+        if let Some(limit) = self.spin_before_park {
+            chan = chan.spin_before_park(limit);
+        }
+        let (s, r) = self.counter(chan);
         let s = Sender {
-            flavor: SenderFlavor::Zero(s),
+            flavor: SenderFlavor::List(s),
+            spin: None,
         };
         let r = Receiver {
-            flavor: ReceiverFlavor::Zero(r),
+            flavor: ReceiverFlavor::List(r),
         };
         (s, r)
-    } else {
-        let (s, r) = counter::new(array::Channel::with_capacity(cap));
+    }
+
+    // 与rendezvous()相同，创建zero channel
+    pub fn rendezvous<T>(self) -> (Sender<T>, Receiver<T>) {
+        let (s, r) = self.counter(zero::Channel::new());
         let s = Sender {
-            flavor: SenderFlavor::Array(s),
+            flavor: SenderFlavor::Zero(s),
+            spin: None,
         };
         let r = Receiver {
-            flavor: ReceiverFlavor::Array(r),
+            flavor: ReceiverFlavor::Zero(r),
         };
         (s, r)
     }
+
+    // 与sync_channel()相同，cap为0时创建zero channel
+    pub fn sync_channel<T>(self, cap: usize) -> (Sender<T>, Receiver<T>) {
+        if cap == 0 {
+            self.rendezvous()
+        } else {
+            let mut chan = array::Channel::with_capacity(cap);
+            if let Some(limit) = self.spin_before_park {
+                chan = chan.spin_before_park(limit);
+            }
+            let (s, r) = self.counter(chan);
+            let s = Sender {
+                flavor: SenderFlavor::Array(s),
+                spin: None,
+            };
+            let r = Receiver {
+                flavor: ReceiverFlavor::Array(r),
+            };
+            (s, r)
+        }
+    }
+}
+
+/*
+ * 把一个channel的两端放在一起，方便在初始化代码中作为一个值传递，需要时再split
+ * 与内部各个flavor的Channel无关，只是(Sender<T>, Receiver<T>)的包装
+ * 只有一个持有者时也可以直接调用send/recv
+ */
+#[cfg(feature = "std")]
+#[derive(Debug, Clone)]
+pub struct Channel<T> {
+    sender: Sender<T>,
+    receiver: Receiver<T>,
+}
+
+#[cfg(feature = "std")]
+impl<T> Channel<T> {
+    // 与sync_channel(cap)相同
+    pub fn bounded(cap: usize) -> Channel<T> {
+        sync_channel(cap).into()
+    }
+
+    // 与channel()相同
+    pub fn unbounded() -> Channel<T> {
+        channel().into()
+    }
+
+    pub fn split(self) -> (Sender<T>, Receiver<T>) {
+        (self.sender, self.receiver)
+    }
+
+    pub fn sender(&self) -> &Sender<T> {
+        &self.sender
+    }
+
+    pub fn receiver(&self) -> &Receiver<T> {
+        &self.receiver
+    }
+
+    // 注意bounded(0)的channel上send需要另一个线程recv，单独的持有者会永远阻塞
+    pub fn send(&self, msg: T) -> Result<(), SendError<T>> {
+        self.sender.send(msg)
+    }
+
+    pub fn recv(&self) -> Result<T, RecvError> {
+        self.receiver.recv()
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T> From<(Sender<T>, Receiver<T>)> for Channel<T> {
+    fn from((sender, receiver): (Sender<T>, Receiver<T>)) -> Self {
+        Channel { sender, receiver }
+    }
+}
+
+/*
+ * channel的标识，由Sender::id/Receiver::id返回，内部是channel共享计数器的地址
+ * 同一个channel的所有Sender和Receiver返回相同的id，可以在擦除了T之后(比如放进Box<dyn Any>)比较、hash
+ * channel被释放之后地址可能被新的channel复用，所以只有在还持有这个channel的某个句柄时比较才有意义
+ */
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ChannelId(usize);
+
+// Sender::send_confirmed返回的msg交接方式
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Handoff {
+    // zero channel：send时已经有receiver在等待，msg直接交给了它
+    Immediate,
+    // zero channel：没有等待的receiver，sender park之后被一个receiver配对并取走了msg
+    Parked,
+    // array/list channel：msg写入了缓冲区，还没有被任何receiver取走
+    Buffered,
 }
 
 /*
@@ -67,11 +450,15 @@ pub fn sync_channel<T>(cap: usize) -> (Sender<T>, Receiver<T>) {
 
 pub struct Sender<T> {
     flavor: SenderFlavor<T>,
+    // 只属于这个handle的自旋上限，覆盖channel的spin_before_park，见Sender::with_spin
+    #[cfg(feature = "std")]
+    spin: Option<u32>,
 }
 
 enum SenderFlavor<T> {
     Array(counter::Sender<array::Channel<T>>),
     List(counter::Sender<list::Channel<T>>),
+    #[cfg(feature = "std")]
     Zero(counter::Sender<zero::Channel<T>>),
 }
 
@@ -82,6 +469,7 @@ unsafe impl<T: Send> Sync for Sender<T> {}
 impl<T> UnwindSafe for Sender<T> {}
 impl<T> RefUnwindSafe for Sender<T> {}
 
+// 非阻塞的部分，no_std下也可以使用
 impl<T> Sender<T> {
     // 尝试向channel发送信息(non-blocking)
     // 这个方法的调用将msg放入channel后立即返回，或者因为channel full/disconnected而包裹原msg返回一个错误
@@ -90,15 +478,115 @@ impl<T> Sender<T> {
         match &self.flavor {
             SenderFlavor::Array(chan) => chan.try_send(msg),
             SenderFlavor::List(chan) => chan.try_send(msg),
+            #[cfg(feature = "std")]
             SenderFlavor::Zero(chan) => chan.try_send(msg),
         }
     }
+    // 与try_send相同，同时返回这次操作中CAS失败后重试的次数，用来观察sender之间的竞争
+    // zero channel在锁中完成配对，没有CAS重试，总是0
+    #[cfg(feature = "profiling")]
+    pub fn try_send_profiled(&self, msg: T) -> (Result<(), TrySendError<T>>, u32) {
+        match &self.flavor {
+            SenderFlavor::Array(chan) => chan.try_send_profiled(msg),
+            SenderFlavor::List(chan) => chan.try_send_profiled(msg),
+            #[cfg(feature = "std")]
+            SenderFlavor::Zero(chan) => (chan.try_send(msg), 0),
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        match &self.flavor {
+            SenderFlavor::Array(chan) => chan.is_empty(),
+            SenderFlavor::List(chan) => chan.is_empty(),
+            #[cfg(feature = "std")]
+            SenderFlavor::Zero(chan) => chan.is_empty(),
+        }
+    }
+    // zero channel只有在有其他线程的receiver等待配对时才不是满的，is_empty总是true
+    pub fn is_full(&self) -> bool {
+        match &self.flavor {
+            SenderFlavor::Array(chan) => chan.is_full(),
+            SenderFlavor::List(chan) => chan.is_full(),
+            #[cfg(feature = "std")]
+            SenderFlavor::Zero(chan) => chan.is_full(),
+        }
+    }
+    pub fn len(&self) -> usize {
+        match &self.flavor {
+            SenderFlavor::Array(chan) => chan.len(),
+            SenderFlavor::List(chan) => chan.len(),
+            #[cfg(feature = "std")]
+            SenderFlavor::Zero(chan) => chan.len(),
+        }
+    }
+    // 见Receiver::len_relaxed
+    pub fn len_relaxed(&self) -> usize {
+        match &self.flavor {
+            SenderFlavor::Array(chan) => chan.len_relaxed(),
+            SenderFlavor::List(chan) => chan.len_relaxed(),
+            #[cfg(feature = "std")]
+            SenderFlavor::Zero(chan) => chan.len_relaxed(),
+        }
+    }
+    pub fn capacity(&self) -> Option<usize> {
+        match &self.flavor {
+            SenderFlavor::Array(chan) => chan.capacity(),
+            SenderFlavor::List(chan) => chan.capacity(),
+            #[cfg(feature = "std")]
+            SenderFlavor::Zero(chan) => chan.capacity(),
+        }
+    }
+    // channel的负载统计快照，需要开启metrics feature
+    #[cfg(feature = "metrics")]
+    pub fn metrics(&self) -> ChannelMetrics {
+        match &self.flavor {
+            SenderFlavor::Array(chan) => chan.metrics.snapshot(),
+            SenderFlavor::List(chan) => chan.metrics.snapshot(),
+            #[cfg(feature = "std")]
+            SenderFlavor::Zero(chan) => chan.metrics.snapshot(),
+        }
+    }
+    // channel是否已经断开(所有Receiver都被drop或者显式调用了disconnect)
+    pub fn is_disconnected(&self) -> bool {
+        match &self.flavor {
+            SenderFlavor::Array(chan) => chan.is_disconnected(),
+            SenderFlavor::List(chan) => chan.is_disconnected(),
+            #[cfg(feature = "std")]
+            SenderFlavor::Zero(chan) => chan.is_disconnected(),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T> Sender<T> {
+    // 尽力发送，不关心失败原因时使用：成功返回None，channel满或者断开时把msg放在Some中返回
+    #[must_use = "the message is returned if it could not be sent"]
+    pub fn offer(&self, msg: T) -> Option<T> {
+        self.try_send(msg).err().map(TrySendError::into_inner)
+    }
+    // 介于try_send和send_timeout之间：channel满时不会park线程，而是用Backoff自旋重试，
+    // 直到发送成功或者自旋时间超过spin，超时返回Full，channel断开时立即返回Disconnected
+    pub fn try_send_for(&self, mut msg: T, spin: Duration) -> Result<(), TrySendError<T>> {
+        let start = Instant::now();
+        let backoff = Backoff::new();
+        loop {
+            match self.try_send(msg) {
+                Err(TrySendError::Full(m)) => {
+                    if start.elapsed() >= spin {
+                        return Err(TrySendError::Full(m));
+                    }
+                    msg = m;
+                    backoff.spin_heavy();
+                }
+                res => return res,
+            }
+        }
+    }
     // 向channel写入msg(blocking),直到消息被发送或channel disconnected
     // 如果channel full，但没有disconnected，就会一直阻塞，直到msg发送成功，同样如果channel disconnected，就会包裹原msg返回一个错误
     pub fn send(&self, msg: T) -> Result<(), SendError<T>> {
         match &self.flavor {
-            SenderFlavor::Array(chan) => chan.send(msg, None),
-            SenderFlavor::List(chan) => chan.send(msg, None),
+            SenderFlavor::Array(chan) => chan.send(msg, None, self.spin),
+            SenderFlavor::List(chan) => chan.send_deadline(msg, None),
             SenderFlavor::Zero(chan) => chan.send(msg, None),
         }
         .map_err(|err| match err{
@@ -106,10 +594,91 @@ impl<T> Sender<T> {
             SendTimeoutError::Timeout(_) => unreachable!(),
         })
     }
+    /*
+     * 与send相同，成功时返回msg是怎样交接的
+     * 对zero channel，返回Ok时msg一定已经被某个receiver取走，Immediate和Parked区分了sender是否需要park，
+     * 可以用来分析交接的延迟。array/list channel总是返回Buffered
+     */
+    pub fn send_confirmed(&self, msg: T) -> Result<Handoff, SendError<T>> {
+        match &self.flavor {
+            SenderFlavor::Array(chan) => chan.send(msg, None, self.spin).map(|()| Handoff::Buffered),
+            SenderFlavor::List(chan) => chan.send_deadline(msg, None).map(|()| Handoff::Buffered),
+            SenderFlavor::Zero(chan) => chan.send_confirmed(msg, None),
+        }
+        .map_err(|err| match err {
+            SendTimeoutError::Disconnected(msg) => SendError(msg),
+            SendTimeoutError::Timeout(_) => unreachable!(),
+        })
+    }
+    /*
+     * 依次阻塞发送msgs中的每个msg，返回发送的数量；中途断开时返回没能发送的msg，之前的msg已经发出
+     * 没有soft_limit的list channel先写入全部msg，再在一次加锁中唤醒最多同样数量的等待的receivers，
+     * 其他情况与逐个调用send相同
+     */
+    pub fn send_all<I: IntoIterator<Item = T>>(&self, msgs: I) -> Result<usize, SendError<T>> {
+        match &self.flavor {
+            SenderFlavor::List(chan) if !chan.has_soft_limit() => chan.send_all(msgs),
+            _ => {
+                let mut sent = 0;
+                for msg in msgs {
+                    self.send(msg)?;
+                    sent += 1;
+                }
+                Ok(sent)
+            }
+        }
+    }
+    /*
+     * 返回只在这个handle上覆盖Builder::spin_before_park的Sender，同一个channel的其他Sender不受影响
+     * 名字、回调这些channel的配置仍然是共享的；clone出来的Sender带着同样的覆盖
+     */
+    pub fn with_spin(mut self, limit: u32) -> Self {
+        self.spin = Some(limit);
+        self
+    }
+    // 返回一个发送前用f转换输入的适配器，发送失败时错误中携带转换后的T
+    pub fn comap<U, F: FnMut(U) -> T>(self, f: F) -> MappedSender<U, T, F> {
+        MappedSender::new(self, f)
+    }
+    /*
+     * 阻塞发送由f生成的msg
+     * 对于有界channel，先预留slot再调用f，msg直接写入slot，避免很大的T在栈上构造之后再拷贝
+     * 如果f panic，预留的slot会被标记为已读，channel保持一致，在receiver越过它之前这个slot仍然占用容量
+     * list和zero channel没有可以预留的slot，会先调用f再send
+     * channel断开时返回SendError(())，此时array channel不会调用f
+     */
+    pub fn send_with<F: FnOnce() -> T>(&self, f: F) -> Result<(), SendError<()>> {
+        match &self.flavor {
+            SenderFlavor::Array(chan) => chan.send_with(f, self.spin).map_err(|_| SendError(())),
+            SenderFlavor::List(chan) => chan.send_deadline(f(), None).map_err(|_| SendError(())),
+            SenderFlavor::Zero(chan) => chan.send(f(), None).map_err(|_| SendError(())),
+        }
+    }
+    /*
+     * 一次预留n个连续的slot，之后通过SendReservation::push依次写入，不会阻塞
+     * array channel中空间不够n个时返回Full，n大于容量时总是返回Full
+     * receiver在预留的slot被写入之前不会越过它，后面的msg也要等待，所以预留之后应该尽快写入或者drop
+     * drop时还没有写入的slot被标记为跳过，receiver不会看到它们
+     * list channel没有容量限制，push直接发送，不保证这些msg与其他sender的msg连续
+     * zero channel没有可以预留的slot，总是返回Full
+     */
+    pub fn reserve(&self, n: usize) -> Result<SendReservation<'_, T>, TrySendError<()>> {
+        let inner = match &self.flavor {
+            SenderFlavor::Array(chan) => ReservationFlavor::Array(chan.reserve_slots(n)?),
+            SenderFlavor::List(chan) => {
+                if chan.is_disconnected() {
+                    return Err(TrySendError::Disconnected(()));
+                }
+                ReservationFlavor::List(chan, n)
+            }
+            SenderFlavor::Zero(_) => return Err(TrySendError::Full(())),
+        };
+        Ok(SendReservation { inner })
+    }
     // 在有限时间内发送msg到channel
     // 同send类似
     pub fn send_timeout(&self, msg: T, timeout: Duration) -> Result<(), SendTimeoutError<T>> {
-        match Instant::now().checked_add(timeout) {
+        match context::now().checked_add(timeout) {
             Some(deadline) => self.send_deadline(msg, deadline),
             // So far in the future that it's practically the same as waiting indefinitely.
             None => self.send(msg).map_err(SendTimeoutError::from),
@@ -118,40 +687,120 @@ impl<T> Sender<T> {
 
     pub fn send_deadline(&self, msg: T, deadline: Instant) -> Result<(), SendTimeoutError<T>> {
         match &self.flavor {
-            SenderFlavor::Array(chan) => chan.send(msg, Some(deadline)),
-            SenderFlavor::List(chan) => chan.send(msg, Some(deadline)),
+            SenderFlavor::Array(chan) => chan.send(msg, Some(deadline), self.spin),
+            // 没有soft_limit的list channel不会满，deadline不起作用，只可能返回Disconnected
+            SenderFlavor::List(chan) => chan.send_deadline(msg, Some(deadline)),
             SenderFlavor::Zero(chan) => chan.send(msg, Some(deadline)),
         }
     }
     // full和empty函数中，zero channel总是为true
-    pub fn is_empty(&self) -> bool {
+    /*
+     * 阻塞直到channel中的msg都被接收(len为0)或者超时，返回是否在超时前变空，timeout为None时一直等待
+     * 所有Receiver都被drop之后channel不会再变空，此时立即返回，
+     * 不过list channel在最后一个Receiver被drop时会丢弃剩余的msg，所以会返回true
+     * zero channel不持有msg，总是返回true
+     */
+    pub fn wait_empty(&self, timeout: Option<Duration>) -> bool {
+        let deadline = timeout.and_then(|t| context::now().checked_add(t));
         match &self.flavor {
-            SenderFlavor::Array(chan) => chan.is_empty(),
-            SenderFlavor::List(chan) => chan.is_empty(),
-            SenderFlavor::Zero(chan) => chan.is_empty(),
+            SenderFlavor::Array(chan) => chan.wait_empty(deadline, || chan.receiver_count() == 0),
+            SenderFlavor::List(chan) => chan.wait_empty(deadline, || chan.receiver_count() == 0),
+            SenderFlavor::Zero(_) => true,
         }
     }
-    pub fn is_full(&self) -> bool {
+    /*
+     * 阻塞直到try_send很可能成功(channel不满)或者channel断开，返回是否在超时前变为可发送，timeout为None时一直等待
+     * 只观察状态而不占用slot，返回之后其他sender仍然可能抢先把channel填满
+     * list channel总是可以发送，zero channel在有receiver等待配对时才可以发送
+     */
+    pub fn wait_sendable(&self, timeout: Option<Duration>) -> bool {
+        let mut sel = Select::new();
+        sel.send(self);
+        match timeout {
+            Some(timeout) => sel.ready_timeout(timeout).is_ok(),
+            None => {
+                sel.ready();
+                true
+            }
+        }
+    }
+    /*
+     * 与send相同的阻塞发送，但是cancel可以接收(有msg或者已经断开)时放弃发送，把msg放在SendError中返回
+     * 只观察cancel是否ready而不从中取出msg，同一个cancel可以用来取消多个操作
+     * 两者同时ready时优先完成发送
+     */
+    pub fn send_cancellable(&self, msg: T, cancel: &Receiver<()>) -> Result<(), SendError<T>> {
+        let mut msg = msg;
+        loop {
+            match self.try_send(msg) {
+                Ok(()) => return Ok(()),
+                Err(TrySendError::Disconnected(m)) => return Err(SendError(m)),
+                Err(TrySendError::Full(m)) => msg = m,
+            }
+            if cancel.is_ready() {
+                return Err(SendError(msg));
+            }
+            let mut sel = Select::new();
+            sel.send(self);
+            sel.recv(cancel);
+            // 被唤醒之后回到循环开头，先尝试发送
+            sel.ready();
+        }
+    }
+    /*
+     * 是否有其他线程的receiver正在阻塞等待配对，只对zero channel有意义，其他channel总是返回false
+     * 只是某一时刻的快照，receiver随时可能超时离开或者被其他sender配对
+     */
+    pub fn has_waiting_receiver(&self) -> bool {
         match &self.flavor {
-            SenderFlavor::Array(chan) => chan.is_full(),
-            SenderFlavor::List(chan) => chan.is_full(),
-            SenderFlavor::Zero(chan) => chan.is_full(),
+            SenderFlavor::Zero(chan) => chan.has_waiting_receiver(),
+            _ => false,
         }
     }
-    pub fn len(&self) -> usize {
+    // try_send是否会立即成功或者返回Disconnected
+    // 与!is_full()不同，channel断开也被认为是ready的
+    // zero channel只有在有receiver等待配对时才是ready的
+    pub fn is_ready(&self) -> bool {
         match &self.flavor {
-            SenderFlavor::Array(chan) => chan.len(),
-            SenderFlavor::List(chan) => chan.len(),
-            SenderFlavor::Zero(chan) => chan.len(),
+            SenderFlavor::Array(chan) => chan.is_send_ready(),
+            SenderFlavor::List(chan) => chan.is_send_ready(),
+            SenderFlavor::Zero(chan) => chan.is_send_ready(),
         }
     }
-    pub fn capacity(&self) -> Option<usize> {
+    // 通过Builder::name设置的channel名字
+    pub fn name(&self) -> Option<&str> {
         match &self.flavor {
-            SenderFlavor::Array(chan) => chan.capacity(),
-            SenderFlavor::List(chan) => chan.capacity(),
-            SenderFlavor::Zero(chan) => chan.capacity(),
+            SenderFlavor::Array(chan) => chan.name(),
+            SenderFlavor::List(chan) => chan.name(),
+            SenderFlavor::Zero(chan) => chan.name(),
+        }
+    }
+    // 判断receiver是否和这个sender属于同一个channel
+    pub fn same_channel_as_receiver(&self, other: &Receiver<T>) -> bool {
+        match (&self.flavor, &other.flavor) {
+            (SenderFlavor::Array(a), ReceiverFlavor::Array(b)) => a == b,
+            (SenderFlavor::List(a), ReceiverFlavor::List(b)) => a == b,
+            (SenderFlavor::Zero(a), ReceiverFlavor::Zero(b)) => a == b,
+            _ => false,
         }
     }
+    // 有界channel返回capacity - len，无界channel返回None，zero channel总是返回Some(0)
+    // 与len一样只是一个快照，可以用来实现自己的流量控制
+    pub fn remaining(&self) -> Option<usize> {
+        match &self.flavor {
+            SenderFlavor::Array(chan) => chan.remaining(),
+            SenderFlavor::List(chan) => chan.remaining(),
+            SenderFlavor::Zero(chan) => chan.remaining(),
+        }
+    }
+    // channel的标识，与T无关，见ChannelId
+    pub fn id(&self) -> ChannelId {
+        ChannelId(match &self.flavor {
+            SenderFlavor::Array(chan) => chan.addr(),
+            SenderFlavor::List(chan) => chan.addr(),
+            SenderFlavor::Zero(chan) => chan.addr(),
+        })
+    }
     pub fn same_channel(&self, other: &Sender<T>) -> bool {
         match (&self.flavor, &other.flavor) {
             (SenderFlavor::Array(ref a), SenderFlavor::Array(ref b)) => a == b,
@@ -160,110 +809,977 @@ impl<T> Sender<T> {
             _ => false,
         }
     }
+    // 返回当前存活的Sender数量(包括自身)
+    // 这只是一个快照，返回时其他线程可能已经clone或drop了Sender
+    pub fn sender_count(&self) -> usize {
+        match &self.flavor {
+            SenderFlavor::Array(chan) => chan.sender_count(),
+            SenderFlavor::List(chan) => chan.sender_count(),
+            SenderFlavor::Zero(chan) => chan.sender_count(),
+        }
+    }
+    // 返回当前存活的Receiver数量，为0说明接收端已经全部被drop
+    // 同样只是一个快照
+    pub fn receiver_count(&self) -> usize {
+        match &self.flavor {
+            SenderFlavor::Array(chan) => chan.receiver_count(),
+            SenderFlavor::List(chan) => chan.receiver_count(),
+            SenderFlavor::Zero(chan) => chan.receiver_count(),
+        }
+    }
+    /*
+     * 显式断开channel，之后的send会返回Disconnected，已经在channel中的msg仍然可以被接收
+     * 调用返回之前，receivers中每个阻塞的receiver都已经被选中为Disconnected并被unpark，
+     * 它们取完剩余的msg之后立即返回RecvError，不需要等待最后一个Sender被drop；调用者仍然持有这个Sender，可以继续查询状态
+     * 这个方法可以重复调用，返回true表示是这次调用断开了channel，之后的调用什么也不做
+     */
+    pub fn disconnect(&self) -> bool {
+        match &self.flavor {
+            SenderFlavor::Array(chan) => chan.disconnect_with(|c| c.disconnect()),
+            SenderFlavor::List(chan) => chan.disconnect_with(|c| c.disconnect()),
+            SenderFlavor::Zero(chan) => chan.disconnect_with(|c| c.disconnect()),
+        }
+    }
+    /*
+     * 关闭channel，但让receiver取完已经缓存的msg：之后的send/try_send立即返回Disconnected，
+     * recv继续返回缓存的msg，channel变空时才真正断开，此后recv返回Disconnected
+     * 与disconnect不同，在缓存的msg被取完之前is_disconnected仍然返回false
+     * on_disconnect回调在这里调用，因为从这时起channel不再接受msg，之后真正断开时不会再调用
+     * zero channel不缓存msg，等同于disconnect
+     * 返回true表示是这次调用关闭了channel
+     */
+    pub fn close_when_empty(&self) -> bool {
+        match &self.flavor {
+            SenderFlavor::Array(chan) => chan.disconnect_with(|c| c.close_when_empty()),
+            SenderFlavor::List(chan) => chan.disconnect_with(|c| c.close_when_empty()),
+            SenderFlavor::Zero(chan) => chan.disconnect_with(|c| c.close_when_empty()),
+        }
+    }
+    // 创建一个WeakSender，它不会阻止channel在所有Sender被drop后断开
+    pub fn downgrade(&self) -> WeakSender<T> {
+        let flavor = match &self.flavor {
+            SenderFlavor::Array(chan) => WeakSenderFlavor::Array(chan.downgrade()),
+            SenderFlavor::List(chan) => WeakSenderFlavor::List(chan.downgrade()),
+            SenderFlavor::Zero(chan) => WeakSenderFlavor::Zero(chan.downgrade()),
+        };
+        WeakSender { flavor }
+    }
 }
 
 // 引用计数-1
-impl<T> Drop for Sender<T> {
-    fn drop(&mut self) {
-        unsafe {
-            match &self.flavor {
-                SenderFlavor::Array(chan) => chan.release(|c| c.disconnect()),
-                SenderFlavor::List(chan) => chan.release(|c| c.disconnect_senders()),
-                SenderFlavor::Zero(chan) => chan.release(|c| c.disconnect()),
-            }
+#[cfg(feature = "std")]
+impl<T> Sender<T> {
+    // 在select选中这个Sender之后，通过token完成写入
+    pub(crate) unsafe fn write(&self, token: &mut Token, msg: T) -> Result<(), T> {
+        match &self.flavor {
+            SenderFlavor::Array(chan) => chan.write(token, msg),
+            SenderFlavor::List(chan) => chan.write(token, msg),
+            SenderFlavor::Zero(chan) => chan.write(token, msg),
         }
     }
 }
 
-// 引用计数+1
-impl<T> Clone for Sender<T> {
-    fn clone(&self) -> Self {
-        let flavor = match &self.flavor {
-            SenderFlavor::Array(chan) => SenderFlavor::Array(chan.acquire()),
-            SenderFlavor::List(chan) => SenderFlavor::List(chan.acquire()),
-            SenderFlavor::Zero(chan) => SenderFlavor::Zero(chan.acquire()),
-        };
+/*
+ * debug构建下的诊断：zero channel上注册select操作时，对端已经注册的操作全部来自当前线程，
+ * 即当前线程的select同时在这个channel上send和recv，它们不可能与自己配对
+ * 只有共享这个Sender/Receiver的其他线程(Sender/Receiver是Sync的，可以通过引用共享)才能完成这次select，
+ * handle的数量无法证明不存在这样的线程，所以只在stderr上提示，不会panic
+ */
+#[cfg(all(feature = "std", debug_assertions))]
+fn report_self_rendezvous() {
+    #[cfg(test)]
+    self_rendezvous::add();
+    eprintln!(
+        "warning: zero channel select waits to rendezvous with itself: every registered peer belongs to \
+         the current thread, only another thread sharing this Sender/Receiver can complete it"
+    );
+}
+
+// 只在测试中使用：统计当前线程中report_self_rendezvous被调用的次数
+#[cfg(all(test, feature = "std", debug_assertions))]
+pub(crate) mod self_rendezvous {
+    use std::cell::Cell;
 
-        Sender { flavor }
+    thread_local! {
+        static REPORTS: Cell<usize> = const { Cell::new(0) };
     }
-}
-impl<T> fmt::Debug for Sender<T> {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.pad("Sender { .. }")
+
+    pub(super) fn add() {
+        REPORTS.with(|reports| reports.set(reports.get() + 1));
     }
-}
 
-pub struct Receiver<T> {
-    flavor: ReceiverFlavor<T>,
-}
-enum ReceiverFlavor<T> {
-    Array(counter::Receiver<array::Channel<T>>),
-    List(counter::Receiver<list::Channel<T>>),
-    Zero(counter::Receiver<zero::Channel<T>>),
+    pub(crate) fn count() -> usize {
+        REPORTS.with(Cell::get)
+    }
 }
 
-impl<T> Receiver<T>{
-
-    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+#[cfg(feature = "std")]
+impl<T> SelectHandle for Sender<T> {
+    fn try_select(&self, token: &mut Token) -> bool {
         match &self.flavor {
-            ReceiverFlavor::Array(chan) => chan.try_recv(),
-            ReceiverFlavor::List(chan) => chan.try_recv(),
-            ReceiverFlavor::Zero(chan) => chan.try_recv(),
+            SenderFlavor::Array(chan) => chan.sender().try_select(token),
+            SenderFlavor::List(chan) => chan.sender().try_select(token),
+            SenderFlavor::Zero(chan) => chan.sender().try_select(token),
         }
     }
-    pub fn recv(&self) -> Result<T, RecvError> {
+    fn register(&self, oper: Operation, cx: &Context) -> bool {
         match &self.flavor {
-            ReceiverFlavor::Array(chan) => chan.recv(None),
-            ReceiverFlavor::List(chan) => chan.recv(None),
-            ReceiverFlavor::Zero(chan) => chan.recv(None),
+            SenderFlavor::Array(chan) => chan.sender().register(oper, cx),
+            SenderFlavor::List(chan) => chan.sender().register(oper, cx),
+            SenderFlavor::Zero(chan) => {
+                #[cfg(debug_assertions)]
+                if chan.is_self_rendezvous(true) {
+                    report_self_rendezvous();
+                }
+                chan.sender().register(oper, cx)
+            }
         }
-        .map_err(|_| RecvError)
     }
-
-    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
-        match Instant::now().checked_add(timeout) {
-            Some(deadline) => self.recv_deadline(deadline),
-            // So far in the future that it's practically the same as waiting indefinitely.
-            None => self.recv().map_err(RecvTimeoutError::from),
+    fn unregister(&self, oper: Operation) {
+        match &self.flavor {
+            SenderFlavor::Array(chan) => chan.sender().unregister(oper),
+            SenderFlavor::List(chan) => chan.sender().unregister(oper),
+            SenderFlavor::Zero(chan) => chan.sender().unregister(oper),
         }
     }
-    pub fn recv_deadline(&self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+    fn accept(&self, token: &mut Token, cx: &Context) -> bool {
         match &self.flavor {
-            ReceiverFlavor::Array(chan) => chan.recv(Some(deadline)),
-            ReceiverFlavor::List(chan) => chan.recv(Some(deadline)),
-            ReceiverFlavor::Zero(chan) => chan.recv(Some(deadline)),
+            SenderFlavor::Array(chan) => chan.sender().accept(token, cx),
+            SenderFlavor::List(chan) => chan.sender().accept(token, cx),
+            SenderFlavor::Zero(chan) => chan.sender().accept(token, cx),
         }
     }
-    pub fn is_empty(&self) -> bool {
+    fn is_ready(&self) -> bool {
         match &self.flavor {
-            ReceiverFlavor::Array(chan) => chan.is_empty(),
+            SenderFlavor::Array(chan) => chan.sender().is_ready(),
+            SenderFlavor::List(chan) => chan.sender().is_ready(),
+            SenderFlavor::Zero(chan) => chan.sender().is_ready(),
+        }
+    }
+    fn watch(&self, oper: Operation, cx: &Context) -> bool {
+        match &self.flavor {
+            SenderFlavor::Array(chan) => chan.sender().watch(oper, cx),
+            SenderFlavor::List(chan) => chan.sender().watch(oper, cx),
+            SenderFlavor::Zero(chan) => chan.sender().watch(oper, cx),
+        }
+    }
+    fn unwatch(&self, oper: Operation) {
+        match &self.flavor {
+            SenderFlavor::Array(chan) => chan.sender().unwatch(oper),
+            SenderFlavor::List(chan) => chan.sender().unwatch(oper),
+            SenderFlavor::Zero(chan) => chan.sender().unwatch(oper),
+        }
+    }
+}
+
+/*
+ * 与Arc::into_raw/from_raw类似，用于在FFI边界上传递handle
+ * 指针是Counter的地址，低2位记录channel的类型(array/list/zero)，转换不改变senders/receivers计数，
+ * 所以这个handle在from_raw之前一直是存活的，channel不会因此断开
+ * 只记录channel，handle自己的配置(Sender::with_spin)不会保留，from_raw得到的Sender使用channel的配置
+ * 不变式：每个into_raw返回的指针只能被from_raw一次，并且T必须和into_raw时相同，Sender和Receiver的指针不能混用
+ */
+#[cfg(feature = "std")]
+impl<T> Sender<T> {
+    pub fn into_raw(self) -> *const () {
+        let this = mem::ManuallyDrop::new(self);
+        let addr = match &this.flavor {
+            SenderFlavor::Array(chan) => chan.addr(),
+            SenderFlavor::List(chan) => chan.addr() | 1,
+            SenderFlavor::Zero(chan) => chan.addr() | 2,
+        };
+        addr as *const ()
+    }
+
+    /// # Safety
+    /// `ptr`必须是`Sender::<T>::into_raw`的返回值，并且还没有被`from_raw`过
+    pub unsafe fn from_raw(ptr: *const ()) -> Sender<T> {
+        let addr = ptr as usize & !counter::ADDR_TAG_MASK;
+        let flavor = match ptr as usize & counter::ADDR_TAG_MASK {
+            0 => SenderFlavor::Array(counter::Sender::from_addr(addr)),
+            1 => SenderFlavor::List(counter::Sender::from_addr(addr)),
+            _ => SenderFlavor::Zero(counter::Sender::from_addr(addr)),
+        };
+        Sender { flavor, spin: None }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        unsafe {
+            match &self.flavor {
+                SenderFlavor::Array(chan) => chan.release(|c| c.disconnect()),
+                SenderFlavor::List(chan) => chan.release(|c| c.disconnect_senders()),
+                #[cfg(feature = "std")]
+                SenderFlavor::Zero(chan) => chan.release(|c| c.disconnect()),
+            }
+        }
+    }
+}
+
+// 引用计数+1
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        let flavor = match &self.flavor {
+            SenderFlavor::Array(chan) => SenderFlavor::Array(chan.acquire()),
+            SenderFlavor::List(chan) => SenderFlavor::List(chan.acquire()),
+            #[cfg(feature = "std")]
+            SenderFlavor::Zero(chan) => SenderFlavor::Zero(chan.acquire()),
+        };
+        // handle自己的配置跟着clone，channel的配置本来就是共享的
+        Sender {
+            flavor,
+            #[cfg(feature = "std")]
+            spin: self.spin,
+        }
+    }
+}
+#[cfg(feature = "std")]
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("Sender");
+        if let Some(name) = self.name() {
+            d.field("name", &name);
+        }
+        d.field("len", &self.len())
+            .field("capacity", &self.capacity())
+            .field("disconnected", &self.is_disconnected())
+            .finish()
+    }
+}
+
+/*
+ * 以channel为标识的相等和哈希：属于同一个channel的Sender(包括clone出来的)相等，与same_channel一致
+ * 可以放入HashSet去重同一个channel的订阅者
+ */
+#[cfg(feature = "std")]
+impl<T> PartialEq for Sender<T> {
+    fn eq(&self, other: &Sender<T>) -> bool {
+        self.same_channel(other)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T> Eq for Sender<T> {}
+
+#[cfg(feature = "std")]
+impl<T> Hash for Sender<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id().hash(state)
+    }
+}
+
+// Sender::reserve返回的预留，持有的slot用完或者被drop之前receiver会在第一个没有写入的slot前等待
+#[cfg(feature = "std")]
+pub struct SendReservation<'a, T> {
+    inner: ReservationFlavor<'a, T>,
+}
+
+#[cfg(feature = "std")]
+enum ReservationFlavor<'a, T> {
+    Array(array::Reservation<'a, T>),
+    List(&'a list::Channel<T>, usize),
+}
+
+#[cfg(feature = "std")]
+impl<T> SendReservation<'_, T> {
+    // 写入下一个预留的slot，预留的slot已经用完(或者list channel已经断开)时把msg返回
+    pub fn push(&mut self, msg: T) -> Result<(), T> {
+        match &mut self.inner {
+            ReservationFlavor::Array(r) => r.push(msg),
+            ReservationFlavor::List(_, 0) => Err(msg),
+            ReservationFlavor::List(chan, remaining) => {
+                chan.send(msg).map_err(|SendError(msg)| msg)?;
+                *remaining -= 1;
+                Ok(())
+            }
+        }
+    }
+    /*
+     * 由f生成msg写入下一个预留的slot，预留的slot已经用完(或者list channel已经断开)时把f原样返回
+     * f panic时已经push的msg仍然可以被接收，当前的slot以及剩下的预留slot都被标记为跳过，
+     * receiver不会读到写了一半的msg，也不会收到重复的msg，之后的send/recv不受影响
+     * list channel先调用f再发送，f panic时什么都没有发送；f返回之后channel才断开时与push相同，把msg返回
+     */
+    pub fn push_with<F: FnOnce() -> T>(&mut self, f: F) -> Result<(), PushWithError<F, T>> {
+        match &mut self.inner {
+            ReservationFlavor::Array(r) => r.push_with(f).map_err(PushWithError::Unused),
+            ReservationFlavor::List(_, 0) => Err(PushWithError::Unused(f)),
+            ReservationFlavor::List(chan, remaining) => {
+                if chan.is_disconnected() {
+                    return Err(PushWithError::Unused(f));
+                }
+                chan.send(f())
+                    .map_err(|SendError(msg)| PushWithError::Disconnected(msg))?;
+                *remaining -= 1;
+                Ok(())
+            }
+        }
+    }
+    // 还没有写入的预留slot数量
+    pub fn remaining(&self) -> usize {
+        match &self.inner {
+            ReservationFlavor::Array(r) => r.remaining(),
+            ReservationFlavor::List(_, remaining) => *remaining,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T> fmt::Debug for SendReservation<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SendReservation")
+            .field("remaining", &self.remaining())
+            .finish()
+    }
+}
+
+/*
+ * Sender的弱引用，不计入senders计数
+ * 当所有的Sender都被drop后channel照常断开，此后upgrade总是返回None
+ */
+#[cfg(feature = "std")]
+pub struct WeakSender<T> {
+    flavor: WeakSenderFlavor<T>,
+}
+
+#[cfg(feature = "std")]
+enum WeakSenderFlavor<T> {
+    Array(counter::WeakSender<array::Channel<T>>),
+    List(counter::WeakSender<list::Channel<T>>),
+    Zero(counter::WeakSender<zero::Channel<T>>),
+}
+
+#[cfg(feature = "std")]
+unsafe impl<T: Send> Send for WeakSender<T> {}
+#[cfg(feature = "std")]
+unsafe impl<T: Send> Sync for WeakSender<T> {}
+
+#[cfg(feature = "std")]
+impl<T> WeakSender<T> {
+    // 如果还有存活的Sender，就返回一个新的Sender，否则返回None
+    pub fn upgrade(&self) -> Option<Sender<T>> {
+        let flavor = match &self.flavor {
+            WeakSenderFlavor::Array(chan) => SenderFlavor::Array(chan.upgrade()?),
+            WeakSenderFlavor::List(chan) => SenderFlavor::List(chan.upgrade()?),
+            WeakSenderFlavor::Zero(chan) => SenderFlavor::Zero(chan.upgrade()?),
+        };
+        Some(Sender { flavor, spin: None })
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T> Drop for WeakSender<T> {
+    fn drop(&mut self) {
+        unsafe {
+            match &self.flavor {
+                WeakSenderFlavor::Array(chan) => chan.release(),
+                WeakSenderFlavor::List(chan) => chan.release(),
+                WeakSenderFlavor::Zero(chan) => chan.release(),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T> Clone for WeakSender<T> {
+    fn clone(&self) -> Self {
+        let flavor = match &self.flavor {
+            WeakSenderFlavor::Array(chan) => WeakSenderFlavor::Array(chan.acquire()),
+            WeakSenderFlavor::List(chan) => WeakSenderFlavor::List(chan.acquire()),
+            WeakSenderFlavor::Zero(chan) => WeakSenderFlavor::Zero(chan.acquire()),
+        };
+        WeakSender { flavor }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T> fmt::Debug for WeakSender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("WeakSender { .. }")
+    }
+}
+
+pub struct Receiver<T> {
+    flavor: ReceiverFlavor<T>,
+}
+enum ReceiverFlavor<T> {
+    Array(counter::Receiver<array::Channel<T>>),
+    List(counter::Receiver<list::Channel<T>>),
+    #[cfg(feature = "std")]
+    Zero(counter::Receiver<zero::Channel<T>>),
+}
+
+unsafe impl<T: Send> Send for Receiver<T> {}
+unsafe impl<T: Send> Sync for Receiver<T> {}
+
+impl<T> UnwindSafe for Receiver<T> {}
+impl<T> RefUnwindSafe for Receiver<T> {}
+
+// 非阻塞的部分，no_std下也可以使用
+impl<T> Receiver<T> {
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        let res = self.try_recv_unhooked();
+        // no_std下没有Builder，也就没有on_recv回调
+        #[cfg(feature = "std")]
+        self.observe_try_recv(&res);
+        res
+    }
+    fn try_recv_unhooked(&self) -> Result<T, TryRecvError> {
+        match &self.flavor {
+            ReceiverFlavor::Array(chan) => chan.try_recv(),
+            ReceiverFlavor::List(chan) => chan.try_recv(),
+            #[cfg(feature = "std")]
+            ReceiverFlavor::Zero(chan) => chan.try_recv(),
+        }
+    }
+    // 与try_recv相同，同时返回这次操作中CAS失败后重试的次数，zero channel总是0
+    #[cfg(feature = "profiling")]
+    pub fn try_recv_profiled(&self) -> (Result<T, TryRecvError>, u32) {
+        let (res, retries) = match &self.flavor {
+            ReceiverFlavor::Array(chan) => chan.try_recv_profiled(),
+            ReceiverFlavor::List(chan) => chan.try_recv_profiled(),
+            #[cfg(feature = "std")]
+            ReceiverFlavor::Zero(chan) => (chan.try_recv(), 0),
+        };
+        #[cfg(feature = "std")]
+        self.observe_try_recv(&res);
+        (res, retries)
+    }
+    /*
+     * 与try_recv/recv相同，供只有一个消费者的场景(MPSC)使用
+     * 持有&mut self时这个receiver不能被clone，如果它还是唯一的receiver并且没有WeakReceiver，
+     * 就不会有其他线程同时接收，array和list channel移动head时不需要CAS；
+     * 一旦存在其他receiver，退回到普通的CAS路径
+     */
+    pub fn try_recv_exclusive(&mut self) -> Result<T, TryRecvError> {
+        let res = match &self.flavor {
+            ReceiverFlavor::Array(chan) if chan.is_sole_receiver() => unsafe { chan.try_recv_exclusive() },
+            ReceiverFlavor::List(chan) if chan.is_sole_receiver() => unsafe { chan.try_recv_exclusive() },
+            _ => return self.try_recv(),
+        };
+        #[cfg(feature = "std")]
+        self.observe_try_recv(&res);
+        res
+    }
+    pub fn is_empty(&self) -> bool {
+        match &self.flavor {
+            ReceiverFlavor::Array(chan) => chan.is_empty(),
             ReceiverFlavor::List(chan) => chan.is_empty(),
+            #[cfg(feature = "std")]
             ReceiverFlavor::Zero(chan) => chan.is_empty(),
         }
     }
+    // 与Sender::is_full相同
     pub fn is_full(&self) -> bool {
         match &self.flavor {
             ReceiverFlavor::Array(chan) => chan.is_full(),
             ReceiverFlavor::List(chan) => chan.is_full(),
+            #[cfg(feature = "std")]
             ReceiverFlavor::Zero(chan) => chan.is_full(),
         }
     }
-
     pub fn len(&self) -> usize {
         match &self.flavor {
             ReceiverFlavor::Array(chan) => chan.len(),
             ReceiverFlavor::List(chan) => chan.len(),
+            #[cfg(feature = "std")]
             ReceiverFlavor::Zero(chan) => chan.len(),
         }
     }
-
+    // 迭代器size_hint的下界：只有这一个receiver时channel中的msg都会被它取到，否则可能被其他receiver取走，只能是0
+    #[cfg(feature = "std")]
+    fn guaranteed_len(&self) -> usize {
+        let sole = match &self.flavor {
+            ReceiverFlavor::Array(chan) => chan.is_sole_receiver(),
+            ReceiverFlavor::List(chan) => chan.is_sole_receiver(),
+            ReceiverFlavor::Zero(chan) => chan.is_sole_receiver(),
+        };
+        if sole {
+            self.len()
+        } else {
+            0
+        }
+    }
+    /*
+     * len的近似值，不会因为其他线程不停地send/recv而自旋重试，是wait-free的
+     * 返回值可能已经过时，并发修改时也不一定等于任何时刻的真实长度，不超过容量
+     * 只适合用于监控、日志这类不需要精确值的场合，精确值用len
+     */
+    pub fn len_relaxed(&self) -> usize {
+        match &self.flavor {
+            ReceiverFlavor::Array(chan) => chan.len_relaxed(),
+            ReceiverFlavor::List(chan) => chan.len_relaxed(),
+            #[cfg(feature = "std")]
+            ReceiverFlavor::Zero(chan) => chan.len_relaxed(),
+        }
+    }
     pub fn capacity(&self) -> Option<usize> {
         match &self.flavor {
             ReceiverFlavor::Array(chan) => chan.capacity(),
             ReceiverFlavor::List(chan) => chan.capacity(),
+            #[cfg(feature = "std")]
             ReceiverFlavor::Zero(chan) => chan.capacity(),
         }
     }
+    // channel的负载统计快照，需要开启metrics feature
+    #[cfg(feature = "metrics")]
+    pub fn metrics(&self) -> ChannelMetrics {
+        match &self.flavor {
+            ReceiverFlavor::Array(chan) => chan.metrics.snapshot(),
+            ReceiverFlavor::List(chan) => chan.metrics.snapshot(),
+            #[cfg(feature = "std")]
+            ReceiverFlavor::Zero(chan) => chan.metrics.snapshot(),
+        }
+    }
+    // channel是否已经断开，断开后channel中可能仍然有没有被接收的msg
+    pub fn is_disconnected(&self) -> bool {
+        match &self.flavor {
+            ReceiverFlavor::Array(chan) => chan.is_disconnected(),
+            ReceiverFlavor::List(chan) => chan.is_disconnected(),
+            #[cfg(feature = "std")]
+            ReceiverFlavor::Zero(chan) => chan.is_disconnected(),
+        }
+    }
+}
+
+// 阻塞接收的结果对应的RecvOutcome
+#[cfg(feature = "std")]
+fn recv_outcome<R>(res: &Result<R, RecvTimeoutError>) -> RecvOutcome {
+    match res {
+        Ok(_) => RecvOutcome::Received,
+        Err(RecvTimeoutError::Timeout) => RecvOutcome::Timeout,
+        Err(RecvTimeoutError::Disconnected) => RecvOutcome::Disconnected,
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T> Receiver<T> {
+
+    fn on_recv_hook(&self) -> Option<&counter::OnRecv> {
+        match &self.flavor {
+            ReceiverFlavor::Array(chan) => chan.on_recv(),
+            ReceiverFlavor::List(chan) => chan.on_recv(),
+            ReceiverFlavor::Zero(chan) => chan.on_recv(),
+        }
+    }
+    // 为一次非阻塞的接收调用on_recv回调
+    fn observe_try_recv(&self, res: &Result<T, TryRecvError>) {
+        if let Some(hook) = self.on_recv_hook() {
+            let outcome = match res {
+                Ok(_) => RecvOutcome::Received,
+                Err(TryRecvError::Empty) => RecvOutcome::Empty,
+                Err(TryRecvError::Disconnected) => RecvOutcome::Disconnected,
+            };
+            hook(RecvEvent { blocked: false, waited: Duration::ZERO, outcome });
+        }
+    }
+    /*
+     * 与recv_hooked相同，但用于不能先try_recv一次的阻塞接收：with_next的msg要留在slot中，
+     * exclusive和zero channel的recv_rendezvous也不走普通的recv路径
+     * blocked用调用时是否有可以立即取走的msg近似
+     */
+    fn recv_observed<R>(&self, recv: impl FnOnce() -> Result<R, RecvTimeoutError>) -> Result<R, RecvTimeoutError> {
+        let Some(hook) = self.on_recv_hook() else {
+            return recv();
+        };
+        let start = Instant::now();
+        let blocked = self.is_empty() && !self.has_waiting_sender();
+        let res = recv();
+        hook(RecvEvent { blocked, waited: start.elapsed(), outcome: recv_outcome(&res) });
+        res
+    }
+    // 批量接收中不需要等待就取出的msg，每条报告一次Received
+    fn observe_batch(&self, received: usize) {
+        if let Some(hook) = self.on_recv_hook() {
+            for _ in 0..received {
+                hook(RecvEvent { blocked: false, waited: Duration::ZERO, outcome: RecvOutcome::Received });
+            }
+        }
+    }
+    /*
+     * recv/recv_deadline的实现，注册了on_recv时先try_recv一次判断是否需要等待，返回之前调用回调
+     * 这时flavor中的操作已经全部返回，不持有任何内部的锁
+     */
+    fn recv_hooked(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        let recv = |deadline| match &self.flavor {
+            ReceiverFlavor::Array(chan) => chan.recv(deadline),
+            ReceiverFlavor::List(chan) => chan.recv(deadline),
+            ReceiverFlavor::Zero(chan) => chan.recv(deadline),
+        };
+        let Some(hook) = self.on_recv_hook() else {
+            return recv(deadline);
+        };
+        let start = Instant::now();
+        let (res, blocked) = match self.try_recv_unhooked() {
+            Ok(msg) => (Ok(msg), false),
+            Err(TryRecvError::Disconnected) => (Err(RecvTimeoutError::Disconnected), false),
+            Err(TryRecvError::Empty) => (recv(deadline), true),
+        };
+        hook(RecvEvent { blocked, waited: start.elapsed(), outcome: recv_outcome(&res) });
+        res
+    }
+    pub fn recv(&self) -> Result<T, RecvError> {
+        self.recv_hooked(None).map_err(|_| RecvError)
+    }
+
+    /*
+     * 与recv相同，但是channel为空时不park线程，而是调用yield_now把执行权交给协作式的调度器
+     * receiver照常注册在channel的waker中，sender选中它之后通过当前线程的Parker(默认是Thread::unpark，
+     * 或者with_parker设置的Parker)的unpark唤醒，yield_now可以阻塞到unpark到来
+     * yield_now返回时还没有被选中就取消注册并重新接收，所以同一个线程上其他任务发送的msg
+     * 也会在yield_now返回之后收到
+     */
+    pub fn recv_with_parker(&self, yield_now: &dyn Fn()) -> Result<T, RecvError> {
+        let recv = || loop {
+            let res = match &self.flavor {
+                ReceiverFlavor::Array(chan) => chan.recv(None),
+                ReceiverFlavor::List(chan) => chan.recv(None),
+                ReceiverFlavor::Zero(chan) => chan.recv(None),
+            };
+            // 没有deadline时只有yield_now返回后放弃等待才会得到Timeout
+            if !matches!(res, Err(RecvTimeoutError::Timeout)) {
+                return res;
+            }
+        };
+        context::with_yield(yield_now, || self.recv_observed(recv)).map_err(|_| RecvError)
+    }
+
+    /*
+     * 与recv相同，但是flag被设置为true之后放弃等待，返回Ok(None)；flag已经是true时不接收msg，直接返回
+     * 设置AtomicBool没有办法唤醒park的线程，所以每次最多park RECV_UNTIL_POLL就重新检查flag，
+     * 从设置flag到返回最多延迟这么长时间。需要立即唤醒时用Select同时等待一个用来取消的channel
+     */
+    pub fn recv_until(&self, flag: &std::sync::atomic::AtomicBool) -> Result<Option<T>, RecvError> {
+        const RECV_UNTIL_POLL: Duration = Duration::from_millis(10);
+        loop {
+            if flag.load(std::sync::atomic::Ordering::Acquire) {
+                return Ok(None);
+            }
+            match self.recv_timeout(RECV_UNTIL_POLL) {
+                Ok(msg) => return Ok(Some(msg)),
+                Err(RecvTimeoutError::Disconnected) => return Err(RecvError),
+                Err(RecvTimeoutError::Timeout) => {}
+            }
+        }
+    }
+
+    /*
+     * 阻塞到有msg可以接收，然后把当前能立即取到的msg全部交给f，channel暂时为空时返回Ok(())
+     * 生产者成批发送时一次唤醒就能处理整批msg，不用每条msg都重新注册、park一次
+     * 在循环中调用直到返回Err，channel为空并且已经断开时返回RecvError，此时f不会被调用
+     */
+    pub fn for_each_available<F: FnMut(T)>(&self, mut f: F) -> Result<(), RecvError> {
+        f(self.recv()?);
+        while let Ok(msg) = self.try_recv() {
+            f(msg);
+        }
+        Ok(())
+    }
+
+    pub fn recv_exclusive(&mut self) -> Result<T, RecvError> {
+        match &self.flavor {
+            ReceiverFlavor::Array(chan) if chan.is_sole_receiver() => {
+                self.recv_observed(|| unsafe { chan.recv_exclusive(None) })
+            }
+            ReceiverFlavor::List(chan) if chan.is_sole_receiver() => {
+                self.recv_observed(|| unsafe { chan.recv_exclusive(None) })
+            }
+            _ => return self.recv(),
+        }
+        .map_err(|_| RecvError)
+    }
+
+    /*
+     * 阻塞直到收到msg，把msg的引用交给f，f返回之后msg才被取走并drop，返回f的结果
+     * array和list channel中msg在f执行期间留在slot中，不需要移动或clone；zero channel先收到msg再调用f
+     * f执行期间这个slot不会被sender复用，所以f不应该长时间阻塞；f panic时msg仍然被取走
+     */
+    pub fn with_next<R>(&self, f: impl FnOnce(&T) -> R) -> Result<R, RecvError> {
+        self.recv_observed(|| match &self.flavor {
+            ReceiverFlavor::Array(chan) => chan.recv_with(None, f),
+            ReceiverFlavor::List(chan) => chan.recv_with(None, f),
+            ReceiverFlavor::Zero(chan) => chan.recv(None).map(|msg| f(&msg)),
+        })
+        .map_err(|_| RecvError)
+    }
+
+    // 与recv相同，但是channel断开时返回None而不是RecvError，适合while let Some(msg) = r.next_blocking()
+    #[must_use]
+    pub fn next_blocking(&self) -> Option<T> {
+        self.recv().ok()
+    }
+
+    // 阻塞地迭代msg，channel断开并且没有剩余msg时结束
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter { rx: self }
+    }
+
+    // 迭代当前已经在channel中的msg，channel为空时立即结束
+    pub fn try_iter(&self) -> TryIter<'_, T> {
+        TryIter { rx: self }
+    }
+
+    /*
+     * 与recv相同，同时返回这次接收是谁先到的、receiver等待了多长时间
+     * 对于zero channel，从receiver注册到与sender配对的时间就是握手时间，sender先到时没有等待
+     * 其他channel中已经有msg时算作sender先到，否则从调用开始计时
+     */
+    pub fn recv_metered(&self) -> Result<(T, Rendezvous), RecvError> {
+        let (msg, registered) = match &self.flavor {
+            ReceiverFlavor::Zero(chan) => self
+                .recv_observed(|| chan.recv_rendezvous(None))
+                .map_err(|_| RecvError)?,
+            _ => {
+                let start = Instant::now();
+                match self.try_recv() {
+                    Ok(msg) => (msg, None),
+                    Err(TryRecvError::Disconnected) => return Err(RecvError),
+                    Err(TryRecvError::Empty) => (self.recv()?, Some(start)),
+                }
+            }
+        };
+        let rendezvous = match registered {
+            Some(start) => Rendezvous { waited: start.elapsed(), first: Side::Receiver },
+            None => Rendezvous { waited: Duration::ZERO, first: Side::Sender },
+        };
+        Ok((msg, rendezvous))
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        match context::now().checked_add(timeout) {
+            Some(deadline) => self.recv_deadline(deadline),
+            // So far in the future that it's practically the same as waiting indefinitely.
+            None => self.recv().map_err(RecvTimeoutError::from),
+        }
+    }
+    // 与recv_timeout相同，失败时同时返回实际等待了多长时间，可以用来调整超时时长
+    // 等待时长用计算超时的同一个时钟(见Clock)测量，所以超时返回的时长不会小于timeout
+    pub fn recv_timeout_detailed(&self, timeout: Duration) -> Result<T, (RecvTimeoutError, Duration)> {
+        let start = context::now();
+        self.recv_timeout(timeout)
+            .map_err(|e| (e, context::now().saturating_duration_since(start)))
+    }
+    pub fn recv_deadline(&self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+        self.recv_hooked(Some(deadline))
+    }
+    // 丢弃channel中当前缓存的所有msg(会执行它们的析构)，返回丢弃的数量
+    // 调用之后并发写入的msg不会被丢弃，zero channel总是返回0
+    pub fn drain(&self) -> usize {
+        match &self.flavor {
+            ReceiverFlavor::Array(chan) => chan.drain(),
+            ReceiverFlavor::List(chan) => chan.drain(),
+            ReceiverFlavor::Zero(chan) => chan.drain(),
+        }
+    }
+    // 与drain类似，但是把取出的msg按顺序返回给调用者而不是丢弃
+    // 先取len的快照再逐个try_recv，channel提前变空时就停止
+    // 由于并发的sender可能继续写入，返回的只是调用时大致存在于channel中的msg
+    pub fn collect_drained(&self) -> Vec<T> {
+        let n = self.len();
+        let mut msgs = Vec::with_capacity(n);
+        for _ in 0..n {
+            match self.try_recv() {
+                Ok(msg) => msgs.push(msg),
+                Err(_) => break,
+            }
+        }
+        msgs
+    }
+    /*
+     * 与collect_drained类似，但是最多取N条msg放在栈上的数组里，不需要分配堆内存
+     * channel变空或者断开时提前停止，返回的迭代器只会产出实际收到的msg
+     * 只是循环调用try_recv的便捷方法：每条msg都单独移动一次head、单独通知一次sender，
+     * 不比自己循环try_recv更快，并发的receiver也可能在其中穿插取走msg，取到的不一定是连续的一段
+     */
+    pub fn try_recv_array<const N: usize>(&self) -> RecvArray<T, N> {
+        let mut batch = RecvArray {
+            buf: [const { MaybeUninit::uninit() }; N],
+            start: 0,
+            end: 0,
+        };
+        while batch.end < N {
+            match self.try_recv() {
+                Ok(msg) => {
+                    batch.buf[batch.end].write(msg);
+                    batch.end += 1;
+                }
+                Err(_) => break,
+            }
+        }
+        batch
+    }
+    // 与try_recv相同，只是结果的形式不同
+    pub fn poll(&self) -> Poll3<T> {
+        self.try_recv().into()
+    }
+    /*
+     * 转换成可以checkpoint/rewind的ReplayReceiver，见ReplayReceiver
+     * 只支持list channel，并且这必须是唯一的receiver(没有其他Receiver和WeakReceiver)，否则原样返回
+     * 内存开销：只要还有一个Checkpoint存活，从最早的checkpoint开始发送的所有msg和block都不会被释放，
+     * 保留的量随发送的msg数量增长、没有上限，即使之后的checkpoint已经不需要其中的大部分，
+     * 长时间运行时应该及时drop不再需要的Checkpoint
+     */
+    pub fn replay(self) -> Result<ReplayReceiver<T>, Receiver<T>> {
+        match &self.flavor {
+            ReceiverFlavor::List(chan) if chan.is_sole_receiver() => Ok(ReplayReceiver::new(self)),
+            _ => Err(self),
+        }
+    }
+    // 返回一个在接收时用f转换msg的适配器
+    pub fn map<U, F: FnMut(T) -> U>(self, f: F) -> MappedReceiver<T, U, F> {
+        MappedReceiver::new(self, f)
+    }
+    /*
+     * 非阻塞地把msg依次放入多个buf，填满一个buf的剩余容量(capacity - len)之后再放入下一个，
+     * 总共最多max_total条，channel变空或者断开时停止，返回放入的总数
+     * buf只会被push，不会扩容，每个buf收到的数量就是它len的增量
+     */
+    pub fn recv_into(&self, bufs: &mut [&mut Vec<T>], max_total: usize) -> usize {
+        let mut total = 0;
+        for buf in bufs.iter_mut() {
+            let want = (buf.capacity() - buf.len()).min(max_total - total);
+            let n = match &self.flavor {
+                ReceiverFlavor::Array(chan) => chan.try_recv_batch(want, |msg| buf.push(msg)),
+                ReceiverFlavor::List(chan) => chan.try_recv_batch(want, |msg| buf.push(msg)),
+                ReceiverFlavor::Zero(chan) => chan.try_recv_batch(want, |msg| buf.push(msg)),
+            };
+            total += n;
+            if n < want || total == max_total {
+                break;
+            }
+        }
+        self.observe_batch(total);
+        total
+    }
+    /*
+     * 收集最多max条msg追加到buf，返回收到的数量
+     * 第一条msg最多等待timeout，之后只取立即可以取到的msg，不再阻塞，所以适合"攒够N条或者每隔T时间刷新一次"的消费者
+     * 超时时返回0；channel断开时返回已经收到的数量，断开并且为空时返回0
+     */
+    pub fn recv_many_timeout(&self, buf: &mut Vec<T>, max: usize, timeout: Duration) -> usize {
+        if max == 0 {
+            return 0;
+        }
+        match self.recv_timeout(timeout) {
+            Ok(msg) => buf.push(msg),
+            Err(_) => return 0,
+        }
+        let rest = match &self.flavor {
+            ReceiverFlavor::Array(chan) => chan.try_recv_batch(max - 1, |msg| buf.push(msg)),
+            ReceiverFlavor::List(chan) => chan.try_recv_batch(max - 1, |msg| buf.push(msg)),
+            ReceiverFlavor::Zero(chan) => chan.try_recv_batch(max - 1, |msg| buf.push(msg)),
+        };
+        self.observe_batch(rest);
+        1 + rest
+    }
+    // 与recv_deadline相同，但是不通过Result返回，而是把msg写入out并返回一个状态
+    // 只有返回RecvStatus::Received时out才会被写入
+    pub fn recv_deadline_into(&self, deadline: Instant, out: &mut Option<T>) -> RecvStatus {
+        match self.recv_deadline(deadline) {
+            Ok(msg) => {
+                *out = Some(msg);
+                RecvStatus::Received
+            }
+            Err(RecvTimeoutError::Timeout) => RecvStatus::TimedOut,
+            Err(RecvTimeoutError::Disconnected) => RecvStatus::Disconnected,
+        }
+    }
+    // 是否有其他线程的sender正在阻塞等待配对，只对zero channel有意义，其他channel总是返回false
+    pub fn has_waiting_sender(&self) -> bool {
+        match &self.flavor {
+            ReceiverFlavor::Zero(chan) => chan.has_waiting_sender(),
+            _ => false,
+        }
+    }
+
+
+
+
+    /*
+     * 不消耗msg，clone当前channel中的msg
+     * 读取的同时不能有其他线程接收，所以需要&mut self，并且只有在这是唯一的receiver、
+     * 也没有WeakReceiver时才返回Some，否则返回None
+     */
+    #[cfg(feature = "serde")]
+    pub fn snapshot(&mut self) -> Option<Snapshot<T>>
+    where
+        T: Clone,
+    {
+        let (capacity, messages) = match &self.flavor {
+            ReceiverFlavor::Array(chan) if chan.is_sole_receiver() => {
+                (chan.capacity(), unsafe { chan.snapshot() })
+            }
+            ReceiverFlavor::List(chan) if chan.is_sole_receiver() => {
+                (chan.capacity(), unsafe { chan.snapshot() })
+            }
+            ReceiverFlavor::Zero(chan) if chan.is_sole_receiver() => (chan.capacity(), chan.snapshot()),
+            _ => return None,
+        };
+        Some(Snapshot { capacity, messages })
+    }
+
+    // try_recv是否会立即收到msg或者返回Disconnected
+    // 与!is_empty()不同，channel断开也被认为是ready的
+    // zero channel只有在有sender等待配对时才是ready的
+    pub fn is_ready(&self) -> bool {
+        match &self.flavor {
+            ReceiverFlavor::Array(chan) => chan.is_recv_ready(),
+            ReceiverFlavor::List(chan) => chan.is_recv_ready(),
+            ReceiverFlavor::Zero(chan) => chan.is_recv_ready(),
+        }
+    }
+
+    // 通过Builder::name设置的channel名字
+    pub fn name(&self) -> Option<&str> {
+        match &self.flavor {
+            ReceiverFlavor::Array(chan) => chan.name(),
+            ReceiverFlavor::List(chan) => chan.name(),
+            ReceiverFlavor::Zero(chan) => chan.name(),
+        }
+    }
+
+
+    /*
+     * channel是否不再接受新的msg：调用了close_when_empty或者已经断开
+     * 返回true时channel中可能还有缓存的msg，取完之后try_recv返回Disconnected，
+     * consumer可以用它区分Empty时channel是暂时为空还是正在关闭，决定处理完剩余的msg就退出
+     */
+    pub fn is_closing(&self) -> bool {
+        match &self.flavor {
+            ReceiverFlavor::Array(chan) => chan.is_closing(),
+            ReceiverFlavor::List(chan) => chan.is_closing(),
+            ReceiverFlavor::Zero(chan) => chan.is_closing(),
+        }
+    }
+
+    // 只有channel已经断开并且缓存的msg全部被取走时才返回true，即之后再也不会有msg可读
+    // 而is_disconnected在断开后仍然可能有缓存的msg
+    pub fn disconnected_and_empty(&self) -> bool {
+        match &self.flavor {
+            ReceiverFlavor::Array(chan) => chan.is_disconnected_and_empty(),
+            ReceiverFlavor::List(chan) => chan.is_disconnected_and_empty(),
+            ReceiverFlavor::Zero(chan) => chan.is_disconnected_and_empty(),
+        }
+    }
+
+    // 语义与Sender::remaining相同
+    pub fn remaining(&self) -> Option<usize> {
+        match &self.flavor {
+            ReceiverFlavor::Array(chan) => chan.remaining(),
+            ReceiverFlavor::List(chan) => chan.remaining(),
+            ReceiverFlavor::Zero(chan) => chan.remaining(),
+        }
+    }
+
+    // channel的标识，与同一个channel的Sender::id相等
+    pub fn id(&self) -> ChannelId {
+        ChannelId(match &self.flavor {
+            ReceiverFlavor::Array(chan) => chan.addr(),
+            ReceiverFlavor::List(chan) => chan.addr(),
+            ReceiverFlavor::Zero(chan) => chan.addr(),
+        })
+    }
 
     /// Returns `true` if receivers belong to the same channel.
     pub fn same_channel(&self, other: &Receiver<T>) -> bool {
@@ -274,26 +1790,283 @@ impl<T> Receiver<T>{
             _ => false,
         }
     }
+
+    // 返回当前存活的Receiver数量(包括自身)
+    // 这只是一个快照，返回时其他线程可能已经clone或drop了Receiver
+    pub fn receiver_count(&self) -> usize {
+        match &self.flavor {
+            ReceiverFlavor::Array(chan) => chan.receiver_count(),
+            ReceiverFlavor::List(chan) => chan.receiver_count(),
+            ReceiverFlavor::Zero(chan) => chan.receiver_count(),
+        }
+    }
+
+    // 返回当前存活的Sender数量，为0说明发送端已经全部被drop
+    // 同样只是一个快照
+    pub fn sender_count(&self) -> usize {
+        match &self.flavor {
+            ReceiverFlavor::Array(chan) => chan.sender_count(),
+            ReceiverFlavor::List(chan) => chan.sender_count(),
+            ReceiverFlavor::Zero(chan) => chan.sender_count(),
+        }
+    }
+
+    // 显式断开channel，语义与Sender::disconnect相同
+    // recv在取完channel中剩余的msg后返回Disconnected
+    pub fn disconnect(&self) -> bool {
+        match &self.flavor {
+            ReceiverFlavor::Array(chan) => chan.disconnect_with(|c| c.disconnect()),
+            ReceiverFlavor::List(chan) => chan.disconnect_with(|c| c.disconnect()),
+            ReceiverFlavor::Zero(chan) => chan.disconnect_with(|c| c.disconnect()),
+        }
+    }
+
+    // 创建一个WeakReceiver，它不会阻止channel在所有Receiver被drop后断开
+    pub fn downgrade(&self) -> WeakReceiver<T> {
+        let flavor = match &self.flavor {
+            ReceiverFlavor::Array(chan) => WeakReceiverFlavor::Array(chan.downgrade()),
+            ReceiverFlavor::List(chan) => WeakReceiverFlavor::List(chan.downgrade()),
+            ReceiverFlavor::Zero(chan) => WeakReceiverFlavor::Zero(chan.downgrade()),
+        };
+        WeakReceiver { flavor }
+    }
+}
+
+
+// recv_metered附带的接收信息
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rendezvous {
+    // receiver等待sender的时间，sender先到时为0
+    pub waited: Duration,
+    // 先到达的一方
+    pub first: Side,
+}
+
+// 一次配对中的一方
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Sender,
+    Receiver,
+}
+
+// try_recv_array的结果，按接收顺序产出msg，buf[start..end]是已经初始化但还没有取走的msg
+#[cfg(feature = "std")]
+pub struct RecvArray<T, const N: usize> {
+    buf: [MaybeUninit<T>; N],
+    start: usize,
+    end: usize,
+}
+
+#[cfg(feature = "std")]
+impl<T, const N: usize> Iterator for RecvArray<T, N> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        if self.start == self.end {
+            return None;
+        }
+        let msg = unsafe { self.buf[self.start].assume_init_read() };
+        self.start += 1;
+        Some(msg)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let n = self.end - self.start;
+        (n, Some(n))
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T, const N: usize> ExactSizeIterator for RecvArray<T, N> {}
+
+#[cfg(feature = "std")]
+impl<T, const N: usize> Drop for RecvArray<T, N> {
+    fn drop(&mut self) {
+        for msg in &mut self.buf[self.start..self.end] {
+            unsafe { msg.assume_init_drop() };
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T, const N: usize> fmt::Debug for RecvArray<T, N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RecvArray").field("len", &(self.end - self.start)).finish()
+    }
+}
+
+// recv_deadline_into的结果
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecvStatus {
+    // 收到了msg
+    Received,
+    // 到达deadline时仍然没有msg
+    TimedOut,
+    // channel为空并且已经断开
+    Disconnected,
+}
+
+// Receiver::poll的结果，与try_recv的三种结果一一对应，适合在poll循环中直接match
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Poll3<T> {
+    // 收到了msg
+    Ready(T),
+    // 暂时没有msg
+    Empty,
+    // channel为空并且已经断开，之后也不会再有msg
+    Closed,
+}
+
+#[cfg(feature = "std")]
+impl<T> Poll3<T> {
+    // 转换Ready中的msg，Empty和Closed保持不变
+    pub fn map<U, F: FnOnce(T) -> U>(self, f: F) -> Poll3<U> {
+        match self {
+            Poll3::Ready(msg) => Poll3::Ready(f(msg)),
+            Poll3::Empty => Poll3::Empty,
+            Poll3::Closed => Poll3::Closed,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T> From<Result<T, TryRecvError>> for Poll3<T> {
+    fn from(res: Result<T, TryRecvError>) -> Self {
+        match res {
+            Ok(msg) => Poll3::Ready(msg),
+            Err(TryRecvError::Empty) => Poll3::Empty,
+            Err(TryRecvError::Disconnected) => Poll3::Closed,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T> Receiver<T> {
+    // 在select选中这个Receiver之后，通过token完成读取
+    pub(crate) unsafe fn read(&self, token: &mut Token) -> Result<T, ()> {
+        match &self.flavor {
+            ReceiverFlavor::Array(chan) => chan.read(token),
+            ReceiverFlavor::List(chan) => chan.read(token),
+            ReceiverFlavor::Zero(chan) => chan.read(token),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T> SelectHandle for Receiver<T> {
+    fn try_select(&self, token: &mut Token) -> bool {
+        match &self.flavor {
+            ReceiverFlavor::Array(chan) => chan.receiver().try_select(token),
+            ReceiverFlavor::List(chan) => chan.receiver().try_select(token),
+            ReceiverFlavor::Zero(chan) => chan.receiver().try_select(token),
+        }
+    }
+    fn register(&self, oper: Operation, cx: &Context) -> bool {
+        match &self.flavor {
+            ReceiverFlavor::Array(chan) => chan.receiver().register(oper, cx),
+            ReceiverFlavor::List(chan) => chan.receiver().register(oper, cx),
+            ReceiverFlavor::Zero(chan) => {
+                #[cfg(debug_assertions)]
+                if chan.is_self_rendezvous(false) {
+                    report_self_rendezvous();
+                }
+                chan.receiver().register(oper, cx)
+            }
+        }
+    }
+    fn unregister(&self, oper: Operation) {
+        match &self.flavor {
+            ReceiverFlavor::Array(chan) => chan.receiver().unregister(oper),
+            ReceiverFlavor::List(chan) => chan.receiver().unregister(oper),
+            ReceiverFlavor::Zero(chan) => chan.receiver().unregister(oper),
+        }
+    }
+    fn accept(&self, token: &mut Token, cx: &Context) -> bool {
+        match &self.flavor {
+            ReceiverFlavor::Array(chan) => chan.receiver().accept(token, cx),
+            ReceiverFlavor::List(chan) => chan.receiver().accept(token, cx),
+            ReceiverFlavor::Zero(chan) => chan.receiver().accept(token, cx),
+        }
+    }
+    fn is_ready(&self) -> bool {
+        match &self.flavor {
+            ReceiverFlavor::Array(chan) => chan.receiver().is_ready(),
+            ReceiverFlavor::List(chan) => chan.receiver().is_ready(),
+            ReceiverFlavor::Zero(chan) => chan.receiver().is_ready(),
+        }
+    }
+    fn watch(&self, oper: Operation, cx: &Context) -> bool {
+        match &self.flavor {
+            ReceiverFlavor::Array(chan) => chan.receiver().watch(oper, cx),
+            ReceiverFlavor::List(chan) => chan.receiver().watch(oper, cx),
+            ReceiverFlavor::Zero(chan) => chan.receiver().watch(oper, cx),
+        }
+    }
+    fn unwatch(&self, oper: Operation) {
+        match &self.flavor {
+            ReceiverFlavor::Array(chan) => chan.receiver().unwatch(oper),
+            ReceiverFlavor::List(chan) => chan.receiver().unwatch(oper),
+            ReceiverFlavor::Zero(chan) => chan.receiver().unwatch(oper),
+        }
+    }
 }
 
+// 与Sender::into_raw/from_raw相同
+#[cfg(feature = "std")]
+impl<T> Receiver<T> {
+    pub fn into_raw(self) -> *const () {
+        let this = mem::ManuallyDrop::new(self);
+        let addr = match &this.flavor {
+            ReceiverFlavor::Array(chan) => chan.addr(),
+            ReceiverFlavor::List(chan) => chan.addr() | 1,
+            ReceiverFlavor::Zero(chan) => chan.addr() | 2,
+        };
+        addr as *const ()
+    }
+
+    /// # Safety
+    /// `ptr`必须是`Receiver::<T>::into_raw`的返回值，并且还没有被`from_raw`过
+    pub unsafe fn from_raw(ptr: *const ()) -> Receiver<T> {
+        let addr = ptr as usize & !counter::ADDR_TAG_MASK;
+        let flavor = match ptr as usize & counter::ADDR_TAG_MASK {
+            0 => ReceiverFlavor::Array(counter::Receiver::from_addr(addr)),
+            1 => ReceiverFlavor::List(counter::Receiver::from_addr(addr)),
+            _ => ReceiverFlavor::Zero(counter::Receiver::from_addr(addr)),
+        };
+        Receiver { flavor }
+    }
+}
 
 impl<T> Drop for Receiver<T> {
     fn drop(&mut self) {
         unsafe {
             match &self.flavor {
-                ReceiverFlavor::Array(chan) => chan.release(|c| c.disconnect()),
+                ReceiverFlavor::Array(chan) => chan.release(|c| c.disconnect_receivers()),
                 ReceiverFlavor::List(chan) => chan.release(|c| c.disconnect_receivers()),
+                #[cfg(feature = "std")]
                 ReceiverFlavor::Zero(chan) => chan.release(|c| c.disconnect()),
             }
         }
     }
 }
 
+/*
+ * clone出来的receiver和原来的receiver竞争同一条msg，每条msg只会被其中一个收到
+ * Receiver没有提供subscribe(让两个receiver各自收到之后的每一条msg)：list channel只有一个head，
+ * block在head越过之后就被释放，要支持多个独立的读取位置需要为每个订阅者维护head并延迟释放block，
+ * array和zero channel的slot在读取后也会立即交还给sender。需要这种语义时使用broadcast，
+ * BroadcastReceiver::subscribe从当前位置开始订阅之后的msg
+ */
 impl<T> Clone for Receiver<T> {
     fn clone(&self) -> Self {
         let flavor = match &self.flavor {
             ReceiverFlavor::Array(chan) => ReceiverFlavor::Array(chan.acquire()),
             ReceiverFlavor::List(chan) => ReceiverFlavor::List(chan.acquire()),
+            #[cfg(feature = "std")]
             ReceiverFlavor::Zero(chan) => ReceiverFlavor::Zero(chan.acquire()),
         };
 
@@ -301,8 +2074,198 @@ impl<T> Clone for Receiver<T> {
     }
 }
 
+#[cfg(feature = "std")]
 impl<T> fmt::Debug for Receiver<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.pad("Receiver { .. }")
+        let mut d = f.debug_struct("Receiver");
+        if let Some(name) = self.name() {
+            d.field("name", &name);
+        }
+        d.field("len", &self.len())
+            .field("capacity", &self.capacity())
+            .field("disconnected", &self.is_disconnected())
+            .finish()
+    }
+}
+
+// 与Sender相同，属于同一个channel的Receiver相等
+#[cfg(feature = "std")]
+impl<T> PartialEq for Receiver<T> {
+    fn eq(&self, other: &Receiver<T>) -> bool {
+        self.same_channel(other)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T> Eq for Receiver<T> {}
+
+#[cfg(feature = "std")]
+impl<T> Hash for Receiver<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id().hash(state)
+    }
+}
+
+/*
+ * Receiver的迭代器，只有一个receiver时size_hint的下界是channel中msg数量的快照(len)，
+ * 用于collect时预先分配空间；存在其他receiver时它们可能取走这些msg，下界为0
+ * sender可能继续发送，所以上界总是None
+ */
+#[cfg(feature = "std")]
+pub struct Iter<'a, T> {
+    rx: &'a Receiver<T>,
+}
+
+#[cfg(feature = "std")]
+impl<T> Iterator for Iter<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.rx.recv().ok()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.rx.guaranteed_len(), None)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T> fmt::Debug for Iter<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Iter").finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "std")]
+pub struct TryIter<'a, T> {
+    rx: &'a Receiver<T>,
+}
+
+#[cfg(feature = "std")]
+impl<T> Iterator for TryIter<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.rx.try_recv().ok()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.rx.guaranteed_len(), None)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T> fmt::Debug for TryIter<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TryIter").finish_non_exhaustive()
+    }
+}
+
+// 消耗Receiver的阻塞迭代器
+#[cfg(feature = "std")]
+pub struct IntoIter<T> {
+    rx: Receiver<T>,
+}
+
+#[cfg(feature = "std")]
+impl<T> Iterator for IntoIter<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.rx.recv().ok()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.rx.guaranteed_len(), None)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T> fmt::Debug for IntoIter<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IntoIter").finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "std")]
+impl<'a, T> IntoIterator for &'a Receiver<T> {
+    type Item = T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T> IntoIterator for Receiver<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> IntoIter<T> {
+        IntoIter { rx: self }
+    }
+}
+
+// Receiver的弱引用，不计入receivers计数
+#[cfg(feature = "std")]
+pub struct WeakReceiver<T> {
+    flavor: WeakReceiverFlavor<T>,
+}
+
+#[cfg(feature = "std")]
+enum WeakReceiverFlavor<T> {
+    Array(counter::WeakReceiver<array::Channel<T>>),
+    List(counter::WeakReceiver<list::Channel<T>>),
+    Zero(counter::WeakReceiver<zero::Channel<T>>),
+}
+
+#[cfg(feature = "std")]
+unsafe impl<T: Send> Send for WeakReceiver<T> {}
+#[cfg(feature = "std")]
+unsafe impl<T: Send> Sync for WeakReceiver<T> {}
+
+#[cfg(feature = "std")]
+impl<T> WeakReceiver<T> {
+    // 如果还有存活的Receiver，就返回一个新的Receiver，否则返回None
+    pub fn upgrade(&self) -> Option<Receiver<T>> {
+        let flavor = match &self.flavor {
+            WeakReceiverFlavor::Array(chan) => ReceiverFlavor::Array(chan.upgrade()?),
+            WeakReceiverFlavor::List(chan) => ReceiverFlavor::List(chan.upgrade()?),
+            WeakReceiverFlavor::Zero(chan) => ReceiverFlavor::Zero(chan.upgrade()?),
+        };
+        Some(Receiver { flavor })
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T> Drop for WeakReceiver<T> {
+    fn drop(&mut self) {
+        unsafe {
+            match &self.flavor {
+                WeakReceiverFlavor::Array(chan) => chan.release(),
+                WeakReceiverFlavor::List(chan) => chan.release(),
+                WeakReceiverFlavor::Zero(chan) => chan.release(),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T> Clone for WeakReceiver<T> {
+    fn clone(&self) -> Self {
+        let flavor = match &self.flavor {
+            WeakReceiverFlavor::Array(chan) => WeakReceiverFlavor::Array(chan.acquire()),
+            WeakReceiverFlavor::List(chan) => WeakReceiverFlavor::List(chan.acquire()),
+            WeakReceiverFlavor::Zero(chan) => WeakReceiverFlavor::Zero(chan.acquire()),
+        };
+        WeakReceiver { flavor }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T> fmt::Debug for WeakReceiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("WeakReceiver { .. }")
     }
 }
@@ -0,0 +1,134 @@
+/*
+ * loom模型检查，只在`--cfg loom`下编译：
+ * RUSTFLAGS="--cfg loom" cargo test --release loom_
+ * 普通的测试直接使用std::thread，不能在loom下运行，所以用loom_前缀过滤
+ */
+use loom::sync::atomic::{AtomicBool, Ordering};
+use loom::sync::Arc;
+use loom::thread;
+
+use super::context::Context;
+use super::select::{Operation, Selected};
+use super::waker::SyncWaker;
+use super::*;
+
+// 自旋和阻塞的路径很长，不限制抢占次数的话状态空间太大
+fn model(f: impl Fn() + Sync + Send + 'static) {
+    let mut builder = loom::model::Builder::new();
+    builder.preemption_bound = Some(2);
+    builder.check(f);
+}
+
+// 容量为1的array channel，一次阻塞的send和一次阻塞的recv总能配对
+#[test]
+fn loom_array_single_slot_send_recv() {
+    model(|| {
+        let (s, r) = sync_channel(1);
+        let t = thread::spawn(move || {
+            s.send(1).unwrap();
+            s.send(2).unwrap();
+        });
+        assert_eq!(r.recv(), Ok(1));
+        assert_eq!(r.recv(), Ok(2));
+        t.join().unwrap();
+        assert_eq!(r.try_recv(), Err(TryRecvError::Disconnected));
+    });
+}
+
+// 阻塞在空list channel上的receiver不会错过send的唤醒
+#[test]
+fn loom_list_blocking_recv() {
+    model(|| {
+        let (s, r) = channel();
+        let t = thread::spawn(move || s.send(1).unwrap());
+        assert_eq!(r.recv(), Ok(1));
+        t.join().unwrap();
+    });
+}
+
+// zero channel的两端同时阻塞时能完成交接
+#[test]
+fn loom_zero_rendezvous() {
+    model(|| {
+        let (s, r) = sync_channel(0);
+        let t = thread::spawn(move || s.send(1).unwrap());
+        assert_eq!(r.recv(), Ok(1));
+        t.join().unwrap();
+    });
+}
+
+// 两端同时disconnect，只有一个返回true，另一端看到的状态一致
+#[test]
+fn loom_disconnect_race() {
+    model(|| {
+        let (s, r) = sync_channel::<i32>(1);
+        let t = thread::spawn(move || s.disconnect());
+        let mine = r.disconnect();
+        let theirs = t.join().unwrap();
+        assert!(mine ^ theirs);
+        assert!(r.is_disconnected());
+    });
+}
+
+// 最后一个sender在发送后立即drop，receiver先收到msg再看到断开
+#[test]
+fn loom_drop_sender_after_send() {
+    model(|| {
+        let (s, r) = channel();
+        let t = thread::spawn(move || {
+            s.send(1).unwrap();
+            drop(s);
+        });
+        assert_eq!(r.recv(), Ok(1));
+        assert_eq!(r.recv(), Err(RecvError));
+        t.join().unwrap();
+    });
+}
+
+// register之后再检查条件，notify一定能唤醒等待者或者让它看到条件已经满足
+#[test]
+fn loom_waker_register_notify() {
+    model(|| {
+        let waker = Arc::new(SyncWaker::new());
+        let ready = Arc::new(AtomicBool::new(false));
+        let t = thread::spawn({
+            let (waker, ready) = (waker.clone(), ready.clone());
+            move || {
+                ready.store(true, Ordering::SeqCst);
+                waker.notify();
+            }
+        });
+        Context::with(|cx| {
+            let mut token = 0u8;
+            let oper = Operation::hook(&mut token);
+            waker.register(oper, cx);
+            if ready.load(Ordering::SeqCst) {
+                let _ = cx.try_select(Selected::Aborted);
+            }
+            match cx.wait_until(None) {
+                Selected::Operation(_) => {}
+                _ => {
+                    waker.unregister(oper).unwrap();
+                }
+            }
+        });
+        t.join().unwrap();
+        assert!(ready.load(Ordering::SeqCst));
+    });
+}
+
+// 唯一的receiver直接store head，与并发的sender之间不会丢失或重复msg
+#[test]
+fn loom_exclusive_recv() {
+    model(|| {
+        let (s, mut r) = sync_channel(1);
+        let t = thread::spawn(move || {
+            s.send(1).unwrap();
+            s.send(2).unwrap();
+        });
+        assert_eq!(r.recv_exclusive(), Ok(1));
+        assert_eq!(r.recv_exclusive(), Ok(2));
+        assert_eq!(r.recv_exclusive(), Err(RecvError));
+        t.join().unwrap();
+    });
+}
@@ -0,0 +1,47 @@
+/*
+ * no_std下代替select和waker的最小实现，见mod.rs开头的说明
+ * 没有阻塞的线程，select只需要Token，waker不需要唤醒任何对象
+ */
+
+// 与std下select::Token相同，只是没有zero channel
+pub(crate) mod select {
+    #[derive(Debug, Default)]
+    pub struct Token {
+        pub(crate) array: crate::mpmc::array::ArrayToken,
+        pub(crate) list: crate::mpmc::list::ListToken,
+        pub(crate) exclusive: bool,
+        #[cfg(feature = "profiling")]
+        pub(crate) retries: u32,
+    }
+
+    impl Token {
+        #[inline]
+        pub(crate) fn exclusive() -> Token {
+            Token {
+                exclusive: true,
+                ..Token::default()
+            }
+        }
+
+        #[inline]
+        pub(crate) fn retry(&mut self) {
+            #[cfg(feature = "profiling")]
+            {
+                self.retries += 1;
+            }
+        }
+    }
+}
+
+// 没有阻塞的线程，也就没有需要唤醒的对象
+pub(crate) mod waker {
+    pub(crate) struct SyncWaker;
+
+    impl SyncWaker {
+        pub(crate) const fn new() -> Self {
+            SyncWaker
+        }
+        pub(crate) fn notify(&self) {}
+        pub(crate) fn disconnect(&self) {}
+    }
+}
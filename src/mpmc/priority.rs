@@ -0,0 +1,201 @@
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+use super::{
+    context::{now, Context},
+    counter,
+    errors::*,
+    list,
+    select::{Selected, Operation, Token},
+};
+
+/*
+ * 优先级channel：内部每个优先级都是一个无界的list::Channel
+ * receiver总是从优先级最高的非空level开始读取，level越大优先级越高
+ * 注意：只要高优先级的level一直有msg，低优先级的msg就永远不会被读取(饥饿)
+ * 阻塞时receiver在每一个level的receivers中注册同一个操作，任何一个level写入msg都会唤醒它
+ */
+pub(crate) struct Channel<T> {
+    levels: Vec<list::Channel<T>>,
+}
+
+impl<T> Channel<T> {
+    fn disconnect_senders(&self) -> bool {
+        self.levels
+            .iter()
+            .fold(false, |res, chan| chan.disconnect_senders() | res)
+    }
+
+    fn disconnect_receivers(&self) -> bool {
+        self.levels
+            .iter()
+            .fold(false, |res, chan| chan.disconnect_receivers() | res)
+    }
+
+    fn try_recv(&self) -> Result<T, TryRecvError> {
+        let mut disconnected = true;
+        for chan in self.levels.iter().rev() {
+            match chan.try_recv() {
+                Ok(msg) => return Ok(msg),
+                Err(TryRecvError::Empty) => disconnected = false,
+                Err(TryRecvError::Disconnected) => {}
+            }
+        }
+        if disconnected {
+            Err(TryRecvError::Disconnected)
+        } else {
+            Err(TryRecvError::Empty)
+        }
+    }
+
+    fn recv(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        let token = &mut Token::default();
+        loop {
+            match self.try_recv() {
+                Ok(msg) => return Ok(msg),
+                Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+                Err(TryRecvError::Empty) => {}
+            }
+
+            if let Some(d) = deadline {
+                if now() >= d {
+                    return Err(RecvTimeoutError::Timeout);
+                }
+            }
+
+            Context::with(|cx| {
+                let oper = Operation::hook(token);
+                for chan in &self.levels {
+                    chan.register_receiver(oper, cx);
+                }
+
+                // Has the channel become ready just now?
+                if self.levels.iter().any(|chan| chan.is_recv_ready()) {
+                    let _ = cx.try_select(Selected::Aborted);
+                }
+
+                let sel = cx.wait_until(deadline);
+                debug_assert!(sel != Selected::Waiting);
+
+                // 唤醒我们的那个level已经移除了entry，其他level需要自己取消注册
+                for chan in &self.levels {
+                    chan.unregister_receiver(oper);
+                }
+            });
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.levels.iter().map(|chan| chan.len()).sum()
+    }
+}
+
+// 创建一个有levels个优先级的无界channel，有效的level为0..levels，level越大优先级越高
+pub fn priority_channel<T>(levels: usize) -> (PrioritySender<T>, PriorityReceiver<T>) {
+    assert!(levels > 0, "levels must be positive");
+    let chan = Channel {
+        levels: (0..levels).map(|_| list::Channel::new()).collect(),
+    };
+    let (s, r) = counter::new(chan, None, None, None);
+    (PrioritySender { chan: s }, PriorityReceiver { chan: r })
+}
+
+pub struct PrioritySender<T> {
+    chan: counter::Sender<Channel<T>>,
+}
+
+unsafe impl<T: Send> Send for PrioritySender<T> {}
+unsafe impl<T: Send> Sync for PrioritySender<T> {}
+
+impl<T> PrioritySender<T> {
+    // 以指定的优先级发送msg，level超出范围时panic
+    pub fn send(&self, msg: T, level: usize) -> Result<(), SendError<T>> {
+        let levels = self.chan.levels.len();
+        assert!(level < levels, "priority level {} out of range 0..{}", level, levels);
+        self.chan.levels[level].send(msg)
+    }
+    pub fn levels(&self) -> usize {
+        self.chan.levels.len()
+    }
+    pub fn len(&self) -> usize {
+        self.chan.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T> Drop for PrioritySender<T> {
+    fn drop(&mut self) {
+        unsafe {
+            self.chan.release(|c| c.disconnect_senders());
+        }
+    }
+}
+
+impl<T> Clone for PrioritySender<T> {
+    fn clone(&self) -> Self {
+        PrioritySender {
+            chan: self.chan.acquire(),
+        }
+    }
+}
+
+impl<T> fmt::Debug for PrioritySender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("PrioritySender { .. }")
+    }
+}
+
+pub struct PriorityReceiver<T> {
+    chan: counter::Receiver<Channel<T>>,
+}
+
+unsafe impl<T: Send> Send for PriorityReceiver<T> {}
+unsafe impl<T: Send> Sync for PriorityReceiver<T> {}
+
+impl<T> PriorityReceiver<T> {
+    // 从优先级最高的非空level中取一条msg
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.chan.try_recv()
+    }
+    pub fn recv(&self) -> Result<T, RecvError> {
+        self.chan.recv(None).map_err(|_| RecvError)
+    }
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        match now().checked_add(timeout) {
+            Some(deadline) => self.chan.recv(Some(deadline)),
+            None => self.recv().map_err(RecvTimeoutError::from),
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.chan.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T> Drop for PriorityReceiver<T> {
+    fn drop(&mut self) {
+        unsafe {
+            self.chan.release(|c| c.disconnect_receivers());
+        }
+    }
+}
+
+impl<T> Clone for PriorityReceiver<T> {
+    fn clone(&self) -> Self {
+        PriorityReceiver {
+            chan: self.chan.acquire(),
+        }
+    }
+}
+
+impl<T> fmt::Debug for PriorityReceiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("PriorityReceiver { .. }")
+    }
+}
@@ -0,0 +1,200 @@
+use std::{
+    fmt,
+    mem::ManuallyDrop,
+    ptr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use super::context::now;
+use super::list::{self, Cursor};
+use super::{errors::*, Receiver, ReceiverFlavor};
+
+/*
+ * Receiver::replay返回的适配器，可以记录读取位置(checkpoint)，之后从这个位置重新读取(rewind)
+ * 只支持list channel，并且必须是唯一的receiver：rewind直接移动head，不能有其他线程同时在接收
+ * 有存活的Checkpoint时接收的msg是clone出来的，原来的msg和block都留在channel中，
+ * 从最早的checkpoint开始的整个范围一直保留到所有Checkpoint都被drop，之后的第一次操作才释放它们
+ * ReplayReceiver自己就是唯一的receiver，不能clone，也不提供对内部Receiver的引用
+ */
+pub struct ReplayReceiver<T> {
+    receiver: ManuallyDrop<Receiver<T>>,
+    // Checkpoint持有它的clone，strong_count大于1时说明还有存活的Checkpoint
+    live: Arc<()>,
+    // 正在保留的范围的起点，block为null表示起点之后还没有读取过msg
+    floor: Option<Cursor>,
+}
+
+// ReplayReceiver::checkpoint记录的读取位置，只能用在创建它的ReplayReceiver上
+pub struct Checkpoint {
+    index: usize,
+    block: usize,
+    owner: Arc<()>,
+}
+
+impl<T> ReplayReceiver<T> {
+    // Receiver::replay检查了channel类型和receiver数量
+    pub(crate) fn new(receiver: Receiver<T>) -> Self {
+        ReplayReceiver {
+            receiver: ManuallyDrop::new(receiver),
+            live: Arc::new(()),
+            floor: None,
+        }
+    }
+
+    fn chan(&self) -> &list::Channel<T> {
+        match &self.receiver.flavor {
+            ReceiverFlavor::List(chan) => chan,
+            _ => unreachable!(),
+        }
+    }
+
+    // 所有Checkpoint都已经被drop时结束保留，释放保留的msg
+    fn release_unused(&mut self) {
+        if Arc::strong_count(&self.live) > 1 {
+            return;
+        }
+        if let Some(floor) = self.floor.take() {
+            if !floor.block.is_null() {
+                unsafe { self.chan().release_retained(floor) };
+            }
+        }
+    }
+
+    // 保留读取之后补上起点的block：起点时还没有发送过msg，读到的第一个msg一定在head当前的block中
+    fn after_read<R>(&mut self, res: R) -> R {
+        let head = self.chan().cursor().block;
+        if let Some(floor) = &mut self.floor {
+            if floor.block.is_null() {
+                floor.block = head;
+            }
+        }
+        res
+    }
+
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError>
+    where
+        T: Clone,
+    {
+        self.release_unused();
+        if self.floor.is_none() {
+            return self.receiver.try_recv();
+        }
+        let res = unsafe { self.chan().try_recv_retained() };
+        self.after_read(res)
+    }
+
+    pub fn recv(&mut self) -> Result<T, RecvError>
+    where
+        T: Clone,
+    {
+        self.recv_deadline_inner(None).map_err(|_| RecvError)
+    }
+
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Result<T, RecvTimeoutError>
+    where
+        T: Clone,
+    {
+        self.recv_deadline_inner(now().checked_add(timeout))
+    }
+
+    fn recv_deadline_inner(&mut self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError>
+    where
+        T: Clone,
+    {
+        self.release_unused();
+        if self.floor.is_none() {
+            return match deadline {
+                Some(deadline) => self.receiver.recv_deadline(deadline),
+                None => self.receiver.recv().map_err(RecvTimeoutError::from),
+            };
+        }
+        let res = unsafe { self.chan().recv_retained(deadline) };
+        self.after_read(res)
+    }
+
+    // 记录当前的读取位置，从这时起接收的msg都会被保留，直到这个Checkpoint(以及之后创建的)被drop
+    pub fn checkpoint(&mut self) -> Checkpoint {
+        self.release_unused();
+        let cursor = self.chan().cursor();
+        self.floor.get_or_insert(cursor);
+        Checkpoint {
+            index: cursor.index,
+            block: cursor.block as usize,
+            owner: self.live.clone(),
+        }
+    }
+
+    // 回到checkpoint记录的位置，之后的接收按原来的顺序重新得到从那时起的msg
+    pub fn rewind(&mut self, checkpoint: &Checkpoint) {
+        assert!(
+            Arc::ptr_eq(&checkpoint.owner, &self.live),
+            "checkpoint belongs to another ReplayReceiver"
+        );
+        let Some(floor) = self.floor else {
+            unreachable!()
+        };
+        let mut cursor = Cursor {
+            index: checkpoint.index,
+            block: checkpoint.block as *const u8,
+        };
+        // 创建checkpoint时还没有发送过msg，它就是保留的起点
+        if cursor.block.is_null() {
+            cursor.block = floor.block;
+        }
+        // 起点之后还没有读取过msg，head没有移动过
+        if cursor.block.is_null() {
+            return;
+        }
+        unsafe { self.chan().rewind(cursor) };
+    }
+
+    // 与Receiver::len相同，rewind之后重新变为未读的msg也计算在内
+    pub fn len(&self) -> usize {
+        self.receiver.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.receiver.is_empty()
+    }
+
+    // 释放保留的msg，取回原来的Receiver，已有的Checkpoint不能再使用
+    pub fn into_inner(mut self) -> Receiver<T> {
+        self.release_all();
+        // 跳过Drop，其余的字段手动释放
+        let mut this = ManuallyDrop::new(self);
+        unsafe {
+            drop(ptr::read(&this.live));
+            ManuallyDrop::take(&mut this.receiver)
+        }
+    }
+
+    fn release_all(&mut self) {
+        // 不再有rewind，所以即使还有存活的Checkpoint也可以释放
+        self.live = Arc::new(());
+        self.release_unused();
+    }
+}
+
+impl<T> Drop for ReplayReceiver<T> {
+    // 先释放保留的msg，恢复正常的状态之后才能drop Receiver(断开时会丢弃head之后的msg)
+    fn drop(&mut self) {
+        self.release_all();
+        unsafe { ManuallyDrop::drop(&mut self.receiver) };
+    }
+}
+
+impl<T> fmt::Debug for ReplayReceiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReplayReceiver")
+            .field("receiver", &*self.receiver)
+            .field("retaining", &self.floor.is_some())
+            .finish()
+    }
+}
+
+impl fmt::Debug for Checkpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Checkpoint").finish_non_exhaustive()
+    }
+}
@@ -1,3 +1,17 @@
+use std::{
+    cell::{Cell, RefCell},
+    cmp::Reverse,
+    fmt,
+    marker::PhantomData,
+    mem,
+    time::{Duration, Instant},
+};
+
+use super::context::{now, Context};
+use super::errors::*;
+use super::utils::UnwindGuard;
+use super::{Receiver, Sender};
+
 // 当前的数据(在阻塞操作期间被初始化)会被read和write消耗
 
 // 每个域包含一个与指定channel flavor关联的数据
@@ -8,6 +22,31 @@ pub struct Token {
     pub(crate) array: super::array::ArrayToken,
     pub(crate) list: super::list::ListToken,
     pub(crate) zero: super::zero::ZeroToken,
+    // 调用者保证没有其他线程同时接收，start_recv可以直接store head而不用CAS
+    pub(crate) exclusive: bool,
+    // start_send/start_recv中CAS失败后重试的次数
+    #[cfg(feature = "profiling")]
+    pub(crate) retries: u32,
+}
+
+impl Token {
+    // 唯一的receiver使用的Token
+    #[inline]
+    pub(crate) fn exclusive() -> Token {
+        Token {
+            exclusive: true,
+            ..Token::default()
+        }
+    }
+
+    // 记录一次CAS失败后的重试，没有开启profiling时什么也不做
+    #[inline]
+    pub(crate) fn retry(&mut self) {
+        #[cfg(feature = "profiling")]
+        {
+            self.retries += 1;
+        }
+    }
 }
 
 // 代表与一个指定的线程在指定的channel上相关联的操作的id
@@ -51,10 +90,10 @@ impl From<usize> for Selected {
     }
 }
 
-impl Into<usize> for Selected {
+impl From<Selected> for usize {
     #[inline]
-    fn into(self) -> usize {
-        match self {
+    fn from(val: Selected) -> usize {
+        match val {
             Selected::Waiting => 0,
             Selected::Aborted => 1,
             Selected::Disconnected => 2,
@@ -62,3 +101,383 @@ impl Into<usize> for Selected {
         }
     }
 }
+
+/*
+ * 可以参与select的操作，每种flavor的接收端和发送端各自实现
+ * try_select/accept只完成操作的第一步(预留slot或者与对端配对)，并把结果记录在token中，
+ * 真正的读写在select返回之后由SelectedOperation::recv/send通过token完成
+ */
+pub(crate) trait SelectHandle {
+    // 尝试立即完成第一步，成功或者channel已经断开时返回true
+    fn try_select(&self, token: &mut Token) -> bool;
+    // 注册到对应的Waker，返回注册时操作是否已经ready
+    fn register(&self, oper: Operation, cx: &Context) -> bool;
+    // 取消register的注册
+    fn unregister(&self, oper: Operation);
+    // 因为这个操作被唤醒之后，尝试完成第一步
+    fn accept(&self, token: &mut Token, cx: &Context) -> bool;
+    // 操作是否可以立即完成
+    fn is_ready(&self) -> bool;
+    // 作为observer注册到对应的Waker，channel状态变化时被唤醒，返回注册时操作是否已经ready
+    fn watch(&self, oper: Operation, cx: &Context) -> bool;
+    // 取消watch的注册
+    fn unwatch(&self, oper: Operation);
+}
+
+// select等待的期限
+#[derive(Clone, Copy)]
+enum Timeout {
+    Now,
+    Never,
+    At(Instant),
+}
+
+/*
+ * 同时等待多个发送和接收操作，返回第一个ready的操作
+ * 所有操作都没有ready时，select只park一次：把每个操作注册到对应的Waker(接收注册在receivers中，
+ * 发送注册在senders中)，任意一个Waker的notify都会唤醒这个线程，之后取消所有注册并重新尝试
+ * 每次select从一个随机的位置开始尝试，避免总是偏向先加入的操作
+ * 注册只需要擦除了类型的SelectHandle，所以同一个Select可以加入msg类型不同的channel，
+ * 根据返回的序号再用对应类型的Sender/Receiver完成操作
+ */
+pub struct Select<'a> {
+    // (操作, 加入时的序号, 对应Sender/Receiver的地址, 优先级)
+    handles: Vec<(&'a dyn SelectHandle, usize, *const u8, u8)>,
+    next_index: usize,
+}
+
+unsafe impl Send for Select<'_> {}
+unsafe impl Sync for Select<'_> {}
+
+impl<'a> Select<'a> {
+    pub fn new() -> Select<'a> {
+        Select {
+            handles: Vec::with_capacity(4),
+            next_index: 0,
+        }
+    }
+
+    // 加入一个发送操作，返回它的序号
+    pub fn send<T>(&mut self, s: &'a Sender<T>) -> usize {
+        let i = self.next_index;
+        let ptr = s as *const Sender<T> as *const u8;
+        self.handles.push((s, i, ptr, 0));
+        self.next_index += 1;
+        i
+    }
+
+    // 加入一个接收操作，返回它的序号
+    pub fn recv<T>(&mut self, r: &'a Receiver<T>) -> usize {
+        self.recv_with_priority(r, 0)
+    }
+
+    /*
+     * 加入一个带优先级的接收操作，返回它的序号
+     * 多个操作同时ready时先选中priority大的，priority相同的操作之间仍然从随机的位置开始选择
+     * 优先级只决定同时ready时的选择，阻塞之后先ready的操作仍然会先被选中。recv/send的priority是0
+     */
+    pub fn recv_with_priority<T>(&mut self, r: &'a Receiver<T>, priority: u8) -> usize {
+        let i = self.next_index;
+        let ptr = r as *const Receiver<T> as *const u8;
+        self.handles.push((r, i, ptr, priority));
+        self.next_index += 1;
+        i
+    }
+
+    // 移除序号为index的操作
+    pub fn remove(&mut self, index: usize) {
+        assert!(index < self.next_index, "index out of bounds; {} >= {}", index, self.next_index);
+        let i = self
+            .handles
+            .iter()
+            .position(|&(_, i, _, _)| i == index)
+            .expect("no operation with this index");
+        self.handles.swap_remove(i);
+    }
+
+    // 不阻塞，如果有操作ready就选中它
+    pub fn try_select(&mut self) -> Result<SelectedOperation<'a>, TrySelectError> {
+        run_select(&mut self.handles, Timeout::Now).ok_or(TrySelectError)
+    }
+
+    // 阻塞直到有一个操作ready
+    // 没有任何操作时会永远阻塞
+    pub fn select(&mut self) -> SelectedOperation<'a> {
+        run_select(&mut self.handles, Timeout::Never).unwrap()
+    }
+
+    pub fn select_timeout(&mut self, timeout: Duration) -> Result<SelectedOperation<'a>, SelectTimeoutError> {
+        match now().checked_add(timeout) {
+            Some(deadline) => self.select_deadline(deadline),
+            None => Ok(self.select()),
+        }
+    }
+
+    pub fn select_deadline(&mut self, deadline: Instant) -> Result<SelectedOperation<'a>, SelectTimeoutError> {
+        run_select(&mut self.handles, Timeout::At(deadline)).ok_or(SelectTimeoutError)
+    }
+
+    /*
+     * ready系列只返回一个ready的操作的序号，不会完成它，之后需要自己调用对应的send/recv
+     * 返回时操作可能已经被其他线程抢先完成，所以之后的try_send/try_recv仍然可能失败
+     * 等待时操作被注册为Waker中的observer，不会被try_select选中，也就不会占用其他线程的唤醒
+     */
+    pub fn try_ready(&mut self) -> Result<usize, TryReadyError> {
+        run_ready(&mut self.handles, Timeout::Now).ok_or(TryReadyError)
+    }
+
+    // 阻塞直到有一个操作ready，没有任何操作时会永远阻塞
+    pub fn ready(&mut self) -> usize {
+        run_ready(&mut self.handles, Timeout::Never).unwrap()
+    }
+
+    pub fn ready_timeout(&mut self, timeout: Duration) -> Result<usize, ReadyTimeoutError> {
+        match now().checked_add(timeout) {
+            Some(deadline) => self.ready_deadline(deadline),
+            None => Ok(self.ready()),
+        }
+    }
+
+    pub fn ready_deadline(&mut self, deadline: Instant) -> Result<usize, ReadyTimeoutError> {
+        run_ready(&mut self.handles, Timeout::At(deadline)).ok_or(ReadyTimeoutError)
+    }
+}
+
+impl Default for Select<'_> {
+    fn default() -> Self {
+        Select::new()
+    }
+}
+
+impl fmt::Debug for Select<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Select").field("len", &self.handles.len()).finish()
+    }
+}
+
+/*
+ * 被选中的操作，第一步已经完成，必须调用recv或者send完成剩下的部分
+ * 例如array channel中已经预留了slot，不完成操作会让这个slot永远无法被使用，所以直接drop会panic
+ */
+#[must_use]
+pub struct SelectedOperation<'a> {
+    token: Token,
+    index: usize,
+    ptr: *const u8,
+    _marker: PhantomData<&'a ()>,
+}
+
+impl SelectedOperation<'_> {
+    // 被选中的操作在加入Select时返回的序号
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    // 完成被选中的发送操作，s必须是加入Select时的那个Sender
+    pub fn send<T>(mut self, s: &Sender<T>, msg: T) -> Result<(), SendError<T>> {
+        assert!(
+            s as *const Sender<T> as *const u8 == self.ptr,
+            "passed a sender that wasn't selected",
+        );
+        let res = unsafe { s.write(&mut self.token, msg) };
+        mem::forget(self);
+        res.map_err(SendError)
+    }
+
+    // 完成被选中的接收操作，r必须是加入Select时的那个Receiver
+    // 已经断开但还有缓存msg的channel仍然按可以接收处理，先返回剩余的msg，取空之后才返回RecvError
+    pub fn recv<T>(mut self, r: &Receiver<T>) -> Result<T, RecvError> {
+        assert!(
+            r as *const Receiver<T> as *const u8 == self.ptr,
+            "passed a receiver that wasn't selected",
+        );
+        let res = unsafe { r.read(&mut self.token) };
+        mem::forget(self);
+        res.map_err(|_| RecvError)
+    }
+}
+
+impl fmt::Debug for SelectedOperation<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SelectedOperation").field("index", &self.index).finish()
+    }
+}
+
+impl Drop for SelectedOperation<'_> {
+    fn drop(&mut self) {
+        panic!("dropped `SelectedOperation` without completing the operation");
+    }
+}
+
+// 没有操作时只需要等到deadline
+fn wait_without_handles(timeout: Timeout) {
+    match timeout {
+        Timeout::Now => {}
+        Timeout::Never => loop {
+            Context::with(|cx| cx.wait_until(None));
+        },
+        Timeout::At(when) => {
+            Context::with(|cx| cx.wait_until(Some(when)));
+        }
+    }
+}
+
+// 返回None时已经到了deadline
+fn remaining(timeout: Timeout) -> Option<Option<Instant>> {
+    match timeout {
+        Timeout::Now => None,
+        Timeout::Never => Some(None),
+        Timeout::At(when) => (now() < when).then_some(Some(when)),
+    }
+}
+
+fn run_select<'a>(
+    handles: &mut [(&'a dyn SelectHandle, usize, *const u8, u8)],
+    timeout: Timeout,
+) -> Option<SelectedOperation<'a>> {
+    if handles.is_empty() {
+        wait_without_handles(timeout);
+        return None;
+    }
+
+    shuffle(handles);
+
+    let mut token = Token::default();
+    let selected = |token: Token, &(_, index, ptr, _): &(&'a dyn SelectHandle, usize, *const u8, u8)| {
+        SelectedOperation { token, index, ptr, _marker: PhantomData }
+    };
+
+    loop {
+        for handle in handles.iter() {
+            if handle.0.try_select(&mut token) {
+                return Some(selected(token, handle));
+            }
+        }
+
+        let deadline = remaining(timeout)?;
+
+        let accepted = Context::with(|cx| {
+            let mut sel = Selected::Waiting;
+            let registered = Cell::new(0);
+            let handles = RefCell::new(&mut *handles);
+
+            // 正常返回、注册时以及阻塞期间panic时都要取消注册
+            let unregister = UnwindGuard::new(|| {
+                for (handle, _, _, _) in handles.borrow_mut().iter_mut().take(registered.get()) {
+                    handle.unregister(Operation::hook::<&dyn SelectHandle>(handle));
+                }
+            });
+
+            // 注册所有操作，某个操作在注册时已经ready或者已经被唤醒时就不需要继续注册
+            for (handle, _, _, _) in handles.borrow_mut().iter_mut() {
+                registered.set(registered.get() + 1);
+                if handle.register(Operation::hook::<&dyn SelectHandle>(handle), cx) {
+                    if let Err(s) = cx.try_select(Selected::Aborted) {
+                        sel = s;
+                    } else {
+                        sel = Selected::Aborted;
+                    }
+                    break;
+                }
+                sel = cx.selected();
+                if sel != Selected::Waiting {
+                    break;
+                }
+            }
+
+            if sel == Selected::Waiting {
+                sel = cx.wait_until(deadline);
+            }
+            drop(unregister);
+
+            // 被某个操作唤醒时，尝试完成这个操作的第一步，失败(被其他线程抢先)就重新开始
+            match sel {
+                Selected::Waiting => unreachable!(),
+                Selected::Aborted | Selected::Disconnected => None,
+                Selected::Operation(_) => handles.borrow_mut().iter_mut().position(|(handle, _, _, _)| {
+                    sel == Selected::Operation(Operation::hook::<&dyn SelectHandle>(handle))
+                        && handle.accept(&mut token, cx)
+                }),
+            }
+        });
+
+        if let Some(i) = accepted {
+            return Some(selected(token, &handles[i]));
+        }
+    }
+}
+
+// 与run_select相同的注册方式，只是注册为observer，被唤醒之后重新检查所有操作的is_ready
+fn run_ready(handles: &mut [(&dyn SelectHandle, usize, *const u8, u8)], timeout: Timeout) -> Option<usize> {
+    if handles.is_empty() {
+        wait_without_handles(timeout);
+        return None;
+    }
+
+    shuffle(handles);
+
+    loop {
+        if let Some(&(_, index, _, _)) = handles.iter().find(|(handle, _, _, _)| handle.is_ready()) {
+            return Some(index);
+        }
+
+        let deadline = remaining(timeout)?;
+
+        Context::with(|cx| {
+            let mut sel = Selected::Waiting;
+            let watched = Cell::new(0);
+            let handles = RefCell::new(&mut *handles);
+
+            // 正常返回、注册时以及阻塞期间panic时都要取消监视
+            let _unwatch = UnwindGuard::new(|| {
+                for (handle, _, _, _) in handles.borrow_mut().iter_mut().take(watched.get()) {
+                    handle.unwatch(Operation::hook::<&dyn SelectHandle>(handle));
+                }
+            });
+
+            for (handle, _, _, _) in handles.borrow_mut().iter_mut() {
+                watched.set(watched.get() + 1);
+                if handle.watch(Operation::hook::<&dyn SelectHandle>(handle), cx) {
+                    sel = match cx.try_select(Selected::Aborted) {
+                        Ok(()) => Selected::Aborted,
+                        Err(s) => s,
+                    };
+                    break;
+                }
+                sel = cx.selected();
+                if sel != Selected::Waiting {
+                    break;
+                }
+            }
+
+            if sel == Selected::Waiting {
+                cx.wait_until(deadline);
+            }
+        });
+    }
+}
+
+// 从随机的位置开始，保证多个ready的操作被公平地选中；有优先级时再按优先级稳定排序，
+// priority相同的操作保持随机起点之后的顺序
+fn shuffle(handles: &mut [(&dyn SelectHandle, usize, *const u8, u8)]) {
+    let start = random(handles.len());
+    handles.rotate_left(start);
+    if handles.iter().any(|&(_, _, _, priority)| priority != 0) {
+        handles.sort_by_key(|&(_, _, _, priority)| Reverse(priority));
+    }
+}
+
+// 线程本地的xorshift随机数，返回0..n之间的数
+fn random(n: usize) -> usize {
+    thread_local! {
+        static RNG: Cell<u32> = const { Cell::new(0x9e37_79b9) };
+    }
+    RNG.with(|rng| {
+        let mut x = rng.get();
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        rng.set(x);
+        x as usize % n
+    })
+}
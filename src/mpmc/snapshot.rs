@@ -0,0 +1,23 @@
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+
+/*
+ * Receiver::snapshot的结果：调用时channel中已经写入、还没有被接收的msg
+ * 并发的sender可能在快照的同时写入，所以这只是尽力而为的一致快照：
+ * 包含的msg一定按照接收顺序排列，但是快照期间正在写入的msg可能被包含也可能被跳过
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot<T> {
+    // channel的容量，None表示无界channel，用于恢复时重建同样的channel
+    pub capacity: Option<usize>,
+    // 按接收顺序排列的msg
+    pub messages: Vec<T>,
+}
+
+impl<T: Serialize> Serialize for Snapshot<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Snapshot", 2)?;
+        state.serialize_field("capacity", &self.capacity)?;
+        state.serialize_field("messages", &self.messages)?;
+        state.end()
+    }
+}
@@ -0,0 +1,42 @@
+/*
+ * channel内部使用的原子类型、Mutex和线程原语
+ * 正常构建时来自core/std；用`RUSTFLAGS="--cfg loom"`构建时换成loom中的实现，
+ * loom会在模型中枚举线程的所有交错以及弱内存序下允许的结果，见model.rs中的测试：
+ * RUSTFLAGS="--cfg loom" cargo test --release loom_
+ */
+
+pub(crate) mod atomic {
+    #[cfg(not(loom))]
+    pub(crate) use core::sync::atomic::{fence, AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+    #[cfg(loom)]
+    pub(crate) use loom::sync::atomic::{fence, AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+}
+
+/*
+ * loom把SeqCst的读写当作AcqRel处理，只有SeqCst的fence是精确的，
+ * 依赖SeqCst全序的地方(例如SyncWaker的is_empty和channel状态之间)在loom下用fence补上，std下什么也不做
+ */
+#[inline]
+pub(crate) fn loom_seq_cst_fence() {
+    #[cfg(loom)]
+    atomic::fence(atomic::Ordering::SeqCst);
+}
+
+/*
+ * 定义一个正常构建时是const、loom构建时不是const的函数，loom中的原子类型和Mutex没有const的构造函数
+ * 用于channel的构造函数，让它们可以在const上下文中求值
+ */
+macro_rules! const_fn {
+    ($(#[$attr:meta])* $vis:vis fn $($sig:tt)*) => {
+        #[cfg(not(loom))]
+        $(#[$attr])* $vis const fn $($sig)*
+        #[cfg(loom)]
+        $(#[$attr])* $vis fn $($sig)*
+    };
+}
+pub(crate) use const_fn;
+
+#[cfg(all(feature = "std", not(loom)))]
+pub(crate) use std::{sync::{Mutex, MutexGuard}, thread, thread_local};
+#[cfg(loom)]
+pub(crate) use loom::{sync::{Mutex, MutexGuard}, thread, thread_local};
@@ -1,9 +1,10 @@
-use std::{cell::Cell, ops::{Deref, DerefMut}};
+use core::{cell::Cell, ops::{Deref, DerefMut}};
 
 // 这是对Channel内部值的一个抽象，它做了缓存行填充的优化
 // list和array channel都会用到这个缓存行优化
 
 // 将一个值填充并对齐到一个缓存行的长度 注意x86_64位架构，目前缓存行对齐128字节
+// s390x的缓存行是256字节，其他架构(arm、riscv64等)按64字节对齐，不能退化为默认对齐
 
 //Cache块 
 #[derive(Clone, Copy, Default, Hash, PartialEq, Eq)]
@@ -11,13 +12,26 @@ use std::{cell::Cell, ops::{Deref, DerefMut}};
     any(target_arch = "x86_64", target_arch = "aarch64", target_arch = "powerpc64",),
     repr(align(128))
 )]
+#[cfg_attr(target_arch = "s390x", repr(align(256)))]
+#[cfg_attr(
+    not(any(
+        target_arch = "x86_64",
+        target_arch = "aarch64",
+        target_arch = "powerpc64",
+        target_arch = "s390x",
+    )),
+    repr(align(64))
+)]
 pub struct CachePadded<T>{
     value:T
 }
 
+// 在编译期检查，任何架构上都至少对齐到64字节
+const _: () = assert!(core::mem::align_of::<CachePadded<u8>>() >= 64);
+
 impl<T> CachePadded<T>{
     //填充并对齐一个值到一个缓存块的长度
-    pub fn new(value:T)->CachePadded<T>{
+    pub const fn new(value:T)->CachePadded<T>{
         CachePadded::<T> { value }
     }
 }
@@ -36,40 +50,167 @@ impl<T> DerefMut for CachePadded<T>{
     }
 }
 
-const SPIN_LIMIT: u32 = 6;
+pub(crate) const SPIN_LIMIT: u32 = 6;
 
 // 在自选环路中执行二次退避
 pub struct Backoff{
-    step:Cell<u32>
+    step:Cell<u32>,
+    // 自旋的上限，超过之后spin_heavy改为yield，is_completed返回true
+    limit:u32
 }
 
 impl Backoff {
 
     pub fn new()->Self{
-        Backoff { step: Cell::new(0) }
+        Backoff::with_limit(SPIN_LIMIT)
+    }
+
+    pub fn with_limit(limit:u32)->Self{
+        Backoff { step: Cell::new(0), limit }
+    }
+
+    // 重置到初始状态，以便在外层循环中重复使用同一个Backoff
+    pub fn reset(&self){
+        self.step.set(0);
     }
 
     pub fn spin_light(&self){
-        let step=self.step.get().min(SPIN_LIMIT);
+        // loom中每次自旋都是一次调度点，只让出一次以免状态空间爆炸
+        #[cfg(loom)]
+        if self.loom_yield(){
+            return;
+        }
+        #[cfg(all(test, feature = "std"))]
+        spins::add();
+        let step=self.step.get().min(self.limit);
         for _ in 0..step.pow(2){
-            std::hint::spin_loop();
+            core::hint::spin_loop();
         }
         self.step.set(self.step.get()+1);
     }
 
     pub fn spin_heavy(&self){
-        if self.step.get()<=SPIN_LIMIT{
+        #[cfg(loom)]
+        if self.loom_yield(){
+            return;
+        }
+        #[cfg(all(test, feature = "std"))]
+        spins::add();
+        if self.step.get()<=self.limit{
             for _ in 0..self.step.get().pow(2){
-                std::hint::spin_loop();
+                core::hint::spin_loop();
             }
         }else{
+            // no_std下没有yield_now，只能继续自旋
+            #[cfg(feature = "std")]
             std::thread::yield_now();
+            #[cfg(not(feature = "std"))]
+            core::hint::spin_loop();
         }
         self.step.set(self.step.get()+1);
     }
 
+    #[cfg(loom)]
+    fn loom_yield(&self)->bool{
+        super::sync::thread::yield_now();
+        self.step.set(self.step.get()+1);
+        true
+    }
+
     pub fn is_completed(&self)->bool{
-        self.step.get() > SPIN_LIMIT
+        self.step.get() > self.limit
+    }
+
+}
+
+/*
+ * 只在测试中使用：统计当前线程中spin_light/spin_heavy被调用的次数，用来比较不同的自旋上限
+ * 计数属于线程，并行运行的其他测试不会影响当前测试读到的值
+ */
+#[cfg(all(test, feature = "std"))]
+pub(crate) mod spins {
+    use std::cell::Cell;
+
+    thread_local! {
+        static SPINS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    pub(super) fn add() {
+        SPINS.with(|spins| spins.set(spins.get() + 1));
+    }
+
+    // 当前线程到目前为止自旋的次数
+    pub(crate) fn count() -> usize {
+        SPINS.with(Cell::get)
+    }
+}
+
+/*
+ * 在waker中注册之后创建，正常返回之前调用disarm
+ * 如果在阻塞期间panic(例如自定义的Parker或Clock)，drop时执行清理，把操作从waker中移除，
+ * 否则留在waker中的Entry会被之后的notify选中，唤醒丢失，zero channel中还会指向已经释放的栈上packet
+ */
+pub(crate) struct UnwindGuard<F: FnOnce()>(Option<F>);
+
+impl<F: FnOnce()> UnwindGuard<F> {
+    pub(crate) fn new(cleanup: F) -> Self {
+        UnwindGuard(Some(cleanup))
+    }
+    pub(crate) fn disarm(mut self) {
+        self.0 = None;
+    }
+}
+
+impl<F: FnOnce()> Drop for UnwindGuard<F> {
+    fn drop(&mut self) {
+        if let Some(cleanup) = self.0.take() {
+            cleanup();
+        }
+    }
+}
+
+// 引用计数溢出时终止进程，no_std下没有process::abort，用panic代替
+#[cfg(feature = "std")]
+pub(crate) fn abort() -> ! {
+    std::process::abort()
+}
+
+#[cfg(not(feature = "std"))]
+pub(crate) fn abort() -> ! {
+    panic!("reference count overflow")
+}
+
+#[test]
+fn backoff_completes_at_configured_limit() {
+    for limit in [0, 3, SPIN_LIMIT] {
+        let backoff = Backoff::with_limit(limit);
+        for _ in 0..=limit {
+            assert!(!backoff.is_completed());
+            backoff.spin_light();
+        }
+        assert!(backoff.is_completed());
     }
+}
 
-}
\ No newline at end of file
+#[test]
+fn backoff_reset_behaves_like_new() {
+    let backoff = Backoff::new();
+    while !backoff.is_completed() {
+        backoff.spin_light();
+    }
+    backoff.reset();
+    let fresh = Backoff::new();
+    while !fresh.is_completed() {
+        assert!(!backoff.is_completed());
+        fresh.spin_light();
+        backoff.spin_light();
+    }
+    assert!(backoff.is_completed());
+}
+
+#[test]
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64", target_arch = "powerpc64"))]
+fn cache_padded_is_128_byte_aligned() {
+    assert_eq!(std::mem::align_of::<CachePadded<u8>>(), 128);
+    assert_eq!(std::mem::size_of::<CachePadded<u8>>(), 128);
+}
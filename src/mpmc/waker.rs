@@ -1,7 +1,9 @@
-use std::sync::{atomic::{AtomicBool,Ordering}, Mutex};
+use super::sync::{atomic::{AtomicBool,Ordering}, const_fn, loom_seq_cst_fence, Mutex};
+use std::time::Instant;
 use super::{
-    context::Context,
+    context::{now, Context},
     select::{Operation, Selected},
+    utils::UnwindGuard,
 };
 
 // 线程的select可能会造成阻塞，Entry代表一个线程阻塞在一个指定的channel上的操作
@@ -12,6 +14,8 @@ pub(crate) struct Entry {
     pub(crate) packet: *mut (),
     // 与线程所做的操作相关的Context
     pub(crate) cx: Context,
+    // 注册的顺序，selectors按ticket从小到大排列，try_select_oldest_with从最小的开始选
+    pub(crate) ticket: usize,
 }
 
 // 这个数据结构被线程用来记录阻塞操作，并在操作准备就绪后被唤醒
@@ -20,14 +24,20 @@ pub(crate) struct Waker {
     selectors: Vec<Entry>,
     // 等待ready的operation list
     observers: Vec<Entry>,
+    // try_select下一次开始查找的位置，使唤醒在selectors之间轮转，而不是总是从0开始
+    cursor: usize,
+    // 下一个注册的entry拿到的ticket
+    next_ticket: usize,
 }
 
 impl Waker {
     #[inline]
-    pub(crate) fn new() -> Self {
+    pub(crate) const fn new() -> Self {
         Self {
             selectors: Vec::new(),
             observers: Vec::new(),
+            cursor: 0,
+            next_ticket: 0,
         }
     }
 
@@ -40,13 +50,55 @@ impl Waker {
     // 注册一个select操作和一个packet
     #[inline]
     pub(crate) fn register_with_packet(&mut self, oper: Operation, packet: *mut (), cx: &Context) {
-        self.selectors.push(Entry {
+        self.register_ticketed(oper, packet, None, cx);
+    }
+
+    /*
+     * 注册一个select操作和一个packet，返回它的ticket
+     * ticket为None时领一个新的ticket，排在所有已注册的entry之后；
+     * 被唤醒之后没能完成操作、需要重新注册的线程传入之前的ticket，回到它原来在队列中的位置
+     */
+    pub(crate) fn register_ticketed(
+        &mut self,
+        oper: Operation,
+        packet: *mut (),
+        ticket: Option<usize>,
+        cx: &Context,
+    ) -> usize {
+        let ticket = ticket.unwrap_or_else(|| {
+            self.next_ticket = self.next_ticket.wrapping_add(1);
+            self.next_ticket
+        });
+        let pos = self.selectors.partition_point(|entry| entry.ticket < ticket);
+        self.selectors.insert(
+            pos,
+            Entry {
+                oper,
+                packet,
+                cx: cx.clone(),
+                ticket,
+            },
+        );
+        ticket
+    }
+
+    // 注册一个observer，它不会被try_select选中，只会在notify时被唤醒
+    #[inline]
+    pub(crate) fn watch(&mut self, oper: Operation, cx: &Context) {
+        self.observers.push(Entry {
             oper,
-            packet,
+            packet: std::ptr::null_mut(),
             cx: cx.clone(),
+            ticket: 0,
         });
     }
 
+    // 取消一个observer，notify已经唤醒并移除它时什么也不做
+    #[inline]
+    pub(crate) fn unwatch(&mut self, oper: Operation) {
+        self.observers.retain(|entry| entry.oper != oper);
+    }
+
     //取消一个select操作
     #[inline]
     pub(crate) fn unregister(&mut self, oper: Operation) -> Option<Entry> {
@@ -64,26 +116,91 @@ impl Waker {
     }
 
     // 尝试寻找其他线程的entry，select这个操作，并唤醒它
+    // 从cursor开始环形查找，被选中的entry移除后它后面的entry会移动到这个位置，下一次就从这里开始
+    // 只会在selectors中查找，observers不会被选中，也不会被移除
     #[inline]
     pub(crate) fn try_select(&mut self) -> Option<Entry> {
-        self.selectors
-            .iter()
-            .position(|selector| {
-                selector.cx.thread_id() != current_thread_id()
-                    && selector
-                        .cx
-                        .try_select(Selected::Operation(selector.oper))
-                        .is_ok()
-                    && {
-                        selector.cx.store_packet(selector.packet);
-                        selector.cx.unpark();
-                        true
-                    }
+        self.try_select_with(|entry| entry.packet)
+    }
+
+    // 与try_select相同，但是entry被选中之后、唤醒之前先调用prepare，存入的是prepare返回的packet
+    #[inline]
+    pub(crate) fn try_select_with(&mut self, prepare: impl FnMut(&Entry) -> *mut ()) -> Option<Entry> {
+        let len = self.selectors.len();
+        if len == 0 {
+            return None;
+        }
+        let start = self.cursor % len;
+        self.select_from(start, prepare)
+    }
+
+    // 与try_select_with相同，但总是从ticket最小，也就是等待最久的entry开始查找
+    #[inline]
+    pub(crate) fn try_select_oldest_with(&mut self, prepare: impl FnMut(&Entry) -> *mut ()) -> Option<Entry> {
+        self.select_from(0, prepare)
+    }
+
+    fn select_from(&mut self, start: usize, mut prepare: impl FnMut(&Entry) -> *mut ()) -> Option<Entry> {
+        let len = self.selectors.len();
+        let thread_id = current_thread_id();
+        let pos = (0..len).map(|i| (start + i) % len).find(|&pos| {
+            let selector = &self.selectors[pos];
+            selector.cx.thread_id() != thread_id
+                && selector
+                    .cx
+                    .try_select(Selected::Operation(selector.oper))
+                    .is_ok()
+                && {
+                    // select和packet都必须在unpark之前写入，被唤醒的线程醒来后会立即读取它们
+                    selector.cx.store_packet(prepare(selector));
+                    true
+                }
+        })?;
+        self.cursor = pos;
+        // 先移除再唤醒，unpark(可能是用户的Parker)panic时selectors中不会留下已经被选中的entry
+        let entry = self.selectors.remove(pos);
+        entry.cx.unpark();
+        Some(entry)
+    }
+
+    // 是否有注册的selector，不区分是哪个线程注册的以及是否已经被select
+    #[inline]
+    pub(crate) fn has_selectors(&self) -> bool {
+        !self.selectors.is_empty()
+    }
+
+    // 有注册的selector并且全部来自当前线程，即除了自己以外没有可以配对的操作
+    #[cfg(debug_assertions)]
+    pub(crate) fn only_current_thread(&self) -> bool {
+        let thread_id = current_thread_id();
+        !self.selectors.is_empty() && self.selectors.iter().all(|selector| selector.cx.thread_id() == thread_id)
+    }
+
+    // 没有任何selector和observer
+    #[inline]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.selectors.is_empty() && self.observers.is_empty()
+    }
+
+    // 是否存在其他线程注册的、还没有被select的操作，即try_select是否可能成功
+    #[inline]
+    pub(crate) fn can_select(&self) -> bool {
+        if self.selectors.is_empty() {
+            false
+        } else {
+            let thread_id = current_thread_id();
+            self.selectors.iter().any(|selector| {
+                selector.cx.thread_id() != thread_id && selector.cx.selected() == Selected::Waiting
             })
-            .map(|pos| self.selectors.remove(pos))
+        }
     }
 
-    // 通知所有等待准备的操作
+    /*
+     * 唤醒并移除所有的observer
+     * 与try_select不同，try_select只唤醒一个selector并把操作交给它完成，
+     * 而observer只关心channel的状态发生了变化，被唤醒之后需要自己重新检查，所以每次notify全部唤醒
+     * observer上select的也是Operation，只是这个操作不需要accept
+     */
     #[inline]
     pub(crate) fn notify(&mut self) {
         // 移除所有的监视者并unpark他们
@@ -118,46 +235,144 @@ impl Drop for Waker {
     }
 }
 
-// 一个可以被线程之间共享而不会被阻塞的Waker
+/*
+ * 一个可以被线程之间共享而不会被阻塞的Waker
+ * is_empty是inner.is_empty()的缓存，让没有等待者时的notify不需要加锁
+ * 不变式：所有修改selectors/observers的操作都在持有锁时完成，并在释放锁之前用inner.is_empty()更新is_empty，
+ * 所以持有锁时读到的is_empty总是准确的，notify中加锁后的第二次读取就是为了依赖这一点
+ * 不加锁的第一次读取可能是过期的，但不会丢失唤醒：等待者总是先register(SeqCst写入false)再检查channel的状态，
+ * 唤醒者总是先修改channel的状态(SeqCst)再在notify中读取is_empty。SeqCst保证两者不可能都读到对方修改之前的值，
+ * 要么notify读到false并唤醒等待者，要么等待者在park之前看到了新的状态并放弃等待
+ */
 pub(crate) struct SyncWaker {
     inner: Mutex<Waker>,
     is_empty: AtomicBool,
 }
 
 impl SyncWaker {
-    #[inline]
-    pub(crate) fn new() -> Self {
-        SyncWaker {
-            inner: Mutex::new(Waker::new()),
-            is_empty: AtomicBool::new(true),
+    const_fn! {
+        #[inline]
+        pub(crate) fn new() -> Self {
+            SyncWaker {
+                inner: Mutex::new(Waker::new()),
+                is_empty: AtomicBool::new(true),
+            }
         }
     }
     #[inline]
     pub(crate) fn register(&self, oper: Operation, cx: &Context) {
+        self.register_with_packet(oper, std::ptr::null_mut(), cx);
+    }
+    #[inline]
+    pub(crate) fn register_with_packet(&self, oper: Operation, packet: *mut (), cx: &Context) {
+        self.register_ticketed(oper, packet, None, cx);
+    }
+    #[inline]
+    pub(crate) fn register_ticketed(
+        &self,
+        oper: Operation,
+        packet: *mut (),
+        ticket: Option<usize>,
+        cx: &Context,
+    ) -> usize {
         let mut inner = self.inner.lock().unwrap();
-        inner.register(oper, cx);
-        self.is_empty
-            .store(inner.selectors.is_empty() && inner.observers.is_empty(), Ordering::SeqCst);
+        let ticket = inner.register_ticketed(oper, packet, ticket, cx);
+        self.is_empty.store(inner.is_empty(), Ordering::SeqCst);
+        loom_seq_cst_fence();
+        ticket
     }
     #[inline]
     pub(crate) fn unregister(&self, oper: Operation) -> Option<Entry> {
         let mut inner = self.inner.lock().unwrap();
         let entry = inner.unregister(oper);
-        self.is_empty
-            .store(inner.selectors.is_empty() && inner.observers.is_empty(), Ordering::SeqCst);
+        self.is_empty.store(inner.is_empty(), Ordering::SeqCst);
         entry
     }
     #[inline]
+    pub(crate) fn watch(&self, oper: Operation, cx: &Context) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.watch(oper, cx);
+        self.is_empty.store(inner.is_empty(), Ordering::SeqCst);
+        loom_seq_cst_fence();
+    }
+    #[inline]
+    pub(crate) fn unwatch(&self, oper: Operation) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.unwatch(oper);
+        self.is_empty.store(inner.is_empty(), Ordering::SeqCst);
+    }
+    /*
+     * 作为observer阻塞，直到done返回true或者到了deadline，返回最后一次检查done的结果
+     * 每次notify都会唤醒所有的observer，醒来之后重新检查done
+     * 注册之后会再检查一次done，与notify之前的修改配合，不会丢失唤醒
+     */
+    pub(crate) fn wait_observed(&self, deadline: Option<Instant>, done: impl Fn() -> bool) -> bool {
+        loop {
+            if done() {
+                return true;
+            }
+            if deadline.is_some_and(|d| now() >= d) {
+                return false;
+            }
+            Context::with(|cx| {
+                let mut hook = 0u8;
+                let oper = Operation::hook(&mut hook);
+                self.watch(oper, cx);
+                // 被notify唤醒时observer已经被移除，其他情况需要自己移除，unwatch不存在的observer什么也不做
+                let _unwatch = UnwindGuard::new(|| self.unwatch(oper));
+                if done() {
+                    let _ = cx.try_select(Selected::Aborted);
+                }
+                cx.wait_until(deadline);
+            });
+        }
+    }
+    #[inline]
     pub(crate) fn notify(&self) {
+        self.notify_with(|entry| entry.packet);
+    }
+    // 唤醒一个selector时由prepare决定交给它的packet，observer照常全部唤醒
+    #[inline]
+    pub(crate) fn notify_with(&self, prepare: impl FnMut(&Entry) -> *mut ()) {
+        self.notify_selecting(|inner| inner.try_select_with(prepare));
+    }
+    // 与notify_with相同，但是选中的是等待最久(ticket最小)的selector，而不是轮转
+    #[inline]
+    pub(crate) fn notify_oldest_with(&self, prepare: impl FnMut(&Entry) -> *mut ()) {
+        self.notify_selecting(|inner| inner.try_select_oldest_with(prepare));
+    }
+    #[inline]
+    fn notify_selecting(&self, select: impl FnOnce(&mut Waker) -> Option<Entry>) {
+        loom_seq_cst_fence();
         if !self.is_empty.load(Ordering::SeqCst) {
             let mut inner = self.inner.lock().unwrap();
             if !self.is_empty.load(Ordering::SeqCst) {
-                inner.try_select();
+                select(&mut inner);
                 inner.notify();
-                self.is_empty.store(
-                    inner.selectors.is_empty() && inner.observers.is_empty(),
-                    Ordering::SeqCst,
-                );
+                self.is_empty.store(inner.is_empty(), Ordering::SeqCst);
+            }
+        }
+    }
+    /*
+     * 在一次加锁中唤醒最多n个selector，observer照常全部唤醒
+     * 每次try_select选中的entry都会被移除，所以被唤醒的是n个不同的selector；selector不够n个时唤醒全部
+     * 用于一次写入了多个msg的情况，比每个msg调用一次notify少加n-1次锁
+     */
+    pub(crate) fn notify_many(&self, n: usize) {
+        if n == 0 {
+            return;
+        }
+        loom_seq_cst_fence();
+        if !self.is_empty.load(Ordering::SeqCst) {
+            let mut inner = self.inner.lock().unwrap();
+            if !self.is_empty.load(Ordering::SeqCst) {
+                for _ in 0..n {
+                    if inner.try_select().is_none() {
+                        break;
+                    }
+                }
+                inner.notify();
+                self.is_empty.store(inner.is_empty(), Ordering::SeqCst);
             }
         }
     }
@@ -165,15 +380,14 @@ impl SyncWaker {
     pub(crate) fn disconnect(&self) {
         let mut inner = self.inner.lock().unwrap();
         inner.disconnect();
-        self.is_empty
-            .store(inner.selectors.is_empty() && inner.observers.is_empty(), Ordering::SeqCst);
+        self.is_empty.store(inner.is_empty(), Ordering::SeqCst);
     }
 }
 
 impl Drop for SyncWaker {
     #[inline]
     fn drop(&mut self) {
-        debug_assert!(self.is_empty.load(std::sync::atomic::Ordering::SeqCst));
+        debug_assert!(self.is_empty.load(Ordering::SeqCst));
     }
 }
 
@@ -181,7 +395,124 @@ impl Drop for SyncWaker {
 pub fn current_thread_id() -> usize {
     // `u8` is not drop so this variable will be available during thread destruction,
     // whereas `thread::current()` would not be
-    thread_local! { static DUMMY: u8 = 0 }
+    #[cfg(not(loom))]
+    thread_local! { static DUMMY: u8 = const { 0 } }
+    // loom的线程都跑在同一个系统线程上，必须用loom的thread_local区分
+    #[cfg(loom)]
+    super::sync::thread_local! { static DUMMY: u8 = 0 }
     
     DUMMY.with(|x| (x as *const u8) as usize)
 }
+
+#[cfg(test)]
+fn other_thread_context() -> Context {
+    std::thread::spawn(|| Context::with(|cx| cx.clone())).join().unwrap()
+}
+
+#[test]
+fn observers_fire_on_notify_but_not_on_try_select() {
+    let (selector, observer) = (other_thread_context(), other_thread_context());
+    let (mut a, mut b) = (0u8, 0u8);
+    let (sel_oper, obs_oper) = (Operation::hook(&mut a), Operation::hook(&mut b));
+
+    let mut waker = Waker::new();
+    waker.register(sel_oper, &selector);
+    waker.watch(obs_oper, &observer);
+
+    // try_select只选中selector，observer仍然在等待
+    assert_eq!(waker.try_select().map(|e| e.oper), Some(sel_oper));
+    assert_eq!(selector.selected(), Selected::Operation(sel_oper));
+    assert_eq!(observer.selected(), Selected::Waiting);
+    assert!(waker.try_select().is_none());
+    assert_eq!(waker.observers.len(), 1);
+
+    waker.notify();
+    assert_eq!(observer.selected(), Selected::Operation(obs_oper));
+    assert!(waker.observers.is_empty());
+}
+
+#[test]
+fn unwatch_removes_only_that_observer() {
+    let (cx1, cx2) = (other_thread_context(), other_thread_context());
+    let (mut a, mut b) = (0u8, 0u8);
+    let (oper1, oper2) = (Operation::hook(&mut a), Operation::hook(&mut b));
+
+    let waker = SyncWaker::new();
+    waker.watch(oper1, &cx1);
+    waker.watch(oper2, &cx2);
+    waker.unwatch(oper1);
+    waker.notify();
+    assert_eq!(cx1.selected(), Selected::Waiting);
+    assert_eq!(cx2.selected(), Selected::Operation(oper2));
+    assert!(waker.is_empty.load(Ordering::SeqCst));
+}
+
+#[test]
+fn notify_many_selects_distinct_selectors() {
+    let cxs: Vec<Context> = (0..3).map(|_| other_thread_context()).collect();
+    let mut slots = [0u8; 3];
+    let opers: Vec<Operation> = slots.iter_mut().map(Operation::hook).collect();
+
+    let waker = SyncWaker::new();
+    for (oper, cx) in opers.iter().zip(&cxs) {
+        waker.register(*oper, cx);
+    }
+    waker.notify_many(2);
+    let selected = cxs.iter().filter(|cx| cx.selected() != Selected::Waiting).count();
+    assert_eq!(selected, 2);
+    assert!(!waker.is_empty.load(Ordering::SeqCst));
+
+    // 比剩下的selector多也没关系
+    waker.notify_many(5);
+    for (oper, cx) in opers.iter().zip(&cxs) {
+        assert_eq!(cx.selected(), Selected::Operation(*oper));
+    }
+    assert!(waker.is_empty.load(Ordering::SeqCst));
+}
+
+#[test]
+fn reregistered_ticket_keeps_its_place() {
+    let cxs: Vec<Context> = (0..3).map(|_| other_thread_context()).collect();
+    let mut slots = [0u8; 3];
+    let opers: Vec<Operation> = slots.iter_mut().map(Operation::hook).collect();
+
+    let mut waker = Waker::new();
+    let first = waker.register_ticketed(opers[0], std::ptr::null_mut(), None, &cxs[0]);
+    waker.register(opers[1], &cxs[1]);
+    waker.register(opers[2], &cxs[2]);
+    // 第一个entry离开队列后带着原来的ticket重新注册，仍然排在最前面
+    waker.unregister(opers[0]);
+    waker.register_ticketed(opers[0], std::ptr::null_mut(), Some(first), &cxs[0]);
+    let order: Vec<_> = (0..3)
+        .map(|_| waker.try_select_oldest_with(|entry| entry.packet).unwrap().oper)
+        .collect();
+    assert_eq!(order, opers);
+}
+
+#[test]
+fn notify_rotates_through_reregistered_selectors() {
+    let mut slots = [0u8; 3];
+    let opers: Vec<Operation> = slots.iter_mut().map(Operation::hook).collect();
+    let mut cxs: Vec<Context> = (0..3).map(|_| other_thread_context()).collect();
+
+    let waker = SyncWaker::new();
+    for (oper, cx) in opers.iter().zip(&cxs) {
+        waker.register(*oper, cx);
+    }
+    // 每次被唤醒的操作用新的context重新注册，notify依次选中每一个，不会总是选中同一个
+    let mut order = Vec::new();
+    for _ in 0..opers.len() * 2 {
+        waker.notify();
+        let i = cxs.iter().position(|cx| cx.selected() != Selected::Waiting).unwrap();
+        assert_eq!(cxs[i].selected(), Selected::Operation(opers[i]));
+        order.push(opers[i]);
+        cxs[i] = other_thread_context();
+        waker.register(opers[i], &cxs[i]);
+    }
+    let expected: Vec<Operation> = opers.iter().chain(&opers).copied().collect();
+    assert_eq!(order, expected);
+
+    for oper in &opers {
+        waker.unregister(*oper);
+    }
+}
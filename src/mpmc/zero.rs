@@ -2,16 +2,17 @@ use std::{
     cell::UnsafeCell,
     fmt,
     marker::PhantomData,
-    sync::{
-        atomic::{AtomicBool, Ordering},
-        Mutex,
-    },
+    ops::{Deref, DerefMut},
+    sync::PoisonError,
     time::Instant,
 };
 
+use super::sync::{atomic::{AtomicBool, Ordering}, Mutex, MutexGuard};
 use super::{
-    context::Context, errors::*, select::{Token,Operation,Selected}, utils::Backoff, waker::Waker,
+    context::Context, errors::*, Handoff, select::{Token,Operation,SelectHandle,Selected}, utils::{Backoff, UnwindGuard}, waker::Waker,
 };
+#[cfg(feature = "metrics")]
+use super::metrics::Metrics;
 
 // 指向Packet的一个指针
 pub(crate) struct ZeroToken(*mut ());
@@ -46,6 +47,14 @@ impl<T> Packet<T> {
             msg: UnsafeCell::new(None),
         }
     }
+    // select注册时使用，select所在的栈帧在配对之前可能已经返回，所以packet分配在堆上
+    fn empty_on_heap() -> Box<Packet<T>> {
+        Box::new(Packet {
+            on_stack: false,
+            ready: AtomicBool::new(false),
+            msg: UnsafeCell::new(None),
+        })
+    }
     fn message_on_stack(msg: T) -> Packet<T> {
         Packet {
             on_stack: true,
@@ -71,9 +80,41 @@ struct Inner {
     // 当channel断开连接时为true
     is_disconnected: bool,
 }
+// Channel::lock返回的guard，释放锁之前根据Inner的状态更新send_maybe_ready
+struct Locked<'a, T> {
+    chan: &'a Channel<T>,
+    inner: MutexGuard<'a, Inner>,
+}
+
+impl<T> Deref for Locked<'_, T> {
+    type Target = Inner;
+    fn deref(&self) -> &Inner {
+        &self.inner
+    }
+}
+
+impl<T> DerefMut for Locked<'_, T> {
+    fn deref_mut(&mut self) -> &mut Inner {
+        &mut self.inner
+    }
+}
+
+impl<T> Drop for Locked<'_, T> {
+    fn drop(&mut self) {
+        let ready = self.inner.receivers.has_selectors() || self.inner.is_disconnected;
+        self.chan.send_maybe_ready.store(ready, Ordering::SeqCst);
+    }
+}
+
 // 注意通道访问的互斥性 Channel的泛型代表sender和recv的消息类型
 pub(crate) struct Channel<T> {
     inner: Mutex<Inner>,
+    // 有receiver注册在等待或者channel已经断开，即try_send可能不返回Full，每次释放锁之前更新
+    // 频繁轮询的try_send看到false时不需要加锁
+    send_maybe_ready: AtomicBool,
+    // zero channel的msg直接在两个线程之间传递，在write/read和阻塞一方被配对时计数
+    #[cfg(feature = "metrics")]
+    pub(crate) metrics: Metrics,
     // 删除通道意味删除一个T
     _marker: PhantomData<T>,
 }
@@ -86,10 +127,25 @@ impl<T> Channel<T> {
                 receivers: Waker::new(),
                 is_disconnected: false,
             }),
+            send_maybe_ready: AtomicBool::new(false),
+            #[cfg(feature = "metrics")]
+            metrics: Metrics::new(),
             _marker: PhantomData,
         }
     }
 
+    /*
+     * 所有的加锁都通过这里
+     * 持有锁时会唤醒其他线程(unpark可能来自用户的Parker)，如果这时panic，Mutex会被poison，
+     * 但是Inner中的状态在每次修改之后都是完整的，所以直接取回guard继续使用，不让一次panic使整个channel不可用
+     */
+    fn lock(&self) -> Locked<'_, T> {
+        Locked {
+            chan: self,
+            inner: self.inner.lock().unwrap_or_else(PoisonError::into_inner),
+        }
+    }
+
     // 向一个packet写入一个msg
     pub(crate) unsafe fn write(&self, token: &mut Token, msg: T) -> Result<(), T> {
         if token.zero.0.is_null() {
@@ -99,6 +155,8 @@ impl<T> Channel<T> {
         let packet = &*(token.zero.0 as *const Packet<T>);
         packet.msg.get().write(Some(msg));
         packet.ready.store(true, Ordering::Release);
+        #[cfg(feature = "metrics")]
+        self.metrics.on_send(0);
         Ok(())
     }
 
@@ -107,6 +165,8 @@ impl<T> Channel<T> {
         if token.zero.0.is_null() {
             return Err(());
         }
+        #[cfg(feature = "metrics")]
+        self.metrics.on_recv();
         let packet = &*(token.zero.0 as *const Packet<T>);
         if packet.on_stack {
             let msg = packet.msg.get().replace(None).unwrap();
@@ -122,10 +182,41 @@ impl<T> Channel<T> {
         }
     }
 
+    // 尝试与一个等待中的receiver配对，配对成功或者channel已经断开时返回true
+    fn start_send(&self, token: &mut Token) -> bool {
+        let mut inner = self.lock();
+        if let Some(operation) = inner.receivers.try_select() {
+            token.zero.0 = operation.packet;
+            true
+        } else if inner.is_disconnected {
+            token.zero.0 = std::ptr::null_mut();
+            true
+        } else {
+            false
+        }
+    }
+
+    // 尝试与一个等待中的sender配对，配对成功或者channel已经断开时返回true
+    fn start_recv(&self, token: &mut Token) -> bool {
+        let mut inner = self.lock();
+        if let Some(operation) = inner.senders.try_select() {
+            token.zero.0 = operation.packet;
+            true
+        } else if inner.is_disconnected {
+            token.zero.0 = std::ptr::null_mut();
+            true
+        } else {
+            false
+        }
+    }
+
     // 尝试将msg写入channel
     pub(crate) fn try_send(&self, msg: T) -> Result<(), TrySendError<T>> {
+        if !self.send_maybe_ready.load(Ordering::SeqCst) {
+            return Err(TrySendError::Full(msg));
+        }
         let token = &mut Token::default();
-        let mut inner = self.inner.lock().unwrap();
+        let mut inner = self.lock();
         if let Some(operation) = inner.receivers.try_select() {
             token.zero.0 = operation.packet;
             drop(inner);
@@ -140,13 +231,28 @@ impl<T> Channel<T> {
         }
     }
 
+    /*
+     * 阻塞发送，直到有receiver配对或者到达deadline
+     * Waker::try_select会跳过当前线程注册的操作，所以一个线程永远不会和自己配对
+     * 如果只有当前线程在使用这个channel，send(None)会永远阻塞，因为阻塞中的线程不可能再去recv，
+     * 单线程下应该使用try_send(返回Full)或者send_timeout(返回Timeout)
+     */
     pub(crate) fn send(
         &self,
         msg: T,
         deadline: Option<Instant>,
     ) -> Result<(), SendTimeoutError<T>> {
+        self.send_confirmed(msg, deadline).map(drop)
+    }
+
+    // 与send相同，返回完成交接的路径：直接交给已经在等待的receiver，或者park之后被receiver配对
+    pub(crate) fn send_confirmed(
+        &self,
+        msg: T,
+        deadline: Option<Instant>,
+    ) -> Result<Handoff, SendTimeoutError<T>> {
         let token = &mut Token::default();
-        let mut inner = self.inner.lock().unwrap();
+        let mut inner = self.lock();
 
         // If there's a waiting receiver, pair up with it.
         if let Some(operation) = inner.receivers.try_select() {
@@ -155,7 +261,7 @@ impl<T> Channel<T> {
             unsafe {
                 self.write(token, msg).ok().unwrap();
             }
-            return Ok(());
+            return Ok(Handoff::Immediate);
         }
 
         if inner.is_disconnected {
@@ -166,31 +272,45 @@ impl<T> Channel<T> {
             // Prepare for blocking until a receiver wakes us up.
             let oper = Operation::hook(token);
             let mut packet = Packet::<T>::message_on_stack(msg);
+            // 先唤醒observer再注册，notify中panic时不会在senders中留下指向栈上packet的entry
+            // 两者都在持有锁时完成，被唤醒的observer重新检查时一定能看到这次注册
+            inner.receivers.notify();
             inner
                 .senders
                 .register_with_packet(oper, &mut packet as *mut Packet<T> as *mut (), cx);
-            inner.receivers.notify();
             drop(inner);
+            // 已经被receiver选中时，它还会从packet中读取msg，必须等它读完才能释放栈上的packet
+            let guard = UnwindGuard::new(|| {
+                let entry = self.lock().senders.unregister(oper);
+                if entry.is_none() {
+                    packet.wait_ready();
+                }
+            });
 
             // Block the current thread.
             let sel = cx.wait_until(deadline);
+            guard.disarm();
 
             match sel {
                 Selected::Waiting => unreachable!(),
                 Selected::Aborted => {
-                    self.inner.lock().unwrap().senders.unregister(oper).unwrap();
+                    // 超时与receiver配对都要CAS同一个select，只有一方能成功：这里成功时receiver的try_select
+                    // 会跳过这个entry，它一定还在senders中，packet中的msg也没有被读取
+                    self.lock().senders.unregister(oper).unwrap();
                     let msg = unsafe { packet.msg.get().replace(None).unwrap() };
                     Err(SendTimeoutError::Timeout(msg))
                 }
                 Selected::Disconnected => {
-                    self.inner.lock().unwrap().senders.unregister(oper).unwrap();
+                    self.lock().senders.unregister(oper).unwrap();
                     let msg = unsafe { packet.msg.get().replace(None).unwrap() };
                     Err(SendTimeoutError::Disconnected(msg))
                 }
                 Selected::Operation(_) => {
                     // Wait until the message is read, then drop the packet.
                     packet.wait_ready();
-                    Ok(())
+                    #[cfg(feature = "metrics")]
+                    self.metrics.on_send(0);
+                    Ok(Handoff::Parked)
                 }
             }
         })
@@ -198,7 +318,7 @@ impl<T> Channel<T> {
 
     pub(crate) fn try_recv(&self) -> Result<T, TryRecvError> {
         let token = &mut Token::default();
-        let mut inner = self.inner.lock().unwrap();
+        let mut inner = self.lock();
 
         // If there's a waiting sender, pair up with it.
         if let Some(operation) = inner.senders.try_select() {
@@ -214,15 +334,24 @@ impl<T> Channel<T> {
 
     /// Receives a message from the channel.
     pub(crate) fn recv(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        self.recv_rendezvous(deadline).map(|(msg, _)| msg)
+    }
+
+    // 与recv相同，同时返回receiver注册到receivers中的时刻
+    // 直接与已经在等待的sender配对时为None，即sender先到
+    pub(crate) fn recv_rendezvous(&self, deadline: Option<Instant>) -> Result<(T, Option<Instant>), RecvTimeoutError> {
         let token = &mut Token::default();
-        let mut inner = self.inner.lock().unwrap();
+        let mut inner = self.lock();
 
         // If there's a waiting sender, pair up with it.
         if let Some(operation) = inner.senders.try_select() {
             token.zero.0 = operation.packet;
             drop(inner);
             unsafe {
-                return self.read(token).map_err(|_| RecvTimeoutError::Disconnected);
+                return self
+                    .read(token)
+                    .map(|msg| (msg, None))
+                    .map_err(|_| RecvTimeoutError::Disconnected);
             }
         }
 
@@ -234,41 +363,44 @@ impl<T> Channel<T> {
             // Prepare for blocking until a sender wakes us up.
             let oper = Operation::hook(token);
             let mut packet = Packet::<T>::empty_on_stack();
+            // 与send相同，先唤醒observer再注册
+            inner.senders.notify();
             inner.receivers.register_with_packet(
                 oper,
                 &mut packet as *mut Packet<T> as *mut (),
                 cx,
             );
-            inner.senders.notify();
+            // 在锁中记录，sender只能在这之后选中这个receiver
+            let registered = Instant::now();
             drop(inner);
+            // 已经被sender选中时，它还会向packet中写入msg
+            let guard = UnwindGuard::new(|| {
+                let entry = self.lock().receivers.unregister(oper);
+                if entry.is_none() {
+                    packet.wait_ready();
+                }
+            });
 
             // Block the current thread.
             let sel = cx.wait_until(deadline);
+            guard.disarm();
 
             match sel {
                 Selected::Waiting => unreachable!(),
                 Selected::Aborted => {
-                    self.inner
-                        .lock()
-                        .unwrap()
-                        .receivers
-                        .unregister(oper)
-                        .unwrap();
+                    self.lock().receivers.unregister(oper).unwrap();
                     Err(RecvTimeoutError::Timeout)
                 }
                 Selected::Disconnected => {
-                    self.inner
-                        .lock()
-                        .unwrap()
-                        .receivers
-                        .unregister(oper)
-                        .unwrap();
+                    self.lock().receivers.unregister(oper).unwrap();
                     Err(RecvTimeoutError::Disconnected)
                 }
                 Selected::Operation(_) => {
                     // Wait until the message is provided, then read it.
                     packet.wait_ready();
-                    unsafe { Ok(packet.msg.get().replace(None).unwrap()) }
+                    #[cfg(feature = "metrics")]
+                    self.metrics.on_recv();
+                    unsafe { Ok((packet.msg.get().replace(None).unwrap(), Some(registered))) }
                 }
             }
         })
@@ -276,7 +408,7 @@ impl<T> Channel<T> {
 
     // 断开channel并唤醒所有阻塞的sender和receiver
     pub(crate) fn disconnect(&self) -> bool {
-        let mut inner = self.inner.lock().unwrap();
+        let mut inner = self.lock();
 
         if !inner.is_disconnected {
             inner.is_disconnected = true;
@@ -287,17 +419,182 @@ impl<T> Channel<T> {
             false
         }
     }
+    pub(crate) fn is_disconnected(&self) -> bool {
+        self.lock().is_disconnected
+    }
+    // zero channel不缓存msg，没有需要取完的msg，关闭就是断开
+    pub(crate) fn close_when_empty(&self) -> bool {
+        self.disconnect()
+    }
+    // 关闭就是断开，没有单独的关闭状态
+    pub(crate) fn is_closing(&self) -> bool {
+        self.is_disconnected()
+    }
+    // zero channel不缓存msg，快照总是空的
+    #[cfg(feature = "serde")]
+    pub(crate) fn snapshot(&self) -> Vec<T> {
+        Vec::new()
+    }
+    // zero channel不缓存msg，断开就意味着不会再有msg
+    pub(crate) fn is_disconnected_and_empty(&self) -> bool {
+        self.is_disconnected()
+    }
+
+    /*
+     * 对端注册的操作全部来自当前线程，即当前线程的select同时在这个channel上send和recv
+     * 当前线程阻塞之后这些操作不可能与自己配对，只能等共享handle的其他线程来完成
+     */
+    #[cfg(debug_assertions)]
+    pub(crate) fn is_self_rendezvous(&self, sending: bool) -> bool {
+        let inner = self.lock();
+        let peers = if sending { &inner.receivers } else { &inner.senders };
+        !inner.is_disconnected && peers.only_current_thread()
+    }
+
+    // zero channel只有在另一端有线程在等待配对时才是ready的，需要查看对端的Waker
+    pub(crate) fn is_send_ready(&self) -> bool {
+        let inner = self.lock();
+        inner.receivers.can_select() || inner.is_disconnected
+    }
+
+    pub(crate) fn is_recv_ready(&self) -> bool {
+        let inner = self.lock();
+        inner.senders.can_select() || inner.is_disconnected
+    }
+
+    // zero channel不持有msg，没有可以丢弃的
+    pub(crate) fn drain(&self) -> usize {
+        0
+    }
+    // 只能取到正在阻塞等待配对的sender的msg
+    pub(crate) fn try_recv_batch(&self, max: usize, mut f: impl FnMut(T)) -> usize {
+        let mut count = 0;
+        while count < max {
+            match self.try_recv() {
+                Ok(msg) => {
+                    f(msg);
+                    count += 1;
+                }
+                Err(_) => break,
+            }
+        }
+        count
+    }
     pub(crate) fn len(&self) -> usize {
         0
     }
+    pub(crate) fn len_relaxed(&self) -> usize {
+        0
+    }
     #[allow(clippy::unnecessary_wraps)]
     pub(crate) fn capacity(&self) -> Option<usize> {
         Some(0)
     }
+    // zero channel不缓存msg，不论是否有receiver在等待都返回Some(0)
+    pub(crate) fn remaining(&self) -> Option<usize> {
+        Some(0)
+    }
+    // zero channel中没有等待交接的msg：sender阻塞时msg还在它自己的栈上，配对之后直接交给receiver
     pub(crate) fn is_empty(&self) -> bool {
         true
     }
+    // 没有receiver在等待配对时是满的，这时try_send会返回Full(或者Disconnected)
     pub(crate) fn is_full(&self) -> bool {
+        !self.has_waiting_receiver()
+    }
+    // 是否有其他线程的receiver正在等待配对，有时try_send可以立即把msg交给它
+    pub(crate) fn has_waiting_receiver(&self) -> bool {
+        self.lock().receivers.can_select()
+    }
+    // 是否有其他线程的sender正在等待配对，有时try_recv可以立即取到msg
+    pub(crate) fn has_waiting_sender(&self) -> bool {
+        self.lock().senders.can_select()
+    }
+}
+
+// select使用的句柄，分别代表在这个channel上的接收操作和发送操作
+pub(crate) struct Receiver<'a, T>(&'a Channel<T>);
+pub(crate) struct Sender<'a, T>(&'a Channel<T>);
+
+impl<T> Channel<T> {
+    pub(crate) fn receiver(&self) -> Receiver<'_, T> {
+        Receiver(self)
+    }
+    pub(crate) fn sender(&self) -> Sender<'_, T> {
+        Sender(self)
+    }
+}
+
+/*
+ * select注册时带上一个堆上的空packet，配对的一方通过Waker::try_select拿到这个packet
+ * 被选中的select在accept中等待packet，然后和普通的send/recv一样通过write/read完成传递，read负责释放堆上的packet
+ * 没有被选中的注册在unregister时由select自己释放packet
+ * 与send/recv一样先notify再注册，notify中panic时不会留下已经注册的packet
+ */
+impl<T> SelectHandle for Receiver<'_, T> {
+    fn try_select(&self, token: &mut Token) -> bool {
+        self.0.start_recv(token)
+    }
+    fn register(&self, oper: Operation, cx: &Context) -> bool {
+        let packet = Box::into_raw(Packet::<T>::empty_on_heap());
+        let mut inner = self.0.lock();
+        inner.senders.notify();
+        inner.receivers.register_with_packet(oper, packet as *mut (), cx);
+        inner.senders.can_select() || inner.is_disconnected
+    }
+    fn unregister(&self, oper: Operation) {
+        if let Some(operation) = self.0.lock().receivers.unregister(oper) {
+            unsafe { drop(Box::from_raw(operation.packet as *mut Packet<T>)) }
+        }
+    }
+    fn accept(&self, token: &mut Token, cx: &Context) -> bool {
+        token.zero.0 = cx.wait_packet();
+        true
+    }
+    fn is_ready(&self) -> bool {
+        self.0.is_recv_ready()
+    }
+    // 有sender注册或者channel断开时receivers.notify会唤醒observer
+    fn watch(&self, oper: Operation, cx: &Context) -> bool {
+        let mut inner = self.0.lock();
+        inner.receivers.watch(oper, cx);
+        inner.senders.can_select() || inner.is_disconnected
+    }
+    fn unwatch(&self, oper: Operation) {
+        self.0.lock().receivers.unwatch(oper);
+    }
+}
+
+impl<T> SelectHandle for Sender<'_, T> {
+    fn try_select(&self, token: &mut Token) -> bool {
+        self.0.start_send(token)
+    }
+    fn register(&self, oper: Operation, cx: &Context) -> bool {
+        let packet = Box::into_raw(Packet::<T>::empty_on_heap());
+        let mut inner = self.0.lock();
+        inner.receivers.notify();
+        inner.senders.register_with_packet(oper, packet as *mut (), cx);
+        inner.receivers.can_select() || inner.is_disconnected
+    }
+    fn unregister(&self, oper: Operation) {
+        if let Some(operation) = self.0.lock().senders.unregister(oper) {
+            unsafe { drop(Box::from_raw(operation.packet as *mut Packet<T>)) }
+        }
+    }
+    fn accept(&self, token: &mut Token, cx: &Context) -> bool {
+        token.zero.0 = cx.wait_packet();
         true
     }
+    fn is_ready(&self) -> bool {
+        self.0.is_send_ready()
+    }
+    fn watch(&self, oper: Operation, cx: &Context) -> bool {
+        let mut inner = self.0.lock();
+        inner.senders.watch(oper, cx);
+        inner.receivers.can_select() || inner.is_disconnected
+    }
+    fn unwatch(&self, oper: Operation) {
+        self.0.lock().senders.unwatch(oper);
+    }
 }
+